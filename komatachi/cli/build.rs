@@ -0,0 +1,66 @@
+//! Embeds the agent's Docker build context in the binary (see
+//! `src/assets.rs`): the files the Dockerfile copies, listed with their
+//! paths relative to `komatachi/` in `$OUT_DIR/assets.rs`.
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Files at the top of `komatachi/` the image build reads; `src/` is
+/// added whole.
+const TOP: [&str; 7] = [
+    "Dockerfile",
+    ".dockerignore",
+    "package.json",
+    "package-lock.json",
+    "tsconfig.json",
+    "tsconfig.build.json",
+    "vitest.config.ts",
+];
+
+fn main() {
+    let manifest = PathBuf::from(env::var("CARGO_MANIFEST_DIR").expect("CARGO_MANIFEST_DIR"));
+    let root = manifest.parent().expect("cli/ is inside komatachi/");
+    let mut files: Vec<String> = TOP.iter().map(|name| name.to_string()).collect();
+    collect(root, "src", &mut files);
+
+    let mut out = String::from("pub const FILES: &[(&str, &[u8])] = &[\n");
+    for name in &files {
+        let path = root.join(name);
+        assert!(
+            path.is_file(),
+            "missing agent build file {}",
+            path.display()
+        );
+        println!("cargo:rerun-if-changed={}", path.display());
+        out.push_str(&format!(
+            "    ({:?}, include_bytes!({:?})),\n",
+            name,
+            path.display().to_string()
+        ));
+    }
+    out.push_str("];\n");
+    println!("cargo:rerun-if-changed={}", root.join("src").display());
+    let out_dir = PathBuf::from(env::var("OUT_DIR").expect("OUT_DIR"));
+    fs::write(out_dir.join("assets.rs"), out).expect("write assets.rs");
+}
+
+/// Every file under `root/dir`, sorted, as paths relative to `root`.
+fn collect(root: &Path, dir: &str, files: &mut Vec<String>) {
+    let mut entries: Vec<(String, bool)> = fs::read_dir(root.join(dir))
+        .unwrap_or_else(|e| panic!("cannot read {}: {}", root.join(dir).display(), e))
+        .map(|entry| {
+            let entry = entry.expect("directory entry");
+            let name = format!("{}/{}", dir, entry.file_name().to_string_lossy());
+            (name, entry.path().is_dir())
+        })
+        .collect();
+    entries.sort();
+    for (name, is_dir) in entries {
+        if is_dir {
+            collect(root, &name, files);
+        } else {
+            files.push(name);
+        }
+    }
+}
@@ -0,0 +1,614 @@
+//! Agent process management.
+//!
+//! Builds the Docker image, spawns the container, performs the ready
+//! handshake, and exchanges protocol messages over the container's
+//! stdin/stdout. A reader thread parses the container's stdout into a
+//! channel, so the CLI can wait for the next message with a time limit.
+
+use std::io::{self, BufReader, BufWriter, Write};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::assets;
+use crate::faults::{Fault, Faults};
+use crate::paths::Paths;
+use crate::protocol::{self, AgentMessage, Answer, ClientMessage, Inputs, SeqCheck, SeqTracker};
+use crate::safe;
+use crate::style;
+use crate::transport::FrameReader;
+
+/// Environment variables passed through to the container when set.
+const PASSTHROUGH_VARS: [&str; 5] = [
+    "KOMATACHI_MODEL",
+    "KOMATACHI_MAX_TOKENS",
+    "KOMATACHI_CONTEXT_WINDOW",
+    "KOMATACHI_ROUTING",
+    "KOMATACHI_SMALL_MODEL",
+];
+
+/// The messages of a turn, which from version 2 say which input they
+/// answer.
+const TURN_MESSAGES: [&str; 6] = [
+    "heartbeat",
+    "output_delta",
+    "usage",
+    "checkpoint",
+    "output",
+    "error",
+];
+
+/// Why a message could not be received from the agent.
+pub enum ReceiveError {
+    /// The agent closed its stdout (the container exited).
+    Closed,
+    /// Reading from the pipe failed.
+    Io(io::Error),
+    /// A line arrived but was not a valid protocol message.
+    Invalid(serde_json::Error),
+}
+
+/// The image built by `build_image`.
+pub const IMAGE: &str = "komatachi-app";
+
+/// Containers started by this process, for unique container names.
+static SPAWNED: AtomicU32 = AtomicU32::new(0);
+
+/// A running agent container.
+pub struct Agent {
+    child: Child,
+    /// Container name, for `docker kill`.
+    name: String,
+    writer: BufWriter<ChildStdin>,
+    messages: Receiver<Result<AgentMessage, ReceiveError>>,
+    /// A message taken off the channel by `wait` but not yet received.
+    pending: Option<Result<AgentMessage, ReceiveError>>,
+    incoming: Incoming,
+}
+
+/// What the agent's messages have said about the stream so far, to
+/// decide which of them `receive` passes on. Kept apart from the process
+/// so it can be fed messages without a container.
+pub struct Incoming {
+    seq: SeqTracker,
+    /// Whether the ready handshake has completed.
+    ready: bool,
+    /// The protocol version named in `ready` (see `protocol.rs`).
+    protocol: u64,
+    /// Inputs sent and not yet answered, from version 2.
+    inputs: Inputs,
+}
+
+/// Build (or reuse the cached) `IMAGE`, from the checkout or the copy
+/// embedded in the binary (see `assets.rs`).
+pub fn build_image() -> Result<(), String> {
+    let context = assets::context()?;
+    eprint!("Building Docker image...");
+    let build_status = Command::new("docker")
+        .args(["build", "--target", "app", "--tag", IMAGE, "."])
+        .current_dir(&context)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status();
+
+    match build_status {
+        Ok(status) if status.success() => {
+            eprintln!(" done.");
+            Ok(())
+        }
+        Ok(status) => {
+            eprintln!(" failed.");
+            Err(format!("docker build exited with {}", status))
+        }
+        Err(e) => {
+            eprintln!(" failed.");
+            Err(format!("cannot run docker: {}", e))
+        }
+    }
+}
+
+impl Agent {
+    /// Spawn the agent container and wait for its ready signal. `safe`
+    /// adds the `--safe` container restrictions (see `safe.rs`); `env` is
+    /// as for `launch`.
+    pub fn spawn(
+        api_key: &str,
+        paths: &Paths,
+        safe: bool,
+        env: &[(&str, String)],
+    ) -> Result<Agent, String> {
+        let mut agent = Agent::launch(api_key, paths, safe, IMAGE, env)?;
+        agent.handshake()?;
+        Ok(agent)
+    }
+
+    /// Wait for the ready signal of a launched agent, which must name a
+    /// protocol version this CLI speaks.
+    pub fn handshake(&mut self) -> Result<(), String> {
+        match self.receive() {
+            Ok(msg) if msg.r#type == "ready" => protocol::negotiated(msg.protocol).map(drop),
+            Ok(msg) if msg.r#type == "error" => Err(format!(
+                "agent failed to start: {}",
+                msg.message.unwrap_or_default()
+            )),
+            Ok(msg) => Err(format!("expected ready, got: {}", msg.r#type)),
+            Err(ReceiveError::Closed) => {
+                Err("agent exited before sending ready signal".to_string())
+            }
+            Err(ReceiveError::Io(e)) => Err(format!("reading from agent: {}", e)),
+            Err(ReceiveError::Invalid(e)) => Err(format!("invalid ready message: {}", e)),
+        }
+    }
+
+    /// Start a container from `image` without waiting for the handshake;
+    /// the first message received should be `ready`. `env` sets agent
+    /// variables in place of the host's values.
+    pub fn launch(
+        api_key: &str,
+        paths: &Paths,
+        safe: bool,
+        image: &str,
+        env: &[(&str, String)],
+    ) -> Result<Agent, String> {
+        let mut env_args: Vec<String> = vec![
+            format!("ANTHROPIC_API_KEY={}", api_key),
+            format!("KOMATACHI_PROTOCOL={}", protocol::offered()),
+        ];
+        for var in PASSTHROUGH_VARS {
+            if env.iter().any(|(name, _)| *name == var) {
+                continue;
+            }
+            if let Ok(val) = std::env::var(var) {
+                env_args.push(format!("{}={}", var, val));
+            }
+        }
+        for (name, val) in env {
+            env_args.push(format!("{}={}", name, val));
+        }
+
+        let name = format!(
+            "komatachi-{}-{}",
+            std::process::id(),
+            SPAWNED.fetch_add(1, Ordering::Relaxed)
+        );
+        // --init reaps any orphaned processes the agent leaves behind.
+        let mut docker_args: Vec<String> = vec![
+            "run".into(),
+            "-i".into(),
+            "--rm".into(),
+            "--init".into(),
+            "--name".into(),
+            name.clone(),
+        ];
+        for env_arg in env_args {
+            docker_args.push("-e".into());
+            docker_args.push(env_arg);
+        }
+        if safe {
+            docker_args.extend(safe::DOCKER_ARGS.map(String::from));
+        }
+        if let Some(user) = safe::container_user(&paths.data_dir) {
+            docker_args.push("--user".into());
+            docker_args.push(user);
+        }
+        docker_args.push("-v".into());
+        docker_args.push(format!("{}:/data", paths.data_dir.display()));
+        docker_args.push("-v".into());
+        docker_args.push(format!(
+            "{}:/home/agent{}",
+            paths.home_dir.display(),
+            if safe { ":ro" } else { "" }
+        ));
+        docker_args.push(image.into());
+
+        let mut child = Command::new("docker")
+            .args(&docker_args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .spawn()
+            .map_err(|e| format!("failed to start Docker container: {}", e))?;
+
+        let child_stdin = child.stdin.take().expect("child stdin");
+        let child_stdout = child.stdout.take().expect("child stdout");
+        let messages = spawn_reader(child_stdout, name.clone(), Faults::from_env());
+
+        Ok(Agent {
+            child,
+            name,
+            writer: BufWriter::new(child_stdin),
+            messages,
+            pending: None,
+            incoming: Incoming::default(),
+        })
+    }
+
+    /// Write one message to the agent and flush it.
+    ///
+    /// The JSON is serialized straight into the buffered pipe rather than
+    /// into an intermediate string, so a multi-megabyte prompt is not held
+    /// twice. Writes block while the pipe is full; that is the flow
+    /// control (see `cli/DECISIONS.md`).
+    pub fn send(&mut self, msg: &ClientMessage) -> Result<(), String> {
+        let written = serde_json::to_writer(&mut self.writer, msg)
+            .map_err(io::Error::from)
+            .and_then(|()| self.writer.write_all(b"\n"));
+        if written.is_err() {
+            return Err("agent stdin closed".to_string());
+        }
+        if self.writer.flush().is_err() {
+            return Err("flush to agent failed".to_string());
+        }
+        Ok(())
+    }
+
+    /// Write one line exactly as given, for deliberately malformed input.
+    pub fn send_raw(&mut self, line: &str) -> Result<(), String> {
+        let written = writeln!(self.writer, "{}", line).and_then(|()| self.writer.flush());
+        written.map_err(|_| "agent stdin closed".to_string())
+    }
+
+    /// The `id` for the next input, which its replies will echo; `None`
+    /// for an agent that speaks a version without input ids.
+    pub fn input_id(&mut self) -> Option<u64> {
+        self.incoming.input_id(Instant::now())
+    }
+
+    /// Whether the agent sends `heartbeat` while a turn runs, so silence
+    /// means it stopped rather than that a model call is slow.
+    pub fn heartbeats(&self) -> bool {
+        self.incoming.heartbeats()
+    }
+
+    /// When the agent was last heard from about the input waiting longest:
+    /// when it was sent, or its latest reply. `None` when no input is
+    /// waiting or the agent does not echo ids.
+    pub fn last_heard(&self) -> Option<Instant> {
+        self.incoming.last_heard()
+    }
+
+    /// Block until the agent writes its next message.
+    ///
+    /// Re-emitted frames (a sequence number already seen) are dropped, and
+    /// a repeated `ready` after the handshake is ignored, as is a turn's
+    /// message for an input that is not waiting (see `protocol::Inputs`);
+    /// all are reported on stderr so a misbehaving agent is visible.
+    pub fn receive(&mut self) -> Result<AgentMessage, ReceiveError> {
+        if let Some(result) = self.pending.take() {
+            return result;
+        }
+        loop {
+            let msg = self.messages.recv().unwrap_or(Err(ReceiveError::Closed))?;
+            if self.incoming.accept(&msg, Instant::now()) {
+                return Ok(msg);
+            }
+        }
+    }
+
+    /// Wait up to `timeout` for the agent to say something. Returns
+    /// `false` if it stayed silent; otherwise the next `receive` returns
+    /// without blocking. Messages `receive` would drop do not count.
+    pub fn wait(&mut self, timeout: Duration) -> bool {
+        if self.pending.is_some() {
+            return true;
+        }
+        let until = Instant::now() + timeout;
+        loop {
+            match self
+                .messages
+                .recv_timeout(until.saturating_duration_since(Instant::now()))
+            {
+                Ok(Ok(msg)) if !self.incoming.accept(&msg, Instant::now()) => continue,
+                Ok(result) => {
+                    self.pending = Some(result);
+                    return true;
+                }
+                Err(RecvTimeoutError::Timeout) => return false,
+                Err(RecvTimeoutError::Disconnected) => return true,
+            }
+        }
+    }
+
+    /// The container's name, as `docker` commands take it.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// How the container process ended, if it has.
+    pub fn exit_status(&mut self) -> Option<std::process::ExitStatus> {
+        self.child.try_wait().ok().flatten()
+    }
+
+    /// Stop an unresponsive container.
+    pub fn kill(&mut self) {
+        docker_kill(&self.name);
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+
+    /// Close the agent's stdin and wait for the container to exit.
+    pub fn shutdown(self) {
+        let Agent {
+            mut child, writer, ..
+        } = self;
+        drop(writer);
+        let _ = child.wait();
+    }
+
+    /// Close the agent's stdin and give the container `limit` to exit.
+    /// Returns how it exited, or `None` if it had to be killed.
+    pub fn shutdown_within(self, limit: Duration) -> Option<std::process::ExitStatus> {
+        let Agent {
+            mut child,
+            name,
+            writer,
+            ..
+        } = self;
+        drop(writer);
+        let started = Instant::now();
+        while started.elapsed() < limit {
+            if let Ok(Some(status)) = child.try_wait() {
+                return Some(status);
+            }
+            thread::sleep(Duration::from_millis(100));
+        }
+        docker_kill(&name);
+        let _ = child.kill();
+        let _ = child.wait();
+        None
+    }
+}
+
+impl Default for Incoming {
+    fn default() -> Incoming {
+        Incoming {
+            seq: SeqTracker::default(),
+            ready: false,
+            protocol: 1,
+            inputs: Inputs::default(),
+        }
+    }
+}
+
+impl Incoming {
+    /// The id for an input sent at `now`, or `None` before version 2.
+    pub fn input_id(&mut self, now: Instant) -> Option<u64> {
+        (self.protocol >= protocol::INPUT_IDS).then(|| self.inputs.start(now))
+    }
+
+    /// Whether the agent's version sends `heartbeat`.
+    pub fn heartbeats(&self) -> bool {
+        self.protocol >= protocol::HEARTBEATS
+    }
+
+    /// When the input waiting longest was sent or last answered.
+    pub fn last_heard(&self) -> Option<Instant> {
+        self.inputs.oldest().map(|(_, heard)| heard)
+    }
+
+    /// Whether `msg`, heard at `now`, is passed on by `receive`,
+    /// recording what it says about the stream on the way.
+    pub fn accept(&mut self, msg: &AgentMessage, now: Instant) -> bool {
+        match self.seq.check(msg.seq) {
+            SeqCheck::InOrder => {}
+            SeqCheck::Duplicate => {
+                eprintln!(
+                    "{} dropped duplicate {} message from agent (seq {})",
+                    style::warning("warning:"),
+                    msg.r#type,
+                    msg.seq.unwrap_or(0)
+                );
+                return false;
+            }
+            SeqCheck::Gap(missed) => eprintln!(
+                "{} {} message(s) from agent went missing before seq {}",
+                style::warning("warning:"),
+                missed,
+                msg.seq.unwrap_or(0)
+            ),
+        }
+        if msg.r#type == "ready" && self.ready {
+            eprintln!(
+                "{} agent sent ready again; ignored",
+                style::warning("warning:")
+            );
+            return false;
+        }
+        if msg.r#type == "ready" {
+            self.ready = true;
+            self.protocol = msg.protocol.unwrap_or(1);
+        }
+        self.protocol < protocol::INPUT_IDS || self.answers_waiting_input(msg, now)
+    }
+
+    /// Whether a turn's message answers an input still waiting, warning
+    /// about it if not, or about inputs it shows were never answered. A
+    /// message without `reply_to` is taken to answer the input waiting
+    /// longest, as in version 1.
+    fn answers_waiting_input(&mut self, msg: &AgentMessage, now: Instant) -> bool {
+        if !TURN_MESSAGES.contains(&msg.r#type.as_str()) {
+            return true;
+        }
+        let Some(id) = msg
+            .reply_to
+            .or_else(|| self.inputs.oldest().map(|(id, _)| id))
+        else {
+            return true;
+        };
+        let ends = matches!(msg.r#type.as_str(), "output" | "error");
+        match self.inputs.answer(id, ends, now) {
+            Answer::Awaited { dropped } => {
+                for old in dropped {
+                    eprintln!(
+                        "{} the reply to input {} never arrived (input {} was answered)",
+                        style::warning("warning:"),
+                        old,
+                        id
+                    );
+                }
+                true
+            }
+            Answer::Late => {
+                eprintln!(
+                    "{} dropped a late {} message for input {}, whose turn is over",
+                    style::warning("warning:"),
+                    msg.r#type,
+                    id
+                );
+                false
+            }
+            Answer::Unknown => {
+                eprintln!(
+                    "{} dropped a {} message for input {}, which was never sent",
+                    style::warning("warning:"),
+                    msg.r#type,
+                    id
+                );
+                false
+            }
+        }
+    }
+}
+
+/// Kill a container by name. Killing the `docker run` client alone would
+/// leave the container running.
+fn docker_kill(name: &str) {
+    let _ = Command::new("docker")
+        .args(["kill", name])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status();
+}
+
+/// Parse frames from the container's stdout on a thread of their own.
+/// The channel ends after end of stream or a read error. With `faults`,
+/// frames after the first (`ready`) may be tampered with, and a kill
+/// fault kills the container `name`.
+fn spawn_reader(
+    stdout: ChildStdout,
+    name: String,
+    mut faults: Option<Faults>,
+) -> Receiver<Result<AgentMessage, ReceiveError>> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let parse = |frame: &[u8]| serde_json::from_slice(frame).map_err(ReceiveError::Invalid);
+        let mut reader = FrameReader::new(BufReader::new(stdout));
+        let mut first = true;
+        loop {
+            let results = match reader.next_frame() {
+                Ok(None) => vec![Err(ReceiveError::Closed)],
+                Ok(Some(frame)) => match faults.as_mut().filter(|_| !first) {
+                    None => vec![parse(frame)],
+                    Some(faults) => match faults.next(frame.len()) {
+                        None => vec![parse(frame)],
+                        Some(fault) => {
+                            eprintln!("fault injection: {}", fault.describe());
+                            if fault == Fault::Kill {
+                                docker_kill(&name);
+                            }
+                            fault.apply(frame).iter().map(|f| parse(f)).collect()
+                        }
+                    },
+                },
+                Err(e) => vec![Err(ReceiveError::Io(e))],
+            };
+            first = false;
+            let last = matches!(
+                results.last(),
+                Some(Err(ReceiveError::Closed | ReceiveError::Io(_)))
+            );
+            for result in results {
+                if sender.send(result).is_err() {
+                    return;
+                }
+            }
+            if last {
+                break;
+            }
+        }
+    });
+    receiver
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(json: &str) -> AgentMessage {
+        serde_json::from_str(json).unwrap()
+    }
+
+    /// An `Incoming` past a `ready` for `version`.
+    fn ready(version: u64, now: Instant) -> Incoming {
+        let mut incoming = Incoming::default();
+        let ready = format!(r#"{{"type":"ready","seq":1,"protocol":{}}}"#, version);
+        assert!(incoming.accept(&message(&ready), now));
+        incoming
+    }
+
+    #[test]
+    fn drops_repeated_frames_and_a_second_ready() {
+        let now = Instant::now();
+        let mut incoming = ready(1, now);
+        let output = message(r#"{"type":"output","seq":2,"text":"hi"}"#);
+        assert!(incoming.accept(&output, now));
+        assert!(!incoming.accept(&output, now));
+        let again = message(r#"{"type":"ready","seq":3,"protocol":1}"#);
+        assert!(!incoming.accept(&again, now));
+        assert_eq!(incoming.input_id(now), None);
+    }
+
+    #[test]
+    fn passes_only_replies_to_waiting_inputs() {
+        let now = Instant::now();
+        let mut incoming = ready(2, now);
+        let first = incoming.input_id(now).unwrap();
+        let reply = |seq, kind: &str, id| {
+            message(&format!(
+                r#"{{"type":"{}","seq":{},"reply_to":{}}}"#,
+                kind, seq, id
+            ))
+        };
+        assert!(incoming.accept(&reply(2, "usage", first), now));
+        assert!(incoming.accept(&reply(3, "output", first), now));
+        assert!(!incoming.accept(&reply(4, "checkpoint", first), now));
+        assert!(!incoming.accept(&reply(5, "output", 9), now));
+        // Not a turn's message, so not matched to an input.
+        assert!(incoming.accept(&reply(6, "inspect", 9), now));
+    }
+
+    #[test]
+    fn unnumbered_replies_answer_the_oldest_input() {
+        let now = Instant::now();
+        let mut incoming = ready(2, now);
+        let first = incoming.input_id(now).unwrap();
+        let second = incoming.input_id(now).unwrap();
+        assert!(incoming.accept(&message(r#"{"type":"output","seq":2}"#), now));
+        let output = format!(r#"{{"type":"output","seq":3,"reply_to":{}}}"#, first);
+        assert!(!incoming.accept(&message(&output), now));
+        assert!(incoming.last_heard().is_some());
+        let output = format!(r#"{{"type":"output","seq":4,"reply_to":{}}}"#, second);
+        assert!(incoming.accept(&message(&output), now));
+        assert_eq!(incoming.last_heard(), None);
+    }
+
+    #[test]
+    fn a_late_reply_leaves_the_stall_clock_alone() {
+        let sent = Instant::now();
+        let mut incoming = ready(3, sent);
+        let first = incoming.input_id(sent).unwrap();
+        let output = format!(r#"{{"type":"output","seq":2,"reply_to":{}}}"#, first);
+        assert!(incoming.accept(&message(&output), sent));
+        let second = incoming.input_id(sent).unwrap();
+        let later = sent + Duration::from_secs(5);
+        let late = format!(r#"{{"type":"heartbeat","seq":3,"reply_to":{}}}"#, first);
+        assert!(!incoming.accept(&message(&late), later));
+        assert_eq!(incoming.last_heard(), Some(sent));
+        let current = format!(r#"{{"type":"heartbeat","seq":4,"reply_to":{}}}"#, second);
+        assert!(incoming.accept(&message(&current), later));
+        assert_eq!(incoming.last_heard(), Some(later));
+        assert!(incoming.heartbeats());
+    }
+}
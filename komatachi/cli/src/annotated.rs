@@ -0,0 +1,503 @@
+//! The annotated config file `komatachi-cli config edit` opens.
+//!
+//! Every setting is listed with a comment saying what it does and its
+//! default: the ones set in `config.json` as they are, the rest commented
+//! out with a sample value, so a setting is turned on by deleting its
+//! `//`. JSON has no comments, so the editor's copy is JSON with
+//! whole-line `//` comments and trailing commas allowed; `strip` turns it
+//! back into plain JSON with every line where it was, so parse errors
+//! point at the line the user sees.
+
+use serde_json::Value;
+
+/// Sections of the config file and what they are for, in file order.
+const SECTIONS: &[(&str, &str)] = &[
+    ("validation", "Rules every answer must satisfy."),
+    (
+        "watchdog",
+        "Restarting an agent that stalls or exits mid-turn.",
+    ),
+    (
+        "substitution",
+        "Replacing !`command` in prompts with its output.",
+    ),
+    ("idle", "Stopping the container while the prompt is idle."),
+    (
+        "limits",
+        "Ending sessions left unattended, at the next prompt.",
+    ),
+    (
+        "notify",
+        "A desktop notification or the bell when a turn ends.",
+    ),
+    ("keys", "Key bindings at the prompt."),
+    ("history", "Prompts kept in ~/.komatachi/history."),
+    ("prompt", "The REPL prompt and status line."),
+    ("pager", "Paging answers taller than the terminal."),
+    ("wrap", "Wrapping answers at word boundaries."),
+    ("stream", "Printing answers as they are written."),
+    ("timestamps", "Times on prompts and answers."),
+    (
+        "theme",
+        "How styled output looks: words (bold, dim, italic, underline, red, \
+         bright-red, on-blue) or SGR numbers (38;5;208), or none.",
+    ),
+    ("references", "Limits on what @name#n-m and @path quote."),
+    (
+        "scrub",
+        "What export --scrub, and clipboard pastes if asked, replace with placeholders.",
+    ),
+    (
+        "routing",
+        "Which model answers each turn; KOMATACHI_ROUTING and KOMATACHI_SMALL_MODEL win.",
+    ),
+];
+
+/// One setting: its `section.key` path, what it does, and the value its
+/// commented-out line shows.
+struct Key {
+    path: &'static str,
+    help: &'static str,
+    sample: &'static str,
+}
+
+const KEYS: &[Key] = &[
+    Key {
+        path: "validation.mustMatch",
+        help: "Regexes every answer must match.",
+        sample: r#"["^\\S"]"#,
+    },
+    Key {
+        path: "validation.mustNotMatch",
+        help: "Regexes no answer may match.",
+        sample: r#"["(?i)as an ai"]"#,
+    },
+    Key {
+        path: "validation.maxLength",
+        help: "Longest answer allowed, in characters (off by default).",
+        sample: "2000",
+    },
+    Key {
+        path: "validation.json",
+        help: "Every answer must be valid JSON.",
+        sample: "true",
+    },
+    Key {
+        path: "validation.retries",
+        help: "Times an answer breaking the rules is sent back for a fix.",
+        sample: "2",
+    },
+    Key {
+        path: "watchdog.stallSecs",
+        help: "Seconds without a message before a turn counts as stalled.",
+        sample: "600",
+    },
+    Key {
+        path: "watchdog.restart",
+        help: "Restart a stalled or exited agent and offer to resend.",
+        sample: "true",
+    },
+    Key {
+        path: "substitution.enabled",
+        help: "Run !`command`s in prompts, after asking.",
+        sample: "true",
+    },
+    Key {
+        path: "substitution.maxBytes",
+        help: "Most output kept from each command.",
+        sample: "16384",
+    },
+    Key {
+        path: "idle.suspendSecs",
+        help: "Stop the container after this long at the prompt (off by default).",
+        sample: "1800",
+    },
+    Key {
+        path: "limits.maxSessionSecs",
+        help: "End the session this long after it started (off by default).",
+        sample: "28800",
+    },
+    Key {
+        path: "limits.idleSecs",
+        help: "End the session after this long at the prompt (off by default).",
+        sample: "3600",
+    },
+    Key {
+        path: "limits.maxTurns",
+        help: "End the session after this many answered turns (off by default).",
+        sample: "100",
+    },
+    Key {
+        path: "notify.afterSecs",
+        help: "Notify when a turn takes at least this long (off by default).",
+        sample: "60",
+    },
+    Key {
+        path: "notify.always",
+        help: "Notify even if the terminal kept focus.",
+        sample: "true",
+    },
+    Key {
+        path: "notify.command",
+        help: "Notify with this instead, run with sh -c, title and message as $1 and $2.",
+        sample: r#""~/bin/notify""#,
+    },
+    Key {
+        path: "notify.bell",
+        help: "Ring the terminal bell when a turn ends (long turns only with afterSecs).",
+        sample: "true",
+    },
+    Key {
+        path: "notify.bellCommand",
+        help: "Run this with sh -c when a turn ends, in place of the bell.",
+        sample: r#""paplay /usr/share/sounds/freedesktop/stereo/complete.oga""#,
+    },
+    Key {
+        path: "keys.mode",
+        help: "emacs (the readline keys) or vi; /keys switches mid-session.",
+        sample: r#""vi""#,
+    },
+    Key {
+        path: "history.enabled",
+        help: "Save prompts and recall them with Up and Ctrl-R.",
+        sample: "false",
+    },
+    Key {
+        path: "history.size",
+        help: "Prompts kept.",
+        sample: "1000",
+    },
+    Key {
+        path: "prompt.format",
+        help: "The prompt; {conversation}, {turn}, {model}, and {tokens} are filled in.",
+        sample: r#""{conversation} #{turn}> ""#,
+    },
+    Key {
+        path: "prompt.status",
+        help: "A status line after each turn, with the same placeholders and \
+               {remaining} and {window} (off by default).",
+        sample: r#""{model} · {tokens} of {window} tokens · {conversation}""#,
+    },
+    Key {
+        path: "pager.enabled",
+        help: "Show tall answers through $PAGER.",
+        sample: "false",
+    },
+    Key {
+        path: "pager.minLines",
+        help: "Page answers taller than this (default: the terminal's height).",
+        sample: "40",
+    },
+    Key {
+        path: "wrap.enabled",
+        help: "Wrap prose in answers to the terminal's width.",
+        sample: "false",
+    },
+    Key {
+        path: "stream.enabled",
+        help: "Print answers as the model writes them, instead of when done.",
+        sample: "true",
+    },
+    Key {
+        path: "timestamps.enabled",
+        help: "Start sessions with /timestamps on.",
+        sample: "true",
+    },
+    Key {
+        path: "theme.dim",
+        help: "Secondary text: turn metadata, headings, status lines.",
+        sample: r#""bright-black""#,
+    },
+    Key {
+        path: "theme.error",
+        help: "The error: label.",
+        sample: r#""bold magenta""#,
+    },
+    Key {
+        path: "theme.warning",
+        help: "The warning: label.",
+        sample: r#""bold yellow""#,
+    },
+    Key {
+        path: "theme.added",
+        help: "Text /diff shows as added.",
+        sample: r#""bold green""#,
+    },
+    Key {
+        path: "theme.removed",
+        help: "Text /diff shows as removed.",
+        sample: r#""none""#,
+    },
+    Key {
+        path: "references.maxTokens",
+        help: "Most estimated tokens quoted into one prompt.",
+        sample: "8000",
+    },
+    Key {
+        path: "references.maxFileBytes",
+        help: "Largest file quoted with @path.",
+        sample: "102400",
+    },
+    Key {
+        path: "scrub.secrets",
+        help: "API keys, tokens, private keys, and password: or token= values.",
+        sample: "false",
+    },
+    Key {
+        path: "scrub.emails",
+        help: "Email addresses.",
+        sample: "false",
+    },
+    Key {
+        path: "scrub.hostnames",
+        help: "Hostnames and IPv4 addresses.",
+        sample: "false",
+    },
+    Key {
+        path: "scrub.patterns",
+        help: "More regexes to replace, such as internal project names.",
+        sample: r#"["(?i)project falcon"]"#,
+    },
+    Key {
+        path: "scrub.clipboard",
+        help: "Also scrub /paste and --from-clipboard text before sending it.",
+        sample: "true",
+    },
+    Key {
+        path: "routing.mode",
+        help: "fixed (every turn to KOMATACHI_MODEL) or auto (simple prompts to the small model).",
+        sample: r#""auto""#,
+    },
+    Key {
+        path: "routing.smallModel",
+        help: "The model simple prompts go to under auto (claude-3-5-haiku-20241022).",
+        sample: r#""claude-3-5-haiku-20241022""#,
+    },
+    Key {
+        path: "sinks",
+        help: "Places /send-to copies exchanges to, by name. Each sets one of \
+               file, dailyDir, or command, and optionally format (markdown or \
+               json) and auto (every answer).",
+        sample: r#"{"notes": {"file": "~/notes.md"}}"#,
+    },
+    Key {
+        path: "snippets",
+        help: "Text :name in a prompt, or /snippet name, stands for, by name.",
+        sample: r#"{"review": "Review this change for correctness and missing tests."}"#,
+    },
+];
+
+/// Settings that are maps of names the user chooses, after the sections.
+const MAPS: [&str; 2] = ["sinks", "snippets"];
+
+/// The editor's copy of a config file: `current` (the file's contents)
+/// with every other setting commented out, and `defaults` (the resolved
+/// defaults) in the comments.
+pub fn annotate(current: &Value, defaults: &Value) -> String {
+    let mut out = String::from(
+        "// Komatachi settings (~/.komatachi/config.json).\n\
+         // Lines starting with // are comments: delete the // to use a setting.\n\
+         // Trailing commas are fine. The file is saved without the comments.\n\
+         {\n",
+    );
+    for (section, help) in SECTIONS {
+        out.push_str(&format!("  // {}\n  \"{}\": {{\n", help, section));
+        for key in KEYS
+            .iter()
+            .filter(|key| key.path.split('.').next() == Some(section))
+        {
+            let name = &key.path[section.len() + 1..];
+            line(
+                &mut out,
+                "    ",
+                key,
+                name,
+                &current[section][name],
+                &defaults[section][name],
+            );
+        }
+        out.push_str("  },\n");
+    }
+    for name in MAPS {
+        let key = KEYS.iter().find(|key| key.path == name).expect("map key");
+        line(&mut out, "  ", key, name, &current[name], &Value::Null);
+    }
+    out.push_str("}\n");
+    out
+}
+
+/// A setting's comment and its line, commented out unless it is set.
+fn line(out: &mut String, indent: &str, key: &Key, name: &str, value: &Value, default: &Value) {
+    out.push_str(&format!("{}// {}", indent, key.help));
+    if !default.is_null() {
+        out.push_str(&format!(" Default: {}.", default));
+    }
+    out.push('\n');
+    match value {
+        Value::Null => out.push_str(&format!("{}// \"{}\": {},\n", indent, name, key.sample)),
+        value => out.push_str(&format!("{}\"{}\": {},\n", indent, name, value)),
+    }
+}
+
+/// The editor's copy as plain JSON: comment lines blanked and trailing
+/// commas dropped, everything else where it was.
+pub fn strip(text: &str) -> String {
+    let lines: Vec<&str> = text
+        .lines()
+        .map(|line| {
+            if line.trim_start().starts_with("//") {
+                ""
+            } else {
+                line
+            }
+        })
+        .collect();
+    let joined = lines.join("\n");
+    let mut out = String::with_capacity(joined.len());
+    let mut in_string = false;
+    let mut escaped = false;
+    for (at, c) in joined.char_indices() {
+        if in_string {
+            in_string = escaped || c != '"';
+            escaped = !escaped && c == '\\';
+        } else if c == '"' {
+            in_string = true;
+        } else if c == ','
+            && matches!(
+                joined[at + 1..].trim_start().chars().next(),
+                Some('}' | ']') | None
+            )
+        {
+            out.push(' ');
+            continue;
+        }
+        out.push(c);
+    }
+    out
+}
+
+/// A config value with its empty sections dropped, as it is saved.
+pub fn compact(value: Value) -> Value {
+    match value {
+        Value::Object(map) => Value::Object(
+            map.into_iter()
+                .filter(|(_, v)| !v.as_object().is_some_and(|section| section.is_empty()))
+                .collect(),
+        ),
+        other => other,
+    }
+}
+
+/// The settings whose resolved values differ, as `path: old -> new`.
+pub fn changes(old: &Value, new: &Value) -> Vec<String> {
+    let mut out = Vec::new();
+    diff("", old, new, &mut out);
+    out
+}
+
+fn diff(path: &str, old: &Value, new: &Value, out: &mut Vec<String>) {
+    match (old, new) {
+        (Value::Object(a), Value::Object(b)) => {
+            let mut names: Vec<&String> = a.keys().chain(b.keys()).collect();
+            names.sort();
+            names.dedup();
+            for name in names {
+                let path = if path.is_empty() {
+                    name.clone()
+                } else {
+                    format!("{}.{}", path, name)
+                };
+                let null = Value::Null;
+                diff(
+                    &path,
+                    a.get(name).unwrap_or(&null),
+                    b.get(name).unwrap_or(&null),
+                    out,
+                );
+            }
+        }
+        (a, b) if a != b => out.push(format!("{}: {} -> {}", path, a, b)),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn leaves(value: &Value, path: &str, out: &mut Vec<String>) {
+        match value.as_object() {
+            Some(map) if !MAPS.contains(&path) => {
+                for (name, value) in map {
+                    let path = if path.is_empty() {
+                        name.clone()
+                    } else {
+                        format!("{}.{}", path, name)
+                    };
+                    leaves(value, &path, out);
+                }
+            }
+            _ => out.push(path.to_string()),
+        }
+    }
+
+    #[test]
+    fn every_setting_is_annotated() {
+        let defaults = crate::config::resolved(&crate::config::Config::default());
+        let mut paths = Vec::new();
+        leaves(&defaults, "", &mut paths);
+        let mut known: Vec<String> = KEYS.iter().map(|key| key.path.to_string()).collect();
+        paths.sort();
+        known.sort();
+        assert_eq!(paths, known);
+        for key in KEYS {
+            assert!(
+                serde_json::from_str::<Value>(key.sample).is_ok(),
+                "{}",
+                key.path
+            );
+        }
+    }
+
+    #[test]
+    fn the_annotated_copy_strips_back_to_the_file() {
+        let defaults = crate::config::resolved(&crate::config::Config::default());
+        let current = json!({"watchdog": {"stallSecs": 60}, "sinks": {"a": {"file": "x"}}});
+        let text = annotate(&current, &defaults);
+        assert!(text.contains("    \"stallSecs\": 60,\n"));
+        assert!(text.contains("    // \"restart\": true,\n"));
+        assert!(text.contains("Default: 600."));
+        let parsed: Value = serde_json::from_str(&strip(&text)).unwrap();
+        assert_eq!(compact(parsed), current);
+        assert_eq!(strip(text.as_str()).lines().count(), text.lines().count());
+
+        let everything = strip(&text.replace("// \"", "\""));
+        assert!(serde_json::from_str::<crate::config::Config>(&everything).is_ok());
+
+        let edited = text.replace("// \"restart\": true,", "\"restart\": false,");
+        let parsed: Value = serde_json::from_str(&strip(&edited)).unwrap();
+        assert_eq!(
+            parsed["watchdog"],
+            json!({"stallSecs": 60, "restart": false})
+        );
+        assert_eq!(
+            strip(r#"{"a": ",}", "b": [1,],}"#),
+            r#"{"a": ",}", "b": [1 ] }"#
+        );
+    }
+
+    #[test]
+    fn changes_list_resolved_differences() {
+        let old = json!({"watchdog": {"stallSecs": 600, "restart": true}, "sinks": {}});
+        let new =
+            json!({"watchdog": {"stallSecs": 60, "restart": true}, "sinks": {"a": {"auto": true}}});
+        assert_eq!(
+            changes(&old, &new),
+            vec![
+                "sinks.a: null -> {\"auto\":true}",
+                "watchdog.stallSecs: 600 -> 60"
+            ]
+        );
+    }
+}
@@ -0,0 +1,98 @@
+//! The agent's Docker build context, embedded in the binary.
+//!
+//! The image is built from `komatachi/`: the Dockerfile, the package
+//! files, and the TypeScript sources. `build.rs` embeds a copy of them, so
+//! a binary installed with `cargo install`, or copied to another machine,
+//! can build the image without the checkout. While the checkout the binary
+//! was built from is still in place it is used as it is, so edits to the
+//! agent are picked up without rebuilding the CLI. Otherwise the copy is
+//! unpacked into `~/.komatachi/agent/<digest>/`, once per version of the
+//! files, and built from there.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::hash;
+use crate::paths;
+
+include!(concat!(env!("OUT_DIR"), "/assets.rs"));
+
+/// Where to build the image: the checkout, or the unpacked copy.
+pub fn context() -> Result<PathBuf, String> {
+    let checkout = Path::new(env!("CARGO_MANIFEST_DIR")).join("..");
+    if checkout.join("Dockerfile").is_file() && checkout.join("src").is_dir() {
+        return Ok(checkout);
+    }
+    unpack(&paths::komatachi_dir().join("agent"))
+}
+
+/// Identifies this version of the files, for the directory name.
+fn digest() -> String {
+    let mut data = Vec::new();
+    for (name, contents) in FILES {
+        data.extend_from_slice(name.as_bytes());
+        data.push(0);
+        data.extend_from_slice(&(contents.len() as u64).to_be_bytes());
+        data.extend_from_slice(contents);
+    }
+    hash::sha256_hex(&data)[..12].to_string()
+}
+
+/// Unpack the files into `parent/<digest>` unless they are there already.
+/// They are written to a temporary directory renamed into place, so an
+/// interrupted unpack is never built from.
+fn unpack(parent: &Path) -> Result<PathBuf, String> {
+    let dir = parent.join(digest());
+    if dir.is_dir() {
+        return Ok(dir);
+    }
+    let partial = parent.join(format!(".partial-{}", std::process::id()));
+    let written = (|| -> io::Result<()> {
+        for (name, contents) in FILES {
+            let path = partial.join(name);
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::write(path, contents)?;
+        }
+        fs::rename(&partial, &dir)
+    })();
+    match written {
+        Ok(()) => Ok(dir),
+        Err(e) => {
+            let _ = fs::remove_dir_all(&partial);
+            // Another session unpacking the same files got there first.
+            if dir.is_dir() {
+                return Ok(dir);
+            }
+            Err(format!(
+                "cannot unpack the agent's build files into {}: {}",
+                dir.display(),
+                e
+            ))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unpacks_the_build_context_once() {
+        let parent = std::env::temp_dir().join(format!("komatachi-assets-{}", std::process::id()));
+        let dir = unpack(&parent).unwrap();
+        assert_eq!(dir, parent.join(digest()));
+        assert!(dir.join("Dockerfile").is_file());
+        assert!(dir.join("src/index.ts").is_file());
+        assert_eq!(
+            fs::read(dir.join("package.json")).unwrap(),
+            fs::read(Path::new(env!("CARGO_MANIFEST_DIR")).join("../package.json")).unwrap()
+        );
+        fs::write(dir.join("marker"), "").unwrap();
+        assert_eq!(unpack(&parent).unwrap(), dir);
+        assert!(dir.join("marker").is_file());
+        fs::remove_dir_all(&parent).unwrap();
+    }
+}
@@ -0,0 +1,230 @@
+//! `komatachi-cli backup create|restore <file>`: the whole of
+//! `~/.komatachi` as one archive.
+//!
+//! The archive is a gzipped tar of the directory -- config, conversation,
+//! identity files, forks, bookmarks, and history -- made with the system
+//! `tar`. A file name ending in `.gpg` is encrypted with a passphrase by
+//! `gpg --symmetric`, which asks for it on the terminal. Restoring never
+//! merges into an existing directory: an agent's memory is either the
+//! backed-up one or the current one, not a mixture.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use crate::paths::{self, Paths};
+use crate::safe;
+use crate::style;
+
+/// Prefix of the temporary directory an encrypted backup is decrypted into.
+const DECRYPT_PREFIX: &str = "komatachi-restore-";
+
+const USAGE: &str = "usage: komatachi-cli backup create|restore <file>  (.gpg: encrypted)";
+
+/// `komatachi-cli backup create|restore <file>`
+pub fn run_command(args: &[String]) {
+    let result = match args {
+        [command, file] if command == "create" => create(Path::new(file)),
+        [command, file] if command == "restore" => restore(Path::new(file)),
+        _ => {
+            eprintln!("{}", USAGE);
+            std::process::exit(2);
+        }
+    };
+    if let Err(e) = result {
+        eprintln!("{} {}", style::error("error:"), e);
+        std::process::exit(1);
+    }
+}
+
+fn create(file: &Path) -> Result<(), String> {
+    let root = paths::komatachi_dir();
+    if !root.is_dir() {
+        return Err(format!(
+            "nothing to back up: {} does not exist",
+            root.display()
+        ));
+    }
+    if file.exists() {
+        return Err(format!("{} already exists", file.display()));
+    }
+    let absolute = std::env::current_dir()
+        .map(|dir| dir.join(file))
+        .unwrap_or_else(|_| file.to_path_buf());
+    if absolute.starts_with(&root) {
+        return Err(format!(
+            "the backup cannot be written inside {}",
+            root.display()
+        ));
+    }
+    warn_relocated(&root);
+    eprintln!(
+        "Backing up {}. Stop any running session first: a turn in progress may be \
+         only partly saved.",
+        root.display()
+    );
+
+    let (parent, name) = split_root(&root)?;
+    let mut tar = Command::new("tar");
+    tar.arg("-czf");
+    if encrypted(file) {
+        tar.arg("-");
+    } else {
+        tar.arg(file);
+    }
+    tar.arg("-C").arg(&parent).arg(name);
+
+    if encrypted(file) {
+        let mut tar = tar
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("cannot run tar: {}", e))?;
+        let archive = tar.stdout.take().expect("tar stdout");
+        let gpg = Command::new("gpg")
+            .args(["--symmetric", "--output"])
+            .arg(file)
+            .stdin(archive)
+            .status();
+        let tar = tar.wait();
+        check("gpg", gpg)?;
+        check("tar", tar)?;
+    } else {
+        check("tar", tar.status())?;
+    }
+    eprintln!("Backed up to {}.", file.display());
+    Ok(())
+}
+
+fn restore(file: &Path) -> Result<(), String> {
+    let root = paths::komatachi_dir();
+    let occupied = fs::read_dir(&root)
+        .map(|mut entries| entries.next().is_some())
+        .unwrap_or(false);
+    if occupied {
+        return Err(format!(
+            "{} already exists; move it aside before restoring",
+            root.display()
+        ));
+    }
+    if !file.is_file() {
+        return Err(format!("{} is not a file", file.display()));
+    }
+
+    let (parent, name) = split_root(&root)?;
+    if !encrypted(file) {
+        extract(file, &parent, name)?;
+        eprintln!("Restored {} from {}.", root.display(), file.display());
+        return Ok(());
+    }
+    // Decrypt once, so the passphrase is asked for once although the
+    // archive is read twice (list, then extract). The plaintext goes in a
+    // directory only this user can enter, created under a random name so
+    // no one can plant or predict it.
+    let private = safe::create_private_dir(DECRYPT_PREFIX)
+        .map_err(|e| format!("cannot create a directory to decrypt into: {}", e))?;
+    let plain = private.join("backup.tar.gz");
+    let result = check(
+        "gpg",
+        Command::new("gpg")
+            .args(["--decrypt", "--output"])
+            .arg(&plain)
+            .arg(file)
+            .status(),
+    )
+    .and_then(|()| extract(&plain, &parent, name));
+    let _ = fs::remove_dir_all(&private);
+    result?;
+    eprintln!("Restored {} from {}.", root.display(), file.display());
+    Ok(())
+}
+
+/// Unpack `archive` into `parent` after checking that everything in it
+/// belongs under `name`.
+fn extract(archive: &Path, parent: &Path, name: &str) -> Result<(), String> {
+    let listing = Command::new("tar")
+        .arg("-tzf")
+        .arg(archive)
+        .output()
+        .map_err(|e| format!("cannot run tar: {}", e))?;
+    if !listing.status.success() {
+        return Err("not a Komatachi backup (tar cannot read it)".to_string());
+    }
+    check_entries(&String::from_utf8_lossy(&listing.stdout), name)?;
+    fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+    let status = Command::new("tar")
+        .arg("-xzf")
+        .arg(archive)
+        .arg("-C")
+        .arg(parent)
+        .status();
+    check("tar", status)
+}
+
+/// Whether `file` is (to be) encrypted with gpg.
+fn encrypted(file: &Path) -> bool {
+    file.extension().is_some_and(|ext| ext == "gpg")
+}
+
+/// The directory holding `root`, and `root`'s own name.
+fn split_root(root: &Path) -> Result<(PathBuf, &str), String> {
+    match (root.parent(), root.file_name().and_then(|n| n.to_str())) {
+        (Some(parent), Some(name)) => Ok((parent.to_path_buf(), name)),
+        _ => Err(format!("cannot back up {}", root.display())),
+    }
+}
+
+/// Every archive entry must lie inside the `name` directory, so
+/// extracting cannot write anywhere else.
+fn check_entries(listing: &str, name: &str) -> Result<(), String> {
+    for entry in listing.lines().filter(|line| !line.is_empty()) {
+        let mut parts = entry.trim_end_matches('/').split('/');
+        let inside = parts.next() == Some(name) && parts.all(|part| part != "..");
+        if !inside {
+            return Err(format!("archive entry outside {}: {}", name, entry));
+        }
+    }
+    Ok(())
+}
+
+/// Data or home directories moved out of `~/.komatachi` by environment
+/// variables are not in the backup; say so.
+fn warn_relocated(root: &Path) {
+    let paths = Paths::resolve();
+    for (label, dir) in [("data", &paths.data_dir), ("home", &paths.home_dir)] {
+        if !dir.starts_with(root) {
+            eprintln!(
+                "{} the {} dir {} is outside {} and is not included",
+                style::warning("warning:"),
+                label,
+                dir.display(),
+                root.display()
+            );
+        }
+    }
+}
+
+fn check(tool: &str, status: std::io::Result<std::process::ExitStatus>) -> Result<(), String> {
+    match status {
+        Ok(status) if status.success() => Ok(()),
+        Ok(status) => Err(format!("{} exited with {}", tool, status)),
+        Err(e) => Err(format!("cannot run {}: {}", tool, e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entries_inside_the_directory_pass() {
+        let listing = ".komatachi/\n.komatachi/data/\n.komatachi/config.json\n";
+        assert!(check_entries(listing, ".komatachi").is_ok());
+    }
+
+    #[test]
+    fn entries_outside_the_directory_fail() {
+        assert!(check_entries(".komatachi/../.bashrc\n", ".komatachi").is_err());
+        assert!(check_entries("other/file\n", ".komatachi").is_err());
+        assert!(check_entries("/etc/passwd\n", ".komatachi").is_err());
+    }
+}
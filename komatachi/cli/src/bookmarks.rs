@@ -0,0 +1,181 @@
+//! Turn bookmarks.
+//!
+//! `/bookmark [note]` in the REPL records the most recent exchange in
+//! `bookmarks.jsonl` inside the data directory. The exchange text is
+//! copied rather than referenced by transcript position: compaction
+//! rewrites the transcript, so positions are not stable.
+
+use serde::{Deserialize, Serialize};
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+
+use crate::paths::Paths;
+use crate::style;
+use crate::time;
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Bookmark {
+    /// When the bookmark was created (epoch ms)
+    pub created_at: u64,
+    #[serde(default)]
+    pub note: Option<String>,
+    pub input: String,
+    pub output: String,
+}
+
+/// Append one bookmark, creating the file if needed.
+pub fn append(path: &Path, bookmark: &Bookmark) -> io::Result<()> {
+    let json = serde_json::to_string(bookmark).expect("serialize bookmark");
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "{}", json)
+}
+
+/// Load all bookmarks. A missing file means no bookmarks.
+///
+/// A trailing line that fails to parse is skipped (crash mid-append);
+/// any other unparseable line is an error.
+pub fn load(path: &Path) -> io::Result<Vec<Bookmark>> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    parse(&contents).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+fn parse(contents: &str) -> Result<Vec<Bookmark>, String> {
+    let lines: Vec<&str> = contents.lines().filter(|l| !l.trim().is_empty()).collect();
+    let mut bookmarks = Vec::with_capacity(lines.len());
+    for (index, line) in lines.iter().enumerate() {
+        match serde_json::from_str(line) {
+            Ok(bookmark) => bookmarks.push(bookmark),
+            Err(_) if index == lines.len() - 1 => {}
+            Err(e) => return Err(format!("corrupt bookmark on line {}: {}", index + 1, e)),
+        }
+    }
+    Ok(bookmarks)
+}
+
+/// `komatachi-cli bookmarks [--export]`
+pub fn run_command(args: &[String]) {
+    let export = match args {
+        [] => false,
+        [flag] if flag == "--export" => true,
+        _ => {
+            eprintln!("usage: komatachi-cli bookmarks [--export]");
+            std::process::exit(2);
+        }
+    };
+
+    let path = Paths::resolve().bookmarks_file();
+    let bookmarks = load(&path).unwrap_or_else(|e| {
+        eprintln!(
+            "{} cannot read {}: {}",
+            style::error("error:"),
+            path.display(),
+            e
+        );
+        std::process::exit(1);
+    });
+
+    if bookmarks.is_empty() {
+        eprintln!("No bookmarks. Use /bookmark [note] in the REPL to add one.");
+        return;
+    }
+
+    if export {
+        print!("{}", render_markdown(&bookmarks));
+    } else {
+        for (index, bookmark) in bookmarks.iter().enumerate() {
+            println!("{}", summary_line(index + 1, bookmark));
+        }
+    }
+}
+
+fn summary_line(number: usize, bookmark: &Bookmark) -> String {
+    let label = match &bookmark.note {
+        Some(note) => note.clone(),
+        None => first_line(&bookmark.input),
+    };
+    format!(
+        "{:>3}. {}  {}",
+        number,
+        time::format_utc(bookmark.created_at),
+        label
+    )
+}
+
+/// Render bookmarked exchanges as a markdown document.
+fn render_markdown(bookmarks: &[Bookmark]) -> String {
+    let mut out = String::new();
+    for (index, bookmark) in bookmarks.iter().enumerate() {
+        out.push_str(&format!(
+            "## {}. {}\n\n",
+            index + 1,
+            bookmark.note.as_deref().unwrap_or("Bookmark")
+        ));
+        out.push_str(&format!(
+            "_{} UTC_\n\n",
+            time::format_utc(bookmark.created_at)
+        ));
+        out.push_str(&format!("**User:** {}\n\n", bookmark.input));
+        out.push_str(&format!("**Agent:** {}\n\n", bookmark.output));
+    }
+    out
+}
+
+fn first_line(text: &str) -> String {
+    text.lines().next().unwrap_or("").to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn line(note: Option<&str>) -> String {
+        serde_json::to_string(&Bookmark {
+            created_at: 0,
+            note: note.map(str::to_string),
+            input: "question".to_string(),
+            output: "answer".to_string(),
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn parses_entries_in_order() {
+        let contents = format!("{}\n{}\n", line(Some("first")), line(None));
+        let bookmarks = parse(&contents).unwrap();
+        assert_eq!(bookmarks.len(), 2);
+        assert_eq!(bookmarks[0].note.as_deref(), Some("first"));
+        assert!(bookmarks[1].note.is_none());
+    }
+
+    #[test]
+    fn skips_truncated_trailing_line() {
+        let contents = format!("{}\n{{\"createdAt\":1,", line(None));
+        assert_eq!(parse(&contents).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn rejects_corrupt_middle_line() {
+        let contents = format!("not json\n{}\n", line(None));
+        assert!(parse(&contents).is_err());
+    }
+
+    #[test]
+    fn summary_falls_back_to_first_input_line() {
+        let bookmark = Bookmark {
+            created_at: 0,
+            note: None,
+            input: "line one\nline two".to_string(),
+            output: String::new(),
+        };
+        assert_eq!(
+            summary_line(1, &bookmark),
+            "  1. 1970-01-01 00:00  line one"
+        );
+    }
+}
@@ -0,0 +1,106 @@
+//! Conversation checkpoints.
+//!
+//! The agent records a checkpoint after every successful turn in
+//! `checkpoints/index.jsonl` inside the data directory, and checkpoint 0
+//! when it creates the conversation (see `src/worker/index.ts`). The CLI only reads that file, for `/checkpoints`;
+//! rolling back is the agent's job (`restore_checkpoint`), since only the
+//! agent may write the conversation.
+
+use serde::Deserialize;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::time;
+
+/// Checkpoints listed by `/checkpoints`, newest last.
+const LIST_ROWS: usize = 20;
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Checkpoint {
+    pub id: u64,
+    /// Epoch ms
+    pub created_at: u64,
+    pub message_count: u64,
+    pub preview: String,
+}
+
+/// Load the checkpoint list. A missing file means none yet. Unreadable
+/// lines are skipped: the list is informational, and the agent validates
+/// the id on restore.
+pub fn load(path: &Path) -> io::Result<Vec<Checkpoint>> {
+    match fs::read_to_string(path) {
+        Ok(contents) => Ok(contents
+            .lines()
+            .filter_map(|line| serde_json::from_str(line).ok())
+            .collect()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e),
+    }
+}
+
+/// The checkpoint before `id`: the conversation one exchange earlier,
+/// since restoring forgets every later checkpoint and the list stays
+/// linear.
+pub fn before(checkpoints: &[Checkpoint], id: u64) -> Option<&Checkpoint> {
+    let index = checkpoints
+        .iter()
+        .position(|checkpoint| checkpoint.id == id)?;
+    index.checked_sub(1).map(|earlier| &checkpoints[earlier])
+}
+
+/// The most recent checkpoints, one per line.
+pub fn render(checkpoints: &[Checkpoint]) -> String {
+    let start = checkpoints.len().saturating_sub(LIST_ROWS);
+    let mut out = String::new();
+    for checkpoint in &checkpoints[start..] {
+        let preview = checkpoint.preview.lines().next().unwrap_or("");
+        out.push_str(&format!(
+            "#{:<4} {}  {:>4} msgs  {}\n",
+            checkpoint.id,
+            time::format_utc(checkpoint.created_at),
+            checkpoint.message_count,
+            preview
+        ));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn checkpoint(id: u64) -> Checkpoint {
+        Checkpoint {
+            id,
+            created_at: 0,
+            message_count: id * 2,
+            preview: "what is\nthis".to_string(),
+        }
+    }
+
+    #[test]
+    fn renders_first_preview_line() {
+        assert_eq!(
+            render(&[checkpoint(3)]),
+            "#3    1970-01-01 00:00     6 msgs  what is\n"
+        );
+    }
+
+    #[test]
+    fn finds_the_checkpoint_before() {
+        let all: Vec<Checkpoint> = [2, 3, 7].into_iter().map(checkpoint).collect();
+        assert_eq!(before(&all, 7).map(|c| c.id), Some(3));
+        assert!(before(&all, 2).is_none());
+        assert!(before(&all, 5).is_none());
+    }
+
+    #[test]
+    fn lists_only_the_most_recent() {
+        let all: Vec<Checkpoint> = (1..=30).map(checkpoint).collect();
+        let rendered = render(&all);
+        assert_eq!(rendered.lines().count(), LIST_ROWS);
+        assert!(rendered.starts_with("#11 "));
+    }
+}
@@ -0,0 +1,222 @@
+//! System clipboard access.
+//!
+//! Shells out to the platform's clipboard tool rather than linking a
+//! clipboard library: `pbpaste` (macOS), `wl-paste` (Wayland), `xclip` or
+//! `xsel` (X11), `Get-Clipboard` (Windows). The first tool that runs
+//! successfully wins; copying works the same way with `pbcopy` and the
+//! rest.
+//!
+//! Over SSH those tools would reach the remote machine's clipboard, so
+//! a copy is sent to the terminal instead as an OSC 52 escape sequence,
+//! which terminals that support it put on the local clipboard. OSC 52 is
+//! also the fallback when no tool runs.
+
+use std::env;
+use std::io::{self, IsTerminal, Write};
+use std::process::{Command, Stdio};
+
+use crate::scrub::{self, Scrubber};
+use crate::style;
+use crate::terminal;
+
+/// Pastes above this size need confirmation before sending.
+const LARGE_PASTE_BYTES: usize = 64 * 1024;
+
+/// Paste commands, tried in order.
+const PASTE_COMMANDS: [&[&str]; 5] = [
+    &["pbpaste"],
+    &["wl-paste", "--no-newline"],
+    &["xclip", "-selection", "clipboard", "-o"],
+    &["xsel", "--clipboard", "--output"],
+    &["powershell", "-NoProfile", "-Command", "Get-Clipboard"],
+];
+
+/// Copy commands, tried in order; each reads the text on stdin.
+const COPY_COMMANDS: [&[&str]; 5] = [
+    &["pbcopy"],
+    &["wl-copy"],
+    &["xclip", "-selection", "clipboard"],
+    &["xsel", "--clipboard", "--input"],
+    &[
+        "powershell",
+        "-NoProfile",
+        "-Command",
+        "$input | Set-Clipboard",
+    ],
+];
+
+/// Where `write` put the text.
+pub enum Copied {
+    Tool(&'static str),
+    /// Sent to the terminal as OSC 52; whether it arrived is not known.
+    Terminal,
+}
+
+/// Read the clipboard as text.
+pub fn read() -> Result<String, String> {
+    for command in PASTE_COMMANDS {
+        let output = Command::new(command[0])
+            .args(&command[1..])
+            .stdin(Stdio::null())
+            .stderr(Stdio::null())
+            .output();
+        if let Ok(output) = output {
+            if output.status.success() {
+                return String::from_utf8(output.stdout)
+                    .map_err(|_| "clipboard does not contain UTF-8 text".to_string());
+            }
+        }
+    }
+    Err("no clipboard tool found (tried pbpaste, wl-paste, xclip, xsel, powershell)".to_string())
+}
+
+/// Put `text` on the clipboard.
+pub fn write(text: &str) -> Result<Copied, String> {
+    let remote = env::var_os("SSH_TTY").is_some() || env::var_os("SSH_CONNECTION").is_some();
+    if !remote {
+        for command in COPY_COMMANDS {
+            if copy_with(command, text) {
+                return Ok(Copied::Tool(command[0]));
+            }
+        }
+    }
+    if !io::stderr().is_terminal() {
+        return Err(
+            "no clipboard tool found (tried pbcopy, wl-copy, xclip, xsel, powershell)".to_string(),
+        );
+    }
+    eprint!("{}", osc52(text));
+    io::stderr().flush().ok();
+    Ok(Copied::Terminal)
+}
+
+fn copy_with(command: &[&str], text: &str) -> bool {
+    let Ok(mut child) = Command::new(command[0])
+        .args(&command[1..])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+    else {
+        return false;
+    };
+    let written = child
+        .stdin
+        .take()
+        .is_some_and(|mut stdin| stdin.write_all(text.as_bytes()).is_ok());
+    child.wait().is_ok_and(|status| status.success()) && written
+}
+
+/// The OSC 52 sequence that sets the clipboard to `text`.
+fn osc52(text: &str) -> String {
+    format!("\x1b]52;c;{}\x07", base64(text.as_bytes()))
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, &b)| n | (b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i) & 63) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+/// The contents of the last fenced code block in `text`, if any.
+pub fn last_code_block(text: &str) -> Option<String> {
+    let mut blocks = Vec::new();
+    let mut current: Option<Vec<&str>> = None;
+    for line in text.lines() {
+        if line.trim_start().starts_with("```") {
+            match current.take() {
+                Some(lines) => blocks.push(lines.join("\n")),
+                None => current = Some(Vec::new()),
+            }
+        } else if let Some(lines) = current.as_mut() {
+            lines.push(line);
+        }
+    }
+    blocks.pop()
+}
+
+/// Build a prompt from the clipboard, optionally preceded by `instruction`.
+/// With `scrub.clipboard` on, the clipboard text is scrubbed first and
+/// what was replaced is reported.
+///
+/// Returns `None` (after reporting why) if there is nothing to send.
+pub fn prompt(instruction: &str, settings: &scrub::Settings) -> Option<String> {
+    let pasted = match read() {
+        Ok(text) => text,
+        Err(e) => {
+            eprintln!("{} {}", style::error("error:"), e);
+            return None;
+        }
+    };
+    if pasted.trim().is_empty() {
+        eprintln!("{} clipboard is empty", style::error("error:"));
+        return None;
+    }
+    if pasted.len() > LARGE_PASTE_BYTES {
+        let question = format!(
+            "Clipboard holds {} KB (~{} tokens). Send it?",
+            pasted.len() / 1024,
+            pasted.chars().count() / 4
+        );
+        if !terminal::confirm(&question) {
+            return None;
+        }
+    }
+    let pasted = if settings.clipboard() {
+        let mut scrubber = match Scrubber::new(settings) {
+            Ok(scrubber) => scrubber,
+            Err(e) => {
+                eprintln!("{} {}", style::error("error:"), e);
+                return None;
+            }
+        };
+        let scrubbed = scrubber.scrub(&pasted);
+        eprint!("{}", style::dim(&scrub::report(scrubber.removed())));
+        scrubbed
+    } else {
+        pasted
+    };
+    if instruction.is_empty() {
+        Some(pasted)
+    } else {
+        Some(format!("{}\n\n{}", instruction, pasted))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_base64_with_padding() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foo"), "Zm9v");
+        assert_eq!(osc52("hi"), "\x1b]52;c;aGk=\x07");
+    }
+
+    #[test]
+    fn finds_the_last_code_block() {
+        let text = "First:\n```sh\nls\n```\nThen:\n```rust\nfn main() {}\n\n// done\n```\nOk.";
+        assert_eq!(
+            last_code_block(text).as_deref(),
+            Some("fn main() {}\n\n// done")
+        );
+        assert_eq!(last_code_block("no code"), None);
+        assert_eq!(last_code_block("```\nunclosed"), None);
+    }
+}
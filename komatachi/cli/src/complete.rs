@@ -0,0 +1,142 @@
+//! Tab completion at the REPL prompt.
+//!
+//! The word before the cursor completes as a command name when it is the
+//! first word and starts with `/` (unless no command fits, as with an
+//! absolute path), and otherwise as a path on the host,
+//! relative to the working directory (`~/` is the home directory). Paths
+//! are what prompts refer to through `` !`command` `` substitution, and
+//! what `/paste` and the shell around the CLI work with. Hidden entries
+//! are offered only once the name being completed starts with `.`.
+
+use std::fs;
+use std::path::PathBuf;
+
+/// Completions of the word ending at the end of `before` (the line up to
+/// the cursor): the word's start, as a char index, and every full
+/// replacement for it. Directories end in `/`.
+pub fn candidates(before: &str, commands: &[&str]) -> (usize, Vec<String>) {
+    let start_byte = before.rfind(char::is_whitespace).map_or(0, |i| {
+        i + before[i..].chars().next().map_or(1, char::len_utf8)
+    });
+    let word = &before[start_byte..];
+    let start = before[..start_byte].chars().count();
+    let first_word = before[..start_byte].trim().is_empty();
+
+    if first_word && word.starts_with('/') && !word.starts_with("//") {
+        let found: Vec<String> = commands
+            .iter()
+            .filter(|name| name.starts_with(word))
+            .map(|name| name.to_string())
+            .collect();
+        if !found.is_empty() {
+            return (start, found);
+        }
+    }
+    (start, paths(word))
+}
+
+/// The longest prefix every candidate shares.
+pub fn common_prefix(candidates: &[String]) -> String {
+    let Some(first) = candidates.first() else {
+        return String::new();
+    };
+    let mut prefix: Vec<char> = first.chars().collect();
+    for candidate in &candidates[1..] {
+        let shared = prefix
+            .iter()
+            .zip(candidate.chars())
+            .take_while(|(a, b)| **a == *b)
+            .count();
+        prefix.truncate(shared);
+    }
+    prefix.into_iter().collect()
+}
+
+/// Directory entries completing `word`, sorted, written the way `word`
+/// was (so `~/` stays `~/`).
+fn paths(word: &str) -> Vec<String> {
+    let (dir_part, name_part) = match word.rfind('/') {
+        Some(i) => (&word[..=i], &word[i + 1..]),
+        None => ("", word),
+    };
+    let dir = if dir_part.is_empty() {
+        PathBuf::from(".")
+    } else if let Some(rest) = dir_part.strip_prefix("~/") {
+        PathBuf::from(std::env::var("HOME").unwrap_or_default()).join(rest)
+    } else {
+        PathBuf::from(dir_part)
+    };
+    let Ok(entries) = fs::read_dir(&dir) else {
+        return Vec::new();
+    };
+    let mut found: Vec<String> = entries
+        .filter_map(|entry| {
+            let entry = entry.ok()?;
+            let name = entry.file_name().into_string().ok()?;
+            if !name.starts_with(name_part)
+                || (name.starts_with('.') && !name_part.starts_with('.'))
+            {
+                return None;
+            }
+            let is_dir = entry.path().is_dir();
+            Some(format!(
+                "{}{}{}",
+                dir_part,
+                name,
+                if is_dir { "/" } else { "" }
+            ))
+        })
+        .collect();
+    found.sort();
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COMMANDS: [&str; 4] = ["/retry", "/rollback", "/diff", "/help"];
+
+    #[test]
+    fn completes_command_names_in_first_position() {
+        assert_eq!(
+            candidates("/r", &COMMANDS),
+            (0, vec!["/retry".to_string(), "/rollback".to_string()])
+        );
+        assert_eq!(candidates("  /di", &COMMANDS).1, vec!["/diff".to_string()]);
+        // Not the first word: a path, not a command.
+        assert!(candidates("see /r", &COMMANDS)
+            .1
+            .iter()
+            .all(|c| !COMMANDS.contains(&c.as_str())));
+    }
+
+    #[test]
+    fn completes_paths_and_hides_dotfiles() {
+        let dir = std::env::temp_dir().join(format!("komatachi-complete-{}", std::process::id()));
+        fs::create_dir_all(dir.join("notes")).unwrap();
+        fs::write(dir.join("notes.md"), "").unwrap();
+        fs::write(dir.join(".hidden"), "").unwrap();
+        let base = format!("{}/", dir.display());
+
+        let (start, found) = candidates(&format!("summarize {}no", base), &COMMANDS);
+        assert_eq!(start, "summarize ".len());
+        assert_eq!(
+            found,
+            vec![format!("{}notes.md", base), format!("{}notes/", base)]
+        );
+        assert_eq!(candidates(&base, &COMMANDS).1.len(), 2);
+        assert_eq!(
+            candidates(&format!("{}.h", base), &COMMANDS).1,
+            vec![format!("{}.hidden", base)]
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn common_prefix_of_candidates() {
+        let found = vec!["/retry".to_string(), "/rollback".to_string()];
+        assert_eq!(common_prefix(&found), "/r");
+        assert_eq!(common_prefix(&[]), "");
+    }
+}
@@ -0,0 +1,67 @@
+//! Writing a prompt in `$EDITOR`: `/edit` and Ctrl-X Ctrl-E.
+//!
+//! The draft goes into a temporary file, the user's editor (`$VISUAL`,
+//! then `$EDITOR`, then `vi`) opens it, and what is saved is the prompt.
+//! Leaving the file empty sends nothing, which is also how to back out.
+//! `config edit` opens the config file the same way.
+
+use std::fs;
+use std::process::Command;
+
+/// Edit `draft` in the user's editor. `Ok(None)` if the file was left
+/// empty.
+pub fn compose(draft: &str) -> Result<Option<String>, String> {
+    let text = edit(draft, "prompt.md").map_err(|e| format!("{}; nothing sent", e))?;
+    Ok(prompt(&text))
+}
+
+/// Edit `text` in the user's editor and return what was saved. `name`
+/// ends the temporary file's name, its extension picking the editor's
+/// syntax highlighting.
+pub fn edit(text: &str, name: &str) -> Result<String, String> {
+    let path = std::env::temp_dir().join(format!("komatachi-{}-{}", std::process::id(), name));
+    fs::write(&path, text).map_err(|e| format!("cannot write {}: {}", path.display(), e))?;
+    let editor = editor();
+    // The editor may be a command with arguments, as in `code --wait`.
+    let status = Command::new("sh")
+        .args(["-c", &format!("{} \"$1\"", editor), "sh"])
+        .arg(&path)
+        .status();
+    let saved = fs::read_to_string(&path);
+    let _ = fs::remove_file(&path);
+    match status {
+        Ok(status) if status.success() => {}
+        Ok(status) => return Err(format!("{} exited with {}", editor, status)),
+        Err(e) => return Err(format!("cannot run {}: {}", editor, e)),
+    }
+    saved.map_err(|e| format!("cannot read {}: {}", path.display(), e))
+}
+
+fn editor() -> String {
+    ["VISUAL", "EDITOR"]
+        .iter()
+        .filter_map(|var| std::env::var(var).ok())
+        .find(|editor| !editor.trim().is_empty())
+        .unwrap_or_else(|| "vi".to_string())
+}
+
+/// The saved file as a prompt: trailing whitespace dropped, `None` if
+/// nothing is left.
+fn prompt(text: &str) -> Option<String> {
+    let text = text.trim_end();
+    (!text.trim_start().is_empty()).then(|| text.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_file_sends_nothing() {
+        assert_eq!(prompt("  \n\n"), None);
+        assert_eq!(
+            prompt("  indented\nsecond line\n\n").as_deref(),
+            Some("  indented\nsecond line")
+        );
+    }
+}
@@ -0,0 +1,528 @@
+//! User configuration.
+//!
+//! Optional settings live in `~/.komatachi/config.json`. A missing file
+//! means defaults; a file that does not parse is an error rather than
+//! being ignored, so a typo never silently disables a setting. Errors
+//! name the key they are about (`validation.retries`), where it is, and
+//! for a misspelled key the one that was probably meant.
+//!
+//! `komatachi-cli config check` runs the same checks without starting a
+//! session, `config show --resolved` prints every setting with the
+//! defaults filled in, and `config edit` opens the file in `$EDITOR` with
+//! every setting described (see `annotated.rs`), checking it on save.
+
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::annotated;
+use crate::compose;
+use crate::history;
+use crate::idle;
+use crate::limits;
+use crate::lineedit;
+use crate::notify;
+use crate::pager;
+use crate::paths;
+use crate::prompt;
+use crate::references;
+use crate::routing;
+use crate::scrub;
+use crate::sinks;
+use crate::snippets;
+use crate::stream;
+use crate::style;
+use crate::substitute;
+use crate::terminal;
+use crate::timestamps;
+use crate::validate::{self, Validator};
+use crate::watchdog;
+use crate::wrap;
+
+const USAGE: &str = "usage: komatachi-cli config check | show [--resolved] | edit";
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Rules every response must satisfy (see `validate.rs`).
+    pub validation: validate::Rules,
+    /// Stall limit and restart policy (see `watchdog.rs`).
+    pub watchdog: watchdog::Settings,
+    /// `` !`command` `` expansion in prompts (see `substitute.rs`).
+    pub substitution: substitute::Settings,
+    /// Stopping the agent while the prompt is idle (see `idle.rs`).
+    pub idle: idle::Settings,
+    /// Ending sessions nobody is attending (see `limits.rs`).
+    pub limits: limits::Settings,
+    /// Notifying when a long turn ends (see `notify.rs`).
+    pub notify: notify::Settings,
+    /// Key bindings at the prompt (see `lineedit.rs`).
+    pub keys: lineedit::Settings,
+    /// Saving and recalling prompts (see `history.rs`).
+    pub history: history::Settings,
+    /// The REPL prompt string (see `prompt.rs`).
+    pub prompt: prompt::Settings,
+    /// Paging answers taller than the terminal (see `pager.rs`).
+    pub pager: pager::Settings,
+    /// Wrapping answers to the terminal's width (see `wrap.rs`).
+    pub wrap: wrap::Settings,
+    /// Printing answers as they are written (see `stream.rs`).
+    pub stream: stream::Settings,
+    /// Times on prompts and answers (see `timestamps.rs`).
+    pub timestamps: timestamps::Settings,
+    /// How styled output looks (see `style.rs`).
+    pub theme: style::Theme,
+    /// Budget for `@name#n` quotes in prompts (see `references.rs`).
+    pub references: references::Settings,
+    /// What `export --scrub` redacts (see `scrub.rs`).
+    pub scrub: scrub::Settings,
+    /// How the agent picks each turn's model (see `routing.rs`).
+    pub routing: routing::Settings,
+    /// Named places `/send-to` copies exchanges to (see `sinks.rs`).
+    pub sinks: sinks::Sinks,
+    /// Text `:name` stands for in prompts (see `snippets.rs`).
+    pub snippets: snippets::Snippets,
+}
+
+pub fn config_file() -> PathBuf {
+    paths::komatachi_dir().join("config.json")
+}
+
+/// Load the config file, or defaults if it does not exist.
+pub fn load() -> Result<Config, String> {
+    let path = config_file();
+    let contents = match fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Config::default()),
+        Err(e) => return Err(format!("cannot read {}: {}", path.display(), e)),
+    };
+    parse(&contents).map_err(|e| format!("invalid {}: {}", path.display(), e))
+}
+
+/// A config file's contents, checked as `load` checks them.
+fn parse(contents: &str) -> Result<Config, String> {
+    let config: Config = serde_json::from_str(contents).map_err(|e| describe(contents, &e))?;
+    sinks::check(&config.sinks)
+        .and_then(|()| prompt::check(&config.prompt))
+        .and_then(|()| snippets::check(&config.snippets))
+        .and_then(|()| style::check(&config.theme))
+        .and_then(|()| scrub::check(&config.scrub))?;
+    Ok(config)
+}
+
+/// A parse error with its position, the key it is about, and for an
+/// unknown key the closest known one.
+fn describe(contents: &str, error: &serde_json::Error) -> String {
+    let full = error.to_string();
+    let message = full
+        .rsplit_once(" at line ")
+        .map_or(full.as_str(), |(m, _)| m);
+    let mut out = format!("line {} column {}", error.line(), error.column());
+    if error.is_data() {
+        let path = key_path(contents, offset(contents, error.line(), error.column()));
+        if !path.is_empty() {
+            out.push_str(&format!(", {}", path));
+        }
+    }
+    out.push_str(&format!(": {}", message));
+    if let Some(suggestion) = suggest(message) {
+        out.push_str(&format!(" (did you mean `{}`?)", suggestion));
+    }
+    out
+}
+
+/// Byte offset of a 1-based line and column.
+fn offset(contents: &str, line: usize, column: usize) -> usize {
+    let start: usize = contents
+        .split_inclusive('\n')
+        .take(line.saturating_sub(1))
+        .map(str::len)
+        .sum();
+    (start + column).min(contents.len())
+}
+
+/// The key being read at `offset`, as `sinks.notes.file` or
+/// `validation.mustMatch[2]`.
+fn key_path(contents: &str, offset: usize) -> String {
+    enum Frame {
+        /// The current key, and whether a key comes next.
+        Object(Option<String>, bool),
+        Array(usize),
+    }
+    let mut stack: Vec<Frame> = Vec::new();
+    let mut chars = contents[..offset].chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' => stack.push(Frame::Object(None, true)),
+            '[' => stack.push(Frame::Array(0)),
+            '}' | ']' => drop(stack.pop()),
+            ',' => match stack.last_mut() {
+                Some(Frame::Object(_, next_is_key)) => *next_is_key = true,
+                Some(Frame::Array(index)) => *index += 1,
+                None => {}
+            },
+            ':' => {
+                if let Some(Frame::Object(_, next_is_key)) = stack.last_mut() {
+                    *next_is_key = false;
+                }
+            }
+            '"' => {
+                let mut string = String::new();
+                while let Some(c) = chars.next() {
+                    match c {
+                        '"' => break,
+                        '\\' => string.extend(chars.next()),
+                        c => string.push(c),
+                    }
+                }
+                if let Some(Frame::Object(key, true)) = stack.last_mut() {
+                    *key = Some(string);
+                }
+            }
+            _ => {}
+        }
+    }
+    let mut path = String::new();
+    for frame in &stack {
+        match frame {
+            Frame::Object(Some(key), _) => {
+                if !path.is_empty() {
+                    path.push('.');
+                }
+                path.push_str(key);
+            }
+            Frame::Object(None, _) => {}
+            Frame::Array(index) => path.push_str(&format!("[{}]", index)),
+        }
+    }
+    path
+}
+
+/// For serde's "unknown field `x`, expected one of `a`, `b`", the
+/// expected name closest to `x`, if it is close enough to be a typo.
+fn suggest(message: &str) -> Option<&str> {
+    let rest = message.strip_prefix("unknown field `")?;
+    let (unknown, expected) = rest.split_once('`')?;
+    expected
+        .split('`')
+        .skip(1)
+        .step_by(2)
+        .map(|name| (edit_distance(unknown, name), name))
+        .filter(|(distance, name)| *distance <= (name.len() / 3).max(2))
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, name)| name)
+}
+
+/// Levenshtein distance, ignoring case.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.to_lowercase().chars().collect();
+    let b: Vec<char> = b.to_lowercase().chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.iter().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != cb);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// Every setting as the CLI will use it, defaults included. `null` means
+/// off, or for `pager.minLines` the terminal's height.
+pub fn resolved(config: &Config) -> Value {
+    let validation = &config.validation;
+    let sinks: serde_json::Map<String, Value> = config
+        .sinks
+        .iter()
+        .map(|(name, sink)| {
+            let mut entry = json!({ "format": sink.format, "auto": sink.auto });
+            for (key, value) in [
+                ("file", &sink.file),
+                ("dailyDir", &sink.daily_dir),
+                ("command", &sink.command),
+            ] {
+                if let Some(value) = value {
+                    entry[key] = json!(value);
+                }
+            }
+            (name.clone(), entry)
+        })
+        .collect();
+    json!({
+        "validation": {
+            "mustMatch": validation.must_match,
+            "mustNotMatch": validation.must_not_match,
+            "maxLength": validation.max_length,
+            "json": validation.json,
+            "retries": validation.retries(),
+        },
+        "watchdog": {
+            "stallSecs": config.watchdog.stall_limit().as_secs(),
+            "restart": config.watchdog.restart(),
+        },
+        "substitution": {
+            "enabled": config.substitution.enabled(),
+            "maxBytes": config.substitution.max_bytes(),
+        },
+        "idle": {
+            "suspendSecs": config.idle.suspend_after().map(|d| d.as_secs()),
+        },
+        "limits": {
+            "maxSessionSecs": config.limits.max_session().map(|d| d.as_secs()),
+            "idleSecs": config.limits.idle().map(|d| d.as_secs()),
+            "maxTurns": config.limits.max_turns(),
+        },
+        "notify": {
+            "afterSecs": config.notify.after_secs,
+            "always": config.notify.always,
+            "command": config.notify.command,
+            "bell": config.notify.bell,
+            "bellCommand": config.notify.bell_command,
+        },
+        "keys": {
+            "mode": config.keys.mode.name(),
+        },
+        "history": {
+            "enabled": config.history.enabled(),
+            "size": config.history.size(),
+        },
+        "prompt": {
+            "format": config.prompt.format(),
+            "status": config.prompt.status,
+        },
+        "pager": {
+            "enabled": config.pager.enabled(),
+            "minLines": config.pager.min_lines,
+        },
+        "wrap": {
+            "enabled": config.wrap.enabled(),
+        },
+        "stream": {
+            "enabled": config.stream.enabled(),
+        },
+        "timestamps": {
+            "enabled": config.timestamps.enabled(),
+        },
+        "theme": config
+            .theme
+            .roles()
+            .into_iter()
+            .map(|(name, value)| (name.to_string(), json!(value)))
+            .collect::<serde_json::Map<_, _>>(),
+        "references": {
+            "maxTokens": config.references.max_tokens(),
+            "maxFileBytes": config.references.max_file_bytes(),
+        },
+        "scrub": {
+            "secrets": config.scrub.secrets(),
+            "emails": config.scrub.emails(),
+            "hostnames": config.scrub.hostnames(),
+            "patterns": config.scrub.patterns,
+            "clipboard": config.scrub.clipboard(),
+        },
+        "routing": {
+            "mode": config.routing.mode().name(),
+            "smallModel": config.routing.small_model,
+        },
+        "sinks": sinks,
+        "snippets": config.snippets,
+    })
+}
+
+/// `komatachi-cli config check | show [--resolved] | edit`.
+pub fn run_command(args: &[String]) {
+    let path = config_file();
+    let checked = || {
+        load()
+            .and_then(|config| Validator::new(&config.validation).map(|_| config))
+            .unwrap_or_else(|e| {
+                eprintln!("{} {}", style::error("error:"), e);
+                std::process::exit(1);
+            })
+    };
+    match args
+        .iter()
+        .map(String::as_str)
+        .collect::<Vec<_>>()
+        .as_slice()
+    {
+        ["check"] => {
+            checked();
+            if path.exists() {
+                println!("{}: OK", path.display());
+            } else {
+                println!("No {}; every setting is at its default.", path.display());
+            }
+        }
+        ["show"] => match fs::read_to_string(&path) {
+            Ok(contents) => print!("{}", contents),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                eprintln!(
+                    "No {}; `config show --resolved` lists the defaults.",
+                    path.display()
+                )
+            }
+            Err(e) => {
+                eprintln!(
+                    "{} cannot read {}: {}",
+                    style::error("error:"),
+                    path.display(),
+                    e
+                );
+                std::process::exit(1);
+            }
+        },
+        ["show", "--resolved"] => {
+            let config = checked();
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&resolved(&config)).expect("serialize config")
+            );
+        }
+        ["edit"] => edit(&path),
+        _ => {
+            eprintln!("{}", USAGE);
+            std::process::exit(2);
+        }
+    }
+}
+
+/// `config edit`: edit the annotated file until it checks out, then save
+/// it without the comments and list what changed.
+fn edit(path: &Path) {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(e) => {
+            eprintln!(
+                "{} cannot read {}: {}",
+                style::error("error:"),
+                path.display(),
+                e
+            );
+            std::process::exit(1);
+        }
+    };
+    let defaults = resolved(&Config::default());
+    // A file that does not check out is opened as it is, to be fixed.
+    let (mut text, before) = match parse(contents_or_empty(&contents)) {
+        Ok(config) => {
+            let value: Value = serde_json::from_str(contents_or_empty(&contents))
+                .expect("a parsed config is JSON");
+            (annotated::annotate(&value, &defaults), Some(config))
+        }
+        Err(_) => (contents.clone(), None),
+    };
+    let (after, value) = loop {
+        text = compose::edit(&text, "config.jsonc").unwrap_or_else(|e| {
+            eprintln!(
+                "{} {}; {} unchanged",
+                style::error("error:"),
+                e,
+                path.display()
+            );
+            std::process::exit(1);
+        });
+        let json = annotated::strip(&text);
+        let checked = parse(contents_or_empty(&json))
+            .and_then(|config| Validator::new(&config.validation).map(|_| config));
+        match checked {
+            Ok(config) => {
+                let value: Value = serde_json::from_str(contents_or_empty(&json))
+                    .expect("a parsed config is JSON");
+                break (config, annotated::compact(value));
+            }
+            Err(e) => {
+                eprintln!("{} {}", style::error("error:"), e);
+                if !terminal::confirm("Edit again?") {
+                    eprintln!("{} unchanged.", path.display());
+                    std::process::exit(1);
+                }
+            }
+        }
+    };
+    let old_value = serde_json::from_str::<Value>(&contents)
+        .ok()
+        .map(annotated::compact);
+    if before.is_some() && old_value.as_ref().unwrap_or(&json!({})) == &value {
+        eprintln!("No changes; {} left as it was.", path.display());
+        return;
+    }
+    let saved = format!(
+        "{}\n",
+        serde_json::to_string_pretty(&value).expect("serialize config")
+    );
+    if let Err(e) = fs::create_dir_all(paths::komatachi_dir()).and_then(|()| fs::write(path, saved))
+    {
+        eprintln!(
+            "{} cannot write {}: {}",
+            style::error("error:"),
+            path.display(),
+            e
+        );
+        std::process::exit(1);
+    }
+    let changes = annotated::changes(&resolved(&before.unwrap_or_default()), &resolved(&after));
+    eprintln!("Saved {}.", path.display());
+    if changes.is_empty() {
+        eprintln!("No setting changed value.");
+    }
+    for change in changes {
+        eprintln!("  {}", style::dim(&change));
+    }
+}
+
+/// `{}` for a blank file, which means defaults.
+fn contents_or_empty(contents: &str) -> &str {
+    if contents.trim().is_empty() {
+        "{}"
+    } else {
+        contents
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(contents: &str) -> String {
+        let e = serde_json::from_str::<Config>(contents).err().unwrap();
+        describe(contents, &e)
+    }
+
+    #[test]
+    fn errors_name_the_key_and_suggest_a_fix() {
+        assert_eq!(
+            error("{\n  \"watchdog\": {\"stallSecs\": 60},\n  \"pager\": {\"minLine\": 3}\n}"),
+            "line 3 column 21, pager.minLine: unknown field `minLine`, \
+             expected `enabled` or `minLines` (did you mean `minLines`?)"
+        );
+        assert_eq!(
+            error(r#"{"validation": {"mustMatch": ["a", 7]}}"#),
+            "line 1 column 36, validation.mustMatch[1]: invalid type: integer `7`, expected a string"
+        );
+        assert_eq!(
+            error(r#"{"sinks": {"notes": {"file": "x", "outo": true}}}"#),
+            "line 1 column 40, sinks.notes.outo: unknown field `outo`, \
+             expected one of `file`, `dailyDir`, `command`, `format`, `auto` (did you mean `auto`?)"
+        );
+        assert!(!error(r#"{"nothing": 1}"#).contains("did you mean"));
+        assert_eq!(
+            error("{\"pager\": "),
+            "line 1 column 10: EOF while parsing a value"
+        );
+    }
+
+    #[test]
+    fn resolved_fills_in_defaults() {
+        let config: Config = serde_json::from_str(r#"{"history": {"size": 50}}"#).unwrap();
+        let resolved = resolved(&config);
+        assert_eq!(resolved["history"], json!({"enabled": true, "size": 50}));
+        assert_eq!(resolved["validation"]["retries"], json!(2));
+        assert_eq!(resolved["idle"]["suspendSecs"], Value::Null);
+    }
+}
@@ -0,0 +1,381 @@
+//! `komatachi-cli conformance --image <tag>`: check an agent image
+//! against the protocol.
+//!
+//! The image runs on a scratch data and home directory and is driven
+//! through a fixed script: handshake, malformed input, inspect,
+//! checkpoints, a turn, a streamed turn, finalize, and shutdown. Each step
+//! is one check in the report. The script follows the protocol as
+//! documented in `src/index.ts`; it needs an API key because three of the
+//! checks are real turns.
+
+use std::time::{Duration, Instant};
+
+use crate::agent::{Agent, ReceiveError};
+use crate::protocol::{self, AgentMessage, ClientMessage};
+use crate::safe;
+use crate::style;
+
+/// How long a message that needs no model call may take.
+const PROMPT_REPLY: Duration = Duration::from_secs(15);
+/// How long a turn may take.
+const TURN_REPLY: Duration = Duration::from_secs(180);
+/// How long the turn runs before the finalize check cuts it short.
+const FINALIZE_AFTER: Duration = Duration::from_secs(2);
+
+const SHORT_PROMPT: &str = "Reply with the single word: ready";
+const LONG_PROMPT: &str = "Write a 3000-word essay on the history of timekeeping.";
+
+const USAGE: &str = "usage: komatachi-cli conformance --image <tag>";
+
+/// `komatachi-cli conformance --image <tag>`
+pub fn run_command(args: &[String], api_key: &str) {
+    let image = match args {
+        [flag, image] if flag == "--image" => image,
+        _ => {
+            eprintln!("{}", USAGE);
+            std::process::exit(2);
+        }
+    };
+
+    let (root, paths) = safe::scratch_paths().unwrap_or_else(|e| {
+        eprintln!(
+            "{} cannot create scratch directory: {}",
+            style::error("error:"),
+            e
+        );
+        std::process::exit(1);
+    });
+    println!("conformance: {}", image);
+    let report = Agent::launch(api_key, &paths, false, image, &[]).map(run);
+    safe::discard(&root);
+
+    let checks = report.unwrap_or_else(|e| {
+        eprintln!("{} {}", style::error("error:"), e);
+        std::process::exit(1);
+    });
+    let passed = checks.iter().filter(|(_, result)| result.is_ok()).count();
+    println!("{}/{} checks passed", passed, checks.len());
+    if passed < checks.len() {
+        std::process::exit(1);
+    }
+}
+
+/// Outcome of one check: its name and why it failed, if it did.
+type Check = (&'static str, Result<(), String>);
+
+/// A conformance run against one container.
+struct Run {
+    agent: Agent,
+    /// Sequence numbers of every message received, in order.
+    seqs: Vec<Option<u64>>,
+    checks: Vec<Check>,
+}
+
+/// Drive the script. Stops after a failed handshake, since nothing else
+/// can be checked without one.
+fn run(agent: Agent) -> Vec<Check> {
+    let mut run = Run {
+        agent,
+        seqs: Vec::new(),
+        checks: Vec::new(),
+    };
+
+    let handshake = run
+        .expect("ready", PROMPT_REPLY)
+        .and_then(|ready| protocol::negotiated(ready.protocol).map(drop));
+    let ready = handshake.is_ok();
+    run.record("handshake", handshake);
+    if !ready {
+        run.agent.kill();
+        return run.checks;
+    }
+
+    let result = run.reject_raw("{not json");
+    run.record("rejects invalid JSON", result);
+    let result = run.reject_raw(r#"{"type":"no_such_message"}"#);
+    run.record("rejects an unknown message type", result);
+    let result = run.inspect(false);
+    run.record("inspect before the first turn", result);
+    let result = run.checkpoints();
+    run.record("checkpoint and restore", result);
+    let result = run.finalize_between_turns();
+    run.record("ignores finalize between turns", result);
+    let result = run.turn();
+    run.record("answers a turn", result);
+    let result = run.streamed_turn();
+    run.record("streamed deltas add up to the output", result);
+    let result = run.inspect(true);
+    run.record("inspect after a turn", result);
+    let result = run.finalize_turn();
+    run.record("finalize cuts a turn short", result);
+    let result = consecutive(&run.seqs);
+    run.record("sequence numbers", result);
+
+    let Run {
+        agent, mut checks, ..
+    } = run;
+    let exited = match agent.shutdown_within(PROMPT_REPLY) {
+        Some(status) if status.success() => Ok(()),
+        Some(status) => Err(format!("exited with {}", status)),
+        None => Err(format!(
+            "still running {}s after stdin closed; killed",
+            PROMPT_REPLY.as_secs()
+        )),
+    };
+    let check = ("exits when stdin closes", exited);
+    print_check(&check);
+    checks.push(check);
+    checks
+}
+
+impl Run {
+    fn record(&mut self, name: &'static str, result: Result<(), String>) {
+        let check = (name, result);
+        print_check(&check);
+        self.checks.push(check);
+    }
+
+    /// The next message other than a `heartbeat`, if it arrives within
+    /// `limit` of the last one.
+    fn next(&mut self, limit: Duration) -> Result<AgentMessage, String> {
+        loop {
+            let msg = self.next_any(limit)?;
+            if msg.r#type != "heartbeat" {
+                return Ok(msg);
+            }
+        }
+    }
+
+    /// The next message of any type, if it arrives within `limit`.
+    fn next_any(&mut self, limit: Duration) -> Result<AgentMessage, String> {
+        if !self.agent.wait(limit) {
+            return Err(format!("no message within {}s", limit.as_secs()));
+        }
+        match self.agent.receive() {
+            Ok(msg) => {
+                self.seqs.push(msg.seq);
+                Ok(msg)
+            }
+            Err(ReceiveError::Closed) => Err("agent exited".to_string()),
+            Err(ReceiveError::Io(e)) => Err(format!("reading from agent: {}", e)),
+            Err(ReceiveError::Invalid(e)) => Err(format!("invalid message: {}", e)),
+        }
+    }
+
+    /// The next message, which must be of type `expected`.
+    fn expect(&mut self, expected: &str, limit: Duration) -> Result<AgentMessage, String> {
+        let msg = self.next(limit)?;
+        if msg.r#type == expected {
+            Ok(msg)
+        } else {
+            Err(format!("expected {}, got {}", expected, describe(&msg)))
+        }
+    }
+
+    fn reject_raw(&mut self, line: &str) -> Result<(), String> {
+        self.agent.send_raw(line)?;
+        self.expect("error", PROMPT_REPLY).map(drop)
+    }
+
+    /// `inspect` answers with the last request: null before any turn,
+    /// present after one.
+    fn inspect(&mut self, after_turn: bool) -> Result<(), String> {
+        self.agent.send(&ClientMessage::Inspect)?;
+        let msg = self.expect("inspect", PROMPT_REPLY)?;
+        match (after_turn, msg.request.is_some()) {
+            (false, true) => Err("request should be null before any turn".to_string()),
+            (true, false) => Err("request is missing".to_string()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Record a checkpoint, refuse an unknown id, then restore the one
+    /// just recorded.
+    fn checkpoints(&mut self) -> Result<(), String> {
+        self.agent.send(&ClientMessage::Checkpoint)?;
+        let id = self
+            .expect("checkpoint", PROMPT_REPLY)?
+            .id
+            .ok_or("checkpoint has no id")?;
+
+        self.agent
+            .send(&ClientMessage::RestoreCheckpoint { id: id + 1000 })?;
+        self.expect("error", PROMPT_REPLY)
+            .map_err(|e| format!("restoring an unknown checkpoint: {}", e))?;
+
+        self.agent.send(&ClientMessage::RestoreCheckpoint { id })?;
+        let restored = self.expect("checkpoint_restored", PROMPT_REPLY)?;
+        if restored.id == Some(id) {
+            Ok(())
+        } else {
+            Err(format!("restored {:?}, asked for {}", restored.id, id))
+        }
+    }
+
+    /// A stray `finalize` produces no reply, so the next reply is the
+    /// answer to the message after it.
+    fn finalize_between_turns(&mut self) -> Result<(), String> {
+        self.agent.send(&ClientMessage::Finalize)?;
+        self.agent.send(&ClientMessage::Inspect)?;
+        self.expect("inspect", PROMPT_REPLY).map(drop)
+    }
+
+    /// A turn answers with `usage`, `checkpoint`, then a complete `output`,
+    /// each echoing the input's `id` from version 2.
+    fn turn(&mut self) -> Result<(), String> {
+        let id = self.agent.input_id();
+        self.agent.send(&ClientMessage::Input {
+            text: SHORT_PROMPT.to_string(),
+            stream: false,
+            id,
+        })?;
+        let usage = self.expect("usage", TURN_REPLY)?;
+        if usage.usage().is_none() {
+            return Err("usage is missing model or token counts".to_string());
+        }
+        let checkpoint = self.expect("checkpoint", PROMPT_REPLY)?;
+        let output = self.expect("output", PROMPT_REPLY)?;
+        for msg in [&usage, &checkpoint, &output] {
+            if msg.reply_to != id {
+                return Err(format!(
+                    "{} has reply_to {:?}, the input's id was {:?}",
+                    msg.r#type, msg.reply_to, id
+                ));
+            }
+        }
+        match output.text.as_deref() {
+            _ if output.partial => Err("output is marked partial".to_string()),
+            Some(text) if !text.trim().is_empty() => Ok(()),
+            _ => Err("output has no text".to_string()),
+        }
+    }
+
+    /// A turn asked to stream may send `output_delta`s before its `usage`;
+    /// together they must be the text of its `output`. An agent that
+    /// sends none still conforms, and the CLI prints the `output`.
+    fn streamed_turn(&mut self) -> Result<(), String> {
+        let id = self.agent.input_id();
+        self.agent.send(&ClientMessage::Input {
+            text: SHORT_PROMPT.to_string(),
+            stream: true,
+            id,
+        })?;
+        let mut streamed = String::new();
+        let mut deltas = 0;
+        loop {
+            let msg = self.next(TURN_REPLY)?;
+            match msg.r#type.as_str() {
+                "output_delta" => {
+                    streamed.push_str(msg.text.as_deref().ok_or("output_delta has no text")?);
+                    deltas += 1;
+                }
+                "usage" => break,
+                _ => {
+                    return Err(format!(
+                        "expected output_delta or usage, got {}",
+                        describe(&msg)
+                    ))
+                }
+            }
+        }
+        self.expect("checkpoint", PROMPT_REPLY)?;
+        let output = self.expect("output", PROMPT_REPLY)?;
+        match output.text.as_deref() {
+            _ if output.partial => Err("output is marked partial".to_string()),
+            Some(text) if deltas > 0 && text != streamed => Err(format!(
+                "{} delta{} make {:?}, output is {:?}",
+                deltas,
+                if deltas == 1 { "" } else { "s" },
+                streamed,
+                text
+            )),
+            Some(_) => Ok(()),
+            None => Err("output has no text".to_string()),
+        }
+    }
+
+    /// After `finalize`, a running turn ends promptly with a partial
+    /// `output` (after its `usage` and `checkpoint`) holding the text
+    /// written so far. `LONG_PROMPT` has the model writing well before
+    /// `FINALIZE_AFTER`, so an `error` means nothing was salvaged.
+    fn finalize_turn(&mut self) -> Result<(), String> {
+        let id = self.agent.input_id();
+        self.agent.send(&ClientMessage::Input {
+            text: LONG_PROMPT.to_string(),
+            stream: false,
+            id,
+        })?;
+        let finalize_at = Instant::now() + FINALIZE_AFTER;
+        while self
+            .agent
+            .wait(finalize_at.saturating_duration_since(Instant::now()))
+        {
+            let msg = self.next_any(PROMPT_REPLY)?;
+            if msg.r#type != "heartbeat" {
+                return Err(format!("turn ended before finalize: {}", describe(&msg)));
+            }
+        }
+        self.agent.send(&ClientMessage::Finalize)?;
+        loop {
+            let msg = self.next(PROMPT_REPLY)?;
+            match msg.r#type.as_str() {
+                "usage" | "checkpoint" => continue,
+                "output" if !msg.partial => return Err("output is not marked partial".to_string()),
+                "output" if msg.text.as_deref().is_some_and(|t| !t.trim().is_empty()) => {
+                    return Ok(())
+                }
+                "output" => return Err("partial output has no text".to_string()),
+                _ => return Err(format!("unexpected {}", describe(&msg))),
+            }
+        }
+    }
+}
+
+/// Every message carried a sequence number, counting up from 1.
+fn consecutive(seqs: &[Option<u64>]) -> Result<(), String> {
+    for (expected, seq) in (1..).zip(seqs) {
+        match seq {
+            Some(seq) if *seq == expected => {}
+            Some(seq) => return Err(format!("message {} has seq {}", expected, seq)),
+            None => return Err(format!("message {} has no seq", expected)),
+        }
+    }
+    Ok(())
+}
+
+fn describe(msg: &AgentMessage) -> String {
+    match &msg.message {
+        Some(message) if msg.r#type == "error" => format!("error ({})", message),
+        _ => msg.r#type.clone(),
+    }
+}
+
+fn print_check((name, result): &Check) {
+    match result {
+        Ok(()) => println!("  pass  {}", name),
+        Err(reason) => println!("  FAIL  {}: {}", name, reason),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn consecutive_seqs_pass() {
+        assert!(consecutive(&[Some(1), Some(2), Some(3)]).is_ok());
+        assert!(consecutive(&[]).is_ok());
+    }
+
+    #[test]
+    fn seq_gaps_and_missing_seqs_fail() {
+        assert_eq!(
+            consecutive(&[Some(1), Some(3)]),
+            Err("message 2 has seq 3".to_string())
+        );
+        assert_eq!(
+            consecutive(&[Some(1), None]),
+            Err("message 2 has no seq".to_string())
+        );
+    }
+}
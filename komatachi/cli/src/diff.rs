@@ -0,0 +1,226 @@
+//! Word-level diff, for `/diff` after `/retry`.
+//!
+//! Texts are split into words, each carrying the whitespace after it, and
+//! compared by word alone, so a change in line wrapping is not a change.
+//! The diff is Myers' O(ND) algorithm: fast when two answers are mostly
+//! the same, and given up on (`None`) when they share too little for a
+//! word diff to be worth reading.
+
+use crate::style;
+
+/// More edits than this and the answers are shown as rewritten.
+const MAX_EDITS: usize = 2000;
+
+#[derive(Debug, PartialEq)]
+pub enum Change<'a> {
+    Same(&'a str),
+    Removed(&'a str),
+    Added(&'a str),
+}
+
+/// Split text into words with their trailing whitespace. Leading
+/// whitespace is a token of its own.
+fn tokens(text: &str) -> Vec<&str> {
+    let mut out = Vec::new();
+    let mut start = 0;
+    let mut in_space = true;
+    for (i, c) in text.char_indices() {
+        let space = c.is_whitespace();
+        if !space && in_space && i > start {
+            out.push(&text[start..i]);
+            start = i;
+        }
+        in_space = space;
+    }
+    if start < text.len() {
+        out.push(&text[start..]);
+    }
+    out
+}
+
+/// Changes that turn `old` into `new`, or `None` if they differ in more
+/// than `MAX_EDITS` words.
+pub fn words<'a>(old: &'a str, new: &'a str) -> Option<Vec<Change<'a>>> {
+    let old = tokens(old);
+    let new = tokens(new);
+    let same = |a: &str, b: &str| a.trim_end() == b.trim_end();
+
+    let prefix = old.iter().zip(&new).take_while(|(a, b)| same(a, b)).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| same(a, b))
+        .count();
+
+    let mut changes: Vec<Change> = new[..prefix].iter().map(|t| Change::Same(t)).collect();
+    changes.extend(myers(
+        &old[prefix..old.len() - suffix],
+        &new[prefix..new.len() - suffix],
+        same,
+    )?);
+    changes.extend(new[new.len() - suffix..].iter().map(|t| Change::Same(t)));
+    Some(changes)
+}
+
+/// Myers' shortest edit script between `old` and `new`.
+fn myers<'a>(
+    old: &[&'a str],
+    new: &[&'a str],
+    same: impl Fn(&str, &str) -> bool,
+) -> Option<Vec<Change<'a>>> {
+    let (n, m) = (old.len() as isize, new.len() as isize);
+    let max = (n + m) as usize;
+    let offset = max as isize + 1;
+    // v[k + offset]: furthest x reached on diagonal k (x - y = k).
+    let mut v = vec![0isize; 2 * max + 3];
+    // The v diagonals -d..=d as they were before round d, for backtracking.
+    let mut trace: Vec<Vec<isize>> = Vec::new();
+
+    'search: for d in 0..=max as isize {
+        if d as usize > MAX_EDITS {
+            return None;
+        }
+        trace.push(v[(offset - d) as usize..=(offset + d) as usize].to_vec());
+        for k in (-d..=d).step_by(2) {
+            let at = (k + offset) as usize;
+            let mut x = if k == -d || (k != d && v[at - 1] < v[at + 1]) {
+                v[at + 1]
+            } else {
+                v[at - 1] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && same(old[x as usize], new[y as usize]) {
+                x += 1;
+                y += 1;
+            }
+            v[at] = x;
+            if x >= n && y >= m {
+                break 'search;
+            }
+        }
+    }
+
+    let mut changes = Vec::new();
+    let (mut x, mut y) = (n, m);
+    for (d, before) in trace.iter().enumerate().rev() {
+        let d = d as isize;
+        let get = |k: isize| before[(k + d) as usize];
+        let k = x - y;
+        let prev_k = if k == -d || (k != d && get(k - 1) < get(k + 1)) {
+            k + 1
+        } else {
+            k - 1
+        };
+        let prev_x = if d == 0 { 0 } else { get(prev_k) };
+        let prev_y = prev_x - prev_k;
+        while x > prev_x && y > prev_y {
+            changes.push(Change::Same(new[(y - 1) as usize]));
+            x -= 1;
+            y -= 1;
+        }
+        if d > 0 {
+            if x == prev_x {
+                changes.push(Change::Added(new[(y - 1) as usize]));
+                y -= 1;
+            } else {
+                changes.push(Change::Removed(old[(x - 1) as usize]));
+                x -= 1;
+            }
+        }
+    }
+    changes.reverse();
+    Some(changes)
+}
+
+/// Render changes inline, git `--word-diff=plain` style: `[-removed-]`
+/// and `{+added+}`, colored when the terminal allows.
+pub fn render(changes: &[Change]) -> String {
+    let mut out = String::new();
+    let mut i = 0;
+    while i < changes.len() {
+        if let Change::Same(token) = changes[i] {
+            out.push_str(token);
+            i += 1;
+            continue;
+        }
+        let run_end = changes[i..]
+            .iter()
+            .position(|c| matches!(c, Change::Same(_)))
+            .map_or(changes.len(), |p| i + p);
+        let run = &changes[i..run_end];
+        let removed: String = run
+            .iter()
+            .filter_map(|c| match c {
+                Change::Removed(t) => Some(*t),
+                _ => None,
+            })
+            .collect();
+        let added: String = run
+            .iter()
+            .filter_map(|c| match c {
+                Change::Added(t) => Some(*t),
+                _ => None,
+            })
+            .collect();
+        if !removed.is_empty() {
+            out.push_str(&style::removed(&format!("[-{}-]", removed.trim_end())));
+        }
+        if !added.is_empty() {
+            out.push_str(&style::added(&format!("{{+{}+}}", added.trim_end())));
+        }
+        // Keep the whitespace that followed the run in the new text.
+        let tail = if added.is_empty() { &removed } else { &added };
+        out.push_str(&tail[tail.trim_end().len()..]);
+        i = run_end;
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Rendered diff without color escapes.
+    fn plain(old: &str, new: &str) -> String {
+        let rendered = render(&words(old, new).expect("diff"));
+        let mut out = String::new();
+        let mut chars = rendered.chars();
+        while let Some(c) = chars.next() {
+            if c == '\x1b' {
+                chars.by_ref().find(|&c| c == 'm');
+            } else {
+                out.push(c);
+            }
+        }
+        out
+    }
+
+    #[test]
+    fn marks_replaced_and_added_words() {
+        assert_eq!(
+            plain("The cat sat on the mat.", "The dog sat on the red mat."),
+            "The [-cat-]{+dog+} sat on the {+red+} mat."
+        );
+    }
+
+    #[test]
+    fn marks_removed_words_and_ignores_rewrapping() {
+        assert_eq!(plain("one two\nthree", "one three"), "one [-two-]\nthree");
+        assert_eq!(plain("a b\nc", "a b c"), "a b c");
+    }
+
+    #[test]
+    fn identical_and_empty_texts() {
+        assert_eq!(plain("same text", "same text"), "same text");
+        assert_eq!(plain("", "new"), "{+new+}");
+        assert_eq!(plain("old", ""), "[-old-]");
+    }
+
+    #[test]
+    fn gives_up_on_unrelated_texts() {
+        let old: String = (0..1500).map(|i| format!("a{} ", i)).collect();
+        let new: String = (0..1500).map(|i| format!("b{} ", i)).collect();
+        assert!(words(&old, &new).is_none());
+    }
+}
@@ -0,0 +1,70 @@
+//! Unsent prompt recovery.
+//!
+//! A prompt is saved to `draft.json` in the data directory before it is
+//! sent and removed once the agent answers. If the agent or the CLI dies
+//! mid-turn, the next session offers to resend it instead of losing it.
+//! The line still being typed uses the same format in `unsent.json` (see
+//! `LineEditor::keep_unsent_in`), and is put back at the prompt instead.
+
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::time;
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Draft {
+    /// When the prompt was saved (epoch ms)
+    pub created_at: u64,
+    pub text: String,
+}
+
+/// Save `text` as the pending draft, replacing any previous one.
+pub fn save(path: &Path, text: &str) -> io::Result<()> {
+    let draft = Draft {
+        created_at: time::now_ms(),
+        text: text.to_string(),
+    };
+    let json = serde_json::to_string(&draft).expect("serialize draft");
+    // Write-to-temp then rename, so a crash never leaves half a draft.
+    let temp = path.with_extension("json.tmp");
+    fs::write(&temp, json)?;
+    fs::rename(&temp, path)
+}
+
+/// Load the pending draft, if any. An unreadable draft is discarded.
+pub fn load(path: &Path) -> Option<Draft> {
+    let contents = fs::read_to_string(path).ok()?;
+    serde_json::from_str(&contents).ok()
+}
+
+/// Remove the pending draft. A missing draft is not an error.
+pub fn clear(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn save_load_clear_round_trip() {
+        let dir = std::env::temp_dir().join(format!("komatachi-draft-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("draft.json");
+
+        assert!(load(&path).is_none());
+        save(&path, "a long\nprompt").unwrap();
+        assert_eq!(load(&path).unwrap().text, "a long\nprompt");
+        clear(&path).unwrap();
+        assert!(load(&path).is_none());
+        clear(&path).unwrap();
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,179 @@
+//! `komatachi-cli export [conversation]`: print a transcript for sharing.
+//!
+//! Prints the main conversation, or the fork named, as markdown: one
+//! heading per message, with its content rendered as `/inspect` shows it.
+//! `--json` prints the messages as JSON instead, one per line, in the
+//! Claude API message format the transcript keeps.
+//! With `--scrub`, secrets, email addresses, hostnames, and the regexes
+//! under `scrub.patterns` in `config.json` are replaced first (see
+//! `scrub.rs`), and what was replaced is reported on stderr, so the
+//! export can go to a file and the report stay on screen. Transcripts are
+//! only read.
+
+use serde_json::Value;
+use std::fs;
+use std::io;
+
+use crate::config;
+use crate::inspect;
+use crate::paths;
+use crate::scrub::{self, Scrubber};
+use crate::style;
+
+const USAGE: &str = "\
+usage: komatachi-cli export [<conversation>] [--scrub] [--json]
+
+Options:
+  --scrub                Replace secrets, emails, hostnames, and scrub.patterns
+                         matches with placeholders, and report what was replaced
+  --json                 The messages as JSON lines instead of markdown";
+
+struct Options {
+    conversation: String,
+    scrub: bool,
+    json: bool,
+}
+
+/// `komatachi-cli export [<conversation>] [--scrub] [--json]`
+pub fn run_command(args: &[String]) {
+    let options = parse_args(args).unwrap_or_else(|e| {
+        eprintln!("{} {}\n\n{}", style::error("error:"), e, USAGE);
+        std::process::exit(2);
+    });
+    let mut scrubber = if options.scrub {
+        let scrubber = config::load().and_then(|config| Scrubber::new(&config.scrub));
+        Some(scrubber.unwrap_or_else(|e| {
+            eprintln!("{} {}", style::error("error:"), e);
+            std::process::exit(1);
+        }))
+    } else {
+        None
+    };
+
+    let Some((_, path)) = paths::conversations()
+        .into_iter()
+        .find(|(name, _)| *name == options.conversation)
+    else {
+        eprintln!(
+            "{} no conversation named '{}' (main or a fork name)",
+            style::error("error:"),
+            options.conversation
+        );
+        std::process::exit(2);
+    };
+    let contents = match fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(e) => {
+            eprintln!(
+                "{} cannot read {}: {}",
+                style::error("error:"),
+                path.display(),
+                e
+            );
+            std::process::exit(1);
+        }
+    };
+    let mut messages: Vec<Value> = contents
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect();
+    if messages.is_empty() {
+        eprintln!("No messages in {}.", options.conversation);
+        return;
+    }
+
+    if let Some(scrubber) = &mut scrubber {
+        for message in &mut messages {
+            scrub_strings(&mut message["content"], scrubber);
+        }
+    }
+    if options.json {
+        for message in &messages {
+            println!("{}", message);
+        }
+    } else {
+        print!("{}", render_markdown(&options.conversation, &messages));
+    }
+    if let Some(scrubber) = &scrubber {
+        eprint!("{}", scrub::report(scrubber.removed()));
+    }
+}
+
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut options = Options {
+        conversation: paths::MAIN.to_string(),
+        scrub: false,
+        json: false,
+    };
+    let mut named = false;
+    for arg in args {
+        match arg.as_str() {
+            "--scrub" => options.scrub = true,
+            "--json" => options.json = true,
+            other if other.starts_with('-') => return Err(format!("unknown option: {}", other)),
+            _ if named => return Err("only one conversation can be given".to_string()),
+            name => {
+                options.conversation = name.to_string();
+                named = true;
+            }
+        }
+    }
+    Ok(options)
+}
+
+/// Scrub every string in message content, leaving its structure alone.
+fn scrub_strings(value: &mut Value, scrubber: &mut Scrubber) {
+    match value {
+        Value::String(text) => *text = scrubber.scrub(text),
+        Value::Array(items) => items
+            .iter_mut()
+            .for_each(|item| scrub_strings(item, scrubber)),
+        Value::Object(fields) => fields
+            .values_mut()
+            .for_each(|field| scrub_strings(field, scrubber)),
+        _ => {}
+    }
+}
+
+/// The conversation as a markdown document, a heading per message.
+fn render_markdown(conversation: &str, messages: &[Value]) -> String {
+    let mut out = format!("# Komatachi conversation: {}\n", conversation);
+    for (index, message) in messages.iter().enumerate() {
+        out.push_str(&format!(
+            "\n## {}. {}\n\n{}",
+            index + 1,
+            message["role"].as_str().unwrap_or("?"),
+            inspect::render_content(&message["content"])
+        ));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn scrubs_content_strings_and_renders_markdown() {
+        let mut messages = vec![
+            json!({ "role": "user", "content": "Ping me at dev@example.com" }),
+            json!({ "role": "assistant", "content": [
+                { "type": "text", "text": "Will do, dev@example.com." }
+            ] }),
+        ];
+        let mut scrubber = Scrubber::new(&scrub::Settings::default()).unwrap();
+        for message in &mut messages {
+            scrub_strings(&mut message["content"], &mut scrubber);
+        }
+        assert_eq!(messages[1]["content"][0]["type"], "text");
+        assert_eq!(
+            render_markdown("main", &messages),
+            "# Komatachi conversation: main\n\
+             \n## 1. user\n\nPing me at [email-1]\n\
+             \n## 2. assistant\n\nWill do, [email-1].\n"
+        );
+        assert_eq!(scrubber.removed()[0].count, 2);
+    }
+}
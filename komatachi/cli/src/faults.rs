@@ -0,0 +1,176 @@
+//! Fault injection, for exercising the recovery paths on purpose.
+//!
+//! With `KOMATACHI_FAULT_INJECT=<probability>` set, the reader thread of
+//! every agent the CLI starts tampers with that share of the agent's
+//! frames after the `ready` handshake: it holds the frame back for a few
+//! seconds, cuts it short, sends it twice, flips a bit in it, or kills
+//! the container instead of delivering it. Each fault is announced on
+//! stderr. `KOMATACHI_FAULT_SEED` makes a run repeatable.
+//!
+//! Not listed in `-h`: this is for developing the CLI (and testing other
+//! agent images), not for use.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::style;
+
+pub const FAULT_INJECT_VAR: &str = "KOMATACHI_FAULT_INJECT";
+pub const FAULT_SEED_VAR: &str = "KOMATACHI_FAULT_SEED";
+
+/// Longest delay injected before a frame.
+const MAX_DELAY: Duration = Duration::from_secs(5);
+
+#[derive(Debug, PartialEq)]
+pub enum Fault {
+    /// Deliver the frame after this long.
+    Delay(Duration),
+    /// Deliver only the first this many bytes.
+    Truncate(usize),
+    /// Deliver the frame twice.
+    Duplicate,
+    /// Flip one bit: byte index and mask.
+    Corrupt(usize, u8),
+    /// Kill the container instead of delivering the frame.
+    Kill,
+}
+
+pub struct Faults {
+    probability: f64,
+    /// xorshift64* state; never zero.
+    state: u64,
+}
+
+impl Faults {
+    /// Fault injection as configured in the environment, or `None` when
+    /// it is off. A bad value is reported and leaves it off.
+    pub fn from_env() -> Option<Faults> {
+        let value = std::env::var(FAULT_INJECT_VAR).ok()?;
+        let probability = match value.parse::<f64>() {
+            Ok(p) if (0.0..=1.0).contains(&p) => p,
+            _ => {
+                eprintln!(
+                    "{} {} must be a probability from 0 to 1, got {:?}; not injecting faults",
+                    style::warning("warning:"),
+                    FAULT_INJECT_VAR,
+                    value
+                );
+                return None;
+            }
+        };
+        let seed = std::env::var(FAULT_SEED_VAR)
+            .ok()
+            .and_then(|seed| seed.parse::<u64>().ok())
+            .unwrap_or_else(|| {
+                SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map_or(1, |d| d.as_nanos() as u64)
+            });
+        eprintln!(
+            "{} injecting faults into {}% of agent frames ({}={})",
+            style::warning("warning:"),
+            probability * 100.0,
+            FAULT_SEED_VAR,
+            seed
+        );
+        Some(Faults::new(probability, seed))
+    }
+
+    fn new(probability: f64, seed: u64) -> Faults {
+        Faults {
+            probability,
+            state: seed.max(1),
+        }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    /// A number in `0..n`, for small `n`.
+    fn below(&mut self, n: u64) -> u64 {
+        self.next_u64() % n.max(1)
+    }
+
+    /// The fault, if any, for the next frame of `len` bytes.
+    pub fn next(&mut self, len: usize) -> Option<Fault> {
+        let roll = (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64;
+        if roll >= self.probability {
+            return None;
+        }
+        let len = len as u64;
+        Some(match self.below(5) {
+            0 => Fault::Delay(Duration::from_millis(
+                self.below(MAX_DELAY.as_millis() as u64) + 1,
+            )),
+            1 => Fault::Truncate(self.below(len) as usize),
+            2 => Fault::Duplicate,
+            3 => Fault::Corrupt(self.below(len) as usize, 1 << self.below(8)),
+            _ => Fault::Kill,
+        })
+    }
+}
+
+impl Fault {
+    /// What the CLI is about to do, for stderr.
+    pub fn describe(&self) -> String {
+        match self {
+            Fault::Delay(delay) => format!("delaying a frame by {}ms", delay.as_millis()),
+            Fault::Truncate(at) => format!("truncating a frame to {} bytes", at),
+            Fault::Duplicate => "duplicating a frame".to_string(),
+            Fault::Corrupt(at, mask) => format!("flipping bit {:#04x} of byte {}", mask, at),
+            Fault::Kill => "killing the container".to_string(),
+        }
+    }
+
+    /// The frames to deliver in place of `frame`, after sleeping out a
+    /// delay. A kill delivers nothing; killing is the caller's job.
+    pub fn apply(&self, frame: &[u8]) -> Vec<Vec<u8>> {
+        match *self {
+            Fault::Delay(delay) => {
+                std::thread::sleep(delay);
+                vec![frame.to_vec()]
+            }
+            Fault::Truncate(at) => vec![frame[..at.min(frame.len())].to_vec()],
+            Fault::Duplicate => vec![frame.to_vec(), frame.to_vec()],
+            Fault::Corrupt(at, mask) => {
+                let mut corrupted = frame.to_vec();
+                if let Some(byte) = corrupted.get_mut(at) {
+                    *byte ^= mask;
+                }
+                vec![corrupted]
+            }
+            Fault::Kill => Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rewrites_frames() {
+        let frame = br#"{"type":"output"}"#;
+        assert_eq!(Fault::Truncate(4).apply(frame), vec![b"{\"ty".to_vec()]);
+        assert_eq!(Fault::Duplicate.apply(frame).len(), 2);
+        assert_eq!(Fault::Corrupt(0, 0x01).apply(frame)[0][0], b'z');
+        assert!(Fault::Kill.apply(frame).is_empty());
+    }
+
+    #[test]
+    fn same_seed_same_faults_and_probability_bounds() {
+        let run = |seed| {
+            let mut faults = Faults::new(0.5, seed);
+            (0..50).map(|_| faults.next(100)).collect::<Vec<_>>()
+        };
+        assert_eq!(run(7), run(7));
+        assert!(run(7).iter().any(Option::is_some));
+        let mut never = Faults::new(0.0, 7);
+        assert!((0..100).all(|_| never.next(10).is_none()));
+        let mut always = Faults::new(1.0, 7);
+        assert!((0..100).all(|_| always.next(10).is_some()));
+    }
+}
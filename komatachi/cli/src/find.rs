@@ -0,0 +1,127 @@
+//! `/find <regex>`: search what this session has shown.
+//!
+//! A long session outgrows the terminal's scrollback, and the transcript
+//! is no substitute: compaction summarizes it and `/rollback` cuts it.
+//! So the REPL keeps each exchange it printed, the prompt and the answer,
+//! in memory until it exits, and `/find` prints the exchanges with a
+//! matching line, each match with `CONTEXT` lines either side. An exchange
+//! later rolled back stays, as it would on screen. Nothing is written to
+//! disk; `komatachi-cli grep` searches the stored transcripts.
+
+use crate::grep;
+use crate::regex::Regex;
+use crate::style;
+use crate::time;
+
+/// Lines shown before and after each match.
+pub const CONTEXT: usize = 2;
+
+/// One exchange as the session printed it.
+pub struct Shown {
+    /// Turn number within the session, from 1.
+    pub turn: usize,
+    /// When the answer arrived, epoch ms.
+    pub at: u64,
+    pub input: String,
+    pub output: String,
+}
+
+/// The exchanges with a line matching `regex`, and how many: a dim
+/// heading each, then the matching lines in context, prompt lines marked
+/// `> `, with `...` where lines are skipped. `None` if nothing matched.
+pub fn render(shown: &[Shown], regex: &Regex) -> Option<(String, usize)> {
+    let mut out = String::new();
+    let mut matched = 0;
+    for exchange in shown {
+        let lines: Vec<String> = exchange
+            .input
+            .lines()
+            .map(|line| format!("> {}", line))
+            .chain(exchange.output.lines().map(String::from))
+            .collect();
+        let prompt_lines = exchange.input.lines().count();
+        let hits: Vec<usize> = (0..lines.len())
+            .filter(|&i| {
+                let text = if i < prompt_lines {
+                    &lines[i][2..]
+                } else {
+                    &lines[i]
+                };
+                regex.is_match(text)
+            })
+            .collect();
+        if hits.is_empty() {
+            continue;
+        }
+        matched += 1;
+        if !out.is_empty() {
+            out.push('\n');
+        }
+        out.push_str(&style::dim(&heading(exchange)));
+        out.push('\n');
+        let mut previous = None;
+        for i in grep::with_context(&hits, CONTEXT, CONTEXT, lines.len()) {
+            if previous.is_some_and(|p| p + 1 != i) || (previous.is_none() && i > 0) {
+                out.push_str("...\n");
+            }
+            out.push_str(&lines[i]);
+            out.push('\n');
+            previous = Some(i);
+        }
+        if previous.is_some_and(|p| p + 1 < lines.len()) {
+            out.push_str("...\n");
+        }
+    }
+    (matched > 0).then_some((out, matched))
+}
+
+/// `[turn 3, 14:02:11 UTC]`
+fn heading(exchange: &Shown) -> String {
+    format!(
+        "[turn {}, {}]",
+        exchange.turn,
+        time::format_utc_time(exchange.at)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prints_matching_exchanges_with_context() {
+        let shown = vec![
+            Shown {
+                turn: 1,
+                at: 0,
+                input: "How do I list ports?".to_string(),
+                output: "Use ss.".to_string(),
+            },
+            Shown {
+                turn: 2,
+                at: 3_600_000,
+                input: "And the firewall?".to_string(),
+                output: "a\nb\nc\nd\nRun nft list ruleset.\ne\nf\ng".to_string(),
+            },
+        ];
+        let regex = Regex::new("(?i)ports|nft").unwrap();
+        let (text, matched) = render(&shown, &regex).unwrap();
+        assert_eq!(matched, 2);
+        assert_eq!(
+            text,
+            "[turn 1, 00:00:00 UTC]\n\
+             > How do I list ports?\n\
+             Use ss.\n\
+             \n\
+             [turn 2, 01:00:00 UTC]\n\
+             ...\n\
+             c\n\
+             d\n\
+             Run nft list ruleset.\n\
+             e\n\
+             f\n\
+             ...\n"
+        );
+        assert!(render(&shown, &Regex::new("^>").unwrap()).is_none());
+    }
+}
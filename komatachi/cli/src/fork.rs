@@ -0,0 +1,122 @@
+//! Conversation forks.
+//!
+//! `/fork [name]` copies the current data directory into
+//! `~/.komatachi/forks/<name>` and restarts the agent on the copy. The
+//! original conversation is never touched. Each fork is a separate data
+//! directory -- one conversation per agent still holds; a fork is simply
+//! another agent's starting memory.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::paths;
+use crate::time;
+
+/// Check that a fork name is usable as a single directory name and does
+/// not stand for another conversation: `main` is the primary one in
+/// references and `grep --in`, and `safe` is what the prompt shows under
+/// `--safe`.
+pub fn validate_name(name: &str) -> Result<(), String> {
+    if name.is_empty() || name.starts_with('.') {
+        return Err(format!("invalid fork name: '{}'", name));
+    }
+    if name == paths::MAIN || name == paths::SAFE {
+        return Err(format!("invalid fork name: '{}' is reserved", name));
+    }
+    let valid = name
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.');
+    if valid {
+        Ok(())
+    } else {
+        Err(format!(
+            "invalid fork name: '{}' (use letters, digits, '-', '_', '.')",
+            name
+        ))
+    }
+}
+
+/// Default name for an unnamed fork.
+pub fn default_name() -> String {
+    format!("fork-{}", time::format_utc_compact(time::now_ms()))
+}
+
+/// Copy `data_dir` into a new fork directory and return its path.
+///
+/// Must be called between turns, while the agent is not writing.
+pub fn create(data_dir: &Path, name: &str) -> Result<PathBuf, String> {
+    validate_name(name)?;
+    let target = paths::forks_dir().join(name);
+    if target.exists() {
+        return Err(format!("fork already exists: {}", target.display()));
+    }
+    copy_dir(data_dir, &target).map_err(|e| {
+        // Leave no half-copied fork behind.
+        let _ = fs::remove_dir_all(&target);
+        format!(
+            "cannot copy {} to {}: {}",
+            data_dir.display(),
+            target.display(),
+            e
+        )
+    })?;
+    Ok(target)
+}
+
+fn copy_dir(from: &Path, to: &Path) -> io::Result<()> {
+    fs::create_dir_all(to)?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let destination = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_dir(&entry.path(), &destination)?;
+        } else {
+            fs::copy(entry.path(), destination)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_simple_names() {
+        assert!(validate_name("try-2_b.v1").is_ok());
+    }
+
+    #[test]
+    fn rejects_paths_and_hidden_names() {
+        assert!(validate_name("../escape").is_err());
+        assert!(validate_name("a/b").is_err());
+        assert!(validate_name(".hidden").is_err());
+        assert!(validate_name("").is_err());
+    }
+
+    #[test]
+    fn rejects_reserved_conversation_names() {
+        assert!(validate_name(paths::MAIN)
+            .unwrap_err()
+            .ends_with("is reserved"));
+        assert!(validate_name(paths::SAFE).is_err());
+        assert!(validate_name("main-2").is_ok());
+    }
+
+    #[test]
+    fn copies_nested_directories() {
+        let root = std::env::temp_dir().join(format!("komatachi-fork-test-{}", std::process::id()));
+        let source = root.join("source");
+        fs::create_dir_all(source.join("conversation")).unwrap();
+        fs::write(source.join("conversation/transcript.jsonl"), "{}\n").unwrap();
+
+        let target = root.join("target");
+        copy_dir(&source, &target).unwrap();
+        assert_eq!(
+            fs::read_to_string(target.join("conversation/transcript.jsonl")).unwrap(),
+            "{}\n"
+        );
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
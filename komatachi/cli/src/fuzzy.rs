@@ -0,0 +1,88 @@
+//! Fuzzy subsequence matching for pickers.
+//!
+//! A query matches a candidate when every query character appears in the
+//! candidate in order (case-insensitive). Matches are scored so that
+//! contiguous runs and matches at word starts rank higher.
+
+/// Score `candidate` against `query`. `None` means no match. An empty
+/// query matches everything with score 0.
+pub fn score(query: &str, candidate: &str) -> Option<i64> {
+    let query: Vec<char> = query
+        .to_lowercase()
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .collect();
+    if query.is_empty() {
+        return Some(0);
+    }
+
+    let chars: Vec<char> = candidate.to_lowercase().chars().collect();
+    let mut total: i64 = 0;
+    let mut query_index = 0;
+    let mut previous_match: Option<usize> = None;
+
+    for (index, &c) in chars.iter().enumerate() {
+        if c != query[query_index] {
+            continue;
+        }
+        total += 1;
+        if previous_match == Some(index.wrapping_sub(1)) {
+            total += 5;
+        }
+        if index == 0 || !chars[index - 1].is_alphanumeric() {
+            total += 3;
+        }
+        previous_match = Some(index);
+        query_index += 1;
+        if query_index == query.len() {
+            // Prefer shorter candidates among equal matches.
+            return Some(total * 100 - chars.len().min(99) as i64);
+        }
+    }
+    None
+}
+
+/// Return indices of `candidates` that match `query`, best first. Ties
+/// keep their original order.
+pub fn rank(query: &str, candidates: &[&str]) -> Vec<usize> {
+    let mut scored: Vec<(i64, usize)> = candidates
+        .iter()
+        .enumerate()
+        .filter_map(|(index, candidate)| score(query, candidate).map(|s| (s, index)))
+        .collect();
+    scored.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)));
+    scored.into_iter().map(|(_, index)| index).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn requires_characters_in_order() {
+        assert!(score("abc", "a-b-c").is_some());
+        assert!(score("cba", "a-b-c").is_none());
+    }
+
+    #[test]
+    fn is_case_insensitive() {
+        assert!(score("RUST", "rustacean").is_some());
+    }
+
+    #[test]
+    fn empty_query_matches_everything() {
+        assert_eq!(score("", "anything"), Some(0));
+    }
+
+    #[test]
+    fn contiguous_match_ranks_first() {
+        let candidates = ["review the draft", "rv", "review"];
+        assert_eq!(rank("rev", &candidates), vec![2, 0]);
+    }
+
+    #[test]
+    fn word_start_beats_mid_word() {
+        let candidates = ["prefix", "fix the bug"];
+        assert_eq!(rank("fix", &candidates)[0], 1);
+    }
+}
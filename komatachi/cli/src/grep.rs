@@ -0,0 +1,306 @@
+//! `komatachi-cli grep <pattern>`: search transcripts line by line.
+//!
+//! Searches the main conversation and every fork (or the one named with
+//! `--in`) and prints matching lines grep-style, with optional context.
+//! Each message is rendered as text (as `/inspect` shows it) and searched
+//! line by line; context never crosses into the next message. Transcripts
+//! are only read. Like grep, the exit status is 0 when something matched,
+//! 1 when nothing did, and 2 on errors.
+//!
+//! Output lines are `<conversation>#<message>:<role>:<line>:<text>` for
+//! matches and the same with `-` separators for context. Message numbers
+//! are positions in the transcript, which compaction renumbers.
+
+use std::collections::BTreeSet;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use serde_json::Value;
+
+use crate::inspect;
+use crate::paths;
+use crate::regex::Regex;
+use crate::style;
+
+const USAGE: &str = "\
+usage: komatachi-cli grep [options] <pattern>
+
+Options:
+  -i                     Ignore case
+  -B, --before <n>       Show n lines before each match
+  -A, --after <n>        Show n lines after each match
+  -C <n>                 Show n lines before and after
+  --role <user|assistant>
+                         Only search messages from this role
+  --in <name>            Only search this conversation (main or a fork name)
+  --json                 One JSON object per match";
+
+struct Options {
+    pattern: String,
+    ignore_case: bool,
+    before: usize,
+    after: usize,
+    role: Option<String>,
+    only: Option<String>,
+    json: bool,
+}
+
+/// A message with at least one match, rendered as lines.
+struct Message<'a> {
+    conversation: &'a str,
+    /// 1-based position in the transcript.
+    number: usize,
+    role: &'a str,
+    lines: &'a [String],
+}
+
+/// `komatachi-cli grep [options] <pattern>`
+pub fn run_command(args: &[String]) {
+    let options = parse_args(args).unwrap_or_else(|e| {
+        eprintln!("{} {}\n\n{}", style::error("error:"), e, USAGE);
+        std::process::exit(2);
+    });
+    let pattern = if options.ignore_case {
+        format!("(?i){}", options.pattern)
+    } else {
+        options.pattern.clone()
+    };
+    let regex = Regex::new(&pattern).unwrap_or_else(|e| {
+        eprintln!("{} invalid pattern: {}", style::error("error:"), e);
+        std::process::exit(2);
+    });
+
+    let searched = conversations(options.only.as_deref());
+    if let (Some(only), true) = (&options.only, searched.is_empty()) {
+        eprintln!(
+            "{} no conversation named '{}' (main or a fork name)",
+            style::error("error:"),
+            only
+        );
+        std::process::exit(2);
+    }
+    let context = options.before + options.after > 0;
+    let mut matched = false;
+    for (name, path) in searched {
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => {
+                eprintln!(
+                    "{} cannot read {}: {}",
+                    style::error("error:"),
+                    path.display(),
+                    e
+                );
+                std::process::exit(2);
+            }
+        };
+        for (index, line) in contents.lines().enumerate() {
+            let Ok(message) = serde_json::from_str::<Value>(line) else {
+                continue;
+            };
+            let role = message["role"].as_str().unwrap_or("?");
+            if options.role.as_deref().is_some_and(|only| only != role) {
+                continue;
+            }
+            let lines: Vec<String> = inspect::render_content(&message["content"])
+                .lines()
+                .map(String::from)
+                .collect();
+            let hits: Vec<usize> = (0..lines.len())
+                .filter(|&i| regex.is_match(&lines[i]))
+                .collect();
+            if hits.is_empty() {
+                continue;
+            }
+            let message = Message {
+                conversation: &name,
+                number: index + 1,
+                role,
+                lines: &lines,
+            };
+            if options.json {
+                for &line in &hits {
+                    println!("{}", message.json(line, options.before, options.after));
+                }
+            } else {
+                if matched && context {
+                    println!("--");
+                }
+                print!("{}", message.render(&hits, options.before, options.after));
+            }
+            matched = true;
+        }
+    }
+    if !matched {
+        std::process::exit(1);
+    }
+}
+
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut options = Options {
+        pattern: String::new(),
+        ignore_case: false,
+        before: 0,
+        after: 0,
+        role: None,
+        only: None,
+        json: false,
+    };
+    let mut pattern = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| {
+            args.next()
+                .cloned()
+                .ok_or_else(|| format!("{} needs a value", name))
+        };
+        let count = |text: String| {
+            text.parse::<usize>()
+                .map_err(|_| format!("expected a number of lines, got {:?}", text))
+        };
+        match arg.as_str() {
+            "-i" => options.ignore_case = true,
+            "-B" | "--before" => options.before = count(value(arg)?)?,
+            "-A" | "--after" => options.after = count(value(arg)?)?,
+            "-C" => {
+                options.before = count(value(arg)?)?;
+                options.after = options.before;
+            }
+            "--role" => match value(arg)?.as_str() {
+                role @ ("user" | "assistant") => options.role = Some(role.to_string()),
+                other => return Err(format!("--role must be user or assistant, got {:?}", other)),
+            },
+            "--in" => options.only = Some(value(arg)?),
+            "--json" => options.json = true,
+            "--" if pattern.is_none() => pattern = Some(value(arg)?),
+            other if other.starts_with('-') && pattern.is_none() && other.len() > 1 => {
+                return Err(format!("unknown option: {}", other))
+            }
+            _ if pattern.is_some() => return Err("only one pattern can be given".to_string()),
+            _ => pattern = Some(arg.clone()),
+        }
+    }
+    options.pattern = pattern.ok_or("missing pattern")?;
+    Ok(options)
+}
+
+/// Transcripts to search, by conversation name: the main one, then forks
+/// in name order.
+fn conversations(only: Option<&str>) -> Vec<(String, PathBuf)> {
+    let found = paths::conversations();
+    match only {
+        Some(only) => found.into_iter().filter(|(name, _)| name == only).collect(),
+        None => found,
+    }
+}
+
+/// The lines to show for `hits` among `len` lines: each hit with up to
+/// `before` and `after` lines around it, overlaps merged, in order.
+pub fn with_context(hits: &[usize], before: usize, after: usize, len: usize) -> BTreeSet<usize> {
+    let last = len.saturating_sub(1);
+    hits.iter()
+        .flat_map(|&i| i.saturating_sub(before)..=(i + after).min(last))
+        .collect()
+}
+
+impl Message<'_> {
+    /// Matching lines with merged context, like grep: `:` after the
+    /// prefix for matches, `-` for context, and `--` between gaps when
+    /// there is context.
+    fn render(&self, hits: &[usize], before: usize, after: usize) -> String {
+        let shown = with_context(hits, before, after, self.lines.len());
+        let mut out = String::new();
+        let mut previous = None;
+        for i in shown {
+            if before + after > 0 && previous.is_some_and(|p| p + 1 != i) {
+                out.push_str("--\n");
+            }
+            let sep = if hits.contains(&i) { ':' } else { '-' };
+            out.push_str(&format!(
+                "{}#{}{sep}{}{sep}{}{sep}{}\n",
+                self.conversation,
+                self.number,
+                self.role,
+                i + 1,
+                self.lines[i],
+            ));
+            previous = Some(i);
+        }
+        out
+    }
+
+    /// One match (0-based `line`) as a JSON object with its context.
+    fn json(&self, line: usize, before: usize, after: usize) -> Value {
+        let end = (line + 1 + after).min(self.lines.len());
+        serde_json::json!({
+            "conversation": self.conversation,
+            "message": self.number,
+            "role": self.role,
+            "line": line + 1,
+            "text": self.lines[line],
+            "before": self.lines[line.saturating_sub(before)..line],
+            "after": self.lines[line + 1..end],
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(text: &str) -> Vec<String> {
+        text.lines().map(String::from).collect()
+    }
+
+    #[test]
+    fn merges_overlapping_context_and_marks_gaps() {
+        let text = lines("a\nmatch one\nb\nc\nd\ne\nmatch two\nf");
+        let message = Message {
+            conversation: "main",
+            number: 3,
+            role: "assistant",
+            lines: &text,
+        };
+        assert_eq!(
+            message.render(&[1, 6], 1, 1),
+            "main#3-assistant-1-a\n\
+             main#3:assistant:2:match one\n\
+             main#3-assistant-3-b\n\
+             --\n\
+             main#3-assistant-6-e\n\
+             main#3:assistant:7:match two\n\
+             main#3-assistant-8-f\n"
+        );
+    }
+
+    #[test]
+    fn json_hit_carries_its_context() {
+        let text = lines("one\ntwo\nthree");
+        let message = Message {
+            conversation: "review",
+            number: 1,
+            role: "user",
+            lines: &text,
+        };
+        let json = message.json(0, 2, 1);
+        assert_eq!(json["line"], 1);
+        assert_eq!(json["before"], serde_json::json!([]));
+        assert_eq!(json["after"], serde_json::json!(["two"]));
+    }
+
+    #[test]
+    fn parses_context_and_filters() {
+        let args: Vec<String> = ["-C", "2", "--role", "user", "-i", "needle"]
+            .iter()
+            .map(|a| a.to_string())
+            .collect();
+        let options = parse_args(&args).unwrap();
+        assert_eq!((options.before, options.after), (2, 2));
+        assert_eq!(options.role.as_deref(), Some("user"));
+        assert!(options.ignore_case);
+        assert_eq!(options.pattern, "needle");
+        assert!(parse_args(&["--role".to_string(), "system".to_string()]).is_err());
+    }
+}
@@ -0,0 +1,180 @@
+//! Help topics for `/help <topic>`.
+//!
+//! Short, offline versions of the README sections a session most often
+//! needs: the editing keys, the config file, the environment, where
+//! things are kept, and the protocol. The README stays the full
+//! reference; these say enough to avoid leaving the prompt for it.
+
+pub struct Topic {
+    pub name: &'static str,
+    pub summary: &'static str,
+    pub text: &'static str,
+}
+
+pub const TOPICS: &[Topic] = &[
+    Topic {
+        name: "keys",
+        summary: "Editing keys at the prompt",
+        text: "\
+Left/Right, Ctrl-B/F      move by character
+Alt-B/F, Ctrl-Left/Right  move by word
+Home/End, Ctrl-A/E        start or end of the line
+Backspace, Delete         delete a character
+Ctrl-W                    delete the word before the cursor
+Ctrl-U, Ctrl-K            delete to the start or end of the line
+Up/Down, Ctrl-P/N         earlier prompts, including past sessions'
+Ctrl-R                    search earlier prompts (again: older; Ctrl-G: back)
+Tab                       complete a /command or a file path
+Ctrl-X Ctrl-E             finish the line in $EDITOR, then send it
+Paste                     several lines are sent as one prompt
+Ctrl-L                    clear the screen
+Ctrl-C                    clear the line; on an empty line, exit
+Ctrl-D                    exit, on an empty line
+
+While a turn runs, Ctrl-C cancels it, keeping the partial answer;
+a second Ctrl-C, or Ctrl-D, abandons it and exits.
+
+/keys vi (or keys.mode \"vi\" in the config) switches to vi keys:
+Escape for normal mode, shown as (cmd) before the prompt, where
+h l w b e 0 ^ $ move, x X D C S s dd cc dw cw r p P u edit, k j
+recall prompts, / searches them, and i a I A return to (ins).
+/keys emacs switches back.",
+    },
+    Topic {
+        name: "config",
+        summary: "Settings in ~/.komatachi/config.json",
+        text: "\
+Every section and key is optional; an unknown key is an error.
+`komatachi-cli config check` checks the file; `config show --resolved`
+prints every setting as used, defaults included; `config edit` opens it
+in $EDITOR with every setting described, and checks it on save.
+
+validation    mustMatch, mustNotMatch (regex lists), maxLength, json,
+              retries (default 2): rules every answer must satisfy
+watchdog      stallSecs (default 600), restart (default true): restart
+              an agent that stalls or exits mid-turn
+idle          suspendSecs: stop the container after this long at the
+              prompt; the next line restarts it (off by default)
+limits        maxSessionSecs, idleSecs, maxTurns: end the session at
+              the next prompt once any is reached (all off by default)
+notify        afterSecs, always, command: a desktop notification when
+              a turn takes that long and the terminal lost focus;
+              bell, bellCommand: the bell (or a command) after turns
+keys          mode (default \"emacs\"): \"vi\" for vi bindings at the
+              prompt, with (ins) or (cmd) before it
+history       enabled (default true), size (default 1000): prompts
+              kept in ~/.komatachi/history and recalled with Up/Ctrl-R
+prompt        format (default \"> \"), status: the prompt, and a line
+              after each turn; {conversation}, {turn}, {model}, {tokens},
+              {remaining}, {window} are filled in; {{ and }} are braces
+pager         enabled (default true), minLines: page answers taller
+              than the terminal through $PAGER
+substitution  enabled (default false), maxBytes (default 16384):
+              replace !`command` in prompts with its output, after
+              asking
+sinks         name: { file | dailyDir | command, format, auto }:
+              where /send-to copies exchanges; auto sinks get every one
+snippets      name: text: what :name in a prompt and /snippet name send
+wrap          enabled (default true): wrap answers' prose at word
+              boundaries to the terminal's width
+stream        enabled (default false): print answers as the model
+              writes them; not with validation rules or the pager
+timestamps    enabled (default false): start with /timestamps on
+theme         dim, error, warning, added, removed: styles such as
+              \"bold red\", \"on-blue\", \"38;5;208\", or \"none\"
+references    maxTokens (default 8000), maxFileBytes (default 102400):
+              limits on what @name#n-m and @path quote into a prompt
+scrub         secrets, emails, hostnames (default true), patterns:
+              what `komatachi-cli export --scrub` replaces;
+              clipboard (default false): scrub /paste and
+              --from-clipboard text too
+routing       mode (default \"fixed\"): \"auto\" sends simple prompts to
+              smallModel; KOMATACHI_ROUTING and
+              KOMATACHI_SMALL_MODEL override both",
+    },
+    Topic {
+        name: "env",
+        summary: "Environment variables the CLI reads or passes on",
+        text: "\
+ANTHROPIC_API_KEY         API key (required)
+KOMATACHI_DATA_DIR        conversation storage (~/.komatachi/data)
+KOMATACHI_HOME_DIR        identity files (~/.komatachi/home)
+KOMATACHI_MODEL           model for answers
+KOMATACHI_MAX_TOKENS      maximum tokens per response (4096)
+KOMATACHI_CONTEXT_WINDOW  context window in tokens (200000)
+KOMATACHI_ROUTING         auto: simple prompts go to KOMATACHI_SMALL_MODEL
+                          (routing.mode in the config)
+KOMATACHI_VOICE_COMMAND   recording and transcription for --voice
+KOMATACHI_SPEAK_COMMAND   text to speech for --speak
+VISUAL, EDITOR            editor for /edit and Ctrl-X Ctrl-E (vi)
+PAGER                     pager for long answers (less -FRX)
+NO_COLOR                  any non-empty value: no styled output",
+    },
+    Topic {
+        name: "files",
+        summary: "What is kept where on the host",
+        text: "\
+~/.komatachi/data/conversation/   the transcript and its metadata
+~/.komatachi/data/checkpoints/    turn checkpoints, for /rollback
+~/.komatachi/data/draft.json      the prompt in flight, until answered
+~/.komatachi/data/unsent.json     the line being typed, until entered
+~/.komatachi/data/bookmarks.jsonl exchanges saved with /bookmark
+~/.komatachi/data/ratings.jsonl   exchanges rated with /good and /bad
+~/.komatachi/home/*.md            identity files, yours to edit
+~/.komatachi/history              every prompt sent from the CLI
+~/.komatachi/timings.jsonl        startup times (komatachi-cli timings)
+~/.komatachi/forks/<name>/        conversations copied with /fork
+~/.komatachi/config.json          settings (/help config)
+~/.komatachi/agent/<digest>/      the agent's build files, when the
+                                  checkout is gone",
+    },
+    Topic {
+        name: "protocol",
+        summary: "How the CLI and the agent talk",
+        text: "\
+The agent runs in a container; the CLI writes one JSON object per line
+to its stdin and reads one per line from its stdout. The agent starts
+with ready, naming the protocol version it speaks; a version this CLI
+does not speak stops the session with what to upgrade. Each prompt is
+{\"type\":\"input\",\"text\":...}, answered by usage, checkpoint, and
+output messages, or by an error; with \"stream\":true it also sends
+the answer's text in output_delta pieces first. The agent numbers
+what it sends (seq), and from version 2 echoes each input's id on
+its replies (reply_to), so a lost, repeated, or stray line is
+noticed; from version 3 it sends a heartbeat during turns, so a silent
+agent can be told from a slow model. Other requests read the last model request (/inspect),
+record or restore checkpoints, and finalize a turn at its deadline.
+`komatachi-cli conformance` checks an agent image against the
+protocol.",
+    },
+];
+
+pub fn find(name: &str) -> Option<&'static Topic> {
+    TOPICS.iter().find(|topic| topic.name == name)
+}
+
+/// Topics mentioning `word`, case-insensitively.
+pub fn search(word: &str) -> Vec<&'static Topic> {
+    let word = word.to_lowercase();
+    TOPICS
+        .iter()
+        .filter(|topic| {
+            [topic.name, topic.summary, topic.text]
+                .iter()
+                .any(|text| text.to_lowercase().contains(&word))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_topics_by_name_and_content() {
+        assert_eq!(find("config").map(|t| t.summary), Some(TOPICS[1].summary));
+        assert!(find("nope").is_none());
+        let names: Vec<&str> = search("PAGER").iter().map(|t| t.name).collect();
+        assert_eq!(names, ["config", "env"]);
+    }
+}
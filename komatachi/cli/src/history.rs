@@ -0,0 +1,213 @@
+//! Prompt history.
+//!
+//! Every prompt sent to the agent is appended to `~/.komatachi/history`
+//! (one JSON object per line, so multi-line prompts survive).
+//! `komatachi-cli history` opens a fuzzy picker over past prompts; the
+//! chosen one starts an interactive session with that prompt at the
+//! prompt line, to edit and send or clear. The REPL
+//! loads the most recent `history.size` prompts for Up/Down and Ctrl-R,
+//! cutting the file down to them; `"enabled": false` in the `history`
+//! config section stops both saving and loading.
+
+use serde::{Deserialize, Serialize};
+use std::fs::{self, OpenOptions};
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+
+use crate::fuzzy;
+use crate::paths;
+use crate::style;
+use crate::time;
+
+/// Number of matches shown at once in the picker.
+const PICKER_ROWS: usize = 15;
+
+/// Default number of prompts kept.
+const DEFAULT_SIZE: usize = 1000;
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "camelCase")]
+pub struct Settings {
+    /// Save prompts and recall them in later sessions (default true).
+    pub enabled: Option<bool>,
+    /// Most prompts kept.
+    pub size: Option<usize>,
+}
+
+impl Settings {
+    pub fn enabled(&self) -> bool {
+        self.enabled.unwrap_or(true)
+    }
+
+    pub fn size(&self) -> usize {
+        self.size.unwrap_or(DEFAULT_SIZE)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HistoryEntry {
+    /// When the prompt was sent (epoch ms)
+    pub created_at: u64,
+    pub text: String,
+}
+
+pub fn history_file() -> PathBuf {
+    paths::komatachi_dir().join("history")
+}
+
+/// Append one prompt to the history file.
+pub fn append(path: &Path, text: &str) -> io::Result<()> {
+    let entry = HistoryEntry {
+        created_at: time::now_ms(),
+        text: text.to_string(),
+    };
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let json = serde_json::to_string(&entry).expect("serialize history entry");
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "{}", json)
+}
+
+/// Load history, oldest first. Unparseable lines are skipped: history is
+/// a convenience, and one bad line should not hide the rest.
+pub fn load(path: &Path) -> io::Result<Vec<HistoryEntry>> {
+    match fs::read_to_string(path) {
+        Ok(contents) => Ok(contents
+            .lines()
+            .filter_map(|line| serde_json::from_str(line).ok())
+            .collect()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e),
+    }
+}
+
+/// The last `size` prompts, oldest first, for the line editor. Older
+/// entries are dropped from the file, which is rewritten whole and
+/// renamed into place.
+pub fn recall(path: &Path, size: usize) -> io::Result<Vec<String>> {
+    let mut entries = load(path)?;
+    if entries.len() > size {
+        entries.drain(..entries.len() - size);
+        let mut kept = String::new();
+        for entry in &entries {
+            kept.push_str(&serde_json::to_string(entry).expect("serialize history entry"));
+            kept.push('\n');
+        }
+        let temp = path.with_extension("tmp");
+        fs::write(&temp, kept)?;
+        fs::rename(&temp, path)?;
+    }
+    Ok(entries.into_iter().map(|entry| entry.text).collect())
+}
+
+/// Distinct prompt texts, most recent first.
+fn distinct_recent(entries: &[HistoryEntry]) -> Vec<&str> {
+    let mut seen = std::collections::HashSet::new();
+    entries
+        .iter()
+        .rev()
+        .map(|entry| entry.text.as_str())
+        .filter(|text| seen.insert(*text))
+        .collect()
+}
+
+/// `komatachi-cli history [query]`: pick a past prompt.
+///
+/// Returns the selected prompt, or `None` if the user cancelled.
+pub fn pick(args: &[String]) -> Option<String> {
+    let path = history_file();
+    let entries = load(&path).unwrap_or_else(|e| {
+        eprintln!(
+            "{} cannot read {}: {}",
+            style::error("error:"),
+            path.display(),
+            e
+        );
+        std::process::exit(1);
+    });
+    let prompts = distinct_recent(&entries);
+    if prompts.is_empty() {
+        eprintln!("No history yet.");
+        return None;
+    }
+
+    let stdin = io::stdin();
+    let mut query = args.join(" ");
+    let mut line = String::new();
+
+    loop {
+        let matches = fuzzy::rank(&query, &prompts);
+        if matches.is_empty() {
+            eprintln!("No prompts match '{}'.", query);
+        }
+        for (row, &index) in matches.iter().take(PICKER_ROWS).enumerate() {
+            eprintln!("{:>3}  {}", row + 1, preview(prompts[index]));
+        }
+        eprint!("\nNumber to select, text to filter, empty to cancel: ");
+        io::stderr().flush().ok();
+
+        line.clear();
+        if stdin.lock().read_line(&mut line).unwrap_or(0) == 0 {
+            return None;
+        }
+        let answer = line.trim();
+        if answer.is_empty() {
+            return None;
+        }
+        match answer.parse::<usize>() {
+            Ok(row) if row >= 1 && row <= matches.len().min(PICKER_ROWS) => {
+                return Some(prompts[matches[row - 1]].to_string());
+            }
+            _ => query = answer.to_string(),
+        }
+    }
+}
+
+/// First line of a prompt, marked when more lines follow.
+fn preview(text: &str) -> String {
+    let mut lines = text.lines();
+    let first = lines.next().unwrap_or("");
+    if lines.next().is_some() {
+        format!("{} ...", first)
+    } else {
+        first.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(text: &str) -> HistoryEntry {
+        HistoryEntry {
+            created_at: 0,
+            text: text.to_string(),
+        }
+    }
+
+    #[test]
+    fn distinct_recent_keeps_latest_occurrence_first() {
+        let entries = vec![entry("a"), entry("b"), entry("a"), entry("c")];
+        assert_eq!(distinct_recent(&entries), vec!["c", "a", "b"]);
+    }
+
+    #[test]
+    fn recall_keeps_the_most_recent() {
+        let path = std::env::temp_dir().join(format!("komatachi-history-{}", std::process::id()));
+        for text in ["a", "b", "c"] {
+            append(&path, text).unwrap();
+        }
+        assert_eq!(recall(&path, 5).unwrap(), ["a", "b", "c"]);
+        assert_eq!(recall(&path, 2).unwrap(), ["b", "c"]);
+        assert_eq!(load(&path).unwrap().len(), 2);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn preview_marks_multiline_prompts() {
+        assert_eq!(preview("one\ntwo"), "one ...");
+        assert_eq!(preview("one"), "one");
+    }
+}
@@ -0,0 +1,84 @@
+//! Suspending an idle agent.
+//!
+//! With `idle.suspendSecs` set, a prompt left waiting that long stops the
+//! agent's container (`docker stop`), freeing its memory. Everything the
+//! agent keeps is on disk, so the next line typed starts a fresh one on
+//! the same data directory -- the same thing the watchdog does after a
+//! crash, here done on purpose between turns. Off by default.
+
+use serde::Deserialize;
+use std::process::{Command, Stdio};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "camelCase")]
+pub struct Settings {
+    /// Seconds at an empty prompt before the agent is stopped.
+    pub suspend_secs: Option<u64>,
+}
+
+impl Settings {
+    pub fn suspend_after(&self) -> Option<Duration> {
+        self.suspend_secs
+            .filter(|&secs| secs > 0)
+            .map(Duration::from_secs)
+    }
+}
+
+/// Stops a container once `after` passes, unless finished first.
+pub struct Timer {
+    cancel: Sender<()>,
+    stopper: JoinHandle<bool>,
+}
+
+impl Timer {
+    pub fn start(after: Duration, container: &str) -> Timer {
+        let (cancel, cancelled) = mpsc::channel();
+        let container = container.to_string();
+        let stopper = thread::spawn(move || match cancelled.recv_timeout(after) {
+            Err(RecvTimeoutError::Timeout) => {
+                let _ = Command::new("docker")
+                    .args(["stop", &container])
+                    .stdout(Stdio::null())
+                    .stderr(Stdio::null())
+                    .status();
+                true
+            }
+            _ => false,
+        });
+        Timer { cancel, stopper }
+    }
+
+    /// Cancel the timer. Returns whether the container was stopped; if
+    /// the timer was stopping it just then, this waits until it is done.
+    pub fn finish(self) -> bool {
+        let _ = self.cancel.send(());
+        self.stopper.join().unwrap_or(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn off_unless_set_and_positive() {
+        assert_eq!(Settings::default().suspend_after(), None);
+        let zero = Settings {
+            suspend_secs: Some(0),
+        };
+        assert_eq!(zero.suspend_after(), None);
+        let set = Settings {
+            suspend_secs: Some(900),
+        };
+        assert_eq!(set.suspend_after(), Some(Duration::from_secs(900)));
+    }
+
+    #[test]
+    fn finishing_early_stops_nothing() {
+        let timer = Timer::start(Duration::from_secs(60), "komatachi-test-unused");
+        assert!(!timer.finish());
+    }
+}
@@ -0,0 +1,121 @@
+//! Rendering for `/inspect`.
+//!
+//! Shows the last model request as the agent sent it: the system prompt
+//! built from identity files, and the messages that survived context
+//! selection. What is not here, the model did not see.
+
+use serde_json::Value;
+
+/// Approximate tokens for display, using the agent's chars/4 heuristic.
+pub fn estimate_tokens(text: &str) -> usize {
+    text.chars().count().div_ceil(4)
+}
+
+/// Render a model request (`CallModelParams`) as readable text.
+pub fn render(request: &Value) -> String {
+    let mut out = String::new();
+
+    let model = request["model"].as_str().unwrap_or("?");
+    let max_tokens = request["max_tokens"].as_u64().unwrap_or(0);
+    out.push_str(&format!("model: {}  max_tokens: {}\n", model, max_tokens));
+
+    let system = request["system"].as_str().unwrap_or("");
+    out.push_str(&format!(
+        "\n=== system ({} chars, ~{} tokens) ===\n{}\n",
+        system.chars().count(),
+        estimate_tokens(system),
+        system
+    ));
+
+    let messages = request["messages"]
+        .as_array()
+        .map(Vec::as_slice)
+        .unwrap_or(&[]);
+    out.push_str(&format!("\n=== messages ({}) ===\n", messages.len()));
+    for (index, message) in messages.iter().enumerate() {
+        let role = message["role"].as_str().unwrap_or("?");
+        out.push_str(&format!("\n[{}] {}\n", index + 1, role));
+        out.push_str(&render_content(&message["content"]));
+    }
+
+    if let Some(tools) = request["tools"].as_array() {
+        let names: Vec<&str> = tools.iter().filter_map(|t| t["name"].as_str()).collect();
+        out.push_str(&format!(
+            "\n=== tools ({}) ===\n{}\n",
+            names.len(),
+            names.join(", ")
+        ));
+    }
+    out
+}
+
+/// Render message content: a plain string or an array of content blocks.
+pub fn render_content(content: &Value) -> String {
+    match content {
+        Value::String(text) => format!("{}\n", text),
+        Value::Array(blocks) => blocks.iter().map(render_block).collect(),
+        other => format!("{}\n", other),
+    }
+}
+
+fn render_block(block: &Value) -> String {
+    match block["type"].as_str() {
+        Some("text") => format!("{}\n", block["text"].as_str().unwrap_or("")),
+        Some("tool_use") => format!(
+            "<tool_use {} id={}> {}\n",
+            block["name"].as_str().unwrap_or("?"),
+            block["id"].as_str().unwrap_or("?"),
+            block["input"]
+        ),
+        Some("tool_result") => {
+            let error = if block["is_error"].as_bool() == Some(true) {
+                " error"
+            } else {
+                ""
+            };
+            format!(
+                "<tool_result id={}{}>\n{}",
+                block["tool_use_id"].as_str().unwrap_or("?"),
+                error,
+                render_content(&block["content"])
+            )
+        }
+        _ => format!("{}\n", block),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn renders_system_and_messages() {
+        let request = json!({
+            "model": "claude-test",
+            "max_tokens": 100,
+            "system": "You are Komatachi.",
+            "messages": [
+                { "role": "user", "content": "hello" },
+                { "role": "assistant", "content": [{ "type": "text", "text": "hi" }] }
+            ]
+        });
+        let text = render(&request);
+        assert!(text.contains("model: claude-test  max_tokens: 100"));
+        assert!(text.contains("=== system (18 chars, ~5 tokens) ===\nYou are Komatachi."));
+        assert!(text.contains("[1] user\nhello\n"));
+        assert!(text.contains("[2] assistant\nhi\n"));
+    }
+
+    #[test]
+    fn renders_tool_blocks() {
+        let content = json!([
+            { "type": "tool_use", "id": "t1", "name": "read", "input": { "path": "a" } },
+            { "type": "tool_result", "tool_use_id": "t1", "content": "data", "is_error": true }
+        ]);
+        assert_eq!(
+            render_content(&content),
+            "<tool_use read id=t1> {\"path\":\"a\"}\n<tool_result id=t1 error>\ndata\n"
+        );
+    }
+}
@@ -0,0 +1,110 @@
+//! Response language: `--lang <code>` and `/lang`.
+//!
+//! The agent's identity files are its system prompt, so the CLI does not
+//! change that. Instead each prompt sent while a language is set starts
+//! with a short note asking for answers in it. The note goes into the
+//! transcript with the prompt, where it records which turns were asked
+//! for in which language. Without a language nothing is added, and the
+//! model answers in whatever language it is addressed in.
+
+/// Names for common language codes; other valid tags are sent as tags.
+const NAMES: [(&str, &str); 24] = [
+    ("ar", "Arabic"),
+    ("de", "German"),
+    ("el", "Greek"),
+    ("en", "English"),
+    ("es", "Spanish"),
+    ("fa", "Persian"),
+    ("fi", "Finnish"),
+    ("fr", "French"),
+    ("he", "Hebrew"),
+    ("hi", "Hindi"),
+    ("id", "Indonesian"),
+    ("it", "Italian"),
+    ("ja", "Japanese"),
+    ("ko", "Korean"),
+    ("nl", "Dutch"),
+    ("pl", "Polish"),
+    ("pt", "Portuguese"),
+    ("ru", "Russian"),
+    ("sv", "Swedish"),
+    ("th", "Thai"),
+    ("tr", "Turkish"),
+    ("uk", "Ukrainian"),
+    ("vi", "Vietnamese"),
+    ("zh", "Chinese"),
+];
+
+/// Check a BCP 47-style tag (`ja`, `pt-BR`, `zh-Hant`) and normalize its
+/// case: lowercase language, uppercase region, titlecase script.
+pub fn parse(tag: &str) -> Result<String, String> {
+    let invalid = || {
+        format!(
+            "expected a language code such as ja or pt-BR, got {:?}",
+            tag
+        )
+    };
+    let mut parts = tag.split('-');
+    let language = parts.next().unwrap_or("");
+    if !(2..=3).contains(&language.len()) || !language.chars().all(|c| c.is_ascii_alphabetic()) {
+        return Err(invalid());
+    }
+    let mut normalized = language.to_ascii_lowercase();
+    for part in parts {
+        if !(2..=8).contains(&part.len()) || !part.chars().all(|c| c.is_ascii_alphanumeric()) {
+            return Err(invalid());
+        }
+        normalized.push('-');
+        match part.len() {
+            2 => normalized.push_str(&part.to_ascii_uppercase()),
+            4 => {
+                normalized.push_str(&part[..1].to_ascii_uppercase());
+                normalized.push_str(&part[1..].to_ascii_lowercase());
+            }
+            _ => normalized.push_str(&part.to_ascii_lowercase()),
+        }
+    }
+    Ok(normalized)
+}
+
+/// `Japanese (ja)` for known languages, the tag alone otherwise.
+pub fn describe(tag: &str) -> String {
+    let language = tag.split('-').next().unwrap_or(tag);
+    match NAMES.iter().find(|(code, _)| *code == language) {
+        Some((_, name)) => format!("{} ({})", name, tag),
+        None => tag.to_string(),
+    }
+}
+
+/// The prompt as sent while `tag` is the response language.
+pub fn prompt(tag: &str, input: &str) -> String {
+    format!(
+        "[Note from the CLI: please answer in {}.]\n\n{}",
+        describe(tag),
+        input
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalizes_tags_and_rejects_others() {
+        assert_eq!(parse("JA").unwrap(), "ja");
+        assert_eq!(parse("pt-br").unwrap(), "pt-BR");
+        assert_eq!(parse("zh-hant").unwrap(), "zh-Hant");
+        assert!(parse("japanese").is_err());
+        assert!(parse("j").is_err());
+        assert!(parse("en-").is_err());
+    }
+
+    #[test]
+    fn prompt_names_the_language() {
+        assert_eq!(
+            prompt("pt-BR", "Olá"),
+            "[Note from the CLI: please answer in Portuguese (pt-BR).]\n\nOlá"
+        );
+        assert_eq!(describe("tlh"), "tlh");
+    }
+}
@@ -0,0 +1,175 @@
+//! Ending sessions nobody is attending.
+//!
+//! A terminal left open keeps its container running and can keep
+//! spending, so `limits` in `config.json` can end a session: after
+//! `maxSessionSecs` since it started, after `idleSecs` waiting at the
+//! prompt, or after `maxTurns` answered turns. A turn in flight is never
+//! cut short; a limit reached during one ends the session at the next
+//! prompt. The session then ends as `quit` does, after saving a line
+//! typed but not sent for the next session to put back at the prompt,
+//! and prints how long it ran and what it used. All off by default. Where
+//! `idle.suspendSecs` stops an idle container and restarts it on the
+//! next line, `idleSecs` ends the session.
+
+use serde::Deserialize;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "camelCase")]
+pub struct Settings {
+    /// Seconds after the session starts before it ends.
+    pub max_session_secs: Option<u64>,
+    /// Seconds at the prompt without a key before the session ends.
+    pub idle_secs: Option<u64>,
+    /// Answered turns after which the session ends.
+    pub max_turns: Option<usize>,
+}
+
+/// Which limit ended a session.
+#[derive(Debug, PartialEq)]
+pub enum Reached {
+    Session(Duration),
+    Idle(Duration),
+    Turns(usize),
+}
+
+fn secs(value: Option<u64>) -> Option<Duration> {
+    value.filter(|&secs| secs > 0).map(Duration::from_secs)
+}
+
+impl Settings {
+    pub fn max_session(&self) -> Option<Duration> {
+        secs(self.max_session_secs)
+    }
+
+    pub fn idle(&self) -> Option<Duration> {
+        secs(self.idle_secs)
+    }
+
+    pub fn max_turns(&self) -> Option<usize> {
+        self.max_turns.filter(|&turns| turns > 0)
+    }
+
+    /// The limit a session `elapsed` old with `turns` answered is past.
+    pub fn reached(&self, elapsed: Duration, turns: usize) -> Option<Reached> {
+        if let Some(max) = self.max_session().filter(|&max| elapsed >= max) {
+            return Some(Reached::Session(max));
+        }
+        self.max_turns()
+            .filter(|&max| turns >= max)
+            .map(Reached::Turns)
+    }
+
+    /// When a prompt shown at `now` in a session begun at `started` has
+    /// to give up waiting, and the limit it would reach then.
+    pub fn deadline(&self, started: Instant, now: Instant) -> Option<(Instant, Reached)> {
+        let session = self
+            .max_session()
+            .map(|max| (started + max, Reached::Session(max)));
+        let idle = self.idle().map(|idle| (now + idle, Reached::Idle(idle)));
+        match (session, idle) {
+            (Some(session), Some(idle)) => Some(if idle.0 < session.0 { idle } else { session }),
+            (session, idle) => session.or(idle),
+        }
+    }
+}
+
+impl fmt::Display for Reached {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Reached::Session(max) => write!(
+                f,
+                "the session has run {} (limits.maxSessionSecs)",
+                duration(*max)
+            ),
+            Reached::Idle(idle) => write!(
+                f,
+                "the prompt was idle for {} (limits.idleSecs)",
+                duration(*idle)
+            ),
+            Reached::Turns(max) => write!(
+                f,
+                "{} turn{} answered (limits.maxTurns)",
+                max,
+                if *max == 1 { "" } else { "s" }
+            ),
+        }
+    }
+}
+
+/// The line printed when a session ends at a limit.
+pub fn summary(reached: &Reached, elapsed: Duration, turns: usize, tokens: u64) -> String {
+    format!(
+        "Ending the session: {}.\n{} turn{} in {}, {} tokens in and out.",
+        reached,
+        turns,
+        if turns == 1 { "" } else { "s" },
+        duration(elapsed),
+        tokens
+    )
+}
+
+/// `1h 05m`, `2h`, `12m 30s`, `30m`, or `45s`.
+fn duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    match (secs / 3600, secs / 60 % 60, secs % 60) {
+        (0, 0, s) => format!("{}s", s),
+        (0, m, 0) => format!("{}m", m),
+        (0, m, s) => format!("{}m {:02}s", m, s),
+        (h, 0, _) => format!("{}h", h),
+        (h, m, _) => format!("{}h {:02}m", h, m),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_earliest_limit_sets_the_deadline() {
+        let limits = Settings {
+            max_session_secs: Some(3600),
+            idle_secs: Some(600),
+            max_turns: Some(20),
+        };
+        let started = Instant::now();
+        let early = started + Duration::from_secs(60);
+        assert_eq!(
+            limits.deadline(started, early),
+            Some((
+                early + Duration::from_secs(600),
+                Reached::Idle(Duration::from_secs(600))
+            ))
+        );
+        let late = started + Duration::from_secs(3300);
+        assert_eq!(
+            limits.deadline(started, late),
+            Some((
+                started + Duration::from_secs(3600),
+                Reached::Session(Duration::from_secs(3600))
+            ))
+        );
+        assert_eq!(limits.reached(Duration::from_secs(60), 19), None);
+        assert_eq!(
+            limits.reached(Duration::from_secs(60), 20),
+            Some(Reached::Turns(20))
+        );
+        assert_eq!(Settings::default().deadline(started, late), None);
+    }
+
+    #[test]
+    fn summary_says_which_limit_and_what_was_used() {
+        assert_eq!(
+            summary(
+                &Reached::Idle(Duration::from_secs(1800)),
+                Duration::from_secs(3900),
+                12,
+                45210
+            ),
+            "Ending the session: the prompt was idle for 30m (limits.idleSecs).\n\
+             12 turns in 1h 05m, 45210 tokens in and out."
+        );
+        assert_eq!(duration(Duration::from_secs(45)), "45s");
+    }
+}
@@ -0,0 +1,1412 @@
+//! Line editing for the REPL prompt.
+//!
+//! When stdin and stderr are a terminal, the prompt is read a key at a
+//! time with the terminal switched out of canonical mode by `stty`, as
+//! other platform tools are shelled out to rather than linked. Keys are
+//! the common readline ones: arrows, Home/End, Ctrl-A/E/B/F, Backspace
+//! and Delete, Ctrl-U/K/W, Alt-B/F, Up/Down (Ctrl-P/N) through earlier
+//! prompts, and Ctrl-R to search them; the REPL loads prompts from past
+//! sessions too (see `history.rs`). A line longer than the terminal
+//! scrolls sideways. Tab completes command names and paths (see `complete.rs`),
+//! and Ctrl-X Ctrl-E moves the line into `$EDITOR` (see `compose.rs`).
+//! Ctrl-C clears the line, or ends input on an empty one, as does
+//! Ctrl-D. Without a terminal (or `stty`), lines are read as before.
+//! A deadline set with `give_up_at` ends a terminal read when no line
+//! is entered by then, for `limits.rs`; what was typed is kept.
+//! With `keep_unsent_in`, the line is written to a file as it changes
+//! and removed once entered, so a crash does not lose a long prompt;
+//! `prefill` puts such a line back for the user to finish.
+//!
+//! `keys.mode` in the config file, or `/keys`, switches to vi bindings:
+//! each line starts in insert mode, where the keys above still work, and
+//! Escape enters normal mode (see `Vi`). The prompt is then preceded by
+//! `(ins)` or `(cmd)`, as bash's `show-mode-in-prompt` does.
+//!
+//! The terminal is asked for bracketed paste, so pasted text arrives
+//! marked as such rather than as keys: a paste of several lines becomes
+//! one prompt, with whatever was typed around it, instead of one prompt
+//! per line. A paste longer than `CONFIRM_PASTE_LINES` is previewed, then
+//! sent, opened in `$EDITOR` to add to first, or dropped, as the user
+//! answers. Piped input is still read a line at a time.
+//!
+//! While a turn runs, `KeyWatch` keeps signal keys off too, so Ctrl-C
+//! and Ctrl-D reach the REPL as keys rather than as a SIGINT that would
+//! also hit the `docker run` client; whatever else is typed meanwhile is
+//! handed to the next prompt. It can also ask the terminal to report
+//! focus changes, for `notify.rs` to tell whether anyone is watching.
+
+use std::io::{self, BufRead, IsTerminal, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Instant;
+
+use serde::Deserialize;
+
+use crate::complete;
+use crate::compose;
+use crate::draft;
+use crate::style;
+use crate::terminal;
+
+/// Most completions listed at once.
+const MAX_LISTED: usize = 40;
+
+/// What precedes the prompt in vi mode, by mode.
+const INSERT_INDICATOR: &str = "(ins) ";
+const NORMAL_INDICATOR: &str = "(cmd) ";
+
+/// Pastes with more lines than this are previewed and confirmed.
+const CONFIRM_PASTE_LINES: usize = 40;
+
+/// What a terminal sends around pasted text, once asked to.
+const PASTE_START: &str = "200";
+const PASTE_END: &[u8] = b"\x1b[201~";
+
+/// One decoded keypress.
+#[derive(Debug, PartialEq)]
+enum Key {
+    Char(char),
+    Enter,
+    Backspace,
+    Delete,
+    Left,
+    Right,
+    WordLeft,
+    WordRight,
+    Home,
+    End,
+    Up,
+    Down,
+    KillToStart,
+    KillToEnd,
+    KillWord,
+    Interrupt,
+    /// Ctrl-D: delete under the cursor, or end of input on an empty line.
+    EndOfInput,
+    Redraw,
+    Tab,
+    /// Ctrl-X Ctrl-E: continue the line in `$EDITOR`.
+    Compose,
+    /// Ctrl-R: search earlier prompts.
+    Search,
+    /// Ctrl-G: leave a search.
+    Cancel,
+    /// Bracketed paste: the text, with `\n` line endings.
+    Paste(String),
+    /// Escape on its own, when reads can tell (see `Timed`).
+    Escape,
+    /// Anything else, including escape sequences not listed here.
+    Ignored,
+}
+
+/// The line being edited: characters and a cursor between them.
+#[derive(Default, Clone)]
+struct Line {
+    chars: Vec<char>,
+    cursor: usize,
+}
+
+pub struct LineEditor {
+    /// Earlier prompts, oldest first.
+    history: Vec<String>,
+    /// Command names, for completion.
+    commands: Vec<&'static str>,
+    /// Keys typed before the prompt appeared, read first.
+    typed_ahead: Vec<u8>,
+    /// When the next read gives up, if it is at a terminal.
+    deadline: Option<Instant>,
+    /// What was typed when a read gave up.
+    abandoned: Option<String>,
+    /// Text the next line starts with, for the user to edit.
+    prefill: Option<String>,
+    /// Where the line being typed is kept as it changes.
+    unsent_file: Option<PathBuf>,
+    /// What was last written there.
+    saved_unsent: String,
+    mode: Mode,
+}
+
+/// Which key bindings edit the line.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Mode {
+    /// The readline keys.
+    #[default]
+    Emacs,
+    /// vi's insert and normal modes.
+    Vi,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "camelCase")]
+pub struct Settings {
+    /// The key bindings sessions start with.
+    pub mode: Mode,
+}
+
+impl Mode {
+    /// The name `/keys` and the config file use.
+    pub fn name(self) -> &'static str {
+        match self {
+            Mode::Emacs => "emacs",
+            Mode::Vi => "vi",
+        }
+    }
+}
+
+/// Terminal input, read a tenth of a second at a time when `polling`.
+struct Timed<R> {
+    input: R,
+    /// Whether the terminal is set so reads return empty after a tenth
+    /// of a second with nothing typed.
+    polling: bool,
+    deadline: Option<Instant>,
+    /// Whether the last byte read was an Escape.
+    after_escape: bool,
+}
+
+impl<R: Read> Read for Timed<R> {
+    /// Without polling, reads as `input` does. With it, an empty read
+    /// waits on, except that it is `TimedOut` once the deadline passes,
+    /// and `WouldBlock` right after an Escape: nothing followed it, so it
+    /// was the Escape key rather than the start of a sequence.
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let read = self.input.read(buf)?;
+            if read > 0 || !self.polling {
+                self.after_escape = read > 0 && buf[read - 1] == 0x1b;
+                return Ok(read);
+            }
+            if self
+                .deadline
+                .is_some_and(|deadline| Instant::now() >= deadline)
+            {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "no line entered in time",
+                ));
+            }
+            if std::mem::take(&mut self.after_escape) {
+                return Err(io::ErrorKind::WouldBlock.into());
+            }
+        }
+    }
+}
+
+/// How editing a line ended.
+enum Ending {
+    Enter(String),
+    /// Ctrl-X Ctrl-E, with the line so far.
+    Compose(String),
+    /// A paste of several lines, with the line around it.
+    Paste(String),
+    EndOfInput,
+}
+
+/// A key pressed while a turn is in flight.
+#[derive(Debug, PartialEq)]
+pub enum Press {
+    Interrupt,
+    EndOfInput,
+}
+
+/// Reads the terminal while the REPL waits for the agent.
+pub struct KeyWatch {
+    presses: Receiver<Press>,
+    stop: Sender<()>,
+    /// Returns every other byte read.
+    reader: JoinHandle<Vec<u8>>,
+    /// False after the terminal reports losing focus, until it reports
+    /// getting it back.
+    focused: Arc<AtomicBool>,
+    reporting_focus: bool,
+}
+
+/// What a terminal sends on gaining and losing focus, once asked to.
+const FOCUS_IN: &[u8] = b"\x1b[I";
+const FOCUS_OUT: &[u8] = b"\x1b[O";
+
+/// Restores the terminal settings when dropped, even on a panic.
+struct RawMode {
+    saved: String,
+}
+
+impl LineEditor {
+    pub fn new(commands: Vec<&'static str>) -> LineEditor {
+        LineEditor {
+            history: Vec::new(),
+            commands,
+            typed_ahead: Vec::new(),
+            deadline: None,
+            abandoned: None,
+            prefill: None,
+            unsent_file: None,
+            saved_unsent: String::new(),
+            mode: Mode::Emacs,
+        }
+    }
+
+    /// Start the next terminal line with `text`, cursor at the end.
+    pub fn prefill(&mut self, text: String) {
+        self.prefill = Some(text);
+    }
+
+    /// Keep the line being typed in `path` (a `draft.rs` file) from now
+    /// on, or nowhere. The file is removed when the line is entered.
+    pub fn keep_unsent_in(&mut self, path: Option<PathBuf>) {
+        self.unsent_file = path;
+        self.saved_unsent.clear();
+    }
+
+    /// The file `keep_unsent_in` set.
+    pub fn unsent_file(&self) -> Option<&Path> {
+        self.unsent_file.as_deref()
+    }
+
+    /// Write `text` to the unsent-line file if it changed, or remove the
+    /// file when the line is empty. A failure is reported once and stops
+    /// the saving, rather than interrupting every key.
+    fn save_unsent(&mut self, text: &str) {
+        let Some(path) = &self.unsent_file else {
+            return;
+        };
+        if text == self.saved_unsent {
+            return;
+        }
+        let saved = if text.trim().is_empty() {
+            draft::clear(path)
+        } else {
+            draft::save(path, text)
+        };
+        match saved {
+            Ok(()) => self.saved_unsent = text.to_string(),
+            Err(e) => {
+                eprint!(
+                    "\r\n{} cannot save the unsent line to {}: {}\r\n",
+                    style::warning("warning:"),
+                    path.display(),
+                    e
+                );
+                self.unsent_file = None;
+            }
+        }
+    }
+
+    /// Use these key bindings from the next line on.
+    pub fn set_mode(&mut self, mode: Mode) {
+        self.mode = mode;
+    }
+
+    /// Whether terminal reads should return every tenth of a second: to
+    /// check the deadline, or for vi mode to tell Escape on its own.
+    fn polling(&self) -> bool {
+        self.deadline.is_some() || self.mode == Mode::Vi
+    }
+
+    /// Remember a prompt for Up/Down and Ctrl-R. Repeats of the last one
+    /// are skipped.
+    pub fn add_history(&mut self, line: &str) {
+        if !line.is_empty() && self.history.last().map(String::as_str) != Some(line) {
+            self.history.push(line.to_string());
+        }
+    }
+
+    /// Keys to treat as typed at the start of the next line.
+    pub fn type_ahead(&mut self, bytes: Vec<u8>) {
+        self.typed_ahead.extend(bytes);
+    }
+
+    /// Make reads at a terminal give up at `deadline` with a `TimedOut`
+    /// error, until set again; `None` waits as long as it takes.
+    pub fn give_up_at(&mut self, deadline: Option<Instant>) {
+        self.deadline = deadline;
+    }
+
+    /// The line being typed when a read last gave up, if any was.
+    pub fn take_abandoned(&mut self) -> Option<String> {
+        self.abandoned.take().filter(|line| !line.trim().is_empty())
+    }
+
+    /// Print `prompt` on stderr and read one line, without its line
+    /// ending. `Ok(None)` means end of input.
+    pub fn read_line(&mut self, prompt: &str) -> io::Result<Option<String>> {
+        let line = self.read_line_once(prompt);
+        if let Ok(Some(_)) = &line {
+            self.save_unsent("");
+        }
+        line
+    }
+
+    fn read_line_once(&mut self, prompt: &str) -> io::Result<Option<String>> {
+        let terminal = io::stdin().is_terminal() && io::stderr().is_terminal();
+        if terminal && self.mode == Mode::Vi {
+            eprint!("{}{}", INSERT_INDICATOR, prompt);
+        } else {
+            eprint!("{}", prompt);
+        }
+        io::stderr().flush().ok();
+        let raw = match (terminal, self.polling()) {
+            (false, _) => None,
+            // Reads return after a tenth of a second, to check the time or
+            // tell Escape from the start of a sequence.
+            (true, true) => RawMode::enter("0", "1"),
+            (true, false) => RawMode::enter("1", "0"),
+        };
+        let Some(raw) = raw else {
+            let mut line = String::new();
+            if io::stdin().lock().read_line(&mut line)? == 0 {
+                return Ok(None);
+            }
+            return Ok(Some(line.trim_end_matches(['\n', '\r']).to_string()));
+        };
+        match self.edit(prompt, terminal_size().1)? {
+            Ending::Enter(line) => Ok(Some(line)),
+            Ending::EndOfInput => Ok(None),
+            Ending::Compose(draft) => {
+                drop(raw);
+                match compose::compose(&draft) {
+                    Ok(Some(text)) => {
+                        eprintln!("{}{}", prompt, text);
+                        Ok(Some(text))
+                    }
+                    Ok(None) => {
+                        eprintln!("Nothing to send (the file was left empty).");
+                        self.read_line(prompt)
+                    }
+                    Err(e) => {
+                        eprintln!("{} {}", style::error("error:"), e);
+                        self.read_line(prompt)
+                    }
+                }
+            }
+            Ending::Paste(text) => {
+                drop(raw);
+                let lines = text.lines().count();
+                if lines <= CONFIRM_PASTE_LINES {
+                    eprintln!("{}{}", prompt, text);
+                    return Ok(Some(text));
+                }
+                eprintln!("{}{}", prompt, preview(&text, 5, 3));
+                let question = format!(
+                    "Send this paste ({} lines, {} characters) as one prompt, or edit it first?",
+                    lines,
+                    text.chars().count()
+                );
+                match terminal::choose(&question, "ye") {
+                    Some('y') => Ok(Some(text)),
+                    Some(_) => match compose::compose(&text) {
+                        Ok(Some(text)) => Ok(Some(text)),
+                        Ok(None) => {
+                            eprintln!("Nothing to send (the file was left empty).");
+                            self.read_line(prompt)
+                        }
+                        Err(e) => {
+                            eprintln!("{} {}", style::error("error:"), e);
+                            self.read_line(prompt)
+                        }
+                    },
+                    None => {
+                        eprintln!("Nothing sent.");
+                        self.read_line(prompt)
+                    }
+                }
+            }
+        }
+    }
+
+    fn edit(&mut self, prompt: &str, columns: usize) -> io::Result<Ending> {
+        let mut line = match self.prefill.take() {
+            Some(text) => Line {
+                cursor: text.chars().count(),
+                chars: text.chars().collect(),
+            },
+            None => Line::default(),
+        };
+        // Position in history while browsing; `history.len()` is the
+        // line being typed, kept in `draft` while away from it.
+        let mut position = self.history.len();
+        let mut draft = String::new();
+        let typed_ahead = std::mem::take(&mut self.typed_ahead);
+        let mut stdin = io::Cursor::new(typed_ahead).chain(Timed {
+            input: io::stdin().lock(),
+            polling: self.polling(),
+            deadline: self.deadline,
+            after_escape: false,
+        });
+        let mut vi = (self.mode == Mode::Vi).then(Vi::default);
+        if !line.chars.is_empty() {
+            let indicator = vi.as_ref().map_or("", |vi| vi.indicator());
+            render(&(indicator.to_string() + prompt), &line, columns);
+        }
+        // A key that ended a search, to act on as if typed next.
+        let mut pending = None;
+        loop {
+            let shown = match &vi {
+                Some(vi) => vi.indicator().to_string() + prompt,
+                None => prompt.to_string(),
+            };
+            let key = match pending.take() {
+                Some(key) => key,
+                None => match read_key(&mut stdin) {
+                    Ok(Some(key)) => key,
+                    Ok(None) => {
+                        eprintln!();
+                        return Ok(Ending::EndOfInput);
+                    }
+                    Err(e) => {
+                        if e.kind() == io::ErrorKind::TimedOut {
+                            self.abandoned = Some(line.chars.iter().collect());
+                            eprint!("\r\n");
+                        }
+                        return Err(e);
+                    }
+                },
+            };
+            let key = match &mut vi {
+                Some(vi) => match vi.key(key, &mut line) {
+                    Some(key) => key,
+                    None => {
+                        render(&(vi.indicator().to_string() + prompt), &line, columns);
+                        self.save_unsent(&line.chars.iter().collect::<String>());
+                        continue;
+                    }
+                },
+                None => key,
+            };
+            match key {
+                Key::Enter => {
+                    line.cursor = line.chars.len();
+                    render(&shown, &line, columns);
+                    eprint!("\r\n");
+                    return Ok(Ending::Enter(line.chars.iter().collect()));
+                }
+                Key::Compose => {
+                    eprint!("\r\n");
+                    return Ok(Ending::Compose(line.chars.iter().collect()));
+                }
+                Key::Paste(text) if text.contains('\n') => {
+                    let before: String = line.chars[..line.cursor].iter().collect();
+                    let after: String = line.chars[line.cursor..].iter().collect();
+                    eprint!("\r\x1b[K");
+                    let pasted = before + &text + &after;
+                    self.save_unsent(&pasted);
+                    return Ok(Ending::Paste(pasted));
+                }
+                Key::Interrupt | Key::EndOfInput if line.chars.is_empty() => {
+                    eprint!("\r\n");
+                    return Ok(Ending::EndOfInput);
+                }
+                Key::Interrupt => {
+                    line = Line::default();
+                    position = self.history.len();
+                }
+                Key::Up | Key::Down => {
+                    let next = match key {
+                        Key::Up => position.checked_sub(1),
+                        _ => (position < self.history.len()).then_some(position + 1),
+                    };
+                    let Some(next) = next else { continue };
+                    if position == self.history.len() {
+                        draft = line.chars.iter().collect();
+                    }
+                    position = next;
+                    let text = self.history.get(position).unwrap_or(&draft);
+                    line = Line {
+                        chars: text.chars().collect(),
+                        cursor: text.chars().count(),
+                    };
+                }
+                Key::Search => {
+                    let (found, next) = self.search(&mut stdin, &line, columns)?;
+                    line = found;
+                    position = self.history.len();
+                    pending = next;
+                }
+                Key::Redraw => eprint!("\x1b[H\x1b[2J"),
+                Key::Tab => self.complete(&mut line),
+                key => line.apply(key),
+            }
+            if let Some(vi) = &vi {
+                vi.settle(&mut line);
+            }
+            render(&shown, &line, columns);
+            self.save_unsent(&line.chars.iter().collect::<String>());
+        }
+    }
+}
+
+impl LineEditor {
+    /// Complete the word before the cursor: fully when one candidate
+    /// fits, else as far as they agree, else by listing them.
+    fn complete(&self, line: &mut Line) {
+        let before: String = line.chars[..line.cursor].iter().collect();
+        let (start, found) = complete::candidates(&before, &self.commands);
+        let replacement = match found.as_slice() {
+            [] => return,
+            [only] if only.ends_with('/') => only.clone(),
+            [only] => format!("{} ", only),
+            _ => complete::common_prefix(&found),
+        };
+        if replacement.chars().count() > line.cursor - start {
+            line.chars.splice(start..line.cursor, replacement.chars());
+            line.cursor = start + replacement.chars().count();
+            return;
+        }
+        let shown: Vec<&str> = found.iter().take(MAX_LISTED).map(String::as_str).collect();
+        eprint!("\r\n{}", shown.join("  "));
+        if found.len() > MAX_LISTED {
+            eprint!("  ... ({} more)", found.len() - MAX_LISTED);
+        }
+        eprint!("\r\n");
+    }
+}
+
+impl LineEditor {
+    /// Ctrl-R: look back through the history for what is typed, as
+    /// readline does. Ctrl-R again goes to the next older match; Ctrl-G
+    /// or Ctrl-C gives the line back as it was. Any other key keeps the
+    /// match as the line and is returned, to act on as usual.
+    fn search(
+        &self,
+        input: &mut impl Read,
+        original: &Line,
+        columns: usize,
+    ) -> io::Result<(Line, Option<Key>)> {
+        let mut query = String::new();
+        let mut found: Option<usize> = None;
+        loop {
+            let shown = match found {
+                Some(index) => Line::at_match(&self.history[index], &query),
+                None => Line::default(),
+            };
+            let failed = if found.is_none() && !query.is_empty() {
+                "failed "
+            } else {
+                ""
+            };
+            render(&format!("({}search: {}) ", failed, query), &shown, columns);
+            let Some(key) = read_key(input)? else {
+                return Ok((shown, None));
+            };
+            match key {
+                Key::Char(c) => {
+                    query.push(c);
+                    found = find_back(
+                        &self.history,
+                        &query,
+                        found.map_or(self.history.len(), |i| i + 1),
+                    );
+                }
+                Key::Backspace => {
+                    query.pop();
+                    found = find_back(&self.history, &query, self.history.len());
+                }
+                Key::Search => {
+                    let before = found.unwrap_or(self.history.len());
+                    found = find_back(&self.history, &query, before).or(found);
+                }
+                Key::Cancel | Key::Interrupt => return Ok((original.clone(), None)),
+                key => {
+                    // Keep a match as the line; with none, the line as it was.
+                    let line = match found {
+                        Some(_) => shown,
+                        None => original.clone(),
+                    };
+                    return Ok((line, Some(key)));
+                }
+            }
+        }
+    }
+}
+
+/// The latest entry before `before` containing `query`.
+fn find_back(history: &[String], query: &str, before: usize) -> Option<usize> {
+    if query.is_empty() {
+        return None;
+    }
+    history[..before]
+        .iter()
+        .rposition(|entry| entry.contains(query))
+}
+
+impl Line {
+    /// `text` with the cursor at the start of `query` in it.
+    fn at_match(text: &str, query: &str) -> Line {
+        let cursor = text.find(query).map_or(0, |at| text[..at].chars().count());
+        Line {
+            chars: text.chars().collect(),
+            cursor,
+        }
+    }
+
+    fn apply(&mut self, key: Key) {
+        match key {
+            Key::Char(c) => {
+                self.chars.insert(self.cursor, c);
+                self.cursor += 1;
+            }
+            Key::Paste(text) => {
+                for c in text.chars() {
+                    self.apply(Key::Char(c));
+                }
+            }
+            Key::Backspace if self.cursor > 0 => {
+                self.cursor -= 1;
+                self.chars.remove(self.cursor);
+            }
+            Key::Delete | Key::EndOfInput if self.cursor < self.chars.len() => {
+                self.chars.remove(self.cursor);
+            }
+            Key::Left => self.cursor = self.cursor.saturating_sub(1),
+            Key::Right => self.cursor = (self.cursor + 1).min(self.chars.len()),
+            Key::WordLeft => self.cursor = self.word_start(),
+            Key::WordRight => self.cursor = self.word_end(),
+            Key::Home => self.cursor = 0,
+            Key::End => self.cursor = self.chars.len(),
+            Key::KillToStart => {
+                self.chars.drain(..self.cursor);
+                self.cursor = 0;
+            }
+            Key::KillToEnd => self.chars.truncate(self.cursor),
+            Key::KillWord => {
+                let start = self.word_start();
+                self.chars.drain(start..self.cursor);
+                self.cursor = start;
+            }
+            _ => {}
+        }
+    }
+
+    /// Start of the word before the cursor (skipping spaces first).
+    fn word_start(&self) -> usize {
+        let mut i = self.cursor;
+        while i > 0 && self.chars[i - 1].is_whitespace() {
+            i -= 1;
+        }
+        while i > 0 && !self.chars[i - 1].is_whitespace() {
+            i -= 1;
+        }
+        i
+    }
+
+    /// Start of the next word, for vi's `w`.
+    fn next_word(&self) -> usize {
+        let mut i = self.cursor;
+        while i < self.chars.len() && !self.chars[i].is_whitespace() {
+            i += 1;
+        }
+        while i < self.chars.len() && self.chars[i].is_whitespace() {
+            i += 1;
+        }
+        i
+    }
+
+    /// The last character of this word or the next, for vi's `e`.
+    fn end_of_word(&self) -> usize {
+        let mut i = self.cursor + 1;
+        while i < self.chars.len() && self.chars[i].is_whitespace() {
+            i += 1;
+        }
+        while i + 1 < self.chars.len() && !self.chars[i + 1].is_whitespace() {
+            i += 1;
+        }
+        i.min(self.chars.len().saturating_sub(1))
+    }
+
+    /// End of the word after the cursor (skipping spaces first).
+    fn word_end(&self) -> usize {
+        let mut i = self.cursor;
+        while i < self.chars.len() && self.chars[i].is_whitespace() {
+            i += 1;
+        }
+        while i < self.chars.len() && !self.chars[i].is_whitespace() {
+            i += 1;
+        }
+        i
+    }
+}
+
+/// vi bindings, and what they remember between keys.
+///
+/// Insert mode edits as the readline keys do, and Escape leaves it.
+/// Normal mode has the common commands: `h l 0 ^ $ w b e` move, `i a I A`
+/// insert, `x X D C S s` and `r<char>` change, `d` or `c` with one of those
+/// motions (or doubled, for the whole line) delete or change, `p P` put
+/// back what was last deleted, `u` undoes the last change, `k j` step
+/// through earlier prompts, and `/` searches them. Words are separated by
+/// spaces, and there are no counts.
+#[derive(Default)]
+struct Vi {
+    normal: bool,
+    /// `d`, `c`, or `r`, waiting for the key it applies to.
+    pending: Option<char>,
+    /// What the last delete removed, for `p` and `P`.
+    register: Vec<char>,
+    /// The line before the last change, for `u`.
+    undo: Option<Line>,
+}
+
+impl Vi {
+    fn indicator(&self) -> &'static str {
+        if self.normal {
+            NORMAL_INDICATOR
+        } else {
+            INSERT_INDICATOR
+        }
+    }
+
+    /// Act on `key`: the key for the editor to handle as usual, or `None`
+    /// if it was a vi command and is done.
+    fn key(&mut self, key: Key, line: &mut Line) -> Option<Key> {
+        if !self.normal {
+            if key != Key::Escape {
+                return Some(key);
+            }
+            self.normal = true;
+            line.cursor = line.cursor.saturating_sub(1);
+            return None;
+        }
+        let c = match key {
+            Key::Char(c) => c,
+            key => {
+                self.pending = None;
+                return match key {
+                    Key::Escape => None,
+                    Key::Backspace => Some(Key::Left),
+                    key => Some(key),
+                };
+            }
+        };
+        let len = line.chars.len();
+        if let Some(operator) = self.pending.take() {
+            self.operate(operator, c, line);
+        } else {
+            match c {
+                'h' => line.apply(Key::Left),
+                'l' => line.cursor += 1,
+                '0' => line.cursor = 0,
+                '^' => line.cursor = line.chars.iter().take_while(|c| c.is_whitespace()).count(),
+                '$' => line.cursor = len,
+                'w' => line.cursor = line.next_word(),
+                'b' => line.cursor = line.word_start(),
+                'e' => line.cursor = line.end_of_word(),
+                'i' | 'a' | 'I' | 'A' => {
+                    line.cursor = match c {
+                        'i' => line.cursor,
+                        'a' => (line.cursor + 1).min(len),
+                        'I' => 0,
+                        _ => len,
+                    };
+                    self.undo = Some(line.clone());
+                    self.normal = false;
+                }
+                'x' => self.cut(line, line.cursor, (line.cursor + 1).min(len)),
+                'X' => self.cut(line, line.cursor.saturating_sub(1), line.cursor),
+                's' => {
+                    self.cut(line, line.cursor, (line.cursor + 1).min(len));
+                    self.normal = false;
+                }
+                'D' => self.operate('d', '$', line),
+                'C' => self.operate('c', '$', line),
+                'S' => self.operate('c', 'c', line),
+                'p' | 'P' if !self.register.is_empty() => {
+                    self.undo = Some(line.clone());
+                    let at = if c == 'p' && len > 0 {
+                        line.cursor + 1
+                    } else {
+                        line.cursor
+                    };
+                    line.chars.splice(at..at, self.register.iter().copied());
+                    line.cursor = at + self.register.len() - 1;
+                }
+                'u' => {
+                    if let Some(before) = self.undo.take() {
+                        self.undo = Some(std::mem::replace(line, before));
+                    }
+                }
+                'k' => return Some(Key::Up),
+                'j' => return Some(Key::Down),
+                '/' => return Some(Key::Search),
+                'd' | 'c' | 'r' => self.pending = Some(c),
+                _ => {}
+            }
+        }
+        self.settle(line);
+        None
+    }
+
+    /// `d` or `c` over `motion`, or `r` with its replacement.
+    fn operate(&mut self, operator: char, motion: char, line: &mut Line) {
+        let (cursor, len) = (line.cursor, line.chars.len());
+        if operator == 'r' {
+            if cursor < len {
+                self.undo = Some(line.clone());
+                line.chars[cursor] = motion;
+            }
+            return;
+        }
+        let (start, end) = match motion {
+            m if m == operator => (0, len),
+            'h' => (cursor.saturating_sub(1), cursor),
+            'l' => (cursor, (cursor + 1).min(len)),
+            '0' => (0, cursor),
+            '$' => (cursor, len),
+            'b' => (line.word_start(), cursor),
+            // As in vim, `cw` changes to the end of the word.
+            'w' if operator == 'd' => (cursor, line.next_word()),
+            'w' | 'e' => (cursor, (line.end_of_word() + 1).min(len)),
+            _ => return,
+        };
+        self.cut(line, start, end);
+        if operator == 'c' {
+            self.normal = false;
+        }
+    }
+
+    /// Delete `start..end` into the register.
+    fn cut(&mut self, line: &mut Line, start: usize, end: usize) {
+        if start >= end {
+            return;
+        }
+        self.undo = Some(line.clone());
+        self.register = line.chars.drain(start..end).collect();
+        line.cursor = start;
+    }
+
+    /// In normal mode the cursor is on a character, not past the last.
+    fn settle(&self, line: &mut Line) {
+        if self.normal {
+            line.cursor = line.cursor.min(line.chars.len().saturating_sub(1));
+        }
+    }
+}
+
+/// Redraw the prompt line in place, scrolled so the cursor is visible.
+fn render(prompt: &str, line: &Line, columns: usize) {
+    let room = columns.saturating_sub(prompt.chars().count() + 1).max(1);
+    let (start, end) = window(&line.chars, line.cursor, room);
+    let shown: String = line.chars[start..end].iter().collect();
+    let before: usize = line.chars[start..line.cursor]
+        .iter()
+        .map(|&c| width(c))
+        .sum();
+    let mut out = format!("\r{}{}\x1b[K\r", prompt, shown);
+    let column = prompt.chars().count() + before;
+    if column > 0 {
+        out.push_str(&format!("\x1b[{}C", column));
+    }
+    eprint!("{}", out);
+    io::stderr().flush().ok();
+}
+
+/// The range of `chars` to show in `room` columns: as much as fits
+/// from the start, or else a span ending just after the cursor.
+fn window(chars: &[char], cursor: usize, room: usize) -> (usize, usize) {
+    let fits = |range: &[char]| range.iter().map(|&c| width(c)).sum::<usize>() < room;
+    if fits(&chars[..cursor]) {
+        let mut end = cursor;
+        while end < chars.len() && fits(&chars[..=end]) {
+            end += 1;
+        }
+        return (0, end);
+    }
+    let mut start = cursor;
+    while start > 0 && fits(&chars[start - 1..cursor]) {
+        start -= 1;
+    }
+    (start, cursor)
+}
+
+/// Columns a character takes: 2 for the wide East Asian ranges and
+/// emoji, 1 otherwise.
+pub fn width(c: char) -> usize {
+    match c as u32 {
+        0x1100..=0x115F
+        | 0x2E80..=0xA4CF
+        | 0xAC00..=0xD7A3
+        | 0xF900..=0xFAFF
+        | 0xFE30..=0xFE4F
+        | 0xFF00..=0xFF60
+        | 0xFFE0..=0xFFE6
+        | 0x1F300..=0x1F64F
+        | 0x1F900..=0x1F9FF
+        | 0x20000..=0x3FFFD => 2,
+        _ => 1,
+    }
+}
+
+/// Read one key. `Ok(None)` means the input has closed.
+fn read_key(input: &mut impl Read) -> io::Result<Option<Key>> {
+    let Some(byte) = read_byte(input)? else {
+        return Ok(None);
+    };
+    let key = match byte {
+        b'\r' | b'\n' => Key::Enter,
+        0x7f | 0x08 => Key::Backspace,
+        0x01 => Key::Home,
+        0x02 => Key::Left,
+        0x03 => Key::Interrupt,
+        0x04 => Key::EndOfInput,
+        0x05 => Key::End,
+        0x06 => Key::Right,
+        0x07 => Key::Cancel,
+        b'\t' => Key::Tab,
+        0x0b => Key::KillToEnd,
+        0x0c => Key::Redraw,
+        0x0e => Key::Down,
+        0x10 => Key::Up,
+        0x12 => Key::Search,
+        0x15 => Key::KillToStart,
+        0x17 => Key::KillWord,
+        0x18 => match read_byte(input)? {
+            Some(0x05) => Key::Compose,
+            _ => Key::Ignored,
+        },
+        0x1b => match read_escape(input) {
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => Key::Escape,
+            key => key?,
+        },
+        b if b < 0x20 => Key::Ignored,
+        b => read_char(input, b)?,
+    };
+    Ok(Some(key))
+}
+
+/// The rest of an escape sequence: `ESC [ ...`, `ESC O ...`, or Alt+key.
+fn read_escape(input: &mut impl Read) -> io::Result<Key> {
+    let key = match read_byte(input)? {
+        Some(b'[') => {
+            // Parameters, then a final byte in @..~.
+            let mut params = String::new();
+            loop {
+                match read_byte(input)? {
+                    Some(b'~') if params == PASTE_START => break read_paste(input)?,
+                    Some(b) if (0x40..=0x7e).contains(&b) => break csi_key(&params, b),
+                    Some(b) => params.push(b as char),
+                    None => break Key::Ignored,
+                }
+            }
+        }
+        Some(b'O') => match read_byte(input)? {
+            Some(b'H') => Key::Home,
+            Some(b'F') => Key::End,
+            _ => Key::Ignored,
+        },
+        Some(b'b') => Key::WordLeft,
+        Some(b'f') => Key::WordRight,
+        Some(0x7f) => Key::KillWord,
+        _ => Key::Ignored,
+    };
+    Ok(key)
+}
+
+/// Pasted text up to the end marker, with trailing line breaks dropped.
+fn read_paste(input: &mut impl Read) -> io::Result<Key> {
+    let mut bytes = Vec::new();
+    while !bytes.ends_with(PASTE_END) {
+        match read_byte(input)? {
+            Some(b) => bytes.push(b),
+            None => break,
+        }
+    }
+    if bytes.ends_with(PASTE_END) {
+        bytes.truncate(bytes.len() - PASTE_END.len());
+    }
+    let text = String::from_utf8_lossy(&bytes)
+        .replace("\r\n", "\n")
+        .replace('\r', "\n");
+    Ok(Key::Paste(text.trim_end_matches('\n').to_string()))
+}
+
+/// The first `head` and last `tail` lines of `text`, with a note of how
+/// many are left out between them.
+fn preview(text: &str, head: usize, tail: usize) -> String {
+    let lines: Vec<&str> = text.lines().collect();
+    if lines.len() <= head + tail {
+        return text.to_string();
+    }
+    format!(
+        "{}\n... ({} more lines) ...\n{}",
+        lines[..head].join("\n"),
+        lines.len() - head - tail,
+        lines[lines.len() - tail..].join("\n")
+    )
+}
+
+fn csi_key(params: &str, last: u8) -> Key {
+    let modified = params.ends_with(";5") || params.ends_with(";3");
+    match (params, last) {
+        (_, b'A') => Key::Up,
+        (_, b'B') => Key::Down,
+        (_, b'C') if modified => Key::WordRight,
+        (_, b'D') if modified => Key::WordLeft,
+        (_, b'C') => Key::Right,
+        (_, b'D') => Key::Left,
+        (_, b'H') | ("1" | "7", b'~') => Key::Home,
+        (_, b'F') | ("4" | "8", b'~') => Key::End,
+        ("3", b'~') => Key::Delete,
+        _ => Key::Ignored,
+    }
+}
+
+/// A UTF-8 character starting with `first`; invalid input is ignored.
+fn read_char(input: &mut impl Read, first: u8) -> io::Result<Key> {
+    let len = match first {
+        0xc0..=0xdf => 2,
+        0xe0..=0xef => 3,
+        0xf0..=0xf7 => 4,
+        0x80..=0xff => return Ok(Key::Ignored),
+        _ => 1,
+    };
+    let mut bytes = vec![first];
+    for _ in 1..len {
+        match read_byte(input)? {
+            Some(b) => bytes.push(b),
+            None => return Ok(Key::Ignored),
+        }
+    }
+    Ok(match std::str::from_utf8(&bytes) {
+        Ok(text) => text.chars().next().map_or(Key::Ignored, Key::Char),
+        Err(_) => Key::Ignored,
+    })
+}
+
+fn read_byte(input: &mut impl Read) -> io::Result<Option<u8>> {
+    let mut byte = [0u8];
+    loop {
+        match input.read(&mut byte) {
+            Ok(0) => return Ok(None),
+            Ok(_) => return Ok(Some(byte[0])),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Terminal rows and columns from `stty size`, or 24 by 80 for any that
+/// is unknown (`0`).
+pub fn terminal_size() -> (usize, usize) {
+    let size = stty(&["size"]).unwrap_or_default();
+    let mut numbers = size
+        .split_whitespace()
+        .map(|n| n.parse::<usize>().ok().filter(|&n| n > 0));
+    let rows = numbers.next().flatten().unwrap_or(24);
+    let columns = numbers.next().flatten().unwrap_or(80);
+    (rows, columns)
+}
+
+/// Run `stty` on the terminal on stdin; its output on success.
+fn stty(args: &[&str]) -> Option<String> {
+    let output = Command::new("stty")
+        .args(args)
+        .stdin(Stdio::inherit())
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+impl RawMode {
+    /// Turn off line buffering, echo, and signal keys, keeping output
+    /// processing. A read waits for `min` bytes or `time` tenths of a
+    /// second, as in `stty`. `None` if `stty` is unavailable.
+    fn enter(min: &str, time: &str) -> Option<RawMode> {
+        let saved = stty(&["-g"])?;
+        stty(&[
+            "-icanon", "-echo", "-isig", "-ixon", "-iexten", "min", min, "time", time,
+        ])?;
+        eprint!("\x1b[?2004h");
+        io::stderr().flush().ok();
+        Some(RawMode { saved })
+    }
+}
+
+impl KeyWatch {
+    /// Start watching, if stdin and stderr are a terminal. With
+    /// `report_focus`, the terminal is asked to report focus changes
+    /// until `finish`.
+    pub fn start(report_focus: bool) -> Option<KeyWatch> {
+        if !io::stdin().is_terminal() || !io::stderr().is_terminal() {
+            return None;
+        }
+        // Reads give up after a tenth of a second, to notice `stop`.
+        let raw = RawMode::enter("0", "1")?;
+        if report_focus {
+            eprint!("\x1b[?1004h");
+            io::stderr().flush().ok();
+        }
+        let (presses_tx, presses) = mpsc::channel();
+        let (stop, stopped) = mpsc::channel();
+        let focused = Arc::new(AtomicBool::new(true));
+        let focus = Arc::clone(&focused);
+        let reader = thread::spawn(move || {
+            let _raw = raw;
+            let mut other = Vec::new();
+            let mut stdin = io::stdin().lock();
+            let mut byte = [0u8];
+            while let Err(TryRecvError::Empty) = stopped.try_recv() {
+                match stdin.read(&mut byte) {
+                    Ok(1) if byte[0] == 0x03 => drop(presses_tx.send(Press::Interrupt)),
+                    Ok(1) if byte[0] == 0x04 => drop(presses_tx.send(Press::EndOfInput)),
+                    Ok(1) => {
+                        other.push(byte[0]);
+                        if let Some(now_focused) = take_focus_report(&mut other) {
+                            focus.store(now_focused, Ordering::Relaxed);
+                        }
+                    }
+                    Ok(_) => {}
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                    Err(_) => break,
+                }
+            }
+            other
+        });
+        Some(KeyWatch {
+            presses,
+            stop,
+            reader,
+            focused,
+            reporting_focus: report_focus,
+        })
+    }
+
+    /// Whether the terminal reported losing focus and has not reported
+    /// getting it back. Always false without focus reports.
+    pub fn lost_focus(&self) -> bool {
+        !self.focused.load(Ordering::Relaxed)
+    }
+
+    /// The next Ctrl-C or Ctrl-D pressed, if any.
+    pub fn pressed(&self) -> Option<Press> {
+        self.presses.try_recv().ok()
+    }
+
+    /// Stop watching and restore the terminal. Returns what else was
+    /// typed, for `LineEditor::type_ahead`.
+    pub fn finish(self) -> Vec<u8> {
+        if self.reporting_focus {
+            eprint!("\x1b[?1004l");
+            io::stderr().flush().ok();
+        }
+        let _ = self.stop.send(());
+        self.reader.join().unwrap_or_default()
+    }
+}
+
+/// If `bytes` ends with a focus report, remove it and say which.
+fn take_focus_report(bytes: &mut Vec<u8>) -> Option<bool> {
+    let focused = if bytes.ends_with(FOCUS_IN) {
+        true
+    } else if bytes.ends_with(FOCUS_OUT) {
+        false
+    } else {
+        return None;
+    };
+    bytes.truncate(bytes.len() - FOCUS_IN.len());
+    Some(focused)
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        eprint!("\x1b[?2004l");
+        io::stderr().flush().ok();
+        let _ = stty(&[&self.saved]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn keys(bytes: &[u8]) -> Vec<Key> {
+        let mut input = bytes;
+        let mut out = Vec::new();
+        while let Some(key) = read_key(&mut input).unwrap() {
+            out.push(key);
+        }
+        out
+    }
+
+    fn typed(bytes: &[u8]) -> String {
+        let mut line = Line::default();
+        for key in keys(bytes) {
+            line.apply(key);
+        }
+        line.chars.iter().collect()
+    }
+
+    #[test]
+    fn decodes_keys_and_escape_sequences() {
+        assert_eq!(
+            keys(b"a\x1b[D\x1b[1;5C\x1b[3~\x1bb\x1bOH\x7f\r"),
+            vec![
+                Key::Char('a'),
+                Key::Left,
+                Key::WordRight,
+                Key::Delete,
+                Key::WordLeft,
+                Key::Home,
+                Key::Backspace,
+                Key::Enter,
+            ]
+        );
+        assert_eq!(keys("é".as_bytes()), vec![Key::Char('é')]);
+        assert_eq!(keys(b"\x18\x05\x18x"), vec![Key::Compose, Key::Ignored]);
+    }
+
+    #[test]
+    fn edits_in_the_middle_of_the_line() {
+        assert_eq!(typed(b"helo\x1b[Dl"), "hello");
+        assert_eq!(typed(b"one two three\x17"), "one two ");
+        assert_eq!(typed(b"one two\x01\x1b[3~"), "ne two");
+        assert_eq!(typed(b"keep cut\x1bb\x0b"), "keep ");
+        assert_eq!(typed(b"cut keep\x1bb\x15"), "keep");
+    }
+
+    #[test]
+    fn a_paste_arrives_as_one_key() {
+        assert_eq!(
+            keys(b"a\x1b[200~one\r\ntwo\x1b[D\r\n\x1b[201~b"),
+            vec![
+                Key::Char('a'),
+                Key::Paste("one\ntwo\x1b[D".to_string()),
+                Key::Char('b'),
+            ]
+        );
+        assert_eq!(typed(b"ab\x1b[D\x1b[200~xy\x1b[201~"), "axyb");
+        let text: Vec<String> = (1..=10).map(|n| n.to_string()).collect();
+        assert_eq!(
+            preview(&text.join("\n"), 2, 1),
+            "1\n2\n... (7 more lines) ...\n10"
+        );
+        assert_eq!(preview("1\n2", 2, 1), "1\n2");
+    }
+
+    #[test]
+    fn searches_back_from_the_latest() {
+        let history: Vec<String> = ["git status", "ls", "git log", "pwd"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        assert_eq!(find_back(&history, "git", history.len()), Some(2));
+        assert_eq!(find_back(&history, "git", 2), Some(0));
+        assert_eq!(find_back(&history, "git", 0), None);
+        assert_eq!(find_back(&history, "", history.len()), None);
+        assert_eq!(keys(b"\x12\x07"), vec![Key::Search, Key::Cancel]);
+        assert_eq!(Line::at_match("git log", "log").cursor, 4);
+    }
+
+    #[test]
+    fn focus_reports_are_not_typed_ahead() {
+        let mut bytes = b"ab\x1b[O".to_vec();
+        assert_eq!(take_focus_report(&mut bytes), Some(false));
+        assert_eq!(bytes, b"ab");
+        bytes.extend(b"\x1b[I");
+        assert_eq!(take_focus_report(&mut bytes), Some(true));
+        assert_eq!(take_focus_report(&mut bytes), None);
+        assert_eq!(bytes, b"ab");
+    }
+
+    fn vi_typed(text: &str) -> (String, bool) {
+        let mut vi = Vi::default();
+        let mut line = Line::default();
+        for c in text.chars() {
+            let key = if c == '\x1b' {
+                Key::Escape
+            } else {
+                Key::Char(c)
+            };
+            if let Some(key) = vi.key(key, &mut line) {
+                line.apply(key);
+            }
+        }
+        (line.chars.iter().collect(), vi.normal)
+    }
+
+    #[test]
+    fn vi_normal_mode_moves_and_changes() {
+        assert_eq!(vi_typed("hello world\x1b0dw").0, "world");
+        assert_eq!(
+            vi_typed("hello world\x1bbcwthere"),
+            ("hello there".to_string(), false)
+        );
+        assert_eq!(vi_typed("one two three\x1b0wD"), ("one ".to_string(), true));
+        assert_eq!(vi_typed("cat\x1brb").0, "cab");
+        assert_eq!(vi_typed("a b\x1bI> \x1bA!").0, "> a b!");
+        assert_eq!(vi_typed("abc\x1bxp").0, "abc");
+        assert_eq!(vi_typed("one two\x1bddu").0, "one two");
+    }
+
+    /// Reads that return these pieces in turn, an empty one standing for
+    /// a tenth of a second with nothing typed; empty ever after.
+    struct Pieces(Vec<&'static [u8]>);
+
+    impl Read for Pieces {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let Some(piece) = self.0.first_mut() else {
+                return Ok(0);
+            };
+            let n = piece.len().min(buf.len());
+            buf[..n].copy_from_slice(&piece[..n]);
+            *piece = &piece[n..];
+            if piece.is_empty() {
+                self.0.remove(0);
+            }
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn polling_tells_escape_from_a_sequence_and_gives_up_at_the_deadline() {
+        let mut input = Timed {
+            input: Pieces(vec![b"\x1b", b"", b"x\x1b[D"]),
+            polling: true,
+            deadline: Some(Instant::now() + std::time::Duration::from_secs(60)),
+            after_escape: false,
+        };
+        assert_eq!(read_key(&mut input).unwrap(), Some(Key::Escape));
+        assert_eq!(read_key(&mut input).unwrap(), Some(Key::Char('x')));
+        assert_eq!(read_key(&mut input).unwrap(), Some(Key::Left));
+        input.deadline = Some(Instant::now());
+        assert_eq!(
+            read_key(&mut input).unwrap_err().kind(),
+            io::ErrorKind::TimedOut
+        );
+    }
+
+    #[test]
+    fn scrolls_to_keep_the_cursor_visible() {
+        let chars: Vec<char> = "abcdefghij".chars().collect();
+        assert_eq!(window(&chars, 0, 5), (0, 4));
+        assert_eq!(window(&chars, 10, 5), (6, 10));
+        let wide: Vec<char> = "日本語です".chars().collect();
+        assert_eq!(window(&wide, 5, 5), (3, 5));
+    }
+
+    #[test]
+    fn keeps_the_unsent_line_until_it_is_empty() {
+        let dir =
+            std::env::temp_dir().join(format!("komatachi-unsent-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("unsent.json");
+        let mut editor = LineEditor::new(Vec::new());
+
+        editor.save_unsent("not kept anywhere");
+        assert!(!path.exists());
+        editor.keep_unsent_in(Some(path.clone()));
+        editor.save_unsent("a long\nprompt");
+        assert_eq!(draft::load(&path).unwrap().text, "a long\nprompt");
+        editor.save_unsent("   ");
+        assert!(!path.exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
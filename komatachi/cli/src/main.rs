@@ -3,231 +3,297 @@
 //! Interactive terminal that communicates with the Komatachi agent
 //! running inside a Docker container via JSON-lines over stdin/stdout.
 
-use serde::{Deserialize, Serialize};
-use std::io::{self, BufRead, BufReader, Write};
-use std::process::{Command, Stdio};
+mod agent;
+mod annotated;
+mod assets;
+mod backup;
+mod bookmarks;
+mod checkpoints;
+mod clipboard;
+mod complete;
+mod compose;
+mod config;
+mod conformance;
+mod diff;
+mod draft;
+mod export;
+mod faults;
+mod find;
+mod fork;
+mod fuzzy;
+mod grep;
+mod hash;
+mod help;
+mod history;
+mod idle;
+mod inspect;
+mod lang;
+mod limits;
+mod lineedit;
+mod notify;
+mod observe;
+mod options;
+mod pager;
+mod paths;
+mod prompt;
+mod protocol;
+mod ratings;
+mod references;
+mod regex;
+mod repl;
+mod review;
+mod routing;
+mod run;
+mod safe;
+mod scrub;
+mod sinks;
+mod snippets;
+mod speech;
+mod spinner;
+mod split;
+mod stream;
+mod style;
+mod substitute;
+mod terminal;
+mod time;
+mod timestamps;
+mod timings;
+mod transport;
+mod turn;
+mod validate;
+mod voice;
+mod watchdog;
+mod wrap;
 
-// ---------------------------------------------------------------------------
-// Protocol types
-// ---------------------------------------------------------------------------
+use agent::Agent;
+use options::Options;
+use paths::Paths;
+use timings::Timings;
+use validate::Validator;
 
-#[derive(Serialize)]
-struct InputMessage {
-    r#type: &'static str,
-    text: String,
-}
+const USAGE: &str = "\
+usage: komatachi-cli [options]
+       komatachi-cli <command> [args]
 
-#[derive(Deserialize)]
-struct AgentMessage {
-    r#type: String,
-    #[serde(default)]
-    text: Option<String>,
-    #[serde(default)]
-    message: Option<String>,
-}
+Options:
+  --from-clipboard       Send the clipboard contents as the first prompt
+  --voice                Speak prompts (needs KOMATACHI_VOICE_COMMAND)
+  --speak                Read responses aloud (say, espeak, or KOMATACHI_SPEAK_COMMAND)
+  --timings              Print how long each startup phase took
+  --safe                 Throwaway, locked-down agent for untrusted prompts
+  --turn-deadline <secs> After this long, ask the agent for its partial answer
+  --lang <code>          Ask the agent to answer in this language (e.g. ja, pt-BR)
+  --no-color             No colors or dimming (also NO_COLOR=1); works with commands too
+
+Commands:
+  backup create|restore <file>
+                         Archive ~/.komatachi, or restore it (.gpg: encrypted)
+  bookmarks [--export]   List bookmarked turns, or export them as markdown
+  config check | show [--resolved] | edit
+                         Check the config file, print it (with defaults),
+                         or edit it with every setting described
+  conformance --image <tag>
+                         Check an agent image against the protocol
+  export [conversation] [--scrub] [--json]
+                         Print a transcript as markdown, with secrets,
+                         emails, and hostnames scrubbed for sharing
+  grep [options] <pattern>
+                         Search conversation transcripts line by line
+  history [query]        Pick a past prompt and start a session with it to edit
+  ratings [--export] [--only good|bad]
+                         List rated turns, or export them as JSON lines
+  run -f <manifest.json> [--summary-file <path>]
+                         Run one turn described by a manifest file
+  observe [fork]         Print the conversation's messages as they are written
+  split <file> [--max-tokens <n>] [--overlap <n>] [--json]
+                         Cut a long text into chunks at paragraph, sentence,
+                         or line boundaries
+  timings [--last <n>]   Startup phase times of recent sessions, with medians";
 
 // ---------------------------------------------------------------------------
 // Main
 // ---------------------------------------------------------------------------
 
 fn main() {
-    let api_key = match std::env::var("ANTHROPIC_API_KEY") {
-        Ok(key) if !key.is_empty() => key,
-        _ => {
-            eprintln!("error: ANTHROPIC_API_KEY environment variable is required");
-            std::process::exit(1);
-        }
-    };
-
-    // Resolve directories
-    let data_dir = std::env::var("KOMATACHI_DATA_DIR")
-        .unwrap_or_else(|_| {
-            let home = std::env::var("HOME").unwrap_or_else(|_| ".".to_string());
-            format!("{}/.komatachi/data", home)
-        });
-    let home_dir = std::env::var("KOMATACHI_HOME_DIR")
-        .unwrap_or_else(|_| {
-            let home = std::env::var("HOME").unwrap_or_else(|_| ".".to_string());
-            format!("{}/.komatachi/home", home)
-        });
-
-    // Ensure directories exist
-    std::fs::create_dir_all(&data_dir).unwrap_or_else(|e| {
-        eprintln!("error: cannot create data dir {}: {}", data_dir, e);
-        std::process::exit(1);
-    });
-    std::fs::create_dir_all(&home_dir).unwrap_or_else(|e| {
-        eprintln!("error: cannot create home dir {}: {}", home_dir, e);
-        std::process::exit(1);
-    });
-
-    // Build Docker image
-    eprint!("Building Docker image...");
-    let build_status = Command::new("docker")
-        .args(["compose", "build", "app"])
-        .current_dir(env!("CARGO_MANIFEST_DIR"))
-        .stdout(Stdio::null())
-        .stderr(Stdio::null())
-        .status();
-
-    match build_status {
-        Ok(status) if status.success() => eprintln!(" done."),
-        Ok(status) => {
-            eprintln!(" failed (exit {}).", status);
-            std::process::exit(1);
-        }
-        Err(e) => {
-            eprintln!(" failed: {}.", e);
-            std::process::exit(1);
-        }
-    }
-
-    // Collect optional env vars to pass through
-    let mut env_args: Vec<String> = vec![
-        format!("ANTHROPIC_API_KEY={}", api_key),
-    ];
-    for var in ["KOMATACHI_MODEL", "KOMATACHI_MAX_TOKENS", "KOMATACHI_CONTEXT_WINDOW"] {
-        if let Ok(val) = std::env::var(var) {
-            env_args.push(format!("{}={}", var, val));
-        }
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    // Before a command's arguments or after them, for every command.
+    if let Some(at) = args
+        .iter()
+        .take_while(|arg| *arg != "--")
+        .position(|arg| arg == "--no-color")
+    {
+        args.remove(at);
+        style::disable();
     }
-
-    // Spawn Docker container
-    let mut docker_args: Vec<&str> = vec!["run", "-i", "--rm"];
-    for env_arg in &env_args {
-        docker_args.push("-e");
-        docker_args.push(env_arg);
-    }
-    docker_args.push("-v");
-    let data_mount = format!("{}:/data", data_dir);
-    docker_args.push(&data_mount);
-    docker_args.push("-v");
-    let home_mount = format!("{}:/home/agent", home_dir);
-    docker_args.push(&home_mount);
-    docker_args.push("komatachi-app");
-
-    let mut child = Command::new("docker")
-        .args(&docker_args)
-        .current_dir(env!("CARGO_MANIFEST_DIR"))
-        .stdin(Stdio::piped())
-        .stdout(Stdio::piped())
-        .stderr(Stdio::inherit())
-        .spawn()
-        .unwrap_or_else(|e| {
-            eprintln!("error: failed to start Docker container: {}", e);
-            std::process::exit(1);
-        });
-
-    let child_stdin = child.stdin.take().expect("child stdin");
-    let child_stdout = child.stdout.take().expect("child stdout");
-
-    let mut writer = io::BufWriter::new(child_stdin);
-    let mut reader = BufReader::new(child_stdout);
-
-    // Wait for ready signal
-    let mut line = String::new();
-    match reader.read_line(&mut line) {
-        Ok(0) => {
-            eprintln!("error: agent exited before sending ready signal");
-            std::process::exit(1);
+    match args.first().map(String::as_str) {
+        Some("backup") => backup::run_command(&args[1..]),
+        Some("bookmarks") => bookmarks::run_command(&args[1..]),
+        Some("config") => config::run_command(&args[1..]),
+        Some("ratings") => ratings::run_command(&args[1..]),
+        Some("conformance") => conformance::run_command(&args[1..], &require_api_key()),
+        Some("run") => run::run_command(&args[1..], &require_api_key()),
+        Some("observe") => observe::run_command(&args[1..]),
+        Some("export") => export::run_command(&args[1..]),
+        Some("grep") => grep::run_command(&args[1..]),
+        Some("timings") => timings::run_command(&args[1..]),
+        Some("split") => split::run_command(&args[1..]),
+        Some("history") => {
+            if let Some(prompt) = history::pick(&args[1..]) {
+                let options = Options {
+                    prefill: Some(prompt),
+                    ..Options::default()
+                };
+                run_interactive(options, None);
+            }
         }
-        Ok(_) => {
-            let msg: AgentMessage = serde_json::from_str(line.trim()).unwrap_or_else(|e| {
-                eprintln!("error: invalid ready message: {}", e);
-                std::process::exit(1);
+        Some("-h") | Some("--help") => println!("{}", USAGE),
+        _ => {
+            let options = Options::parse(&args).unwrap_or_else(|e| {
+                eprintln!("{} {}\n\n{}", style::error("error:"), e, USAGE);
+                std::process::exit(2);
             });
-            if msg.r#type != "ready" {
-                eprintln!("error: expected ready, got: {}", msg.r#type);
-                std::process::exit(1);
+            if options.voice && voice::command().is_none() {
+                eprintln!(
+                    "{} --voice needs {} (a command that records and prints a transcription)",
+                    style::error("error:"),
+                    voice::VOICE_COMMAND_VAR
+                );
+                std::process::exit(2);
             }
-        }
-        Err(e) => {
-            eprintln!("error: reading from agent: {}", e);
-            std::process::exit(1);
+            let initial_input = if options.from_clipboard {
+                let config = config::load().unwrap_or_else(|e| {
+                    eprintln!("{} {}", style::error("error:"), e);
+                    std::process::exit(1);
+                });
+                match clipboard::prompt("", &config.scrub) {
+                    Some(prompt) => Some(prompt),
+                    None => std::process::exit(1),
+                }
+            } else {
+                None
+            };
+            run_interactive(options, initial_input);
         }
     }
+}
 
-    eprintln!("Komatachi ready. Type 'quit' or 'exit' to stop.\n");
+fn run_interactive(options: Options, initial_input: Option<String>) {
+    let mut timings = Timings::start();
+    let api_key = require_api_key();
 
-    // REPL loop
-    let stdin = io::stdin();
-    let mut input_buf = String::new();
+    // The image check dominates startup even when the image is current
+    // (docker build has to inspect the build context), so it runs
+    // alongside config loading and directory setup instead of before them.
+    let image_check = std::thread::spawn(|| {
+        let started = std::time::Instant::now();
+        let result = agent::build_image();
+        (result, started.elapsed())
+    });
 
-    loop {
-        eprint!("> ");
-        io::stderr().flush().ok();
+    let loaded = timings.measure("config", || {
+        config::load().and_then(|config| {
+            let validator = Validator::new(&config.validation)?;
+            Ok((config, validator))
+        })
+    });
+    let (config, validator) = loaded.unwrap_or_else(|e| {
+        eprintln!("{} {}", style::error("error:"), e);
+        std::process::exit(1);
+    });
 
-        input_buf.clear();
-        match stdin.lock().read_line(&mut input_buf) {
-            Ok(0) => break, // EOF
-            Err(e) => {
-                eprintln!("error: reading input: {}", e);
-                break;
-            }
-            Ok(_) => {}
+    let (scratch, paths) = timings.measure("directories", || {
+        if options.safe {
+            let (root, paths) = safe::scratch_paths().unwrap_or_else(|e| {
+                eprintln!(
+                    "{} cannot create scratch directory: {}",
+                    style::error("error:"),
+                    e
+                );
+                std::process::exit(1);
+            });
+            return (Some(root), paths);
         }
+        let paths = Paths::resolve();
+        ensure_dir("data", &paths.data_dir);
+        ensure_dir("home", &paths.home_dir);
+        (None, paths)
+    });
 
-        let input = input_buf.trim();
-        if input.is_empty() {
-            continue;
-        }
-        if input == "quit" || input == "exit" {
-            break;
+    let (built, elapsed) = image_check.join().expect("image check thread");
+    timings.record("image check", elapsed);
+    let agent = built.and_then(|()| {
+        let mut agent = timings.measure("launch", || {
+            Agent::launch(
+                &api_key,
+                &paths,
+                options.safe,
+                agent::IMAGE,
+                &config.routing.env(),
+            )
+        })?;
+        timings.measure("handshake", || agent.handshake())?;
+        Ok(agent)
+    });
+    let agent = agent.unwrap_or_else(|e| {
+        eprintln!("{} {}", style::error("error:"), e);
+        if let Some(root) = &scratch {
+            safe::discard(root);
         }
+        std::process::exit(1);
+    });
 
-        // Send input to agent
-        let msg = InputMessage {
-            r#type: "input",
-            text: input.to_string(),
-        };
-        let json = serde_json::to_string(&msg).expect("serialize input");
-        if writeln!(writer, "{}", json).is_err() {
-            eprintln!("error: agent stdin closed");
-            break;
-        }
-        if writer.flush().is_err() {
-            eprintln!("error: flush to agent failed");
-            break;
+    timings.finish();
+    if options.timings {
+        eprintln!("{}", style::dim(&timings.render()));
+    }
+
+    let safe = options.safe;
+    let first_turn = repl::run(
+        agent,
+        paths,
+        api_key,
+        options,
+        config,
+        validator,
+        initial_input,
+    );
+    if !safe {
+        if let Err(e) = timings.save(first_turn) {
+            eprintln!(
+                "{} cannot record startup timings: {}",
+                style::warning("warning:"),
+                e
+            );
         }
+    }
 
-        // Read response
-        line.clear();
-        match reader.read_line(&mut line) {
-            Ok(0) => {
-                eprintln!("error: agent exited unexpectedly");
-                break;
-            }
-            Ok(_) => {
-                match serde_json::from_str::<AgentMessage>(line.trim()) {
-                    Ok(msg) => match msg.r#type.as_str() {
-                        "output" => {
-                            if let Some(text) = msg.text {
-                                println!("{}", text);
-                            }
-                        }
-                        "error" => {
-                            eprintln!(
-                                "error: {}",
-                                msg.message.as_deref().unwrap_or("unknown error")
-                            );
-                        }
-                        other => {
-                            eprintln!("warning: unexpected message type: {}", other);
-                        }
-                    },
-                    Err(e) => {
-                        eprintln!("error: invalid response from agent: {}", e);
-                    }
-                }
-            }
-            Err(e) => {
-                eprintln!("error: reading from agent: {}", e);
-                break;
-            }
+    if let Some(root) = scratch {
+        safe::discard(&root);
+    }
+}
+
+fn require_api_key() -> String {
+    match std::env::var("ANTHROPIC_API_KEY") {
+        Ok(key) if !key.is_empty() => key,
+        _ => {
+            eprintln!(
+                "{} ANTHROPIC_API_KEY environment variable is required",
+                style::error("error:")
+            );
+            std::process::exit(1);
         }
     }
+}
 
-    // Clean up: drop writer closes stdin, Docker container exits
-    drop(writer);
-    let _ = child.wait();
+fn ensure_dir(label: &str, dir: &std::path::Path) {
+    std::fs::create_dir_all(dir).unwrap_or_else(|e| {
+        eprintln!(
+            "{} cannot create {} dir {}: {}",
+            style::error("error:"),
+            label,
+            dir.display(),
+            e
+        );
+        std::process::exit(1);
+    });
 }
@@ -0,0 +1,177 @@
+//! Desktop notifications when a long turn finishes.
+//!
+//! With `notify.afterSecs` set, a turn that takes at least that long ends
+//! with a notification, so the terminal can be left during slow work.
+//! Only if the terminal lost focus meanwhile: while a turn runs the CLI
+//! asks the terminal to report focus changes (`CSI ? 1004 h`), and a
+//! terminal that never reports leaving is taken as watched. `always`
+//! notifies regardless, for terminals without focus reporting.
+//! Notifications go through `command` if set, else `osascript` (macOS)
+//! or `notify-send` (Linux); with neither, the terminal bell rings.
+//! Off by default.
+//!
+//! Separately, `bell` rings the terminal bell at the end of every turn,
+//! or of turns at least `afterSecs` long, whatever the focus, so a
+//! terminal multiplexer marks the pane or window (tmux shows a bell flag
+//! on a background window). `bellCommand` runs in its place, to play a
+//! sound, say.
+
+use serde::Deserialize;
+use std::io::IsTerminal;
+use std::io::{self, Write};
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::Duration;
+
+/// Longest answer excerpt shown in a notification.
+const EXCERPT_CHARS: usize = 100;
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "camelCase")]
+pub struct Settings {
+    /// Turns at least this long end with a notification.
+    pub after_secs: Option<u64>,
+    /// Notify even while the terminal has focus (default false).
+    pub always: bool,
+    /// Run with `sh -c`, the title and message as `$1` and `$2`.
+    pub command: Option<String>,
+    /// Ring the terminal bell when a turn ends (default false).
+    pub bell: bool,
+    /// Run with `sh -c` when a turn ends, in place of the bell.
+    pub bell_command: Option<String>,
+}
+
+impl Settings {
+    pub fn after(&self) -> Option<Duration> {
+        self.after_secs.map(Duration::from_secs)
+    }
+
+    /// Whether a turn that took `elapsed`, with the terminal losing focus
+    /// or not, ends with a notification.
+    pub fn wanted(&self, elapsed: Duration, lost_focus: bool) -> bool {
+        self.after()
+            .is_some_and(|after| elapsed >= after && (lost_focus || self.always))
+    }
+
+    /// Whether a turn that took `elapsed` ends with the bell: every turn,
+    /// or with `afterSecs` set, turns at least that long.
+    pub fn rings(&self, elapsed: Duration) -> bool {
+        (self.bell || self.bell_command.is_some())
+            && self.after().is_none_or(|after| elapsed >= after)
+    }
+}
+
+/// Notify that a turn finished, with the start of `text`. False if
+/// there was no way to notify and the bell rang instead.
+pub fn send(settings: &Settings, title: &str, text: &str) -> bool {
+    let message = excerpt(text);
+    let sent = match &settings.command {
+        Some(command) => run(&[
+            "sh",
+            "-c",
+            &format!("{} \"$1\" \"$2\"", command),
+            "sh",
+            title,
+            &message,
+        ]),
+        None => {
+            run(&[
+                "osascript",
+                "-e",
+                &format!(
+                    "display notification {} with title {}",
+                    applescript_string(&message),
+                    applescript_string(title)
+                ),
+            ]) || run(&["notify-send", title, &message])
+        }
+    };
+    if !sent {
+        eprint!("\x07");
+        io::stderr().flush().ok();
+    }
+    sent
+}
+
+/// Ring the bell at the end of a turn, or start `bellCommand` without
+/// waiting for it to finish.
+pub fn ring(settings: &Settings) {
+    match &settings.bell_command {
+        Some(command) => {
+            let command = command.clone();
+            thread::spawn(move || run(&["sh", "-c", &command]));
+        }
+        None if io::stderr().is_terminal() => {
+            eprint!("\x07");
+            io::stderr().flush().ok();
+        }
+        None => {}
+    }
+}
+
+fn run(command: &[&str]) -> bool {
+    Command::new(command[0])
+        .args(&command[1..])
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success())
+}
+
+/// The first line of `text`, cut to `EXCERPT_CHARS`.
+fn excerpt(text: &str) -> String {
+    let first = text.trim().lines().next().unwrap_or("");
+    if first.chars().count() > EXCERPT_CHARS {
+        let cut: String = first.chars().take(EXCERPT_CHARS - 3).collect();
+        format!("{}...", cut)
+    } else {
+        first.to_string()
+    }
+}
+
+fn applescript_string(text: &str) -> String {
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn notifies_long_turns_away_from_the_terminal() {
+        let settings = Settings {
+            after_secs: Some(30),
+            ..Settings::default()
+        };
+        assert!(settings.wanted(Duration::from_secs(31), true));
+        assert!(!settings.wanted(Duration::from_secs(31), false));
+        assert!(!settings.wanted(Duration::from_secs(29), true));
+        assert!(!Settings::default().wanted(Duration::from_secs(999), true));
+    }
+
+    #[test]
+    fn the_bell_rings_after_every_turn_or_only_long_ones() {
+        let bell = Settings {
+            bell: true,
+            ..Settings::default()
+        };
+        assert!(bell.rings(Duration::from_secs(1)));
+        let long_only = Settings {
+            after_secs: Some(30),
+            bell_command: Some("paplay done.oga".to_string()),
+            ..Settings::default()
+        };
+        assert!(!long_only.rings(Duration::from_secs(29)));
+        assert!(long_only.rings(Duration::from_secs(30)));
+        assert!(!Settings::default().rings(Duration::from_secs(999)));
+    }
+
+    #[test]
+    fn excerpts_the_first_line() {
+        assert_eq!(excerpt("\n  Done.\nDetails..."), "Done.");
+        let long = "x".repeat(150);
+        assert_eq!(excerpt(&long).chars().count(), EXCERPT_CHARS);
+        assert_eq!(applescript_string(r#"say "hi""#), r#""say \"hi\"""#);
+    }
+}
@@ -0,0 +1,189 @@
+//! `komatachi-cli observe [fork]`: follow a conversation without taking
+//! part in it.
+//!
+//! Reads the agent's transcript as it grows and prints each new message.
+//! Observing only reads the file -- the agent stays its only writer
+//! (PROGRESS Decision #9) -- so it works beside a running session in another
+//! terminal. Compaction and `/rollback` replace the transcript file
+//! rather than append to it; that is reported, and following resumes at
+//! the end of the new file.
+
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+
+use serde_json::Value;
+
+use crate::fork;
+use crate::inspect;
+use crate::paths::{self, Paths};
+use crate::style;
+
+/// How often the transcript is checked for new messages.
+const POLL: Duration = Duration::from_millis(500);
+/// Messages already in the transcript shown for context on start.
+const CONTEXT_MESSAGES: usize = 4;
+
+/// `komatachi-cli observe [fork]`
+pub fn run_command(args: &[String]) {
+    let paths = match args {
+        [] => Paths::resolve(),
+        [name] if !name.starts_with('-') => {
+            if let Err(e) = fork::validate_name(name) {
+                eprintln!("{} {}", style::error("error:"), e);
+                std::process::exit(2);
+            }
+            let data_dir = paths::forks_dir().join(name);
+            if !data_dir.is_dir() {
+                eprintln!("{} no fork named '{}'", style::error("error:"), name);
+                std::process::exit(1);
+            }
+            Paths {
+                data_dir,
+                ..Paths::resolve()
+            }
+        }
+        _ => {
+            eprintln!("usage: komatachi-cli observe [fork]");
+            std::process::exit(2);
+        }
+    };
+    let path = paths.transcript_file();
+    if let Err(e) = follow(&path) {
+        eprintln!(
+            "{} cannot read {}: {}",
+            style::error("error:"),
+            path.display(),
+            e
+        );
+        std::process::exit(1);
+    }
+}
+
+/// Position in one version of the transcript file.
+struct Position {
+    /// Inode of the file being followed; a new one means it was replaced.
+    inode: u64,
+    offset: u64,
+    /// Bytes of a line the agent has not finished writing.
+    partial: Vec<u8>,
+}
+
+/// Print new transcript messages until interrupted.
+fn follow(path: &Path) -> io::Result<()> {
+    let mut position: Option<Position> = None;
+    let mut waiting = false;
+    loop {
+        let meta = match fs::metadata(path) {
+            Ok(meta) => meta,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                if !waiting {
+                    eprintln!("Waiting for {} ...", path.display());
+                    waiting = true;
+                }
+                thread::sleep(POLL);
+                continue;
+            }
+            Err(e) => return Err(e),
+        };
+
+        match &mut position {
+            Some(at) if at.inode == meta.ino() && meta.len() >= at.offset => {
+                if meta.len() > at.offset {
+                    for line in read_lines(path, at)? {
+                        println!("{}", render(&line));
+                    }
+                }
+            }
+            _ => {
+                let replaced = position.is_some();
+                let mut at = Position {
+                    inode: meta.ino(),
+                    offset: 0,
+                    partial: Vec::new(),
+                };
+                let lines = read_lines(path, &mut at)?;
+                if replaced {
+                    println!(
+                        "{}",
+                        style::dim(&format!(
+                            "--- transcript replaced (compaction or rollback): {} message(s) ---",
+                            lines.len()
+                        ))
+                    );
+                } else {
+                    let skip = lines.len().saturating_sub(CONTEXT_MESSAGES);
+                    if skip > 0 {
+                        println!("{}", style::dim(&format!("({} earlier message(s))", skip)));
+                    }
+                    for line in &lines[skip..] {
+                        println!("{}", render(line));
+                    }
+                    println!("{}", style::dim("--- following; Ctrl-C to stop ---"));
+                }
+                position = Some(at);
+            }
+        }
+        thread::sleep(POLL);
+    }
+}
+
+/// Read what was appended since `at` and return the complete lines.
+fn read_lines(path: &Path, at: &mut Position) -> io::Result<Vec<String>> {
+    let mut file = File::open(path)?;
+    file.seek(SeekFrom::Start(at.offset))?;
+    let read = file.read_to_end(&mut at.partial)?;
+    at.offset += read as u64;
+    Ok(take_lines(&mut at.partial))
+}
+
+/// Remove and return the complete (newline-terminated, non-blank) lines
+/// at the front of `buffer`, leaving an unfinished last line in place.
+fn take_lines(buffer: &mut Vec<u8>) -> Vec<String> {
+    let Some(end) = buffer.iter().rposition(|&b| b == b'\n') else {
+        return Vec::new();
+    };
+    let complete: Vec<u8> = buffer.drain(..=end).collect();
+    String::from_utf8_lossy(&complete)
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(String::from)
+        .collect()
+}
+
+/// Render one transcript line (a Claude API message) for the terminal.
+fn render(line: &str) -> String {
+    match serde_json::from_str::<Value>(line) {
+        Ok(message) => format!(
+            "{}\n{}",
+            style::dim(&format!("[{}]", message["role"].as_str().unwrap_or("?"))),
+            inspect::render_content(&message["content"]).trim_end()
+        ),
+        Err(_) => style::dim("[unreadable transcript line]"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn take_lines_keeps_an_unfinished_line() {
+        let mut buffer = b"{\"a\":1}\n\n{\"b\":2}\n{\"c\"".to_vec();
+        assert_eq!(take_lines(&mut buffer), vec!["{\"a\":1}", "{\"b\":2}"]);
+        assert_eq!(buffer, b"{\"c\"");
+        buffer.extend_from_slice(b":3}\n");
+        assert_eq!(take_lines(&mut buffer), vec!["{\"c\":3}"]);
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn renders_role_and_content() {
+        let line = r#"{"role":"assistant","content":[{"type":"text","text":"Hello."}]}"#;
+        assert_eq!(render(line), "[assistant]\nHello.");
+        assert_eq!(render(r#"{"role":"user","content":"Hi"}"#), "[user]\nHi");
+    }
+}
@@ -0,0 +1,98 @@
+//! Command-line options for an interactive session.
+
+use std::time::Duration;
+
+use crate::lang;
+
+#[derive(Default)]
+pub struct Options {
+    /// Send the clipboard as the first prompt.
+    pub from_clipboard: bool,
+    /// Capture prompts with `KOMATACHI_VOICE_COMMAND` on an empty line.
+    pub voice: bool,
+    /// Read responses aloud with a TTS command.
+    pub speak: bool,
+    /// Print how long each startup phase took.
+    pub timings: bool,
+    /// Throwaway, locked-down agent for untrusted prompts.
+    pub safe: bool,
+    /// Ask for a partial answer once a turn has run this long.
+    pub turn_deadline: Option<Duration>,
+    /// Ask for answers in this language (a normalized tag such as `ja`).
+    pub lang: Option<String>,
+    /// Text the first prompt starts with, to edit before sending; set by
+    /// `komatachi-cli history`, not by a flag.
+    pub prefill: Option<String>,
+}
+
+impl Options {
+    pub fn parse(args: &[String]) -> Result<Options, String> {
+        let mut options = Options::default();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--from-clipboard" => options.from_clipboard = true,
+                "--voice" => options.voice = true,
+                "--speak" => options.speak = true,
+                "--timings" => options.timings = true,
+                "--safe" => options.safe = true,
+                "--turn-deadline" => {
+                    let value = args
+                        .next()
+                        .ok_or("--turn-deadline needs a number of seconds")?;
+                    options.turn_deadline = Some(parse_seconds(value)?);
+                }
+                "--lang" => {
+                    let value = args.next().ok_or("--lang needs a language code")?;
+                    options.lang = Some(lang::parse(value)?);
+                }
+                other => return Err(format!("unknown option: {}", other)),
+            }
+        }
+        Ok(options)
+    }
+}
+
+fn parse_seconds(value: &str) -> Result<Duration, String> {
+    match value.parse::<u64>() {
+        Ok(secs) if secs > 0 => Ok(Duration::from_secs(secs)),
+        _ => Err(format!(
+            "expected a positive number of seconds, got {:?}",
+            value
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Options, String> {
+        let args: Vec<String> = args.iter().map(|a| a.to_string()).collect();
+        Options::parse(&args)
+    }
+
+    #[test]
+    fn turn_deadline_takes_seconds() {
+        let options = parse(&["--speak", "--turn-deadline", "90"]).unwrap();
+        assert!(options.speak);
+        assert_eq!(options.turn_deadline, Some(Duration::from_secs(90)));
+    }
+
+    #[test]
+    fn turn_deadline_rejects_missing_or_bad_values() {
+        assert!(parse(&["--turn-deadline"]).is_err());
+        assert!(parse(&["--turn-deadline", "0"]).is_err());
+        assert!(parse(&["--turn-deadline", "soon"]).is_err());
+    }
+
+    #[test]
+    fn lang_takes_a_language_code() {
+        assert_eq!(
+            parse(&["--lang", "Ja"]).unwrap().lang.as_deref(),
+            Some("ja")
+        );
+        assert!(parse(&["--lang"]).is_err());
+        assert!(parse(&["--lang", "klingon"]).is_err());
+    }
+}
@@ -0,0 +1,93 @@
+//! Paging long answers.
+//!
+//! An answer too tall for the terminal is shown through `$PAGER` (`less`
+//! when unset) so its start can be read, instead of scrolling out of
+//! sight; shorter answers print inline as before. Only when stdout is a
+//! terminal: redirected output is never paged. If the pager cannot be
+//! started, the answer is printed inline.
+
+use serde::Deserialize;
+use std::io::{self, IsTerminal, Write};
+use std::process::{Command, Stdio};
+
+use crate::lineedit;
+
+/// The pager when `$PAGER` is unset: `-R` passes colors through, `-F`
+/// quits at once if the text fits after all, `-X` leaves it on screen.
+const DEFAULT_PAGER: &str = "less -FRX";
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "camelCase")]
+pub struct Settings {
+    /// Page long answers at all (default true).
+    pub enabled: Option<bool>,
+    /// Answers taller than this many lines are paged (default: the
+    /// terminal's height, less the prompt and metadata lines).
+    pub min_lines: Option<usize>,
+}
+
+impl Settings {
+    pub fn enabled(&self) -> bool {
+        self.enabled.unwrap_or(true)
+    }
+}
+
+/// Print an answer to stdout, through the pager if it is too tall.
+pub fn print(text: &str, settings: &Settings) {
+    if settings.enabled() && io::stdout().is_terminal() {
+        let (rows, columns) = lineedit::terminal_size();
+        let limit = settings.min_lines.unwrap_or(rows.saturating_sub(2));
+        if height(text, columns) > limit && page(text) {
+            return;
+        }
+    }
+    println!("{}", text);
+}
+
+/// Lines `text` takes on a terminal `columns` wide, counting wrapping.
+fn height(text: &str, columns: usize) -> usize {
+    text.lines()
+        .map(|line| {
+            let width: usize = line.chars().map(lineedit::width).sum();
+            width.div_ceil(columns.max(1)).max(1)
+        })
+        .sum()
+}
+
+/// Show `text` in the pager; false if no pager could be run.
+fn page(text: &str) -> bool {
+    let pager = std::env::var("PAGER").unwrap_or_else(|_| DEFAULT_PAGER.to_string());
+    if pager.trim().is_empty() || pager.trim() == "cat" {
+        return false;
+    }
+    let child = Command::new("sh")
+        .args(["-c", &pager])
+        .stdin(Stdio::piped())
+        .spawn();
+    let Ok(mut child) = child else {
+        return false;
+    };
+    if let Some(mut stdin) = child.stdin.take() {
+        // Quitting the pager early closes the pipe; that is not an error.
+        let _ = writeln!(stdin, "{}", text);
+    }
+    // A pager that was not found exits 127 before reading anything.
+    !matches!(
+        child.wait().map(|status| status.code()),
+        Ok(Some(127)) | Err(_)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn height_counts_wrapped_lines() {
+        assert_eq!(height("one\ntwo", 80), 2);
+        assert_eq!(height(&"x".repeat(81), 80), 2);
+        assert_eq!(height("a\n\nb", 80), 3);
+        // Wide characters take two columns each.
+        assert_eq!(height(&"日".repeat(41), 80), 2);
+    }
+}
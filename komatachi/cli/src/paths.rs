@@ -0,0 +1,110 @@
+//! Host-side directory layout.
+//!
+//! Everything Komatachi keeps on the host lives under `~/.komatachi/`.
+//! The data and home directories can be relocated with environment
+//! variables; both are mounted into the agent container.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Name of the default conversation, beside fork names.
+pub const MAIN: &str = "main";
+
+/// Name the prompt shows for a `--safe` scratch conversation.
+pub const SAFE: &str = "safe";
+
+pub struct Paths {
+    /// Conversation storage, mounted at `/data` in the container.
+    pub data_dir: PathBuf,
+    /// Agent identity files, mounted at `/home/agent` in the container.
+    pub home_dir: PathBuf,
+}
+
+impl Paths {
+    /// Resolve directories from the environment. Does not touch the filesystem.
+    pub fn resolve() -> Paths {
+        Paths {
+            data_dir: dir_from_env("KOMATACHI_DATA_DIR", "data"),
+            home_dir: dir_from_env("KOMATACHI_HOME_DIR", "home"),
+        }
+    }
+
+    /// The agent's message history (written only by the agent).
+    pub fn transcript_file(&self) -> PathBuf {
+        self.data_dir.join("conversation").join("transcript.jsonl")
+    }
+
+    /// Prompt that was sent but not yet answered.
+    pub fn draft_file(&self) -> PathBuf {
+        self.data_dir.join("draft.json")
+    }
+
+    /// Line being typed at the prompt, not yet sent.
+    pub fn unsent_file(&self) -> PathBuf {
+        self.data_dir.join("unsent.json")
+    }
+
+    /// Checkpoint list written by the agent after each turn.
+    pub fn checkpoint_index(&self) -> PathBuf {
+        self.data_dir.join("checkpoints").join("index.jsonl")
+    }
+
+    /// File holding turns marked with `/bookmark`.
+    pub fn bookmarks_file(&self) -> PathBuf {
+        self.data_dir.join("bookmarks.jsonl")
+    }
+
+    /// File holding exchanges rated with `/good` and `/bad`.
+    pub fn ratings_file(&self) -> PathBuf {
+        self.data_dir.join("ratings.jsonl")
+    }
+}
+
+/// Parent of data directories created by `/fork`.
+pub fn forks_dir() -> PathBuf {
+    komatachi_dir().join("forks")
+}
+
+/// Every conversation's transcript by name: the main one, then each
+/// fork, sorted.
+pub fn conversations() -> Vec<(String, PathBuf)> {
+    let mut found = vec![(MAIN.to_string(), Paths::resolve().transcript_file())];
+    let mut forks: Vec<String> = fs::read_dir(forks_dir())
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+                .collect()
+        })
+        .unwrap_or_default();
+    forks.sort();
+    for name in forks {
+        let fork = Paths {
+            data_dir: forks_dir().join(&name),
+            ..Paths::resolve()
+        };
+        found.push((name, fork.transcript_file()));
+    }
+    found
+}
+
+/// The name `grep` and references use for the conversation kept in
+/// `data_dir`: a fork's name, or `main`.
+pub fn conversation_name(data_dir: &Path) -> String {
+    match (data_dir.parent(), data_dir.file_name()) {
+        (Some(parent), Some(name)) if parent == forks_dir() => name.to_string_lossy().into_owned(),
+        _ => MAIN.to_string(),
+    }
+}
+
+/// Root of all host-side Komatachi state (`~/.komatachi`).
+pub fn komatachi_dir() -> PathBuf {
+    let home = std::env::var("HOME").unwrap_or_else(|_| ".".to_string());
+    PathBuf::from(home).join(".komatachi")
+}
+
+fn dir_from_env(var: &str, default_name: &str) -> PathBuf {
+    match std::env::var(var) {
+        Ok(dir) => PathBuf::from(dir),
+        Err(_) => komatachi_dir().join(default_name),
+    }
+}
@@ -0,0 +1,189 @@
+//! The REPL prompt string: `prompt.format` in the config file.
+//!
+//! The default is `> `. A format can name the conversation, the model
+//! and token count of the last turn, and the number of the next one, so
+//! several sessions open side by side can be told apart:
+//! `"{conversation} #{turn}> "`. `{{` and `}}` are literal braces. An
+//! unknown placeholder is a config error, like an unknown key.
+//!
+//! `prompt.status` is a second format with the same placeholders, for a
+//! dimmed status line printed after each turn, above the next prompt:
+//! `"{model} · {tokens} of {window} tokens · {conversation}"`. Off by
+//! default. The context window is the agent's, `KOMATACHI_CONTEXT_WINDOW`.
+
+use serde::Deserialize;
+
+const DEFAULT_FORMAT: &str = "> ";
+
+/// The agent's context window when `KOMATACHI_CONTEXT_WINDOW` is unset.
+const DEFAULT_WINDOW: u64 = 200_000;
+
+const PLACEHOLDERS: [&str; 6] = [
+    "conversation",
+    "model",
+    "turn",
+    "tokens",
+    "remaining",
+    "window",
+];
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "camelCase")]
+pub struct Settings {
+    /// The prompt, with `{placeholder}`s.
+    pub format: Option<String>,
+    /// A status line printed after each turn, with `{placeholder}`s.
+    pub status: Option<String>,
+}
+
+impl Settings {
+    pub fn format(&self) -> &str {
+        self.format.as_deref().unwrap_or(DEFAULT_FORMAT)
+    }
+}
+
+/// The agent's context window, as the agent reads it.
+pub fn window() -> u64 {
+    std::env::var("KOMATACHI_CONTEXT_WINDOW")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(DEFAULT_WINDOW)
+}
+
+/// What the placeholders stand for.
+pub struct Context<'a> {
+    /// `main`, a fork's name, or `safe`.
+    pub conversation: &'a str,
+    /// The model that answered the last turn, if any yet.
+    pub model: Option<&'a str>,
+    /// The number of the next turn in this session, from 1.
+    pub turn: usize,
+    /// Tokens in and out over the last turn, if any yet: about the
+    /// context the conversation now takes.
+    pub tokens: Option<u64>,
+    /// The agent's context window.
+    pub window: u64,
+}
+
+/// Check the formats' placeholders, for `config::load`.
+pub fn check(settings: &Settings) -> Result<(), String> {
+    let blank = Context {
+        conversation: "",
+        model: None,
+        turn: 1,
+        tokens: None,
+        window: DEFAULT_WINDOW,
+    };
+    render(settings.format(), &blank).map_err(|e| format!("prompt.format: {}", e))?;
+    if let Some(status) = &settings.status {
+        render(status, &blank).map_err(|e| format!("prompt.status: {}", e))?;
+    }
+    Ok(())
+}
+
+/// `format` with each placeholder filled in; unknown values are empty.
+pub fn render(format: &str, context: &Context) -> Result<String, String> {
+    let mut out = String::new();
+    let mut rest = format;
+    while let Some(at) = rest.find(['{', '}']) {
+        out.push_str(&rest[..at]);
+        let after = &rest[at + 1..];
+        if rest[at..].starts_with("{{") || rest[at..].starts_with("}}") {
+            out.push_str(&rest[at..at + 1]);
+            rest = &after[1..];
+            continue;
+        }
+        if rest[at..].starts_with('}') {
+            return Err("unmatched '}' (write '}}' for a brace)".to_string());
+        }
+        let Some(end) = after.find('}') else {
+            return Err("unclosed '{' (write '{{' for a brace)".to_string());
+        };
+        let value = match &after[..end] {
+            "conversation" => context.conversation.to_string(),
+            "model" => context.model.unwrap_or("").to_string(),
+            "turn" => context.turn.to_string(),
+            "tokens" => context.tokens.map_or(String::new(), |t| t.to_string()),
+            "remaining" => context.tokens.map_or(String::new(), |t| {
+                context.window.saturating_sub(t).to_string()
+            }),
+            "window" => context.window.to_string(),
+            name => {
+                return Err(format!(
+                    "unknown placeholder {{{}}} (known: {})",
+                    name,
+                    PLACEHOLDERS.map(|p| format!("{{{}}}", p)).join(", ")
+                ))
+            }
+        };
+        out.push_str(&value);
+        rest = &after[end + 1..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fills_in_placeholders() {
+        let context = Context {
+            conversation: "idea",
+            model: Some("claude-test"),
+            turn: 3,
+            tokens: Some(1234),
+            window: 200_000,
+        };
+        assert_eq!(
+            render(
+                "{conversation} #{turn} [{model}, {tokens}] {{x}}> ",
+                &context
+            )
+            .unwrap(),
+            "idea #3 [claude-test, 1234] {x}> "
+        );
+        let fresh = Context {
+            model: None,
+            tokens: None,
+            ..context
+        };
+        assert_eq!(render("{model}|{tokens}> ", &fresh).unwrap(), "|> ");
+        assert_eq!(
+            render("{remaining} of {window} left", &context).unwrap(),
+            "198766 of 200000 left"
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_placeholders() {
+        assert!(render(
+            "{session}> ",
+            &Context {
+                conversation: "",
+                model: None,
+                turn: 1,
+                tokens: None,
+                window: 1,
+            }
+        )
+        .unwrap_err()
+        .contains("unknown placeholder {session}"));
+        let bad = Settings {
+            format: Some("{turn> ".to_string()),
+            status: None,
+        };
+        assert!(check(&bad)
+            .unwrap_err()
+            .starts_with("prompt.format: unclosed"));
+        let bad = Settings {
+            format: None,
+            status: Some("{context}".to_string()),
+        };
+        assert!(check(&bad)
+            .unwrap_err()
+            .starts_with("prompt.status: unknown placeholder"));
+        assert!(check(&Settings::default()).is_ok());
+    }
+}
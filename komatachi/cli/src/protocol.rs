@@ -0,0 +1,346 @@
+//! JSON-lines protocol shared with the agent process.
+//!
+//! Each message is one JSON object on one line. See `src/index.ts` for
+//! the agent side.
+//!
+//! The CLI offers the versions in `VERSIONS` when it starts the agent
+//! (`KOMATACHI_PROTOCOL`), and the agent names the one it speaks in
+//! `ready`. A version outside the list ends the session at the handshake
+//! with what to upgrade, rather than with a message neither side expects
+//! halfway through a turn.
+//!
+//! Version 2 numbers each input, and the agent echoes the number on that
+//! turn's messages as `reply_to`. `Inputs` tracks which inputs are still
+//! waiting, so a reply that arrives after its turn was given up, or for an
+//! input never sent, is dropped rather than taken as the current answer,
+//! and an input whose reply never came is reported.
+//!
+//! Version 3 adds `heartbeat`, sent by the agent every few seconds while a
+//! turn runs. A turn that sends nothing for minutes is then an agent that
+//! stopped, not a slow model call, so the watchdog can time silence on
+//! every turn.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::time::Instant;
+
+/// Protocol versions this CLI speaks, oldest first.
+pub const VERSIONS: &[u64] = &[1, 2, 3];
+
+/// The first version whose inputs carry an `id`.
+pub const INPUT_IDS: u64 = 2;
+
+/// The first version that sends `heartbeat` during a turn.
+pub const HEARTBEATS: u64 = 3;
+
+/// Any message from the CLI to the agent.
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ClientMessage {
+    /// A user prompt for one turn; `stream` asks for `output_delta`s, and
+    /// `id` (from `Agent::input_id`) is echoed back as `reply_to`.
+    Input {
+        text: String,
+        #[serde(skip_serializing_if = "std::ops::Not::not")]
+        stream: bool,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        id: Option<u64>,
+    },
+    /// Ask for the most recent model request.
+    Inspect,
+    /// Record a checkpoint now, outside a turn.
+    Checkpoint,
+    /// Roll the conversation back to a checkpoint.
+    RestoreCheckpoint { id: u64 },
+    /// Cut the running turn short and answer with what it has so far.
+    Finalize,
+}
+
+/// Any message from the agent. Fields not used by a given `type` are
+/// absent.
+#[derive(Deserialize)]
+pub struct AgentMessage {
+    pub r#type: String,
+    /// Position in the agent's output stream, starting at 1.
+    #[serde(default)]
+    pub seq: Option<u64>,
+    #[serde(default)]
+    pub text: Option<String>,
+    /// On "output": the turn was finalized before it completed.
+    #[serde(default)]
+    pub partial: bool,
+    #[serde(default)]
+    pub message: Option<String>,
+    // "usage" fields
+    #[serde(default)]
+    pub model: Option<String>,
+    #[serde(default)]
+    pub input_tokens: Option<u64>,
+    #[serde(default)]
+    pub output_tokens: Option<u64>,
+    /// Why an auto-routed turn went to `model`.
+    #[serde(default)]
+    pub route: Option<String>,
+    // "inspect" field: the last model request, or null before the first call
+    #[serde(default)]
+    pub request: Option<serde_json::Value>,
+    // "checkpoint" / "checkpoint_restored" field
+    #[serde(default)]
+    pub id: Option<u64>,
+    /// On "ready": the protocol version the agent speaks.
+    #[serde(default)]
+    pub protocol: Option<u64>,
+    /// On a turn's messages, from version 2: the `id` of its input.
+    #[serde(default)]
+    pub reply_to: Option<u64>,
+}
+
+/// Token usage for one turn, summed over every model call in the turn.
+pub struct Usage {
+    pub model: String,
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+    /// Routing reason, when the agent picked the model per turn.
+    pub route: Option<String>,
+}
+
+impl Usage {
+    /// Usage over this turn and a `later` one, such as a validation
+    /// retry. The model and route are the later turn's.
+    pub fn then(self, later: Usage) -> Usage {
+        Usage {
+            input_tokens: self.input_tokens + later.input_tokens,
+            output_tokens: self.output_tokens + later.output_tokens,
+            ..later
+        }
+    }
+}
+
+impl AgentMessage {
+    /// Extract usage from a `usage` message. `None` if fields are missing.
+    pub fn usage(&self) -> Option<Usage> {
+        Some(Usage {
+            model: self.model.clone()?,
+            input_tokens: self.input_tokens?,
+            output_tokens: self.output_tokens?,
+            route: self.route.clone(),
+        })
+    }
+}
+
+/// `VERSIONS` as the agent reads them from `KOMATACHI_PROTOCOL`.
+pub fn offered() -> String {
+    let versions: Vec<String> = VERSIONS.iter().map(u64::to_string).collect();
+    versions.join(",")
+}
+
+/// The version a `ready` names, if this CLI speaks it. A `ready` without
+/// one is from an agent that predates version numbers, which speaks
+/// version 1.
+pub fn negotiated(version: Option<u64>) -> Result<u64, String> {
+    let version = version.unwrap_or(1);
+    if VERSIONS.contains(&version) {
+        return Ok(version);
+    }
+    let upgrade = if version > VERSIONS[VERSIONS.len() - 1] {
+        "upgrade komatachi-cli"
+    } else {
+        "upgrade the agent image"
+    };
+    Err(format!(
+        "protocol mismatch: the agent speaks version {} and this CLI speaks {}; {}",
+        version,
+        offered().replace(',', ", "),
+        upgrade
+    ))
+}
+
+/// What a message answering an input id is.
+#[derive(Debug, PartialEq)]
+pub enum Answer {
+    /// For an input still waiting. `dropped` lists older inputs, still
+    /// waiting when this one's turn ended, whose replies never came.
+    Awaited { dropped: Vec<u64> },
+    /// For an input answered already, or given up on.
+    Late,
+    /// For an id no input was sent with.
+    Unknown,
+}
+
+/// Inputs sent and not yet answered, by id, each with when the agent was
+/// last heard from about it: when it was sent, then its latest message.
+#[derive(Default)]
+pub struct Inputs {
+    last: u64,
+    waiting: BTreeMap<u64, Instant>,
+}
+
+impl Inputs {
+    /// The id for an input about to be sent at `now`.
+    pub fn start(&mut self, now: Instant) -> u64 {
+        self.last += 1;
+        self.waiting.insert(self.last, now);
+        self.last
+    }
+
+    /// Check a message answering `id`, heard at `now`. One that `ends` the
+    /// turn (`output` or `error`) stops the input waiting, and with it any
+    /// older input still waiting.
+    pub fn answer(&mut self, id: u64, ends: bool, now: Instant) -> Answer {
+        let Some(heard) = self.waiting.get_mut(&id) else {
+            return if id >= 1 && id <= self.last {
+                Answer::Late
+            } else {
+                Answer::Unknown
+            };
+        };
+        *heard = now;
+        if !ends {
+            return Answer::Awaited {
+                dropped: Vec::new(),
+            };
+        }
+        let newer = self.waiting.split_off(&(id + 1));
+        let dropped = self
+            .waiting
+            .keys()
+            .copied()
+            .filter(|&old| old != id)
+            .collect();
+        self.waiting = newer;
+        Answer::Awaited { dropped }
+    }
+
+    /// The input waiting longest, and when the agent was last heard from
+    /// about it, for the stall limit.
+    pub fn oldest(&self) -> Option<(u64, Instant)> {
+        self.waiting.iter().next().map(|(&id, &heard)| (id, heard))
+    }
+}
+
+/// Result of checking a message's sequence number.
+#[derive(Debug, PartialEq)]
+pub enum SeqCheck {
+    /// The next expected message (or one without a sequence number).
+    InOrder,
+    /// Already seen: a re-emitted frame that must be dropped.
+    Duplicate,
+    /// Newer than expected: this many messages never arrived.
+    Gap(u64),
+}
+
+/// Tracks the agent's sequence numbers to catch duplicates and gaps.
+#[derive(Default)]
+pub struct SeqTracker {
+    last: u64,
+}
+
+impl SeqTracker {
+    pub fn check(&mut self, seq: Option<u64>) -> SeqCheck {
+        let Some(seq) = seq else {
+            return SeqCheck::InOrder;
+        };
+        if seq <= self.last {
+            return SeqCheck::Duplicate;
+        }
+        let missed = seq - self.last - 1;
+        self.last = seq;
+        if missed == 0 {
+            SeqCheck::InOrder
+        } else {
+            SeqCheck::Gap(missed)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ready_must_name_a_version_this_cli_speaks() {
+        assert_eq!(negotiated(Some(1)), Ok(1));
+        assert_eq!(negotiated(None), Ok(1));
+        assert_eq!(
+            negotiated(Some(99)).unwrap_err(),
+            "protocol mismatch: the agent speaks version 99 and this CLI speaks 1, 2, 3; \
+             upgrade komatachi-cli"
+        );
+        assert!(negotiated(Some(0))
+            .unwrap_err()
+            .ends_with("upgrade the agent image"));
+    }
+
+    #[test]
+    fn replies_are_matched_to_the_inputs_waiting() {
+        let now = Instant::now();
+        let mut inputs = Inputs::default();
+        let first = inputs.start(now);
+        let second = inputs.start(now);
+        assert_eq!(inputs.oldest(), Some((first, now)));
+        assert_eq!(
+            inputs.answer(second, false, now),
+            Answer::Awaited { dropped: vec![] }
+        );
+        assert_eq!(
+            inputs.answer(second, true, now),
+            Answer::Awaited {
+                dropped: vec![first]
+            }
+        );
+        assert_eq!(inputs.answer(first, true, now), Answer::Late);
+        assert_eq!(inputs.answer(second, false, now), Answer::Late);
+        assert_eq!(inputs.answer(7, false, now), Answer::Unknown);
+        assert_eq!(inputs.oldest(), None);
+    }
+
+    #[test]
+    fn retry_usage_adds_to_the_turn() {
+        let usage = |model: &str, input_tokens, output_tokens| Usage {
+            model: model.to_string(),
+            input_tokens,
+            output_tokens,
+            route: None,
+        };
+        let total = usage("haiku", 100, 20).then(usage("sonnet", 150, 30));
+        assert_eq!(
+            (
+                total.model.as_str(),
+                total.input_tokens,
+                total.output_tokens
+            ),
+            ("sonnet", 250, 50)
+        );
+    }
+
+    #[test]
+    fn accepts_consecutive_sequence() {
+        let mut tracker = SeqTracker::default();
+        assert_eq!(tracker.check(Some(1)), SeqCheck::InOrder);
+        assert_eq!(tracker.check(Some(2)), SeqCheck::InOrder);
+    }
+
+    #[test]
+    fn flags_repeated_frames() {
+        let mut tracker = SeqTracker::default();
+        tracker.check(Some(1));
+        tracker.check(Some(2));
+        assert_eq!(tracker.check(Some(2)), SeqCheck::Duplicate);
+        assert_eq!(tracker.check(Some(1)), SeqCheck::Duplicate);
+        assert_eq!(tracker.check(Some(3)), SeqCheck::InOrder);
+    }
+
+    #[test]
+    fn reports_missing_frames() {
+        let mut tracker = SeqTracker::default();
+        tracker.check(Some(1));
+        assert_eq!(tracker.check(Some(4)), SeqCheck::Gap(2));
+    }
+
+    #[test]
+    fn tolerates_unnumbered_messages() {
+        let mut tracker = SeqTracker::default();
+        assert_eq!(tracker.check(None), SeqCheck::InOrder);
+        assert_eq!(tracker.check(Some(1)), SeqCheck::InOrder);
+    }
+}
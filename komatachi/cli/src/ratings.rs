@@ -0,0 +1,217 @@
+//! Turn ratings.
+//!
+//! `/good` and `/bad [reason]` in the REPL rate the most recent exchange
+//! in `ratings.jsonl` inside the data directory. As with bookmarks, the
+//! exchange text is copied, since compaction rewrites the transcript.
+//! Rating the same exchange again replaces the earlier rating: the file
+//! is only appended to, and the last line for an exchange wins.
+//!
+//! `komatachi-cli ratings --export` prints the rated exchanges as JSON
+//! lines of Claude API messages, for curating fine-tuning or preference
+//! data out of everyday use.
+
+use serde::{Deserialize, Serialize};
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+
+use crate::paths::Paths;
+use crate::style;
+use crate::time;
+
+const USAGE: &str = "usage: komatachi-cli ratings [--export] [--only good|bad]";
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Verdict {
+    Good,
+    Bad,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Rating {
+    /// When the rating was given (epoch ms)
+    pub created_at: u64,
+    pub verdict: Verdict,
+    #[serde(default)]
+    pub reason: Option<String>,
+    pub input: String,
+    pub output: String,
+}
+
+/// Append one rating, creating the file if needed.
+pub fn append(path: &Path, rating: &Rating) -> io::Result<()> {
+    let json = serde_json::to_string(rating).expect("serialize rating");
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "{}", json)
+}
+
+/// Load the current rating of every rated exchange, in the order they
+/// were first rated. A missing file means none.
+pub fn load(path: &Path) -> io::Result<Vec<Rating>> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    parse(&contents).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// Parse the file, keeping the last rating of each exchange. A trailing
+/// line that fails to parse is skipped (crash mid-append).
+fn parse(contents: &str) -> Result<Vec<Rating>, String> {
+    let lines: Vec<&str> = contents.lines().filter(|l| !l.trim().is_empty()).collect();
+    let mut ratings: Vec<Rating> = Vec::with_capacity(lines.len());
+    for (index, line) in lines.iter().enumerate() {
+        let rating: Rating = match serde_json::from_str(line) {
+            Ok(rating) => rating,
+            Err(_) if index == lines.len() - 1 => continue,
+            Err(e) => return Err(format!("corrupt rating on line {}: {}", index + 1, e)),
+        };
+        let earlier = ratings
+            .iter_mut()
+            .find(|r| r.input == rating.input && r.output == rating.output);
+        match earlier {
+            Some(earlier) => *earlier = rating,
+            None => ratings.push(rating),
+        }
+    }
+    Ok(ratings)
+}
+
+/// `komatachi-cli ratings [--export] [--only good|bad]`
+pub fn run_command(args: &[String]) {
+    let mut export = false;
+    let mut only = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--export" => export = true,
+            "--only" => match args.next().map(String::as_str) {
+                Some("good") => only = Some(Verdict::Good),
+                Some("bad") => only = Some(Verdict::Bad),
+                _ => usage(),
+            },
+            _ => usage(),
+        }
+    }
+
+    let path = Paths::resolve().ratings_file();
+    let ratings = load(&path).unwrap_or_else(|e| {
+        eprintln!(
+            "{} cannot read {}: {}",
+            style::error("error:"),
+            path.display(),
+            e
+        );
+        std::process::exit(1);
+    });
+    let ratings: Vec<&Rating> = ratings
+        .iter()
+        .filter(|r| only.is_none_or(|only| r.verdict == only))
+        .collect();
+
+    if ratings.is_empty() {
+        eprintln!("No rated exchanges. Use /good or /bad [reason] in the REPL to rate one.");
+        return;
+    }
+    for (index, rating) in ratings.iter().enumerate() {
+        if export {
+            println!("{}", export_line(rating));
+        } else {
+            println!("{}", summary_line(index + 1, rating));
+        }
+    }
+}
+
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    std::process::exit(2);
+}
+
+fn summary_line(number: usize, rating: &Rating) -> String {
+    let verdict = match rating.verdict {
+        Verdict::Good => "good",
+        Verdict::Bad => "bad ",
+    };
+    let mut line = format!(
+        "{:>3}. {}  {}  {}",
+        number,
+        time::format_utc(rating.created_at),
+        verdict,
+        rating.input.lines().next().unwrap_or("")
+    );
+    if let Some(reason) = &rating.reason {
+        line.push_str(&format!("  ({})", reason));
+    }
+    line
+}
+
+/// One exchange as a JSON line: the user and assistant messages in
+/// Claude API format, with the verdict and reason beside them.
+fn export_line(rating: &Rating) -> serde_json::Value {
+    serde_json::json!({
+        "messages": [
+            {"role": "user", "content": rating.input},
+            {"role": "assistant", "content": rating.output},
+        ],
+        "verdict": rating.verdict,
+        "reason": rating.reason,
+        "ratedAt": rating.created_at,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn line(verdict: Verdict, input: &str) -> String {
+        serde_json::to_string(&Rating {
+            created_at: 0,
+            verdict,
+            reason: None,
+            input: input.to_string(),
+            output: "answer".to_string(),
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn later_rating_of_the_same_exchange_wins() {
+        let contents = format!(
+            "{}\n{}\n{}\n",
+            line(Verdict::Good, "one"),
+            line(Verdict::Good, "two"),
+            line(Verdict::Bad, "one")
+        );
+        let ratings = parse(&contents).unwrap();
+        assert_eq!(ratings.len(), 2);
+        assert_eq!(
+            (ratings[0].input.as_str(), ratings[0].verdict),
+            ("one", Verdict::Bad)
+        );
+        assert_eq!(ratings[1].verdict, Verdict::Good);
+    }
+
+    #[test]
+    fn skips_truncated_trailing_line() {
+        let contents = format!("{}\n{{\"createdAt\":1,", line(Verdict::Bad, "q"));
+        assert_eq!(parse(&contents).unwrap().len(), 1);
+        assert!(parse(&format!("nope\n{}\n", line(Verdict::Bad, "q"))).is_err());
+    }
+
+    #[test]
+    fn exports_api_messages_with_the_verdict() {
+        let json = export_line(&Rating {
+            created_at: 5,
+            verdict: Verdict::Bad,
+            reason: Some("too long".to_string()),
+            input: "q".to_string(),
+            output: "a".to_string(),
+        });
+        assert_eq!(json["messages"][1]["role"], "assistant");
+        assert_eq!(json["verdict"], "bad");
+        assert_eq!(json["reason"], "too long");
+    }
+}
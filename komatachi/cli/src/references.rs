@@ -0,0 +1,395 @@
+//! Quoting into a prompt: `@<name>#<from>[-<to>]` and `@<path>`.
+//!
+//! A message reference names a conversation (`main` or a fork) and a
+//! range of its messages, numbered as `komatachi-cli grep` prints them,
+//! so a line found with grep can be quoted by its address. A file
+//! reference is a path, relative to the working directory or under `~/`;
+//! a word is taken as one if it contains a `/` or names a file that
+//! exists, so `@someone` in prose is left alone. Files over
+//! `references.maxFileBytes`, and files that look binary (a NUL byte, or
+//! not UTF-8), are refused; the same limits apply to a `run` manifest's
+//! inputs. A quoted file's note gives its size and the start of its
+//! SHA-256, so the transcript records which version the agent saw.
+//!
+//! The prompt is sent as written (after any command substitution),
+//! followed by each quote under a note saying where it came from, so the
+//! agent sees the reference and the quote together. Transcripts and files
+//! are only read. Quotes together may take at most `references.maxTokens`
+//! (estimated); over that, nothing is sent. Never with `--safe`, whose
+//! point is that a prompt cannot read the real conversations or the
+//! host's files.
+
+use serde::Deserialize;
+use serde_json::Value;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::hash;
+use crate::inspect;
+use crate::paths;
+
+/// Default budget for everything quoted into one prompt.
+const DEFAULT_MAX_TOKENS: usize = 8000;
+
+/// Default size limit for one quoted file.
+const DEFAULT_MAX_FILE_BYTES: u64 = 100 * 1024;
+
+/// How much of a file is checked for NUL bytes, as `git` does.
+const BINARY_CHECK_BYTES: usize = 8000;
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "camelCase")]
+pub struct Settings {
+    /// Most estimated tokens quoted into one prompt.
+    pub max_tokens: Option<usize>,
+    /// Largest file quoted, in bytes.
+    pub max_file_bytes: Option<u64>,
+}
+
+impl Settings {
+    pub fn max_tokens(&self) -> usize {
+        self.max_tokens.unwrap_or(DEFAULT_MAX_TOKENS)
+    }
+
+    pub fn max_file_bytes(&self) -> u64 {
+        self.max_file_bytes.unwrap_or(DEFAULT_MAX_FILE_BYTES)
+    }
+}
+
+/// One `@...` in a prompt, as typed, and what it quotes.
+#[derive(Debug, PartialEq)]
+struct Reference<'a> {
+    text: &'a str,
+    source: Source<'a>,
+}
+
+#[derive(Debug, PartialEq)]
+enum Source<'a> {
+    /// `@name#from-to`: 1-based, inclusive message numbers.
+    Messages {
+        conversation: &'a str,
+        from: usize,
+        to: usize,
+    },
+    File(PathBuf),
+}
+
+/// The references in a prompt, in order. An `@` counts only at the start
+/// of a word, so addresses in e-mail and URLs are left alone.
+fn find(input: &str) -> Vec<Reference<'_>> {
+    let mut found = Vec::new();
+    for (at, _) in input.match_indices('@') {
+        let word_start = input[..at]
+            .chars()
+            .next_back()
+            .is_none_or(|c| c.is_whitespace() || c == '(');
+        if word_start {
+            if let Some(reference) = parse(&input[at..]).or_else(|| parse_file(&input[at..])) {
+                found.push(reference);
+            }
+        }
+    }
+    found
+}
+
+/// A message reference at the start of `text` (which starts with `@`).
+fn parse(text: &str) -> Option<Reference<'_>> {
+    let name_len = text[1..]
+        .find(|c: char| !(c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.'))
+        .unwrap_or(text.len() - 1);
+    let conversation = &text[1..1 + name_len];
+    let rest = text[1 + name_len..].strip_prefix('#')?;
+    let number = |s: &str| {
+        let len = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+        Some((s[..len].parse::<usize>().ok()?, len))
+    };
+    let (from, mut len) = number(rest)?;
+    let mut to = from;
+    if let Some((end, end_len)) = rest[len..].strip_prefix('-').and_then(number) {
+        to = end;
+        len += 1 + end_len;
+    }
+    if conversation.is_empty() || conversation.starts_with('.') || from == 0 || to < from {
+        return None;
+    }
+    let end = 1 + name_len + 1 + len;
+    Some(Reference {
+        text: &text[..end],
+        source: Source::Messages {
+            conversation,
+            from,
+            to,
+        },
+    })
+}
+
+/// A file reference at the start of `text`: the rest of the word, less
+/// trailing punctuation, if it looks like a path or names a file.
+fn parse_file(text: &str) -> Option<Reference<'_>> {
+    let word_len = text.find(char::is_whitespace).unwrap_or(text.len());
+    let word = text[..word_len].trim_end_matches(['.', ',', ';', ':', '!', '?', ')', '"', '\'']);
+    let path = &word[1..];
+    if path.is_empty() {
+        return None;
+    }
+    let expanded = match path.strip_prefix("~/") {
+        Some(rest) => PathBuf::from(std::env::var("HOME").unwrap_or_default()).join(rest),
+        None => PathBuf::from(path),
+    };
+    if !path.contains('/') && !expanded.is_file() {
+        return None;
+    }
+    Some(Reference {
+        text: word,
+        source: Source::File(expanded),
+    })
+}
+
+/// The quotes for every reference in `input`, to send after the prompt
+/// (empty without references), or an error saying why it cannot be sent.
+pub fn quotes(input: &str, settings: &Settings) -> Result<String, String> {
+    let references = find(input);
+    if references.is_empty() {
+        return Ok(String::new());
+    }
+    let conversations = paths::conversations();
+    let mut quoted = String::new();
+    let mut files = Vec::new();
+    for reference in &references {
+        let quote = match &reference.source {
+            Source::Messages {
+                conversation,
+                from,
+                to,
+            } => {
+                let Some((_, path)) = conversations.iter().find(|(name, _)| name == conversation)
+                else {
+                    return Err(format!(
+                        "{}: no conversation named '{}' (main or a fork name)",
+                        reference.text, conversation
+                    ));
+                };
+                let transcript = fs::read_to_string(path).map_err(|e| {
+                    format!("{}: cannot read {}: {}", reference.text, path.display(), e)
+                })?;
+                quote_messages(reference.text, conversation, *from, *to, &transcript)?
+            }
+            Source::File(path) => {
+                let text = read_file(path, settings.max_file_bytes())
+                    .map_err(|e| format!("{}: {}", reference.text, e))?;
+                let file = Attachment::of(reference.text, &text);
+                let quote = quote_file(&file, path, &text);
+                files.push(file.describe());
+                quote
+            }
+        };
+        quoted.push_str(&quote);
+    }
+    let tokens = inspect::estimate_tokens(&quoted);
+    if tokens > settings.max_tokens() {
+        return Err(format!(
+            "the references quote ~{} tokens, over references.maxTokens ({}); quote fewer messages",
+            tokens,
+            settings.max_tokens()
+        ));
+    }
+    eprintln!(
+        "Quoted {} reference(s), ~{} tokens.",
+        references.len(),
+        tokens
+    );
+    for file in files {
+        eprintln!("  {}", file);
+    }
+    Ok(quoted)
+}
+
+/// Messages `from` to `to` of a transcript, rendered as `/inspect`
+/// shows them.
+fn quote_messages(
+    text: &str,
+    conversation: &str,
+    from: usize,
+    to: usize,
+    transcript: &str,
+) -> Result<String, String> {
+    let messages: Vec<&str> = transcript.lines().collect();
+    if to > messages.len() {
+        return Err(format!(
+            "{}: '{}' has {} messages",
+            text,
+            conversation,
+            messages.len()
+        ));
+    }
+    let mut out = format!(
+        "\n\n[Note from the CLI: {} is messages {} to {} of the conversation '{}':]\n",
+        text, from, to, conversation
+    );
+    for number in from..=to {
+        let message: Value = serde_json::from_str(messages[number - 1]).unwrap_or(Value::Null);
+        out.push_str(&format!(
+            "\n[{}] {}\n",
+            number,
+            message["role"].as_str().unwrap_or("?")
+        ));
+        out.push_str(&inspect::render_content(&message["content"]));
+    }
+    Ok(out)
+}
+
+/// A file attached to a turn: its name as given, size, and digest.
+pub struct Attachment {
+    pub name: String,
+    pub bytes: usize,
+    pub sha256: String,
+}
+
+impl Attachment {
+    pub fn of(name: &str, text: &str) -> Attachment {
+        Attachment {
+            name: name.to_string(),
+            bytes: text.len(),
+            sha256: hash::sha256_hex(text.as_bytes()),
+        }
+    }
+
+    /// `name (N bytes, sha256 <first 12 digits>)`.
+    pub fn describe(&self) -> String {
+        format!(
+            "{} ({} bytes, sha256 {})",
+            self.name,
+            self.bytes,
+            &self.sha256[..12]
+        )
+    }
+}
+
+/// A file's text, if it is small enough and does not look binary.
+pub fn read_file(path: &Path, max_bytes: u64) -> Result<String, String> {
+    let size = fs::metadata(path)
+        .map_err(|e| format!("cannot read {}: {}", path.display(), e))?
+        .len();
+    if size > max_bytes {
+        return Err(format!(
+            "{} is {} bytes, over references.maxFileBytes ({})",
+            path.display(),
+            size,
+            max_bytes
+        ));
+    }
+    let bytes = fs::read(path).map_err(|e| format!("cannot read {}: {}", path.display(), e))?;
+    if bytes.iter().take(BINARY_CHECK_BYTES).any(|&b| b == 0) {
+        return Err(format!("{} looks like a binary file", path.display()));
+    }
+    String::from_utf8(bytes).map_err(|_| format!("{} is not UTF-8 text", path.display()))
+}
+
+/// A file's text in a code fence longer than any backtick run inside it.
+fn quote_file(file: &Attachment, path: &Path, contents: &str) -> String {
+    let longest = contents
+        .split(|c| c != '`')
+        .map(str::len)
+        .max()
+        .unwrap_or(0);
+    let fence = "`".repeat(longest.max(2) + 1);
+    format!(
+        "\n\n[Note from the CLI: {} is the file {} ({} bytes, sha256 {}):]\n{}\n{}\n{}\n",
+        file.name,
+        path.display(),
+        file.bytes,
+        &file.sha256[..12],
+        fence,
+        contents.trim_end_matches('\n'),
+        fence
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_references_at_word_starts() {
+        let refs = find("compare @main#3-4 with (@idea.v2#7). mail me@host#1");
+        assert_eq!(
+            refs,
+            vec![
+                Reference {
+                    text: "@main#3-4",
+                    source: Source::Messages {
+                        conversation: "main",
+                        from: 3,
+                        to: 4
+                    }
+                },
+                Reference {
+                    text: "@idea.v2#7",
+                    source: Source::Messages {
+                        conversation: "idea.v2",
+                        from: 7,
+                        to: 7
+                    }
+                },
+            ]
+        );
+        assert!(find("@main#0 @main#5-2 @main #3 @main#x").is_empty());
+    }
+
+    #[test]
+    fn quotes_messages_by_number() {
+        let transcript = concat!(
+            r#"{"role":"user","content":"first question"}"#,
+            "\n",
+            r#"{"role":"assistant","content":[{"type":"text","text":"first answer"}]}"#,
+            "\n",
+        );
+        assert_eq!(
+            quote_messages("@main#1-2", "main", 1, 2, transcript).unwrap(),
+            "\n\n[Note from the CLI: @main#1-2 is messages 1 to 2 of the conversation 'main':]\n\
+             \n[1] user\nfirst question\n\
+             \n[2] assistant\nfirst answer\n"
+        );
+        assert!(quote_messages("@main#2-3", "main", 2, 3, transcript)
+            .unwrap_err()
+            .contains("has 2 messages"));
+    }
+
+    #[test]
+    fn file_references_need_a_path_or_a_file() {
+        let refs = find("read @src/main.rs, then ask @someone");
+        assert_eq!(
+            refs,
+            vec![Reference {
+                text: "@src/main.rs",
+                source: Source::File(PathBuf::from("src/main.rs"))
+            }]
+        );
+        assert_eq!(find("see @Cargo.toml.")[0].text, "@Cargo.toml");
+    }
+
+    #[test]
+    fn refuses_large_and_binary_files() {
+        let dir = std::env::temp_dir().join(format!("komatachi-refs-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let text = dir.join("notes.md");
+        fs::write(&text, "has ``` inside\n").unwrap();
+        let contents = read_file(&text, 100).unwrap();
+        assert_eq!(
+            quote_file(&Attachment::of("@notes.md", &contents), &text, &contents),
+            format!(
+                "\n\n[Note from the CLI: @notes.md is the file {} (15 bytes, sha256 {}):]\n\
+                 ````\nhas ``` inside\n````\n",
+                text.display(),
+                &hash::sha256_hex(b"has ``` inside\n")[..12]
+            )
+        );
+        assert!(read_file(&text, 4)
+            .unwrap_err()
+            .contains("over references.maxFileBytes"));
+        let binary = dir.join("image.png");
+        fs::write(&binary, b"\x89PNG\0\0").unwrap();
+        assert!(read_file(&binary, 100).unwrap_err().contains("binary"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,689 @@
+//! A small backtracking regular expression engine.
+//!
+//! The CLI's dependencies are `serde` and `serde_json`, so patterns from
+//! the config file are compiled here. Supported syntax:
+//!
+//! - literals, `.`, `^`, `$`, `\b`
+//! - classes `[abc]`, `[^a-z]`, and `\d \w \s` (negated: `\D \W \S`)
+//! - groups `(...)` and alternation `|` (groups do not capture)
+//! - repetition `* + ? {n} {n,} {n,m}`, lazy with a trailing `?`
+//! - a leading `(?i)` for case-insensitive matching
+//!
+//! A pattern compiles to a list of instructions run with an explicit
+//! backtrack stack, so the Rust stack stays flat whatever the pattern or
+//! text. Each choice point is tried at most once at each position, which
+//! bounds the work by the pattern's size times the text's length and
+//! makes a repeated empty group fail instead of looping.
+
+use std::fmt;
+
+/// Largest `{n,m}` bound; each repetition is a copy of its atom in the
+/// compiled program.
+const MAX_REPEAT: usize = 1000;
+
+/// Largest compiled program, in instructions.
+const MAX_PROGRAM: usize = 20_000;
+
+#[derive(Debug)]
+pub struct Regex {
+    pattern: String,
+    program: Vec<Inst>,
+    /// Number of `Split` instructions, each numbered in `Inst::Split`.
+    splits: usize,
+    ignore_case: bool,
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+    Char(char),
+    Any,
+    Class {
+        items: Vec<ClassItem>,
+        negated: bool,
+    },
+    Start,
+    End,
+    WordBoundary,
+    Group(Vec<Vec<Node>>),
+    Repeat {
+        node: Box<Node>,
+        min: usize,
+        max: Option<usize>,
+        greedy: bool,
+    },
+}
+
+/// One step of a compiled pattern.
+#[derive(Debug)]
+enum Inst {
+    /// A single-character node: `Char`, `Any`, or `Class`.
+    One(Node),
+    Start,
+    End,
+    WordBoundary,
+    /// Try the first target, then the second on failure. The number
+    /// identifies the split in the matcher's visited set.
+    Split(usize, usize, usize),
+    Jump(usize),
+    Match,
+}
+
+#[derive(Debug, Clone)]
+enum ClassItem {
+    Range(char, char),
+    Perl(Perl, bool),
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Perl {
+    Digit,
+    Word,
+    Space,
+}
+
+impl Perl {
+    fn matches(self, c: char) -> bool {
+        match self {
+            Perl::Digit => c.is_ascii_digit(),
+            Perl::Word => c.is_alphanumeric() || c == '_',
+            Perl::Space => c.is_whitespace(),
+        }
+    }
+}
+
+impl Regex {
+    pub fn new(pattern: &str) -> Result<Regex, String> {
+        let (ignore_case, body) = match pattern.strip_prefix("(?i)") {
+            Some(rest) => (true, rest),
+            None => (false, pattern),
+        };
+        let mut parser = Parser {
+            chars: body.chars().collect(),
+            pos: 0,
+        };
+        let root = parser.alternation()?;
+        if parser.pos < parser.chars.len() {
+            return Err(format!("unmatched ')' at position {}", parser.pos));
+        }
+        let mut compiler = Compiler::default();
+        compiler.alternation(&root)?;
+        compiler.emit(Inst::Match)?;
+        Ok(Regex {
+            pattern: pattern.to_string(),
+            program: compiler.program,
+            splits: compiler.splits,
+            ignore_case,
+        })
+    }
+
+    pub fn is_match(&self, text: &str) -> bool {
+        self.find(text).is_some()
+    }
+
+    /// Byte range of the leftmost match.
+    pub fn find(&self, text: &str) -> Option<(usize, usize)> {
+        self.search(text, 1).into_iter().next()
+    }
+
+    /// Byte ranges of all non-overlapping matches, left to right.
+    pub fn find_all(&self, text: &str) -> Vec<(usize, usize)> {
+        self.search(text, usize::MAX)
+    }
+
+    /// Up to `limit` non-overlapping matches, left to right.
+    fn search(&self, text: &str, limit: usize) -> Vec<(usize, usize)> {
+        let chars: Vec<char> = text.chars().collect();
+        let mut offsets: Vec<usize> = text.char_indices().map(|(i, _)| i).collect();
+        offsets.push(text.len());
+        let mut matcher = Matcher {
+            program: &self.program,
+            chars: &chars,
+            ignore_case: self.ignore_case,
+            visited: vec![0; (self.splits * (chars.len() + 1)).div_ceil(64)],
+            marked: Vec::new(),
+            stack: Vec::new(),
+        };
+
+        let mut found = Vec::new();
+        let mut start = 0;
+        while start <= chars.len() && found.len() < limit {
+            match matcher.run(start) {
+                Some(end) => {
+                    found.push((offsets[start], offsets[end]));
+                    start = if end > start { end } else { start + 1 };
+                }
+                None => start += 1,
+            }
+        }
+        found
+    }
+}
+
+impl fmt::Display for Regex {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.pattern)
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Parsing
+// ---------------------------------------------------------------------------
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += 1;
+        Some(c)
+    }
+
+    fn alternation(&mut self) -> Result<Vec<Vec<Node>>, String> {
+        let mut branches = vec![self.sequence()?];
+        while self.peek() == Some('|') {
+            self.pos += 1;
+            branches.push(self.sequence()?);
+        }
+        Ok(branches)
+    }
+
+    fn sequence(&mut self) -> Result<Vec<Node>, String> {
+        let mut nodes = Vec::new();
+        while let Some(c) = self.peek() {
+            if c == '|' || c == ')' {
+                break;
+            }
+            let atom = self.atom()?;
+            nodes.push(self.repetition(atom)?);
+        }
+        Ok(nodes)
+    }
+
+    fn atom(&mut self) -> Result<Node, String> {
+        let at = self.pos;
+        match self.next().expect("atom at end of pattern") {
+            '.' => Ok(Node::Any),
+            '^' => Ok(Node::Start),
+            '$' => Ok(Node::End),
+            '(' => {
+                let group = self.alternation()?;
+                if self.next() != Some(')') {
+                    return Err(format!("unclosed '(' at position {}", at));
+                }
+                Ok(Node::Group(group))
+            }
+            '[' => self.class(at),
+            '\\' => self.escape(at),
+            c @ ('*' | '+' | '?') => {
+                Err(format!("'{}' at position {} has nothing to repeat", c, at))
+            }
+            c => Ok(Node::Char(c)),
+        }
+    }
+
+    fn escape(&mut self, at: usize) -> Result<Node, String> {
+        let Some(c) = self.next() else {
+            return Err(format!("trailing '\\' at position {}", at));
+        };
+        if let Some((perl, negated)) = perl_class(c) {
+            return Ok(Node::Class {
+                items: vec![ClassItem::Perl(perl, false)],
+                negated,
+            });
+        }
+        Ok(match c {
+            'b' => Node::WordBoundary,
+            'n' => Node::Char('\n'),
+            't' => Node::Char('\t'),
+            c => Node::Char(c),
+        })
+    }
+
+    fn class(&mut self, at: usize) -> Result<Node, String> {
+        let negated = self.peek() == Some('^');
+        if negated {
+            self.pos += 1;
+        }
+        let mut items = Vec::new();
+        let mut first = true;
+        loop {
+            let Some(c) = self.next() else {
+                return Err(format!("unclosed '[' at position {}", at));
+            };
+            if c == ']' && !first {
+                break;
+            }
+            first = false;
+            let low = if c == '\\' {
+                let Some(escaped) = self.next() else {
+                    return Err(format!("unclosed '[' at position {}", at));
+                };
+                if let Some((perl, negated)) = perl_class(escaped) {
+                    items.push(ClassItem::Perl(perl, negated));
+                    continue;
+                }
+                match escaped {
+                    'n' => '\n',
+                    't' => '\t',
+                    c => c,
+                }
+            } else {
+                c
+            };
+            let is_range = self.peek() == Some('-')
+                && self
+                    .chars
+                    .get(self.pos + 1)
+                    .is_some_and(|&next| next != ']');
+            if is_range {
+                self.pos += 1;
+                let high = self.next().expect("range end");
+                if high < low {
+                    return Err(format!("invalid range {}-{} in class", low, high));
+                }
+                items.push(ClassItem::Range(low, high));
+            } else {
+                items.push(ClassItem::Range(low, low));
+            }
+        }
+        Ok(Node::Class { items, negated })
+    }
+
+    fn repetition(&mut self, atom: Node) -> Result<Node, String> {
+        let at = self.pos;
+        let (min, max) = match self.peek() {
+            Some('*') => (0, None),
+            Some('+') => (1, None),
+            Some('?') => (0, Some(1)),
+            Some('{') => match self.bounds()? {
+                Some(bounds) => bounds,
+                None => return Ok(atom),
+            },
+            _ => return Ok(atom),
+        };
+        if self.pos == at {
+            self.pos += 1;
+        }
+        if matches!(atom, Node::Start | Node::End | Node::WordBoundary) {
+            return Err(format!("anchor before position {} cannot repeat", at));
+        }
+        let greedy = if self.peek() == Some('?') {
+            self.pos += 1;
+            false
+        } else {
+            true
+        };
+        if matches!(self.peek(), Some('*' | '+' | '?')) {
+            return Err(format!("nested repetition at position {}", self.pos));
+        }
+        Ok(Node::Repeat {
+            node: Box::new(atom),
+            min,
+            max,
+            greedy,
+        })
+    }
+
+    /// Parse `{n}`, `{n,}`, or `{n,m}` and step past the closing brace.
+    /// A `{` that does not start valid bounds is left for `atom` as a
+    /// literal.
+    fn bounds(&mut self) -> Result<Option<(usize, Option<usize>)>, String> {
+        let start = self.pos;
+        let close = match self.chars[start..].iter().position(|&c| c == '}') {
+            Some(offset) => start + offset,
+            None => return Ok(None),
+        };
+        let inner: String = self.chars[start + 1..close].iter().collect();
+        let parse = |s: &str| s.parse::<usize>().ok();
+        let bounds = match inner.split_once(',') {
+            None => parse(&inner).map(|n| (n, Some(n))),
+            Some((min, "")) => parse(min).map(|n| (n, None)),
+            Some((min, max)) => match (parse(min), parse(max)) {
+                (Some(min), Some(max)) => Some((min, Some(max))),
+                _ => None,
+            },
+        };
+        let Some((min, max)) = bounds else {
+            return Ok(None);
+        };
+        if max.is_some_and(|max| max < min) {
+            return Err(format!("invalid bounds {{{}}}", inner));
+        }
+        if max.unwrap_or(min) > MAX_REPEAT {
+            return Err(format!(
+                "bounds {{{}}} exceed the limit of {}",
+                inner, MAX_REPEAT
+            ));
+        }
+        self.pos = close + 1;
+        Ok(Some((min, max)))
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Compiling
+// ---------------------------------------------------------------------------
+
+#[derive(Default)]
+struct Compiler {
+    program: Vec<Inst>,
+    splits: usize,
+}
+
+impl Compiler {
+    fn emit(&mut self, inst: Inst) -> Result<usize, String> {
+        if self.program.len() >= MAX_PROGRAM {
+            return Err(format!(
+                "pattern is too large (over {} steps once repetitions are expanded)",
+                MAX_PROGRAM
+            ));
+        }
+        self.program.push(inst);
+        Ok(self.program.len() - 1)
+    }
+
+    /// Emit a split whose targets are filled in by `patch`.
+    fn split(&mut self) -> Result<usize, String> {
+        self.splits += 1;
+        self.emit(Inst::Split(0, 0, self.splits - 1))
+    }
+
+    /// Point the split at `at` to `prefer` first, then `other`.
+    fn patch(&mut self, at: usize, prefer: usize, other: usize) {
+        if let Inst::Split(first, second, _) = &mut self.program[at] {
+            *first = prefer;
+            *second = other;
+        }
+    }
+
+    fn alternation(&mut self, branches: &[Vec<Node>]) -> Result<(), String> {
+        let mut jumps = Vec::new();
+        for (i, branch) in branches.iter().enumerate() {
+            if i + 1 == branches.len() {
+                self.sequence(branch)?;
+                break;
+            }
+            let split = self.split()?;
+            self.sequence(branch)?;
+            jumps.push(self.emit(Inst::Jump(0))?);
+            let next = self.program.len();
+            self.patch(split, split + 1, next);
+        }
+        let end = self.program.len();
+        for jump in jumps {
+            self.program[jump] = Inst::Jump(end);
+        }
+        Ok(())
+    }
+
+    fn sequence(&mut self, seq: &[Node]) -> Result<(), String> {
+        seq.iter().try_for_each(|node| self.node(node))
+    }
+
+    fn node(&mut self, node: &Node) -> Result<(), String> {
+        match node {
+            Node::Start => self.emit(Inst::Start).map(drop),
+            Node::End => self.emit(Inst::End).map(drop),
+            Node::WordBoundary => self.emit(Inst::WordBoundary).map(drop),
+            Node::Group(branches) => self.alternation(branches),
+            Node::Repeat {
+                node,
+                min,
+                max,
+                greedy,
+            } => self.repeat(node, *min, *max, *greedy),
+            single => self.emit(Inst::One(single.clone())).map(drop),
+        }
+    }
+
+    fn repeat(
+        &mut self,
+        node: &Node,
+        min: usize,
+        max: Option<usize>,
+        greedy: bool,
+    ) -> Result<(), String> {
+        for _ in 0..min {
+            self.node(node)?;
+        }
+        let Some(max) = max else {
+            // Loop: split into the body or past it, and jump back.
+            let split = self.split()?;
+            self.node(node)?;
+            self.emit(Inst::Jump(split))?;
+            let exit = self.program.len();
+            self.choose(split, split + 1, exit, greedy);
+            return Ok(());
+        };
+        // Optional copies: stopping at any of them skips the rest.
+        let mut splits = Vec::new();
+        for _ in min..max {
+            splits.push(self.split()?);
+            self.node(node)?;
+        }
+        let exit = self.program.len();
+        for split in splits {
+            self.choose(split, split + 1, exit, greedy);
+        }
+        Ok(())
+    }
+
+    /// Patch a repetition's split: into the body first when greedy.
+    fn choose(&mut self, split: usize, body: usize, exit: usize, greedy: bool) {
+        if greedy {
+            self.patch(split, body, exit);
+        } else {
+            self.patch(split, exit, body);
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Matching
+// ---------------------------------------------------------------------------
+
+struct Matcher<'a> {
+    program: &'a [Inst],
+    chars: &'a [char],
+    ignore_case: bool,
+    /// One bit per (split, position) already tried. A choice point that
+    /// failed once fails again from any later start, so bits survive
+    /// between starts and are cleared only after a match.
+    visited: Vec<u64>,
+    /// Bits set since the last clear.
+    marked: Vec<usize>,
+    /// Backtrack points: an instruction and a position to resume at.
+    stack: Vec<(usize, usize)>,
+}
+
+impl Matcher<'_> {
+    /// The end of the first match starting at `start`, in priority order.
+    fn run(&mut self, start: usize) -> Option<usize> {
+        self.stack.clear();
+        self.stack.push((0, start));
+        while let Some((mut pc, mut pos)) = self.stack.pop() {
+            loop {
+                match &self.program[pc] {
+                    Inst::One(node) => {
+                        if !self.single(node, pos) {
+                            break;
+                        }
+                        pos += 1;
+                    }
+                    Inst::Start if pos != 0 => break,
+                    Inst::End if pos != self.chars.len() => break,
+                    Inst::WordBoundary if !self.at_word_boundary(pos) => break,
+                    Inst::Start | Inst::End | Inst::WordBoundary => {}
+                    &Inst::Split(first, second, id) => {
+                        if !self.visit(id, pos) {
+                            break;
+                        }
+                        self.stack.push((second, pos));
+                        pc = first;
+                        continue;
+                    }
+                    &Inst::Jump(target) => {
+                        pc = target;
+                        continue;
+                    }
+                    Inst::Match => {
+                        self.clear();
+                        return Some(pos);
+                    }
+                }
+                pc += 1;
+            }
+        }
+        None
+    }
+
+    /// Mark a split as tried at `pos`; false if it already was.
+    fn visit(&mut self, id: usize, pos: usize) -> bool {
+        let bit = id * (self.chars.len() + 1) + pos;
+        let (word, mask) = (bit / 64, 1u64 << (bit % 64));
+        if self.visited[word] & mask != 0 {
+            return false;
+        }
+        self.visited[word] |= mask;
+        self.marked.push(bit);
+        true
+    }
+
+    fn clear(&mut self) {
+        for bit in self.marked.drain(..) {
+            self.visited[bit / 64] &= !(1u64 << (bit % 64));
+        }
+    }
+
+    /// Whether the character at `pos` matches a single-character node.
+    fn single(&self, node: &Node, pos: usize) -> bool {
+        let Some(&c) = self.chars.get(pos) else {
+            return false;
+        };
+        match node {
+            Node::Char(expected) => {
+                c == *expected || (self.ignore_case && c.to_lowercase().eq(expected.to_lowercase()))
+            }
+            Node::Any => c != '\n',
+            Node::Class { items, negated } => {
+                let hit = items.iter().any(|item| self.class_item(item, c));
+                hit != *negated
+            }
+            _ => unreachable!("not a single-character node"),
+        }
+    }
+
+    fn class_item(&self, item: &ClassItem, c: char) -> bool {
+        match *item {
+            ClassItem::Perl(perl, negated) => perl.matches(c) != negated,
+            ClassItem::Range(low, high) => {
+                let within = |c: char| low <= c && c <= high;
+                within(c)
+                    || (self.ignore_case
+                        && (c.to_lowercase().any(within) || c.to_uppercase().any(within)))
+            }
+        }
+    }
+
+    fn at_word_boundary(&self, pos: usize) -> bool {
+        let is_word = |i: Option<&char>| i.is_some_and(|&c| Perl::Word.matches(c));
+        let before = pos.checked_sub(1).and_then(|i| self.chars.get(i));
+        is_word(before) != is_word(self.chars.get(pos))
+    }
+}
+
+/// `\d \w \s` and their negations.
+fn perl_class(c: char) -> Option<(Perl, bool)> {
+    match c {
+        'd' => Some((Perl::Digit, false)),
+        'D' => Some((Perl::Digit, true)),
+        'w' => Some((Perl::Word, false)),
+        'W' => Some((Perl::Word, true)),
+        's' => Some((Perl::Space, false)),
+        'S' => Some((Perl::Space, true)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn find(pattern: &str, text: &str) -> Option<String> {
+        let regex = Regex::new(pattern).unwrap();
+        regex.find(text).map(|(s, e)| text[s..e].to_string())
+    }
+
+    #[test]
+    fn matches_literals_classes_and_anchors() {
+        assert_eq!(find("b.d", "abcde").as_deref(), Some("bcd"));
+        assert_eq!(find("[0-9]+", "order 66 now").as_deref(), Some("66"));
+        assert_eq!(
+            find("\\d{3}-\\d{4}", "call 555-1234").as_deref(),
+            Some("555-1234")
+        );
+        assert_eq!(find("^done$", "done"), Some("done".to_string()));
+        assert_eq!(find("^done$", "not done"), None);
+        assert_eq!(find("[^a-z ]+", "abc DEF"), Some("DEF".to_string()));
+        assert_eq!(find("[\\d.]+", "v1.2.3").as_deref(), Some("1.2.3"));
+        assert_eq!(find("a{2}", "a aa").as_deref(), Some("aa"));
+        assert_eq!(find("x{", "x{").as_deref(), Some("x{"));
+    }
+
+    #[test]
+    fn backtracks_through_groups_and_alternation() {
+        assert_eq!(find("(ab|a)c", "xabc").as_deref(), Some("abc"));
+        assert_eq!(find("(ha)+!", "hahaha!").as_deref(), Some("hahaha!"));
+        assert_eq!(find("a.*b", "a1b2b3").as_deref(), Some("a1b2b"));
+        assert_eq!(find("a.*?b", "a1b2b3").as_deref(), Some("a1b"));
+        assert_eq!(find("(a|b)*c", "ababc").as_deref(), Some("ababc"));
+    }
+
+    #[test]
+    fn word_boundaries_and_case_folding() {
+        assert_eq!(find("\\bcat\\b", "concat cat").as_deref(), Some("cat"));
+        assert!(!Regex::new("\\bcat\\b").unwrap().is_match("concatenate"));
+        assert_eq!(find("(?i)error", "Fatal ERROR").as_deref(), Some("ERROR"));
+        assert_eq!(find("(?i)[a-c]+", "xxABC").as_deref(), Some("ABC"));
+    }
+
+    #[test]
+    fn finds_all_matches_with_byte_offsets() {
+        let regex = Regex::new("\\w+@\\w+\\.com").unwrap();
+        let text = "é a@b.com, c@d.com";
+        let ranges = regex.find_all(text);
+        let found: Vec<&str> = ranges.iter().map(|&(s, e)| &text[s..e]).collect();
+        assert_eq!(found, ["a@b.com", "c@d.com"]);
+    }
+
+    #[test]
+    fn long_input_does_not_overflow() {
+        let text = "x".repeat(200_000);
+        assert!(Regex::new("^x*$").unwrap().is_match(&text));
+        let words = "word ".repeat(20_000);
+        assert!(Regex::new("(\\w+\\s)*$").unwrap().is_match(&words));
+    }
+
+    #[test]
+    fn empty_and_ambiguous_repetition_terminates() {
+        assert_eq!(find("(a*)*b", "aaab").as_deref(), Some("aaab"));
+        assert_eq!(find("(a?)+$", "aa").as_deref(), Some("aa"));
+        let text = "a".repeat(5_000);
+        assert!(!Regex::new("^(a|a)*b").unwrap().is_match(&text));
+        assert!(!Regex::new("^(a*)*b").unwrap().is_match(&text));
+    }
+
+    #[test]
+    fn rejects_malformed_patterns() {
+        for pattern in ["(ab", "ab)", "[a-", "*a", "a**", "x{3,1}", "\\", "x{1001}"] {
+            assert!(Regex::new(pattern).is_err(), "accepted {:?}", pattern);
+        }
+    }
+}
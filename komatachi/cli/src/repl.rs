@@ -0,0 +1,1844 @@
+//! Interactive read-eval-print loop.
+//!
+//! Reads a line from the terminal, sends it to the agent, prints the
+//! response. Lines starting with a known `/command` are handled locally.
+
+use std::fs;
+use std::io::{self, IsTerminal};
+use std::time::{Duration, Instant};
+
+use crate::agent::{Agent, ReceiveError};
+use crate::bookmarks::{self, Bookmark};
+use crate::checkpoints;
+use crate::clipboard::{self, Copied};
+use crate::compose;
+use crate::config::Config;
+use crate::diff;
+use crate::draft;
+use crate::find;
+use crate::fork;
+use crate::help;
+use crate::history;
+use crate::idle;
+use crate::inspect;
+use crate::lang;
+use crate::limits;
+use crate::lineedit::{self, KeyWatch, LineEditor};
+use crate::notify;
+use crate::options::Options;
+use crate::pager;
+use crate::paths::{self, Paths};
+use crate::prompt;
+use crate::protocol::{AgentMessage, ClientMessage, Usage};
+use crate::ratings::{self, Rating, Verdict};
+use crate::references;
+use crate::regex::Regex;
+use crate::review;
+use crate::scrub;
+use crate::sinks::{self, Sinks};
+use crate::snippets::{self, Snippets};
+use crate::speech::{self, Speaker};
+use crate::spinner::Spinner;
+use crate::stream;
+use crate::style;
+use crate::substitute;
+use crate::terminal;
+use crate::time;
+use crate::timestamps;
+use crate::turn::{self, Reply};
+use crate::validate::{self, Validator};
+use crate::voice;
+use crate::watchdog::{self, Lost};
+use crate::wrap;
+
+/// One completed user/agent exchange in this run.
+struct Exchange {
+    input: String,
+    output: String,
+    /// Checkpoint the conversation was at before the exchange, for `/retry`
+    /// and `/undo`.
+    before: Option<u64>,
+}
+
+/// Whether the REPL keeps going after handling a line.
+enum Flow {
+    Continue,
+    Quit,
+}
+
+/// A REPL command: `/name args`, handled by `run`.
+struct SlashCommand {
+    name: &'static str,
+    /// Argument synopsis for `/help`. Commands with none reject arguments.
+    args: &'static str,
+    help: &'static str,
+    run: fn(&mut Repl, &str) -> Flow,
+}
+
+/// Every REPL command, in `/help` order.
+const COMMANDS: &[SlashCommand] = &[
+    SlashCommand {
+        name: "/help",
+        args: "[topic|word]",
+        help: "List these commands, show a topic, or search the help",
+        run: |repl, topic| {
+            repl.help(topic);
+            Flow::Continue
+        },
+    },
+    SlashCommand {
+        name: "/bookmark",
+        args: "[note]",
+        help: "Bookmark the most recent exchange",
+        run: |repl, note| {
+            repl.bookmark(note);
+            Flow::Continue
+        },
+    },
+    SlashCommand {
+        name: "/good",
+        args: "[reason]",
+        help: "Rate the most recent exchange good",
+        run: |repl, reason| {
+            repl.rate(Verdict::Good, reason);
+            Flow::Continue
+        },
+    },
+    SlashCommand {
+        name: "/bad",
+        args: "[reason]",
+        help: "Rate the most recent exchange bad",
+        run: |repl, reason| {
+            repl.rate(Verdict::Bad, reason);
+            Flow::Continue
+        },
+    },
+    SlashCommand {
+        name: "/edit",
+        args: "[text]",
+        help: "Write a prompt in $EDITOR, starting from the optional text",
+        run: |repl, text| match compose::compose(text) {
+            Ok(Some(prompt)) => {
+                eprintln!("> {}", prompt);
+                repl.send_prompt(&prompt)
+            }
+            Ok(None) => {
+                eprintln!("Nothing to send (the file was left empty).");
+                Flow::Continue
+            }
+            Err(e) => {
+                eprintln!("{} {}", style::error("error:"), e);
+                Flow::Continue
+            }
+        },
+    },
+    SlashCommand {
+        name: "/send-to",
+        args: "[sink]",
+        help: "Copy the most recent exchange to a sink, or list the sinks",
+        run: |repl, sink| {
+            repl.send_to(sink);
+            Flow::Continue
+        },
+    },
+    SlashCommand {
+        name: "/paste",
+        args: "[instruction]",
+        help: "Send the clipboard, after the optional instruction",
+        run: |repl, instruction| match clipboard::prompt(instruction, &repl.scrub) {
+            Some(prompt) => repl.send_turn(&prompt),
+            None => Flow::Continue,
+        },
+    },
+    SlashCommand {
+        name: "/snippet",
+        args: "[name] [text]",
+        help: "Send a snippet from config.json, then the optional text, or list them",
+        run: Repl::snippet,
+    },
+    SlashCommand {
+        name: "/copy",
+        args: "[code]",
+        help: "Copy the last answer, or its last code block, to the clipboard",
+        run: |repl, what| {
+            repl.copy(what);
+            Flow::Continue
+        },
+    },
+    SlashCommand {
+        name: "/fork",
+        args: "[name]",
+        help: "Copy the conversation and continue in the copy",
+        run: |repl, name| {
+            repl.fork(name);
+            Flow::Continue
+        },
+    },
+    SlashCommand {
+        name: "/inspect",
+        args: "[--json]",
+        help: "Show the last request sent to the model",
+        run: Repl::inspect,
+    },
+    SlashCommand {
+        name: "/history",
+        args: "[n]",
+        help: "Print the last n exchanges of the conversation again (default 3)",
+        run: |repl, count| {
+            repl.history(count);
+            Flow::Continue
+        },
+    },
+    SlashCommand {
+        name: "/find",
+        args: "<regex>",
+        help: "Search this session's prompts and answers, with context",
+        run: |repl, pattern| {
+            repl.find(pattern);
+            Flow::Continue
+        },
+    },
+    SlashCommand {
+        name: "/checkpoints",
+        args: "",
+        help: "List recent turn checkpoints",
+        run: |repl, _| {
+            repl.list_checkpoints();
+            Flow::Continue
+        },
+    },
+    SlashCommand {
+        name: "/rollback",
+        args: "<id>",
+        help: "Return the conversation to a checkpoint",
+        run: Repl::rollback,
+    },
+    SlashCommand {
+        name: "/undo",
+        args: "",
+        help: "Remove the last exchange, or a failed prompt, from the conversation",
+        run: |repl, _| repl.undo(),
+    },
+    SlashCommand {
+        name: "/retry",
+        args: "[edit]",
+        help: "Roll back the last turn, or a failed one, and ask again (edit: in $EDITOR first)",
+        run: Repl::retry,
+    },
+    SlashCommand {
+        name: "/diff",
+        args: "",
+        help: "After /retry, diff the new answer against the old one",
+        run: |repl, _| {
+            repl.diff();
+            Flow::Continue
+        },
+    },
+    SlashCommand {
+        name: "/lang",
+        args: "[code|off]",
+        help: "Show, set, or clear the response language",
+        run: |repl, args| {
+            repl.set_lang(args);
+            Flow::Continue
+        },
+    },
+    SlashCommand {
+        name: "/meta",
+        args: "[on|off]",
+        help: "Toggle the metadata line under each response",
+        run: |repl, args| {
+            repl.toggle_turn_meta(args);
+            Flow::Continue
+        },
+    },
+    SlashCommand {
+        name: "/keys",
+        args: "[vi|emacs]",
+        help: "Show or switch the key bindings at the prompt",
+        run: |repl, args| {
+            repl.switch_keys(args);
+            Flow::Continue
+        },
+    },
+    SlashCommand {
+        name: "/timestamps",
+        args: "[on|off]",
+        help: "Toggle the wall-clock time on each prompt and answer",
+        run: |repl, args| {
+            repl.toggle_timestamps(args);
+            Flow::Continue
+        },
+    },
+    SlashCommand {
+        name: "/clear",
+        args: "",
+        help: "Clear the screen",
+        run: |_, _| {
+            if std::io::stderr().is_terminal() {
+                eprint!("\x1b[H\x1b[2J");
+            }
+            Flow::Continue
+        },
+    },
+    SlashCommand {
+        name: "/quit",
+        args: "",
+        help: "Stop the agent and exit (also /exit)",
+        run: |_, _| Flow::Quit,
+    },
+    SlashCommand {
+        name: "/exit",
+        args: "",
+        help: "Same as /quit",
+        run: |_, _| Flow::Quit,
+    },
+];
+
+/// A prompt written `//...` is sent as `/...`.
+fn unescape_slash(input: &str) -> &str {
+    match input.strip_prefix('/') {
+        Some(rest) if rest.starts_with('/') => rest,
+        _ => input,
+    }
+}
+
+fn find_command(name: &str) -> Option<&'static SlashCommand> {
+    COMMANDS.iter().find(|command| command.name == name)
+}
+
+/// Whether `input` ends the session: `quit`, `exit`, `/quit`, or `/exit`.
+fn is_quit(input: &str) -> bool {
+    matches!(input, "quit" | "exit")
+        || find_command(input).is_some_and(|command| matches!(command.name, "/quit" | "/exit"))
+}
+
+struct Repl {
+    agent: Agent,
+    paths: Paths,
+    /// Needed to respawn the agent when switching conversations.
+    api_key: String,
+    last_exchange: Option<Exchange>,
+    /// Every exchange printed this session, for `/find`.
+    shown: Vec<find::Shown>,
+    /// The answer `/retry` replaced, for `/diff`.
+    replaced_answer: Option<String>,
+    /// The prompt of the last turn if it ended in an error, for `/retry`.
+    failed_input: Option<String>,
+    /// Most recent checkpoint of the conversation, if known.
+    checkpoint: Option<u64>,
+    /// Print model, latency, and token counts under each response.
+    show_turn_meta: bool,
+    /// Print when each prompt was sent and its answer arrived.
+    show_timestamps: bool,
+    /// Transcription command for `--voice`; an empty line starts recording.
+    voice_command: Option<String>,
+    /// TTS backend for `--speak`.
+    speaker: Option<Speaker>,
+    /// Response rules from the config file.
+    validator: Option<Validator>,
+    /// `--safe`: scratch conversation; nothing is kept or copied out.
+    safe: bool,
+    /// Variables every agent started gets, from `routing` in the config.
+    agent_env: Vec<(&'static str, String)>,
+    /// Stall limit and restart policy.
+    watchdog: watchdog::Settings,
+    /// `` !`command` `` expansion in prompts; never on with `--safe`.
+    substitution: substitute::Settings,
+    /// When to stop the agent while waiting at the prompt.
+    idle: idle::Settings,
+    /// Budget for `@name#n` quotes; none are made with `--safe`.
+    references: references::Settings,
+    /// What `/paste` scrubs, if anything.
+    scrub: scrub::Settings,
+    /// The prompt's format (see `prompt.rs`).
+    prompt: prompt::Settings,
+    /// `main`, the fork's name, or `safe`, for the prompt.
+    conversation: String,
+    /// Turns answered this session.
+    turns: usize,
+    /// When the session started, for `limits`.
+    started: Instant,
+    /// Tokens in and out over every turn this session.
+    tokens_used: u64,
+    /// When to end a session nobody is attending.
+    limits: limits::Settings,
+    /// Model and tokens in and out of the last turn, for the prompt.
+    last_usage: Option<(String, u64)>,
+    /// When a long turn ends with a desktop notification.
+    notify: notify::Settings,
+    /// Whether answers are printed as they are written.
+    stream: stream::Settings,
+    /// Whether the terminal was left during the last turn.
+    lost_focus: bool,
+    /// Latency of the first answered turn, for the startup timings.
+    first_turn: Option<Duration>,
+    /// Key bindings at the prompt; `/keys` changes them.
+    keys: lineedit::Mode,
+    /// Whether prompts are saved, and how many are recalled.
+    history: history::Settings,
+    /// When answers go through the pager.
+    pager: pager::Settings,
+    wrap: wrap::Settings,
+    /// `--turn-deadline`: how long a turn may run before it is finalized.
+    turn_deadline: Option<Duration>,
+    /// When the turn in flight must be finalized.
+    finalize_at: Option<Instant>,
+    /// `--lang` / `/lang`: language every prompt asks the answer in.
+    lang: Option<String>,
+    /// Keys typed while a turn ran, for the next prompt.
+    typed_ahead: Vec<u8>,
+    /// Where `/send-to` copies exchanges; never used with `--safe`.
+    sinks: Sinks,
+    /// Text `:name` and `/snippet name` stand for.
+    snippets: Snippets,
+}
+
+/// Run the REPL until the user quits or the agent exits, then shut the
+/// agent down. `initial_input`, if given, is sent as the first prompt.
+/// Returns how long the first answered turn took, if there was one.
+pub fn run(
+    agent: Agent,
+    paths: Paths,
+    api_key: String,
+    options: Options,
+    config: Config,
+    validator: Option<Validator>,
+    initial_input: Option<String>,
+) -> Option<Duration> {
+    eprintln!("Komatachi ready. Type /help for commands, 'quit' or 'exit' to stop.\n");
+    let voice_command = if options.voice {
+        voice::command()
+    } else {
+        None
+    };
+    if voice_command.is_some() {
+        eprintln!("Voice input on: press Enter on an empty line to speak.\n");
+    }
+    let speaker = if options.speak {
+        let speaker = Speaker::detect();
+        if speaker.is_none() {
+            eprintln!(
+                "{} --speak: no TTS command found (install say/espeak or set {})",
+                style::warning("warning:"),
+                speech::SPEAK_COMMAND_VAR
+            );
+        }
+        speaker
+    } else {
+        None
+    };
+
+    if options.safe {
+        eprintln!(
+            "Safe mode: scratch agent in {}, deleted on exit. Prompts are not saved to history.\n",
+            paths.data_dir.parent().unwrap_or(&paths.data_dir).display()
+        );
+    }
+    let initial_input = initial_input.or_else(|| offer_draft(&paths));
+    let prefill = options.prefill;
+    let checkpoint = latest_checkpoint(&paths);
+    let conversation = if options.safe {
+        paths::SAFE.to_string()
+    } else {
+        paths::conversation_name(&paths.data_dir)
+    };
+    let mut repl = Repl {
+        agent,
+        paths,
+        api_key,
+        last_exchange: None,
+        shown: Vec::new(),
+        replaced_answer: None,
+        failed_input: None,
+        checkpoint,
+        show_turn_meta: true,
+        show_timestamps: config.timestamps.enabled(),
+        voice_command,
+        speaker,
+        validator,
+        safe: options.safe,
+        agent_env: config.routing.env(),
+        watchdog: config.watchdog,
+        substitution: config.substitution,
+        idle: config.idle,
+        references: config.references,
+        scrub: config.scrub,
+        keys: config.keys.mode,
+        history: config.history,
+        first_turn: None,
+        notify: config.notify,
+        stream: config.stream,
+        prompt: config.prompt,
+        conversation,
+        turns: 0,
+        started: Instant::now(),
+        tokens_used: 0,
+        limits: config.limits,
+        last_usage: None,
+        lost_focus: false,
+        pager: config.pager,
+        wrap: config.wrap,
+        turn_deadline: options.turn_deadline,
+        finalize_at: None,
+        lang: options.lang,
+        typed_ahead: Vec::new(),
+        sinks: config.sinks,
+        snippets: config.snippets,
+    };
+    repl.run_loop(initial_input, prefill);
+
+    // Clean up: closing stdin makes the container exit
+    repl.agent.shutdown();
+    repl.first_turn
+}
+
+impl Repl {
+    /// The prompt string, from `prompt.format`.
+    fn prompt_context(&self) -> prompt::Context<'_> {
+        prompt::Context {
+            conversation: &self.conversation,
+            model: self.last_usage.as_ref().map(|(model, _)| model.as_str()),
+            turn: self.turns + 1,
+            tokens: self.last_usage.as_ref().map(|(_, tokens)| *tokens),
+            window: prompt::window(),
+        }
+    }
+
+    fn prompt(&self) -> String {
+        // The format was checked when the config was loaded.
+        prompt::render(self.prompt.format(), &self.prompt_context())
+            .unwrap_or_else(|_| "> ".to_string())
+    }
+
+    /// `prefill`, if given, starts the first line read at the prompt.
+    fn run_loop(&mut self, initial_input: Option<String>, mut prefill: Option<String>) {
+        let mut editor = LineEditor::new(COMMANDS.iter().map(|c| c.name).collect());
+        if !self.safe && self.history.enabled() {
+            let path = history::history_file();
+            match history::recall(&path, self.history.size()) {
+                Ok(prompts) => prompts.iter().for_each(|prompt| editor.add_history(prompt)),
+                Err(e) => eprintln!(
+                    "{} cannot read {}: {}",
+                    style::warning("warning:"),
+                    path.display(),
+                    e
+                ),
+            }
+        }
+        let mut input_buf;
+        let mut pending_input = initial_input;
+
+        loop {
+            if let Some(reached) = self.limits.reached(self.started.elapsed(), self.turns) {
+                self.end_at_limit(&reached, None);
+                break;
+            }
+            if let Some(text) = pending_input.take() {
+                eprintln!("{}{}", self.prompt(), text);
+                input_buf = text;
+            } else {
+                let timer = self
+                    .idle
+                    .suspend_after()
+                    .map(|after| idle::Timer::start(after, self.agent.name()));
+                let deadline = self.limits.deadline(self.started, Instant::now());
+                editor.give_up_at(deadline.as_ref().map(|(at, _)| *at));
+                editor.set_mode(self.keys);
+                self.keep_unsent(&mut editor);
+                if let Some(text) = prefill.take() {
+                    editor.prefill(text);
+                }
+                editor.type_ahead(std::mem::take(&mut self.typed_ahead));
+                let line = editor.read_line(&self.prompt());
+                let suspended = timer.is_some_and(idle::Timer::finish);
+                match line {
+                    Ok(Some(line)) => input_buf = line,
+                    Ok(None) => break, // EOF
+                    Err(e) if e.kind() == io::ErrorKind::TimedOut => {
+                        if let Some((_, reached)) = deadline {
+                            self.end_at_limit(&reached, editor.take_abandoned());
+                        }
+                        break;
+                    }
+                    Err(e) => {
+                        eprintln!("{} reading input: {}", style::error("error:"), e);
+                        break;
+                    }
+                }
+                if suspended && !is_quit(input_buf.trim()) && !self.resume() {
+                    break;
+                }
+            }
+
+            if input_buf.trim().is_empty() {
+                match self.voice_input() {
+                    Some(text) => input_buf = text,
+                    None => continue,
+                }
+            }
+            let input = input_buf.trim();
+            editor.add_history(input);
+            let flow = match self.handle_command(input) {
+                Some(flow) => flow,
+                None => self.send_prompt(unescape_slash(input)),
+            };
+            if let Flow::Quit = flow {
+                break;
+            }
+        }
+    }
+
+    /// Record and transcribe a prompt, if voice input is on. The user
+    /// confirms the transcription before it is sent.
+    fn voice_input(&self) -> Option<String> {
+        let command = self.voice_command.as_deref()?;
+        eprintln!("Listening...");
+        match voice::transcribe(command) {
+            Ok(text) => {
+                eprintln!("\n{}\n", text);
+                if terminal::confirm("Send this?") {
+                    Some(text)
+                } else {
+                    None
+                }
+            }
+            Err(e) => {
+                eprintln!("{} {}", style::error("error:"), e);
+                None
+            }
+        }
+    }
+
+    /// Handle a REPL command. Returns `None` if `input` is not a command
+    /// and should go to the agent.
+    fn handle_command(&mut self, input: &str) -> Option<Flow> {
+        if input == "quit" || input == "exit" {
+            return Some(Flow::Quit);
+        }
+        if !input.starts_with('/') || input.starts_with("//") {
+            return None;
+        }
+        let (name, args) = match input.split_once(char::is_whitespace) {
+            Some((name, args)) => (name, args.trim()),
+            None => (input, ""),
+        };
+        let Some(command) = find_command(name) else {
+            eprintln!(
+                "{} unknown command {} (/help lists them; start a prompt with // to send a leading /)",
+                style::error("error:"),
+                name
+            );
+            return Some(Flow::Continue);
+        };
+        if command.args.is_empty() && !args.is_empty() {
+            eprintln!("usage: {}", command.name);
+            return Some(Flow::Continue);
+        }
+        Some((command.run)(self, args))
+    }
+
+    /// `/help [topic|word]`: list the REPL commands, show one command or
+    /// topic, or list those mentioning a word.
+    fn help(&self, topic: &str) {
+        if topic.is_empty() {
+            print_commands(&COMMANDS.iter().collect::<Vec<_>>());
+            eprintln!(
+                "\nOther lines are prompts for the agent (// sends a leading /). quit or exit also stop."
+            );
+            eprintln!("Topics (/help <topic>; /help <word> searches):");
+            for topic in help::TOPICS {
+                eprintln!("  {:<10}  {}", topic.name, topic.summary);
+            }
+            return;
+        }
+        let name = format!("/{}", topic.trim_start_matches('/'));
+        if let Some(command) = find_command(&name) {
+            print_commands(&[command]);
+            return;
+        }
+        if let Some(topic) = help::find(topic) {
+            eprintln!("{}", topic.text);
+            return;
+        }
+        let word = topic.to_lowercase();
+        let commands: Vec<&SlashCommand> = COMMANDS
+            .iter()
+            .filter(|c| c.name.contains(&word) || c.help.to_lowercase().contains(&word))
+            .collect();
+        let topics = help::search(topic);
+        if commands.is_empty() && topics.is_empty() {
+            eprintln!("Nothing in the help mentions {:?}.", topic);
+            return;
+        }
+        print_commands(&commands);
+        for topic in topics {
+            eprintln!("  /help {:<10}  {}", topic.name, topic.summary);
+        }
+    }
+
+    /// Send a typed prompt: `:name` snippets replaced first, then
+    /// `` !`command` `` spans expanded if substitution is on, and
+    /// `@name#n` references quoted after it.
+    fn send_prompt(&mut self, input: &str) -> Flow {
+        let (expanded, used) = snippets::expand(input, &self.snippets);
+        if !used.is_empty() {
+            let names: Vec<String> = used.iter().map(|name| format!(":{}", name)).collect();
+            eprintln!("{}", style::dim(&format!("Expanded {}.", names.join(", "))));
+        }
+        let input = expanded.as_str();
+        // Without --safe, references are checked before commands run.
+        let quotes = if self.safe {
+            String::new()
+        } else {
+            match references::quotes(input, &self.references) {
+                Ok(quotes) => quotes,
+                Err(e) => {
+                    eprintln!("{} {}; not sent", style::error("error:"), e);
+                    return Flow::Continue;
+                }
+            }
+        };
+        let prompt = if self.safe || !self.substitution.enabled() {
+            input.to_string()
+        } else {
+            match substitute::expand(input, &self.substitution) {
+                Some(prompt) => prompt,
+                None => return Flow::Continue,
+            }
+        };
+        self.send_turn(&(prompt + &quotes))
+    }
+
+    /// Send one prompt and print the agent's reply.
+    fn send_turn(&mut self, input: &str) -> Flow {
+        let before = self.checkpoint;
+        self.replaced_answer = None;
+        let draft_path = self.paths.draft_file();
+        if let Err(e) = draft::save(&draft_path, input) {
+            eprintln!(
+                "{} cannot save draft {}: {}",
+                style::warning("warning:"),
+                draft_path.display(),
+                e
+            );
+        }
+        let text = match &self.lang {
+            Some(tag) => lang::prompt(tag, input),
+            None => input.to_string(),
+        };
+        let streaming = self.stream.wanted() && self.validator.is_none();
+        let id = self.agent.input_id();
+        if let Err(e) = self.agent.send(&ClientMessage::Input {
+            text,
+            stream: streaming,
+            id,
+        }) {
+            return self.recover(
+                Lost::Exited(format!("stopped accepting input ({})", e)),
+                input,
+            );
+        }
+        if self.show_timestamps {
+            eprintln!(
+                "{}",
+                style::dim(&timestamps::line("you", time::now_ms(), None))
+            );
+        }
+        if !self.safe && self.history.enabled() {
+            let history_path = history::history_file();
+            if let Err(e) = history::append(&history_path, input) {
+                eprintln!(
+                    "{} cannot write {}: {}",
+                    style::warning("warning:"),
+                    history_path.display(),
+                    e
+                );
+            }
+        }
+
+        let started = Instant::now();
+        self.finalize_at = self.turn_deadline.map(|deadline| started + deadline);
+        let mut printer = streaming.then(|| stream::Printer::new(&self.wrap));
+        let reply = match self.receive_reply(printer.as_mut()) {
+            Ok(reply) => reply,
+            Err(lost) => return self.recover(lost, input),
+        };
+        if let Err(e) = draft::clear(&draft_path) {
+            eprintln!(
+                "{} cannot remove draft {}: {}",
+                style::warning("warning:"),
+                draft_path.display(),
+                e
+            );
+        }
+        let (reply, violations) = match self.enforce_rules(reply) {
+            Ok(checked) => checked,
+            Err(lost) => return self.recover(lost, input),
+        };
+        let elapsed = started.elapsed();
+        self.first_turn.get_or_insert(elapsed);
+        self.turns += 1;
+        if let Some(usage) = &reply.usage {
+            self.tokens_used += usage.input_tokens + usage.output_tokens;
+            self.last_usage = Some((
+                usage.model.clone(),
+                usage.input_tokens + usage.output_tokens,
+            ));
+        }
+        if reply.checkpoint.is_some() {
+            self.checkpoint = reply.checkpoint;
+        }
+
+        if self.show_timestamps {
+            let line = timestamps::line("agent", time::now_ms(), Some(elapsed));
+            eprintln!("{}", style::dim(&line));
+        }
+        if reply.cancelled {
+            eprintln!("{}", style::dim("[turn cancelled; partial answer]"));
+        } else if reply.partial {
+            eprintln!("{}", style::dim("[turn deadline reached; partial answer]"));
+        }
+        let answered = reply.result.is_ok();
+        self.failed_input = (!answered).then(|| input.to_string());
+        match reply.result {
+            Ok(text) => {
+                if !printer.as_ref().is_some_and(stream::Printer::started) {
+                    pager::print(&wrap::for_terminal(&text, &self.wrap), &self.pager);
+                }
+                if let Some(speaker) = &mut self.speaker {
+                    speaker.speak(&text);
+                }
+                self.shown.push(find::Shown {
+                    turn: self.turns,
+                    at: time::now_ms(),
+                    input: input.to_string(),
+                    output: text.clone(),
+                });
+                self.last_exchange = Some(Exchange {
+                    input: input.to_string(),
+                    output: text,
+                    before,
+                });
+                self.send_to_auto_sinks();
+            }
+            Err(message) => eprintln!("{} {}", style::error("error:"), message),
+        }
+        if !violations.is_empty() {
+            eprintln!(
+                "{} the response still breaks the validation rules:",
+                style::error("error:")
+            );
+            for violation in &violations {
+                eprintln!("  - it {}", violation);
+            }
+        }
+        if self.show_turn_meta {
+            eprintln!(
+                "{}",
+                style::dim(&turn_meta(elapsed, reply.usage.as_ref(), reply.checkpoint))
+            );
+        }
+        if let Some(status) = &self.prompt.status {
+            if let Ok(line) = prompt::render(status, &self.prompt_context()) {
+                eprintln!("{}", style::dim(&line));
+            }
+        }
+        // A notification with nowhere to go has rung the bell already.
+        let mut rang = false;
+        if self.notify.wanted(elapsed, self.lost_focus) {
+            rang = !match self.last_exchange.as_ref().filter(|_| answered) {
+                Some(exchange) => {
+                    notify::send(&self.notify, "Komatachi answered", &exchange.output)
+                }
+                None => notify::send(&self.notify, "Komatachi", "The turn failed."),
+            };
+        }
+        if self.notify.rings(elapsed) && !rang {
+            notify::ring(&self.notify);
+        }
+        Flow::Continue
+    }
+
+    /// Ask the agent to correct a response that breaks the configured
+    /// rules, up to the retry limit. Returns the final reply with whatever
+    /// violations remain, and its usage summed over the retries.
+    fn enforce_rules(&mut self, mut reply: Reply) -> Result<(Reply, Vec<String>), Lost> {
+        let Some(retries) = self.validator.as_ref().map(|v| v.retries) else {
+            return Ok((reply, Vec::new()));
+        };
+        let mut attempt = 0;
+        loop {
+            let violations = match (&reply.result, &self.validator) {
+                (Ok(text), Some(validator)) => validator.check(text),
+                _ => Vec::new(),
+            };
+            if violations.is_empty() || attempt == retries {
+                return Ok((reply, violations));
+            }
+            attempt += 1;
+            eprintln!(
+                "{}",
+                style::dim(&format!(
+                    "[response broke {} rule(s); asking for a fix ({}/{})]",
+                    violations.len(),
+                    attempt,
+                    retries
+                ))
+            );
+            let id = self.agent.input_id();
+            if let Err(e) = self.agent.send(&ClientMessage::Input {
+                text: validate::fix_prompt(&violations),
+                stream: false,
+                id,
+            }) {
+                return Err(Lost::Exited(format!("stopped accepting input ({})", e)));
+            }
+            let spent = reply.usage.take();
+            reply = self.receive_reply(None)?;
+            reply.usage = match (spent, reply.usage.take()) {
+                (Some(spent), Some(retry)) => Some(spent.then(retry)),
+                (spent, retry) => retry.or(spent),
+            };
+        }
+    }
+
+    /// Read the turn's reply under the watchdog's stall limit and the
+    /// turn deadline, with a spinner until it arrives and Ctrl-C to cut it
+    /// short. Streamed text goes to `printer`, which is then finished.
+    fn receive_reply(&mut self, mut printer: Option<&mut stream::Printer>) -> Result<Reply, Lost> {
+        let keys = KeyWatch::start(self.notify.after().is_some());
+        let mut spinner = Spinner::start();
+        let reply = turn::receive_reply(
+            &mut self.agent,
+            self.watchdog.stall_limit(),
+            &mut self.finalize_at,
+            keys.as_ref().map(|keys| keys as &dyn turn::Keys),
+            &mut |text| {
+                if let Some(printer) = printer.as_deref_mut() {
+                    spinner.finish();
+                    printer.delta(text);
+                }
+            },
+        );
+        spinner.finish();
+        if let Some(printer) = printer {
+            printer.finish();
+        }
+        if let Some(keys) = keys {
+            self.lost_focus = keys.lost_focus();
+            self.typed_ahead.extend(keys.finish());
+        }
+        reply
+    }
+
+    /// The agent died or stalled mid-turn. Report it and, if the watchdog
+    /// may restart, start a fresh agent on the same conversation, roll the
+    /// interrupted turn back to the checkpoint before it, and offer to
+    /// resend `input` as it was sent.
+    fn recover(&mut self, lost: Lost, input: &str) -> Flow {
+        if let Lost::Abandoned = lost {
+            self.agent.kill();
+            eprintln!("Turn abandoned. Your prompt was saved and will be offered next time.");
+            return Flow::Quit;
+        }
+        eprintln!("\nwatchdog: {}.", lost.describe());
+        if !self.watchdog.restart() {
+            self.agent.kill();
+            eprintln!("Your prompt was saved and will be offered next time.");
+            return Flow::Quit;
+        }
+        // The old container must be gone before the new one opens the
+        // same data directory: one writer per conversation.
+        self.agent.kill();
+        match Agent::spawn(&self.api_key, &self.paths, self.safe, &self.agent_env) {
+            Ok(agent) => self.agent = agent,
+            Err(e) => {
+                eprintln!(
+                    "{} restarting the agent failed: {}",
+                    style::error("error:"),
+                    e
+                );
+                eprintln!("Your prompt was saved and will be offered next time.");
+                return Flow::Quit;
+            }
+        }
+        // The turn's checkpoint is not taken until its reply is in, so
+        // this is still the one from before the prompt.
+        match self.checkpoint {
+            Some(id) => {
+                if let Err(e) = self.agent.send(&ClientMessage::RestoreCheckpoint { id }) {
+                    eprintln!("{} {}", style::error("error:"), e);
+                    return Flow::Quit;
+                }
+                if let Err(flow) = self.await_message("checkpoint_restored") {
+                    return flow;
+                }
+                eprintln!(
+                    "watchdog: agent restarted; the interrupted turn was rolled back to checkpoint #{}.",
+                    id
+                );
+            }
+            None => eprintln!(
+                "watchdog: agent restarted; no checkpoint to roll the interrupted turn back to, so its prompt stays in the conversation."
+            ),
+        }
+        if terminal::confirm("Resend your prompt?") {
+            return self.send_turn(input);
+        }
+        let draft_path = self.paths.draft_file();
+        if let Err(e) = draft::clear(&draft_path) {
+            eprintln!(
+                "{} cannot remove draft {}: {}",
+                style::warning("warning:"),
+                draft_path.display(),
+                e
+            );
+        }
+        Flow::Continue
+    }
+
+    /// Keep the line being typed in this conversation's unsent-line file,
+    /// and, on starting or switching to a conversation, put back the line
+    /// left there by a session that crashed or switched away.
+    fn keep_unsent(&self, editor: &mut LineEditor) {
+        let path = (!self.safe).then(|| self.paths.unsent_file());
+        if editor.unsent_file() == path.as_deref() {
+            return;
+        }
+        if let Some(unsent) = path.as_deref().and_then(draft::load) {
+            eprintln!(
+                "{}",
+                style::dim(&format!(
+                    "[restored the line you were typing at {} UTC]",
+                    time::format_utc(unsent.created_at)
+                ))
+            );
+            editor.prefill(unsent.text);
+        }
+        editor.keep_unsent_in(path);
+    }
+
+    /// End the session at a limit: keep the line being typed, if any, for
+    /// the next session to put back at the prompt, and say why and what
+    /// was used.
+    fn end_at_limit(&self, reached: &limits::Reached, unsent: Option<String>) {
+        if let Some(line) = unsent.filter(|_| !self.safe) {
+            let path = self.paths.unsent_file();
+            match draft::save(&path, &line) {
+                Ok(()) => {
+                    eprintln!(
+                        "The unsent line is saved; the next session puts it back at the prompt."
+                    )
+                }
+                Err(e) => eprintln!(
+                    "{} cannot save draft {}: {}",
+                    style::warning("warning:"),
+                    path.display(),
+                    e
+                ),
+            }
+        }
+        eprintln!(
+            "{}",
+            limits::summary(
+                reached,
+                self.started.elapsed(),
+                self.turns,
+                self.tokens_used
+            )
+        );
+    }
+
+    /// Start a fresh agent after the idle timer stopped the last one.
+    fn resume(&mut self) -> bool {
+        eprintln!("Restarting the agent (it was stopped while idle)...");
+        self.agent.kill();
+        match Agent::spawn(&self.api_key, &self.paths, self.safe, &self.agent_env) {
+            Ok(agent) => {
+                self.agent = agent;
+                true
+            }
+            Err(e) => {
+                eprintln!(
+                    "{} restarting the agent failed: {}",
+                    style::error("error:"),
+                    e
+                );
+                false
+            }
+        }
+    }
+
+    /// `/bookmark [note]`: record the most recent exchange.
+    fn bookmark(&self, note: &str) {
+        let Some(exchange) = &self.last_exchange else {
+            eprintln!("{} nothing to bookmark yet", style::error("error:"));
+            return;
+        };
+        let entry = Bookmark {
+            created_at: time::now_ms(),
+            note: if note.is_empty() {
+                None
+            } else {
+                Some(note.to_string())
+            },
+            input: exchange.input.clone(),
+            output: exchange.output.clone(),
+        };
+        let path = self.paths.bookmarks_file();
+        match bookmarks::append(&path, &entry) {
+            Ok(()) => eprintln!("Bookmarked."),
+            Err(e) => eprintln!(
+                "{} cannot write {}: {}",
+                style::error("error:"),
+                path.display(),
+                e
+            ),
+        }
+    }
+
+    /// `/copy [code]`: put the most recent answer, or its last code
+    /// block, on the clipboard.
+    fn copy(&self, what: &str) {
+        let Some(exchange) = &self.last_exchange else {
+            eprintln!("{} nothing to copy yet", style::error("error:"));
+            return;
+        };
+        let text = match what {
+            "" => exchange.output.clone(),
+            "code" => match clipboard::last_code_block(&exchange.output) {
+                Some(code) => code,
+                None => {
+                    eprintln!(
+                        "{} the last answer has no code block",
+                        style::error("error:")
+                    );
+                    return;
+                }
+            },
+            _ => {
+                eprintln!("usage: /copy [code]");
+                return;
+            }
+        };
+        match clipboard::write(&text) {
+            Ok(Copied::Tool(tool)) => {
+                eprintln!("Copied {} characters ({}).", text.chars().count(), tool)
+            }
+            Ok(Copied::Terminal) => eprintln!(
+                "Sent {} characters to the terminal's clipboard (OSC 52).",
+                text.chars().count()
+            ),
+            Err(e) => eprintln!("{} {}", style::error("error:"), e),
+        }
+    }
+
+    /// `/send-to [sink]`: copy the most recent exchange to a sink, or
+    /// list them.
+    fn send_to(&self, name: &str) {
+        if self.safe {
+            eprintln!(
+                "{} /send-to is not available with --safe (nothing is copied out)",
+                style::error("error:")
+            );
+            return;
+        }
+        if name.is_empty() {
+            if self.sinks.is_empty() {
+                eprintln!("No sinks configured (add them under \"sinks\" in config.json).");
+            }
+            for (name, sink) in &self.sinks {
+                eprintln!("  {}{}", name, if sink.auto { " (auto)" } else { "" });
+            }
+            return;
+        }
+        let Some(sink) = self.sinks.get(name) else {
+            eprintln!(
+                "{} no sink named {:?} (/send-to lists them)",
+                style::error("error:"),
+                name
+            );
+            return;
+        };
+        let Some(exchange) = &self.last_exchange else {
+            eprintln!("{} nothing to send yet", style::error("error:"));
+            return;
+        };
+        match sink.send(&exchange.input, &exchange.output, time::now_ms()) {
+            Ok(()) => eprintln!("Sent to {}.", name),
+            Err(e) => eprintln!("{} {}", style::error("error:"), e),
+        }
+    }
+
+    /// `/snippet [name] [text]`: send a snippet, followed by `text`, as if
+    /// typed as `:name text`; without a name, list the snippets.
+    fn snippet(&mut self, args: &str) -> Flow {
+        let (name, text) = args.split_once(char::is_whitespace).unwrap_or((args, ""));
+        if name.is_empty() {
+            if self.snippets.is_empty() {
+                eprintln!("No snippets configured (add them under \"snippets\" in config.json).");
+            }
+            for (name, text) in &self.snippets {
+                let first = text.lines().next().unwrap_or("");
+                eprintln!("  :{:<12}  {}", name, first);
+            }
+            return Flow::Continue;
+        }
+        if !self.snippets.contains_key(name) {
+            eprintln!(
+                "{} no snippet named {:?} (/snippet lists them)",
+                style::error("error:"),
+                name
+            );
+            return Flow::Continue;
+        }
+        let prompt = format!(":{} {}", name, text.trim());
+        self.send_prompt(prompt.trim_end())
+    }
+
+    /// Copy the exchange just completed to every `auto` sink.
+    fn send_to_auto_sinks(&self) {
+        let Some(exchange) = self.last_exchange.as_ref().filter(|_| !self.safe) else {
+            return;
+        };
+        sinks::broadcast(
+            &self.sinks,
+            &exchange.input,
+            &exchange.output,
+            time::now_ms(),
+        );
+    }
+
+    /// `/good [reason]` and `/bad [reason]`: rate the most recent exchange.
+    fn rate(&self, verdict: Verdict, reason: &str) {
+        let Some(exchange) = &self.last_exchange else {
+            eprintln!("{} nothing to rate yet", style::error("error:"));
+            return;
+        };
+        let entry = Rating {
+            created_at: time::now_ms(),
+            verdict,
+            reason: if reason.is_empty() {
+                None
+            } else {
+                Some(reason.to_string())
+            },
+            input: exchange.input.clone(),
+            output: exchange.output.clone(),
+        };
+        let path = self.paths.ratings_file();
+        match ratings::append(&path, &entry) {
+            Ok(()) => eprintln!("Rated."),
+            Err(e) => eprintln!(
+                "{} cannot write {}: {}",
+                style::error("error:"),
+                path.display(),
+                e
+            ),
+        }
+    }
+
+    /// `/lang [code|off]`: show, set, or clear the response language.
+    fn set_lang(&mut self, args: &str) {
+        match args {
+            "" => match &self.lang {
+                Some(tag) => eprintln!("Answers are requested in {}.", lang::describe(tag)),
+                None => eprintln!("No response language set (/lang <code> to set one)."),
+            },
+            "off" => {
+                self.lang = None;
+                eprintln!("Prompts no longer ask for a language.");
+            }
+            code => match lang::parse(code) {
+                Ok(tag) => {
+                    eprintln!("Answers will be requested in {}.", lang::describe(&tag));
+                    self.lang = Some(tag);
+                }
+                Err(e) => eprintln!("{} {}", style::error("error:"), e),
+            },
+        }
+    }
+
+    /// `/fork [name]`: copy the conversation and switch the agent to the copy.
+    fn fork(&mut self, name: &str) {
+        if self.safe {
+            eprintln!(
+                "{} /fork is not available with --safe (the scratch conversation is not kept)",
+                style::error("error:")
+            );
+            return;
+        }
+        let name = if name.is_empty() {
+            fork::default_name()
+        } else {
+            name.to_string()
+        };
+        let fork_dir = match fork::create(&self.paths.data_dir, &name) {
+            Ok(dir) => dir,
+            Err(e) => {
+                eprintln!("{} {}", style::error("error:"), e);
+                return;
+            }
+        };
+
+        let fork_paths = Paths {
+            data_dir: fork_dir.clone(),
+            home_dir: self.paths.home_dir.clone(),
+        };
+        // Start the new agent before stopping the old one, so a failed start
+        // leaves the current conversation usable.
+        let fork_agent = match Agent::spawn(&self.api_key, &fork_paths, self.safe, &self.agent_env)
+        {
+            Ok(fork_agent) => fork_agent,
+            Err(e) => {
+                eprintln!(
+                    "{} fork created at {} but agent failed to start: {}",
+                    style::error("error:"),
+                    fork_dir.display(),
+                    e
+                );
+                return;
+            }
+        };
+        std::mem::replace(&mut self.agent, fork_agent).shutdown();
+        self.checkpoint = latest_checkpoint(&fork_paths);
+        self.paths = fork_paths;
+        self.conversation = name.clone();
+        self.last_exchange = None;
+        self.replaced_answer = None;
+        self.failed_input = None;
+
+        eprintln!(
+            "Switched to fork '{}'. The original conversation is unchanged.\n\
+             Resume this fork later with KOMATACHI_DATA_DIR={}",
+            name,
+            fork_dir.display()
+        );
+    }
+
+    /// `/inspect [--json]`: show the last request the agent sent to the model.
+    fn inspect(&mut self, args: &str) -> Flow {
+        let raw = match args {
+            "" => false,
+            "--json" => true,
+            _ => {
+                eprintln!("usage: /inspect [--json]");
+                return Flow::Continue;
+            }
+        };
+        if let Err(e) = self.agent.send(&ClientMessage::Inspect) {
+            eprintln!("{} {}", style::error("error:"), e);
+            return Flow::Quit;
+        }
+        let msg = match self.await_message("inspect") {
+            Ok(msg) => msg,
+            Err(flow) => return flow,
+        };
+        match msg.request {
+            Some(request) if raw => println!(
+                "{}",
+                serde_json::to_string_pretty(&request).expect("serialize request")
+            ),
+            Some(request) => print!("{}", inspect::render(&request)),
+            None => eprintln!("No model request yet. Send a prompt first."),
+        }
+        Flow::Continue
+    }
+
+    /// `/checkpoints`: list the most recent turn checkpoints.
+    fn list_checkpoints(&self) {
+        let path = self.paths.checkpoint_index();
+        match checkpoints::load(&path) {
+            Ok(list) if list.is_empty() => eprintln!("No checkpoints yet."),
+            Ok(list) => print!("{}", checkpoints::render(&list)),
+            Err(e) => eprintln!(
+                "{} cannot read {}: {}",
+                style::error("error:"),
+                path.display(),
+                e
+            ),
+        }
+    }
+
+    /// `/rollback <id>`: return the conversation to a checkpoint.
+    fn rollback(&mut self, args: &str) -> Flow {
+        let Ok(id) = args.trim_start_matches('#').parse::<u64>() else {
+            eprintln!("usage: /rollback <checkpoint id>  (see /checkpoints)");
+            return Flow::Continue;
+        };
+        eprintln!(
+            "Rolling back to #{} permanently removes every later turn from the conversation.\n\
+             Use /fork first to keep a copy.",
+            id
+        );
+        if !terminal::confirm("Roll back?") {
+            return Flow::Continue;
+        }
+        if let Err(e) = self.agent.send(&ClientMessage::RestoreCheckpoint { id }) {
+            eprintln!("{} {}", style::error("error:"), e);
+            return Flow::Quit;
+        }
+        if let Err(flow) = self.await_message("checkpoint_restored") {
+            return flow;
+        }
+        self.last_exchange = None;
+        self.replaced_answer = None;
+        self.failed_input = None;
+        self.checkpoint = Some(id);
+        eprintln!("Rolled back to checkpoint #{}.", id);
+        Flow::Continue
+    }
+
+    /// `/undo`: return to the checkpoint before the last exchange, or,
+    /// after a failed turn, drop its prompt by restoring the current one.
+    /// The exchange includes any validation fix-up turns; without one this
+    /// session, step back one checkpoint.
+    fn undo(&mut self) -> Flow {
+        let (target, removed) = if let Some(input) = &self.failed_input {
+            (self.checkpoint, input.clone())
+        } else if let Some(exchange) = &self.last_exchange {
+            (exchange.before, exchange.input.clone())
+        } else {
+            let path = self.paths.checkpoint_index();
+            let list = match checkpoints::load(&path) {
+                Ok(list) => list,
+                Err(e) => {
+                    eprintln!(
+                        "{} cannot read {}: {}",
+                        style::error("error:"),
+                        path.display(),
+                        e
+                    );
+                    return Flow::Continue;
+                }
+            };
+            let Some(current) = self.checkpoint.or(list.last().map(|c| c.id)) else {
+                eprintln!("{} nothing to undo yet", style::error("error:"));
+                return Flow::Continue;
+            };
+            let removed = list
+                .iter()
+                .find(|checkpoint| checkpoint.id == current)
+                .map(|checkpoint| checkpoint.preview.clone())
+                .unwrap_or_default();
+            (
+                checkpoints::before(&list, current).map(|checkpoint| checkpoint.id),
+                removed,
+            )
+        };
+        let Some(target) = target else {
+            eprintln!(
+                "{} no checkpoint from before the last exchange to return to",
+                style::error("error:")
+            );
+            return Flow::Continue;
+        };
+        if let Err(e) = self
+            .agent
+            .send(&ClientMessage::RestoreCheckpoint { id: target })
+        {
+            eprintln!("{} {}", style::error("error:"), e);
+            return Flow::Quit;
+        }
+        if let Err(flow) = self.await_message("checkpoint_restored") {
+            return flow;
+        }
+        self.checkpoint = Some(target);
+        self.last_exchange = None;
+        self.replaced_answer = None;
+        self.failed_input = None;
+        eprintln!(
+            "Removed the exchange for: {}\nThe conversation is back at checkpoint #{}.",
+            removed.lines().next().unwrap_or(""),
+            target
+        );
+        Flow::Continue
+    }
+
+    /// `/find <regex>`: this session's exchanges with a matching line.
+    fn find(&self, pattern: &str) {
+        if pattern.is_empty() {
+            eprintln!("usage: /find <regex>  ((?i) first to ignore case)");
+            return;
+        }
+        let regex = match Regex::new(pattern) {
+            Ok(regex) => regex,
+            Err(e) => {
+                eprintln!("{} invalid pattern: {}", style::error("error:"), e);
+                return;
+            }
+        };
+        match find::render(&self.shown, &regex) {
+            Some((text, matched)) => {
+                pager::print(&text, &self.pager);
+                eprintln!(
+                    "{}",
+                    style::dim(&format!(
+                        "[{} of {} exchange{} this session]",
+                        matched,
+                        self.shown.len(),
+                        if self.shown.len() == 1 { "" } else { "s" }
+                    ))
+                );
+            }
+            None => eprintln!(
+                "No match in this session's {} exchange{}.",
+                self.shown.len(),
+                if self.shown.len() == 1 { "" } else { "s" }
+            ),
+        }
+    }
+
+    /// `/history [n]`: the conversation's last exchanges, from its
+    /// transcript.
+    fn history(&self, count: &str) {
+        let count = match count {
+            "" => review::DEFAULT_COUNT,
+            count => match count.parse::<usize>() {
+                Ok(count) if count > 0 => count,
+                _ => {
+                    eprintln!("usage: /history [n]");
+                    return;
+                }
+            },
+        };
+        let path = self.paths.transcript_file();
+        let transcript = match fs::read_to_string(&path) {
+            Ok(transcript) => transcript,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(e) => {
+                eprintln!(
+                    "{} cannot read {}: {}",
+                    style::error("error:"),
+                    path.display(),
+                    e
+                );
+                return;
+            }
+        };
+        match review::render(&transcript, &self.conversation, count) {
+            Some(text) => pager::print(&wrap::for_terminal(&text, &self.wrap), &self.pager),
+            None => eprintln!("No exchanges yet."),
+        }
+    }
+
+    /// `/retry`: roll back the last turn and send its prompt again.
+    fn retry(&mut self, args: &str) -> Flow {
+        let edit = match args {
+            "" => false,
+            "edit" => true,
+            _ => {
+                eprintln!("usage: /retry [edit]");
+                return Flow::Continue;
+            }
+        };
+        // A failed turn left its prompt in the conversation but no
+        // checkpoint, so the current one is from before it.
+        let (input, before, replaced) = match (&self.failed_input, &self.last_exchange) {
+            (Some(input), _) => (input.clone(), self.checkpoint, None),
+            (None, Some(exchange)) => (
+                exchange.input.clone(),
+                exchange.before,
+                Some(exchange.output.clone()),
+            ),
+            (None, None) => {
+                eprintln!("{} nothing to retry yet", style::error("error:"));
+                return Flow::Continue;
+            }
+        };
+        let Some(before) = before else {
+            eprintln!(
+                "{} no checkpoint from before the last turn to roll back to",
+                style::error("error:")
+            );
+            return Flow::Continue;
+        };
+        let input = if edit {
+            match compose::compose(&input) {
+                Ok(Some(prompt)) => {
+                    eprintln!("> {}", prompt);
+                    prompt
+                }
+                Ok(None) => {
+                    eprintln!("Nothing sent (the file was left empty); the last turn stays.");
+                    return Flow::Continue;
+                }
+                Err(e) => {
+                    eprintln!("{} {}", style::error("error:"), e);
+                    return Flow::Continue;
+                }
+            }
+        } else {
+            input
+        };
+        if let Err(e) = self
+            .agent
+            .send(&ClientMessage::RestoreCheckpoint { id: before })
+        {
+            eprintln!("{} {}", style::error("error:"), e);
+            return Flow::Quit;
+        }
+        if let Err(flow) = self.await_message("checkpoint_restored") {
+            return flow;
+        }
+        self.checkpoint = Some(before);
+        self.last_exchange = None;
+        self.failed_input = None;
+
+        let flow = self.send_turn(&input);
+        if let (Some(old), Some(_)) = (replaced, &self.last_exchange) {
+            self.replaced_answer = Some(old);
+            eprintln!(
+                "{}",
+                style::dim("[/diff compares this answer with the one it replaced]")
+            );
+        }
+        flow
+    }
+
+    /// `/diff`: word-level changes from the answer `/retry` replaced.
+    fn diff(&self) {
+        let (Some(old), Some(exchange)) = (&self.replaced_answer, &self.last_exchange) else {
+            eprintln!(
+                "{} nothing to compare; /diff works right after /retry",
+                style::error("error:")
+            );
+            return;
+        };
+        match diff::words(old, &exchange.output) {
+            Some(changes) => println!("{}", diff::render(&changes)),
+            None => eprintln!("The answers have too little in common for a word diff."),
+        }
+    }
+
+    /// Wait for the agent's answer to a local command. An `error` reply or
+    /// a lost agent is reported here; the `Err` says whether to go on.
+    fn await_message(&mut self, expected: &str) -> Result<AgentMessage, Flow> {
+        loop {
+            match self.agent.receive() {
+                Ok(msg) if msg.r#type == expected => return Ok(msg),
+                Ok(msg) if msg.r#type == "error" => {
+                    eprintln!(
+                        "{} {}",
+                        style::error("error:"),
+                        msg.message.as_deref().unwrap_or("unknown error")
+                    );
+                    return Err(Flow::Continue);
+                }
+                Ok(msg) => eprintln!(
+                    "{} unexpected message type: {}",
+                    style::warning("warning:"),
+                    msg.r#type
+                ),
+                Err(ReceiveError::Invalid(e)) => {
+                    eprintln!(
+                        "{} invalid response from agent: {}",
+                        style::error("error:"),
+                        e
+                    );
+                    return Err(Flow::Continue);
+                }
+                Err(ReceiveError::Closed) => {
+                    eprintln!("{} agent exited unexpectedly", style::error("error:"));
+                    return Err(Flow::Quit);
+                }
+                Err(ReceiveError::Io(e)) => {
+                    eprintln!("{} reading from agent: {}", style::error("error:"), e);
+                    return Err(Flow::Quit);
+                }
+            }
+        }
+    }
+
+    /// `/meta [on|off]`: toggle the per-turn metadata line.
+    fn toggle_turn_meta(&mut self, args: &str) {
+        self.show_turn_meta = match args {
+            "" => !self.show_turn_meta,
+            "on" => true,
+            "off" => false,
+            _ => {
+                eprintln!("usage: /meta [on|off]");
+                return;
+            }
+        };
+        eprintln!(
+            "Turn metadata {}.",
+            if self.show_turn_meta { "on" } else { "off" }
+        );
+    }
+
+    /// `/keys [vi|emacs]`: show or change the key bindings at the prompt.
+    fn switch_keys(&mut self, args: &str) {
+        self.keys = match args {
+            "" => {
+                eprintln!("Key bindings: {}.", self.keys.name());
+                return;
+            }
+            "vi" => lineedit::Mode::Vi,
+            "emacs" => lineedit::Mode::Emacs,
+            _ => {
+                eprintln!("usage: /keys [vi|emacs]");
+                return;
+            }
+        };
+        eprintln!("Key bindings: {}.", self.keys.name());
+    }
+
+    /// `/timestamps [on|off]`: toggle or set the time lines on exchanges.
+    fn toggle_timestamps(&mut self, args: &str) {
+        self.show_timestamps = match args {
+            "" => !self.show_timestamps,
+            "on" => true,
+            "off" => false,
+            _ => {
+                eprintln!("usage: /timestamps [on|off]");
+                return;
+            }
+        };
+        eprintln!(
+            "Timestamps {}.",
+            if self.show_timestamps { "on" } else { "off" }
+        );
+    }
+}
+
+/// Print command synopses and descriptions in aligned columns.
+fn print_commands(commands: &[&SlashCommand]) {
+    let synopses: Vec<String> = commands
+        .iter()
+        .map(|c| format!("{} {}", c.name, c.args).trim_end().to_string())
+        .collect();
+    let width = synopses.iter().map(String::len).max().unwrap_or(0);
+    for (synopsis, command) in synopses.iter().zip(commands) {
+        eprintln!("  {:<width$}  {}", synopsis, command.help);
+    }
+}
+
+/// The conversation's most recent checkpoint, from the agent's index.
+fn latest_checkpoint(paths: &Paths) -> Option<u64> {
+    let list = checkpoints::load(&paths.checkpoint_index()).ok()?;
+    list.last().map(|checkpoint| checkpoint.id)
+}
+
+/// If the previous session died mid-turn, offer to resend its prompt.
+fn offer_draft(paths: &Paths) -> Option<String> {
+    let path = paths.draft_file();
+    let pending = draft::load(&path)?;
+    eprintln!(
+        "A prompt from {} UTC was never answered:\n\n{}\n",
+        time::format_utc(pending.created_at),
+        pending.text
+    );
+    let resend = terminal::confirm("Send it now?");
+    if let Err(e) = draft::clear(&path) {
+        eprintln!(
+            "{} cannot remove draft {}: {}",
+            style::warning("warning:"),
+            path.display(),
+            e
+        );
+    }
+    if resend {
+        Some(pending.text)
+    } else {
+        None
+    }
+}
+
+/// One-line summary of a turn: model, latency, token counts, checkpoint.
+fn turn_meta(elapsed: Duration, usage: Option<&Usage>, checkpoint: Option<u64>) -> String {
+    let mut parts = Vec::new();
+    if let Some(usage) = usage {
+        parts.push(match &usage.route {
+            Some(route) => format!("{} ({})", usage.model, route),
+            None => usage.model.clone(),
+        });
+    }
+    parts.push(format!("{:.1}s", elapsed.as_secs_f64()));
+    if let Some(usage) = usage {
+        parts.push(format!(
+            "{} in / {} out",
+            usage.input_tokens, usage.output_tokens
+        ));
+    }
+    if let Some(id) = checkpoint {
+        parts.push(format!("#{}", id));
+    }
+    format!("[{}]", parts.join(" · "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn commands_are_unique_slash_names() {
+        for (i, command) in COMMANDS.iter().enumerate() {
+            assert!(command.name.starts_with('/'), "{}", command.name);
+            assert!(
+                COMMANDS[..i].iter().all(|c| c.name != command.name),
+                "{} listed twice",
+                command.name
+            );
+        }
+        assert!(find_command("/retry").is_some());
+        assert!(find_command("/etc/hosts").is_none());
+    }
+
+    #[test]
+    fn double_slash_sends_one() {
+        assert_eq!(unescape_slash("//etc/hosts?"), "/etc/hosts?");
+        assert_eq!(unescape_slash("plain"), "plain");
+    }
+
+    #[test]
+    fn quit_words_include_the_slash_forms() {
+        for input in ["quit", "exit", "/quit", "/exit"] {
+            assert!(is_quit(input), "{}", input);
+        }
+        assert!(!is_quit("/help"));
+        assert!(!is_quit("quit now"));
+    }
+
+    #[test]
+    fn turn_meta_includes_usage() {
+        let usage = Usage {
+            model: "claude-test".to_string(),
+            input_tokens: 1200,
+            output_tokens: 34,
+            route: None,
+        };
+        assert_eq!(
+            turn_meta(Duration::from_millis(2340), Some(&usage), None),
+            "[claude-test · 2.3s · 1200 in / 34 out]"
+        );
+    }
+
+    #[test]
+    fn turn_meta_shows_routing_reason() {
+        let usage = Usage {
+            model: "claude-small".to_string(),
+            input_tokens: 10,
+            output_tokens: 2,
+            route: Some("short prompt".to_string()),
+        };
+        assert_eq!(
+            turn_meta(Duration::from_millis(800), Some(&usage), None),
+            "[claude-small (short prompt) · 0.8s · 10 in / 2 out]"
+        );
+    }
+
+    #[test]
+    fn turn_meta_ends_with_checkpoint() {
+        assert_eq!(
+            turn_meta(Duration::from_millis(1000), None, Some(7)),
+            "[1.0s · #7]"
+        );
+    }
+
+    #[test]
+    fn turn_meta_without_usage_shows_latency_only() {
+        assert_eq!(turn_meta(Duration::from_millis(500), None, None), "[0.5s]");
+    }
+}
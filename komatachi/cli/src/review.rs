@@ -0,0 +1,130 @@
+//! `/history [n]`: the last exchanges of the conversation, printed again.
+//!
+//! Read from the transcript, which holds what the agent remembers, so it
+//! also covers turns from earlier sessions. An exchange is a prompt and
+//! everything after it up to the next prompt; a user message made only
+//! of tool results continues the exchange rather than starting one.
+//! Turns are numbered through the transcript as it stands, so after
+//! compaction the summary is turn 1. Each heading gives the messages'
+//! `@<conversation>#<from>-<to>` address, for quoting them into a prompt.
+
+use serde_json::Value;
+
+use crate::inspect;
+use crate::style;
+
+/// Exchanges shown by `/history` without a count.
+pub const DEFAULT_COUNT: usize = 3;
+
+/// One prompt and the messages that answered it.
+#[derive(Debug, PartialEq)]
+struct Exchange {
+    turn: usize,
+    /// 1-based message numbers, as `grep` and references count them.
+    from: usize,
+    to: usize,
+}
+
+/// The turns in a transcript, in order.
+fn exchanges(messages: &[Value]) -> Vec<Exchange> {
+    let mut found: Vec<Exchange> = Vec::new();
+    for (index, message) in messages.iter().enumerate() {
+        let number = index + 1;
+        match found.last_mut() {
+            Some(last) if !starts_turn(message) => last.to = number,
+            _ => found.push(Exchange {
+                turn: found.len() + 1,
+                from: number,
+                to: number,
+            }),
+        }
+    }
+    found
+}
+
+/// Whether a message is a prompt: from the user, and not tool results.
+fn starts_turn(message: &Value) -> bool {
+    message["role"] == "user"
+        && !message["content"].as_array().is_some_and(|blocks| {
+            !blocks.is_empty() && blocks.iter().all(|b| b["type"] == "tool_result")
+        })
+}
+
+/// The last `count` exchanges of `transcript`, rendered for the terminal;
+/// `None` if it has none.
+pub fn render(transcript: &str, conversation: &str, count: usize) -> Option<String> {
+    let messages: Vec<Value> = transcript
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap_or(Value::Null))
+        .collect();
+    let all = exchanges(&messages);
+    let shown = &all[all.len().saturating_sub(count)..];
+    if shown.is_empty() {
+        return None;
+    }
+    let mut out = String::new();
+    for exchange in shown {
+        let address = if exchange.from == exchange.to {
+            format!("@{}#{}", conversation, exchange.from)
+        } else {
+            format!("@{}#{}-{}", conversation, exchange.from, exchange.to)
+        };
+        out.push_str(&style::dim(&format!(
+            "--- turn {} of {} ({}) ---",
+            exchange.turn,
+            all.len(),
+            address
+        )));
+        out.push('\n');
+        for message in &messages[exchange.from - 1..exchange.to] {
+            out.push_str(&style::dim(&format!(
+                "[{}]",
+                message["role"].as_str().unwrap_or("?")
+            )));
+            out.push('\n');
+            out.push_str(&inspect::render_content(&message["content"]));
+        }
+        out.push('\n');
+    }
+    Some(out.trim_end().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tool_results_continue_a_turn() {
+        let transcript = [
+            r#"{"role":"user","content":"first"}"#,
+            r#"{"role":"assistant","content":[{"type":"tool_use","id":"t1","name":"read","input":{}}]}"#,
+            r#"{"role":"user","content":[{"type":"tool_result","tool_use_id":"t1","content":"data"}]}"#,
+            r#"{"role":"assistant","content":[{"type":"text","text":"one"}]}"#,
+            r#"{"role":"user","content":"second"}"#,
+            r#"{"role":"assistant","content":[{"type":"text","text":"two"}]}"#,
+        ];
+        let messages: Vec<Value> = transcript
+            .iter()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(
+            exchanges(&messages),
+            vec![
+                Exchange {
+                    turn: 1,
+                    from: 1,
+                    to: 4
+                },
+                Exchange {
+                    turn: 2,
+                    from: 5,
+                    to: 6
+                },
+            ]
+        );
+        let shown = render(&transcript.join("\n"), "main", 1).unwrap();
+        assert!(shown.contains("--- turn 2 of 2 (@main#5-6) ---"));
+        assert!(shown.ends_with("second\n[assistant]\ntwo"));
+        assert!(render("", "main", 3).is_none());
+    }
+}
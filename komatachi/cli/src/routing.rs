@@ -0,0 +1,87 @@
+//! Picking the model for each turn.
+//!
+//! The agent does the routing (see `src/worker/`); the CLI only tells it
+//! how, through `KOMATACHI_ROUTING` and `KOMATACHI_SMALL_MODEL`. With
+//! `routing.mode` set to "auto", simple prompts go to the small model and
+//! the rest to `KOMATACHI_MODEL`. The config file sets the defaults; a
+//! variable set in the environment wins, as it does for the CLI's other
+//! settings.
+
+use serde::Deserialize;
+
+/// How the agent picks a turn's model.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Mode {
+    /// Every turn goes to `KOMATACHI_MODEL`.
+    #[default]
+    Fixed,
+    /// Simple prompts go to the small model.
+    Auto,
+}
+
+impl Mode {
+    /// The name the config file and `KOMATACHI_ROUTING` use.
+    pub fn name(self) -> &'static str {
+        match self {
+            Mode::Fixed => "fixed",
+            Mode::Auto => "auto",
+        }
+    }
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "camelCase")]
+pub struct Settings {
+    pub mode: Option<Mode>,
+    /// The model simple prompts go to under "auto".
+    pub small_model: Option<String>,
+}
+
+impl Settings {
+    pub fn mode(&self) -> Mode {
+        self.mode.unwrap_or_default()
+    }
+
+    /// The agent variables these settings give, for `Agent::launch`,
+    /// leaving out any set in the CLI's own environment.
+    pub fn env(&self) -> Vec<(&'static str, String)> {
+        self.env_unless(|var| std::env::var_os(var).is_some())
+    }
+
+    fn env_unless(&self, set: impl Fn(&str) -> bool) -> Vec<(&'static str, String)> {
+        let mode = self.mode.map(|mode| mode.name().to_string());
+        [
+            ("KOMATACHI_ROUTING", mode),
+            ("KOMATACHI_SMALL_MODEL", self.small_model.clone()),
+        ]
+        .into_iter()
+        .filter_map(|(var, value)| value.filter(|_| !set(var)).map(|value| (var, value)))
+        .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_environment_overrides_the_config_file() {
+        let settings: Settings =
+            serde_json::from_str(r#"{"mode": "auto", "smallModel": "small"}"#).unwrap();
+        assert_eq!(settings.mode(), Mode::Auto);
+        assert_eq!(
+            settings.env_unless(|_| false),
+            [
+                ("KOMATACHI_ROUTING", "auto".to_string()),
+                ("KOMATACHI_SMALL_MODEL", "small".to_string()),
+            ]
+        );
+        assert_eq!(
+            settings.env_unless(|var| var == "KOMATACHI_ROUTING"),
+            [("KOMATACHI_SMALL_MODEL", "small".to_string())]
+        );
+        assert!(Settings::default().env_unless(|_| false).is_empty());
+        assert!(serde_json::from_str::<Settings>(r#"{"mode": "smart"}"#).is_err());
+    }
+}
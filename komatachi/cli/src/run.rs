@@ -0,0 +1,482 @@
+//! `komatachi-cli run -f <manifest>`: one turn, described by a file.
+//!
+//! A manifest is a JSON file that says everything about a job that would
+//! otherwise come from flags, environment variables, and typing: the
+//! prompt and the files to attach to it, the image, the model settings,
+//! which conversation and identity to use, container restrictions, the
+//! turn deadline, and the output format. Checked into version control
+//! next to its inputs, it is a reviewable description of what the agent
+//! was asked to do. Relative paths are relative to the manifest.
+//!
+//! Without `dataDir` the turn runs in a fresh scratch conversation that
+//! is deleted afterwards, so the same manifest starts from the same state
+//! every time.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use serde::Deserialize;
+
+use crate::agent::{self, Agent};
+use crate::config::{self, Config};
+use crate::paths::Paths;
+use crate::protocol::ClientMessage;
+use crate::references::{self, Attachment};
+use crate::safe;
+use crate::sinks;
+use crate::style;
+use crate::time;
+use crate::turn::{self, Reply};
+
+const USAGE: &str = "usage: komatachi-cli run -f <manifest.json> [--summary-file <path>]";
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "camelCase")]
+pub struct Manifest {
+    /// The prompt for the turn.
+    pub prompt: String,
+    /// Files whose contents are attached to the prompt.
+    #[serde(default)]
+    pub inputs: Vec<PathBuf>,
+    /// Agent image; `komatachi-app` (built if needed) when absent.
+    #[serde(default)]
+    pub image: Option<String>,
+    /// `KOMATACHI_MODEL` for the agent.
+    #[serde(default)]
+    pub model: Option<String>,
+    /// `KOMATACHI_MAX_TOKENS` for the agent.
+    #[serde(default)]
+    pub max_tokens: Option<u64>,
+    /// Conversation to continue; a scratch conversation when absent.
+    #[serde(default)]
+    pub data_dir: Option<PathBuf>,
+    /// Identity files; the usual home dir when absent (with `safe` and no
+    /// `dataDir`, an empty scratch one).
+    #[serde(default)]
+    pub home_dir: Option<PathBuf>,
+    /// The `--safe` container restrictions.
+    #[serde(default)]
+    pub safe: bool,
+    /// Finalize the turn after this many seconds.
+    #[serde(default)]
+    pub turn_deadline_secs: Option<u64>,
+    #[serde(default)]
+    pub output: Output,
+}
+
+/// How the answer is written to stdout.
+#[derive(Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Output {
+    /// The answer text alone.
+    #[default]
+    Text,
+    /// One JSON object with the answer and the turn's usage.
+    Json,
+}
+
+/// `komatachi-cli run -f <manifest> [--summary-file <path>]`
+pub fn run_command(args: &[String], api_key: &str) {
+    let (file, summary_file) = parse_args(args).unwrap_or_else(|| {
+        eprintln!("{}", USAGE);
+        std::process::exit(2);
+    });
+    let base = file.parent().unwrap_or(Path::new("")).to_path_buf();
+    let manifest = load(&file).unwrap_or_else(|e| {
+        eprintln!("{} {}", style::error("error:"), e);
+        std::process::exit(2);
+    });
+    let config = config::load().unwrap_or_else(|e| {
+        eprintln!("{} {}", style::error("error:"), e);
+        std::process::exit(2);
+    });
+    let (prompt, inputs) = build_prompt(&manifest, &base, config.references.max_file_bytes())
+        .unwrap_or_else(|e| {
+            eprintln!("{} {}", style::error("error:"), e);
+            std::process::exit(2);
+        });
+
+    let started = Instant::now();
+    let outcome = execute(&manifest, &base, prompt, api_key, &config);
+    let summary = Summary::new(&file, inputs, &outcome, started.elapsed());
+    match &outcome {
+        Ok(reply) => match &reply.result {
+            Ok(text) => {
+                print_answer(&manifest, reply, text);
+                if !manifest.safe {
+                    sinks::broadcast(&config.sinks, &manifest.prompt, text, time::now_ms());
+                }
+            }
+            Err(e) => eprintln!("{} {}", style::error("error:"), e),
+        },
+        Err(e) => eprintln!("{} {}", style::error("error:"), e),
+    }
+    eprintln!("{}", style::dim(&summary.table()));
+    if let Some(path) = &summary_file {
+        if let Err(e) = fs::write(path, format!("{}\n", summary.json())) {
+            eprintln!(
+                "{} cannot write {}: {}",
+                style::warning("warning:"),
+                path.display(),
+                e
+            );
+        }
+    }
+    if summary.status == Status::Failed {
+        std::process::exit(1);
+    }
+}
+
+/// The manifest path and the optional `--summary-file`.
+fn parse_args(args: &[String]) -> Option<(PathBuf, Option<PathBuf>)> {
+    let mut file = None;
+    let mut summary_file = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-f" => file = Some(PathBuf::from(args.next()?)),
+            "--summary-file" => summary_file = Some(PathBuf::from(args.next()?)),
+            _ => return None,
+        }
+    }
+    Some((file?, summary_file))
+}
+
+/// Read and parse a manifest. Unknown fields are errors, as in the
+/// config file.
+pub fn load(file: &Path) -> Result<Manifest, String> {
+    let contents =
+        fs::read_to_string(file).map_err(|e| format!("cannot read {}: {}", file.display(), e))?;
+    let manifest: Manifest = serde_json::from_str(&contents)
+        .map_err(|e| format!("invalid manifest {}: {}", file.display(), e))?;
+    if manifest.turn_deadline_secs == Some(0) {
+        return Err("turnDeadlineSecs must be positive".to_string());
+    }
+    Ok(manifest)
+}
+
+/// The prompt with each input file attached in a `<file>` element, and
+/// what was attached. Inputs are limited as `@path` references are.
+fn build_prompt(
+    manifest: &Manifest,
+    base: &Path,
+    max_file_bytes: u64,
+) -> Result<(String, Vec<Attachment>), String> {
+    let mut prompt = manifest.prompt.clone();
+    let mut attached = Vec::new();
+    for input in &manifest.inputs {
+        let path = base.join(input);
+        let contents = references::read_file(&path, max_file_bytes)
+            .map_err(|e| format!("input {}: {}", input.display(), e))?;
+        attached.push(Attachment::of(&input.display().to_string(), &contents));
+        prompt.push_str(&format!(
+            "\n\n<file path=\"{}\">\n{}\n</file>",
+            input.display(),
+            contents.trim_end()
+        ));
+    }
+    Ok((prompt, attached))
+}
+
+fn execute(
+    manifest: &Manifest,
+    base: &Path,
+    prompt: String,
+    api_key: &str,
+    config: &Config,
+) -> Result<Reply, String> {
+    let image = match &manifest.image {
+        Some(image) => image.clone(),
+        None => {
+            agent::build_image()?;
+            agent::IMAGE.to_string()
+        }
+    };
+
+    let (scratch, default_paths) = match &manifest.data_dir {
+        Some(_) => (None, Paths::resolve()),
+        None => {
+            let (root, paths) = safe::scratch_paths()
+                .map_err(|e| format!("cannot create scratch directory: {}", e))?;
+            (Some(root), paths)
+        }
+    };
+    let home_dir = match &manifest.home_dir {
+        Some(dir) => base.join(dir),
+        None if manifest.safe => default_paths.home_dir,
+        None => Paths::resolve().home_dir,
+    };
+    let paths = Paths {
+        data_dir: manifest
+            .data_dir
+            .as_ref()
+            .map_or(default_paths.data_dir, |dir| base.join(dir)),
+        home_dir,
+    };
+
+    let mut env = config.routing.env();
+    if let Some(model) = &manifest.model {
+        env.push(("KOMATACHI_MODEL", model.clone()));
+    }
+    if let Some(max_tokens) = manifest.max_tokens {
+        env.push(("KOMATACHI_MAX_TOKENS", max_tokens.to_string()));
+    }
+
+    let result = run_turn(
+        &paths,
+        &image,
+        &env,
+        manifest,
+        prompt,
+        api_key,
+        config.watchdog.stall_limit(),
+    );
+    if let Some(root) = &scratch {
+        safe::discard(root);
+    }
+    result
+}
+
+fn run_turn(
+    paths: &Paths,
+    image: &str,
+    env: &[(&str, String)],
+    manifest: &Manifest,
+    prompt: String,
+    api_key: &str,
+    stall_limit: Duration,
+) -> Result<Reply, String> {
+    for dir in [&paths.data_dir, &paths.home_dir] {
+        fs::create_dir_all(dir).map_err(|e| format!("cannot create {}: {}", dir.display(), e))?;
+    }
+    let mut agent = Agent::launch(api_key, paths, manifest.safe, image, env)?;
+    if let Err(e) = agent.handshake() {
+        agent.kill();
+        return Err(e);
+    }
+
+    let id = agent.input_id();
+    if let Err(e) = agent.send(&ClientMessage::Input {
+        text: prompt,
+        stream: false,
+        id,
+    }) {
+        agent.kill();
+        return Err(e);
+    }
+    let mut finalize_at = manifest
+        .turn_deadline_secs
+        .map(|secs| Instant::now() + Duration::from_secs(secs));
+    let reply = turn::receive_reply(&mut agent, stall_limit, &mut finalize_at, None, &mut |_| {});
+    match reply {
+        Ok(reply) => {
+            agent.shutdown();
+            Ok(reply)
+        }
+        Err(lost) => {
+            agent.kill();
+            Err(lost.describe())
+        }
+    }
+}
+
+fn print_answer(manifest: &Manifest, reply: &Reply, text: &str) {
+    if reply.partial {
+        eprintln!("{}", style::dim("[turn deadline reached; partial answer]"));
+    }
+    match manifest.output {
+        Output::Text => println!("{}", text),
+        Output::Json => {
+            let usage = reply.usage.as_ref();
+            let json = serde_json::json!({
+                "output": text,
+                "partial": reply.partial,
+                "model": usage.map(|u| u.model.clone()),
+                "inputTokens": usage.map(|u| u.input_tokens),
+                "outputTokens": usage.map(|u| u.output_tokens),
+                "checkpoint": reply.checkpoint,
+            });
+            println!("{}", json);
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Status {
+    Completed,
+    /// Finalized at the turn deadline.
+    Partial,
+    Failed,
+}
+
+/// How a run went, for stderr and `--summary-file`.
+struct Summary {
+    manifest: PathBuf,
+    inputs: Vec<Attachment>,
+    status: Status,
+    error: Option<String>,
+    model: Option<String>,
+    input_tokens: Option<u64>,
+    output_tokens: Option<u64>,
+    duration: Duration,
+}
+
+impl Summary {
+    fn new(
+        manifest: &Path,
+        inputs: Vec<Attachment>,
+        outcome: &Result<Reply, String>,
+        duration: Duration,
+    ) -> Summary {
+        let usage = outcome.as_ref().ok().and_then(|reply| reply.usage.as_ref());
+        let (status, error) = match outcome {
+            Ok(Reply {
+                result: Ok(_),
+                partial: true,
+                ..
+            }) => (Status::Partial, None),
+            Ok(Reply { result: Ok(_), .. }) => (Status::Completed, None),
+            Ok(Reply { result: Err(e), .. }) | Err(e) => (Status::Failed, Some(e.clone())),
+        };
+        Summary {
+            manifest: manifest.to_path_buf(),
+            inputs,
+            status,
+            error,
+            model: usage.map(|u| u.model.clone()),
+            input_tokens: usage.map(|u| u.input_tokens),
+            output_tokens: usage.map(|u| u.output_tokens),
+            duration,
+        }
+    }
+
+    fn status_name(&self) -> &'static str {
+        match self.status {
+            Status::Completed => "completed",
+            Status::Partial => "partial",
+            Status::Failed => "failed",
+        }
+    }
+
+    /// One JSON object, for CI steps to read.
+    fn json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "manifest": self.manifest.display().to_string(),
+            "inputs": self.inputs.iter().map(|input| serde_json::json!({
+                "path": input.name,
+                "bytes": input.bytes,
+                "sha256": input.sha256,
+            })).collect::<Vec<_>>(),
+            "status": self.status_name(),
+            "error": self.error,
+            "model": self.model,
+            "inputTokens": self.input_tokens,
+            "outputTokens": self.output_tokens,
+            "durationMs": self.duration.as_millis() as u64,
+        })
+    }
+
+    /// A few aligned lines for people.
+    fn table(&self) -> String {
+        let mut rows = vec![
+            ("status", self.status_name().to_string()),
+            ("duration", format!("{:.1}s", self.duration.as_secs_f64())),
+        ];
+        if let Some(model) = &self.model {
+            rows.push(("model", model.clone()));
+        }
+        if let (Some(input), Some(output)) = (self.input_tokens, self.output_tokens) {
+            rows.push(("tokens", format!("{} in / {} out", input, output)));
+        }
+        for input in &self.inputs {
+            rows.push(("input", input.describe()));
+        }
+        if let Some(error) = &self.error {
+            rows.push(("error", error.clone()));
+        }
+        let mut out = format!("run {}:", self.manifest.display());
+        for (name, value) in rows {
+            out.push_str(&format!("\n  {:<9}{}", name, value));
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(json: &str) -> Result<Manifest, serde_json::Error> {
+        serde_json::from_str(json)
+    }
+
+    #[test]
+    fn minimal_manifest_uses_defaults() {
+        let manifest = parse(r#"{"prompt": "Summarize."}"#).unwrap();
+        assert!(manifest.inputs.is_empty());
+        assert!(manifest.data_dir.is_none());
+        assert!(manifest.output == Output::Text);
+    }
+
+    #[test]
+    fn unknown_fields_are_rejected() {
+        assert!(parse(r#"{"prompt": "x", "systemPrompt": "y"}"#).is_err());
+        assert!(parse(r#"{"prompt": "x", "output": "yaml"}"#).is_err());
+    }
+
+    #[test]
+    fn inputs_are_attached_to_the_prompt() {
+        let dir = std::env::temp_dir().join(format!("komatachi-run-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("notes.txt"), "line one\n").unwrap();
+        let manifest = parse(r#"{"prompt": "Review:", "inputs": ["notes.txt"]}"#).unwrap();
+        let built = build_prompt(&manifest, &dir, 100);
+        let too_big = build_prompt(&manifest, &dir, 4);
+        fs::remove_dir_all(&dir).unwrap();
+        let (prompt, inputs) = built.unwrap();
+        assert_eq!(
+            prompt,
+            "Review:\n\n<file path=\"notes.txt\">\nline one\n</file>"
+        );
+        assert_eq!(inputs[0].name, "notes.txt");
+        assert_eq!(inputs[0].bytes, 9);
+        assert!(too_big
+            .err()
+            .unwrap()
+            .contains("over references.maxFileBytes"));
+    }
+
+    #[test]
+    fn parses_summary_file_in_any_order() {
+        let args = |list: &[&str]| list.iter().map(|a| a.to_string()).collect::<Vec<_>>();
+        let (file, summary) =
+            parse_args(&args(&["--summary-file", "s.json", "-f", "job.json"])).expect("valid args");
+        assert_eq!(file, PathBuf::from("job.json"));
+        assert_eq!(summary, Some(PathBuf::from("s.json")));
+        assert!(parse_args(&args(&["--summary-file", "s.json"])).is_none());
+        assert!(parse_args(&args(&["-f"])).is_none());
+    }
+
+    #[test]
+    fn failed_run_summary() {
+        let outcome = Err("agent exited before sending ready signal".to_string());
+        let inputs = vec![Attachment::of("notes.txt", "line one\n")];
+        let summary = Summary::new(
+            Path::new("job.json"),
+            inputs,
+            &outcome,
+            Duration::from_millis(1500),
+        );
+        assert_eq!(summary.status, Status::Failed);
+        assert_eq!(
+            summary.table(),
+            "run job.json:\n  status   failed\n  duration 1.5s\n  \
+             input    notes.txt (9 bytes, sha256 31f21b1dae81)\n  \
+             error    agent exited before sending ready signal"
+        );
+        assert_eq!(summary.json()["durationMs"], 1500);
+        assert_eq!(summary.json()["inputs"][0]["bytes"], 9);
+        assert_eq!(summary.json()["inputTokens"], serde_json::Value::Null);
+    }
+}
@@ -0,0 +1,138 @@
+//! `--safe`: run an untrusted prompt against a throwaway agent.
+//!
+//! The agent gets a scratch data and home directory under the system temp
+//! directory, so it has no memory or identity to leak and nothing it says
+//! reaches the real conversation. The container runs with a read-only
+//! root filesystem, the home directory mounted read-only, no Linux
+//! capabilities, and a process limit. The scratch directory is private
+//! to the user, the container runs as that user, and the directory is
+//! deleted when the CLI exits.
+
+use std::fs;
+use std::io::{self, Read};
+use std::os::unix::fs::{DirBuilderExt, MetadataExt};
+use std::path::{Path, PathBuf};
+
+use crate::paths::Paths;
+use crate::style;
+
+/// Extra `docker run` arguments in safe mode.
+pub const DOCKER_ARGS: [&str; 9] = [
+    "--read-only",
+    "--tmpfs",
+    "/tmp",
+    "--cap-drop",
+    "ALL",
+    "--security-opt",
+    "no-new-privileges",
+    "--pids-limit",
+    "128",
+];
+
+/// Prefix of scratch directory names under the system temp directory.
+const SCRATCH_PREFIX: &str = "komatachi-safe-";
+
+/// Create an empty scratch data and home directory. Returns the scratch
+/// root (to delete on exit) and the paths to mount.
+///
+/// The root is created exclusively, under a random name and mode 0700,
+/// so another user of the temp directory can neither predict it nor
+/// plant one first. The container runs as its owner (`container_user`),
+/// which lets it write the data dir and lets `discard` delete what it
+/// wrote.
+pub fn scratch_paths() -> io::Result<(PathBuf, Paths)> {
+    let root = create_private_dir(SCRATCH_PREFIX)?;
+    let paths = Paths {
+        data_dir: root.join("data"),
+        home_dir: root.join("home"),
+    };
+    let created = private_dir_builder()
+        .create(&paths.data_dir)
+        .and_then(|()| private_dir_builder().create(&paths.home_dir));
+    if let Err(e) = created {
+        discard(&root);
+        return Err(e);
+    }
+    Ok((root, paths))
+}
+
+/// The `--user` for a container whose data dir is a scratch directory:
+/// the directory's owner, as `uid:gid`. `None` for any other data dir,
+/// whose files the container has always written as root.
+pub fn container_user(data_dir: &Path) -> Option<String> {
+    let root = data_dir.parent()?;
+    let is_scratch = root.parent() == Some(std::env::temp_dir().as_path())
+        && root
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| name.starts_with(SCRATCH_PREFIX));
+    if !is_scratch {
+        return None;
+    }
+    let metadata = fs::metadata(data_dir).ok()?;
+    Some(format!("{}:{}", metadata.uid(), metadata.gid()))
+}
+
+/// A new directory under the system temp directory, named `prefix` and
+/// a random suffix, created exclusively with mode 0700.
+pub fn create_private_dir(prefix: &str) -> io::Result<PathBuf> {
+    let temp = std::env::temp_dir();
+    let mut attempts = 0;
+    loop {
+        let root = temp.join(format!("{}{}", prefix, random_suffix()?));
+        match private_dir_builder().create(&root) {
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists && attempts < 8 => attempts += 1,
+            created => return created.map(|()| root),
+        }
+    }
+}
+
+fn private_dir_builder() -> fs::DirBuilder {
+    let mut builder = fs::DirBuilder::new();
+    builder.mode(0o700);
+    builder
+}
+
+/// 16 hex digits from the system's random source.
+fn random_suffix() -> io::Result<String> {
+    let mut bytes = [0u8; 8];
+    fs::File::open("/dev/urandom")?.read_exact(&mut bytes)?;
+    Ok(bytes.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+/// Delete the scratch directory. Failure is reported, not fatal.
+pub fn discard(root: &Path) {
+    if let Err(e) = fs::remove_dir_all(root) {
+        eprintln!(
+            "{} cannot remove scratch directory {}: {}",
+            style::warning("warning:"),
+            root.display(),
+            e
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scratch_directories_are_private_and_run_as_their_owner() {
+        let (root, paths) = scratch_paths().unwrap();
+        let (other, _) = scratch_paths().unwrap();
+        assert_ne!(root, other);
+        for dir in [&root, &paths.data_dir, &paths.home_dir] {
+            let mode = fs::metadata(dir).unwrap().mode();
+            assert_eq!(mode & 0o777, 0o700, "{}", dir.display());
+        }
+        let owner = fs::metadata(&root).unwrap();
+        assert_eq!(
+            container_user(&paths.data_dir),
+            Some(format!("{}:{}", owner.uid(), owner.gid()))
+        );
+        assert_eq!(container_user(&root.join("elsewhere").join("data")), None);
+        discard(&root);
+        discard(&other);
+        assert!(!root.exists());
+    }
+}
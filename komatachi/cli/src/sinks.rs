@@ -0,0 +1,244 @@
+//! Output sinks: `/send-to <sink>`.
+//!
+//! A sink is a named place outside Komatachi where exchanges are copied
+//! as markdown: a file appended to (a note in an Obsidian vault, say), a
+//! directory of daily notes with one `YYYY-MM-DD.md` per day, or a
+//! command that reads the entry on stdin. Sinks are configured under
+//! `sinks` in `config.json`; those marked `auto` receive every answer,
+//! from the REPL and from `run`, all at once. A sink with `format: json`
+//! gets one JSON line per exchange instead, for webhooks and programs.
+//! Nothing is sent anywhere with `--safe`.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::thread;
+
+use crate::style;
+use crate::time;
+
+/// Sinks by name.
+pub type Sinks = BTreeMap<String, Sink>;
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "camelCase")]
+pub struct Sink {
+    /// Append to this file.
+    pub file: Option<String>,
+    /// Append to `<dailyDir>/YYYY-MM-DD.md`, for today's date (UTC).
+    pub daily_dir: Option<String>,
+    /// Run this with `sh -c`, the entry on its stdin.
+    pub command: Option<String>,
+    /// How each exchange is written (default markdown).
+    pub format: Format,
+    /// Send every answer here, not only on `/send-to`.
+    pub auto: bool,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    /// A `## <date>` heading, then the prompt and the answer.
+    #[default]
+    Markdown,
+    /// `{"createdAt":...,"input":...,"output":...}` and a newline.
+    Json,
+}
+
+/// Where a sink's entries go; exactly one per sink.
+enum Target<'a> {
+    File(PathBuf),
+    DailyDir(PathBuf),
+    Command(&'a str),
+}
+
+impl Sink {
+    fn target(&self) -> Result<Target<'_>, String> {
+        match (&self.file, &self.daily_dir, &self.command) {
+            (Some(file), None, None) => Ok(Target::File(expand_home(file))),
+            (None, Some(dir), None) => Ok(Target::DailyDir(expand_home(dir))),
+            (None, None, Some(command)) => Ok(Target::Command(command)),
+            _ => Err("set exactly one of file, dailyDir, and command".to_string()),
+        }
+    }
+
+    /// Deliver one exchange; `now_ms` dates it and picks the day for
+    /// daily notes.
+    pub fn send(&self, input: &str, output: &str, now_ms: u64) -> Result<(), String> {
+        let entry = &match self.format {
+            Format::Markdown => entry(input, output, now_ms),
+            Format::Json => json_entry(input, output, now_ms),
+        };
+        let path = match self.target()? {
+            Target::File(path) => path,
+            Target::DailyDir(dir) => {
+                let date = &time::format_utc(now_ms)[..10];
+                fs::create_dir_all(&dir)
+                    .map_err(|e| format!("cannot create {}: {}", dir.display(), e))?;
+                dir.join(format!("{}.md", date))
+            }
+            Target::Command(command) => return run(command, entry),
+        };
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .and_then(|mut file| file.write_all(entry.as_bytes()))
+            .map_err(|e| format!("cannot write {}: {}", path.display(), e))
+    }
+}
+
+/// Check every sink names exactly one target, for `config::load`.
+pub fn check(sinks: &Sinks) -> Result<(), String> {
+    for (name, sink) in sinks {
+        sink.target()
+            .map_err(|e| format!("sink {:?}: {}", name, e))?;
+    }
+    Ok(())
+}
+
+/// Deliver one exchange to every `auto` sink, each on its own thread so
+/// a slow command (a webhook, say) does not hold up the others. Failures
+/// are warnings: the answer has already been shown.
+pub fn broadcast(sinks: &Sinks, input: &str, output: &str, now_ms: u64) {
+    thread::scope(|scope| {
+        let sending: Vec<_> = sinks
+            .iter()
+            .filter(|(_, sink)| sink.auto)
+            .map(|(name, sink)| (name, scope.spawn(move || sink.send(input, output, now_ms))))
+            .collect();
+        for (name, send) in sending {
+            if let Err(e) = send.join().unwrap_or_else(|_| Err("panicked".to_string())) {
+                eprintln!("{} sink {}: {}", style::warning("warning:"), name, e);
+            }
+        }
+    });
+}
+
+/// One exchange as a markdown entry, in the style of
+/// `bookmarks --export`.
+fn entry(input: &str, output: &str, now_ms: u64) -> String {
+    format!(
+        "## {} UTC\n\n**User:** {}\n\n**Agent:** {}\n\n",
+        time::format_utc(now_ms),
+        input,
+        output
+    )
+}
+
+/// One exchange as a JSON line, with the time in epoch milliseconds as
+/// in `ratings.jsonl`.
+fn json_entry(input: &str, output: &str, now_ms: u64) -> String {
+    let entry = serde_json::json!({
+        "createdAt": now_ms,
+        "input": input,
+        "output": output,
+    });
+    format!("{}\n", entry)
+}
+
+fn run(command: &str, entry: &str) -> Result<(), String> {
+    let mut child = Command::new("sh")
+        .args(["-c", command])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .spawn()
+        .map_err(|e| format!("cannot run sh: {}", e))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin
+            .write_all(entry.as_bytes())
+            .map_err(|e| format!("{}: {}", command, e))?;
+    }
+    match child.wait() {
+        Ok(status) if status.success() => Ok(()),
+        Ok(status) => Err(format!("{}: exited with {}", command, status)),
+        Err(e) => Err(format!("{}: {}", command, e)),
+    }
+}
+
+/// `~/notes` to `$HOME/notes`; other paths unchanged.
+fn expand_home(path: &str) -> PathBuf {
+    match path.strip_prefix("~/") {
+        Some(rest) => PathBuf::from(std::env::var("HOME").unwrap_or_default()).join(rest),
+        None => PathBuf::from(path),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 2026-10-14 09:30 UTC.
+    const NOW: u64 = 1_791_970_200_000;
+
+    #[test]
+    fn each_sink_needs_one_target() {
+        let sinks: Sinks = serde_json::from_str(r#"{"a": {"file": "x", "command": "y"}}"#).unwrap();
+        assert!(check(&sinks).unwrap_err().contains("sink \"a\""));
+        let sinks: Sinks = serde_json::from_str(r#"{"b": {"auto": true}}"#).unwrap();
+        assert!(check(&sinks).is_err());
+        let sinks: Sinks = serde_json::from_str(r#"{"c": {"dailyDir": "~/notes"}}"#).unwrap();
+        assert!(check(&sinks).is_ok());
+    }
+
+    #[test]
+    fn appends_to_files_and_daily_notes() {
+        let dir = std::env::temp_dir().join(format!("komatachi-sinks-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let daily = Sink {
+            daily_dir: Some(dir.join("daily").display().to_string()),
+            ..Sink::default()
+        };
+        let entry = entry("question", "answer", NOW);
+        daily.send("question", "answer", NOW).unwrap();
+        daily.send("question", "answer", NOW).unwrap();
+        let written = fs::read_to_string(dir.join("daily/2026-10-14.md")).unwrap();
+        assert_eq!(written, entry.repeat(2));
+        assert!(entry.starts_with("## 2026-10-14 09:30 UTC\n\n**User:** question"));
+
+        let command = Sink {
+            command: Some(format!("cat > {}", dir.join("piped.md").display())),
+            ..Sink::default()
+        };
+        command.send("question", "answer", NOW).unwrap();
+        assert_eq!(fs::read_to_string(dir.join("piped.md")).unwrap(), entry);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn broadcasts_to_auto_sinks_in_their_formats() {
+        let dir = std::env::temp_dir().join(format!("komatachi-broadcast-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let sink = |name: &str, format, auto| Sink {
+            file: Some(dir.join(name).display().to_string()),
+            format,
+            auto,
+            ..Sink::default()
+        };
+        let sinks = Sinks::from([
+            ("tee".to_string(), sink("tee.md", Format::Markdown, true)),
+            ("hook".to_string(), sink("hook.jsonl", Format::Json, true)),
+            (
+                "manual".to_string(),
+                sink("manual.md", Format::Markdown, false),
+            ),
+        ]);
+        broadcast(&sinks, "question", "line one\n\"two\"", NOW);
+        assert_eq!(
+            fs::read_to_string(dir.join("tee.md")).unwrap(),
+            entry("question", "line one\n\"two\"", NOW)
+        );
+        assert_eq!(
+            fs::read_to_string(dir.join("hook.jsonl")).unwrap(),
+            format!(
+                "{{\"createdAt\":{},\"input\":\"question\",\"output\":\"line one\\n\\\"two\\\"\"}}\n",
+                NOW
+            )
+        );
+        assert!(!dir.join("manual.md").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
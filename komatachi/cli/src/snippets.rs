@@ -0,0 +1,103 @@
+//! Prompt snippets: `:name` in a prompt, or `/snippet name`.
+//!
+//! A snippet is a named piece of text under `snippets` in `config.json`,
+//! such as a long code-review instruction, so it is typed once:
+//! `{"snippets": {"review": "Review this change for..."}}`. A `:name`
+//! that starts a word is replaced by the snippet's text before anything
+//! else happens to the prompt, so a snippet can hold `@path` references
+//! and `` !`command` `` spans, which are then quoted and run as if typed.
+//! Snippets are expanded once: a snippet naming another is sent as it is.
+//! A `:word` that is not a snippet's name is left alone, and `::name` is
+//! sent as `:name`. The text is plain; there are no parameters (Decision
+//! #15 keeps templates out).
+
+use std::collections::BTreeMap;
+
+/// Snippets by name.
+pub type Snippets = BTreeMap<String, String>;
+
+/// Check the snippets' names, for `config::load`.
+pub fn check(snippets: &Snippets) -> Result<(), String> {
+    for name in snippets.keys() {
+        if name.is_empty() || !name.chars().all(is_name_char) {
+            return Err(format!(
+                "snippet {:?}: names are letters, digits, '-', and '_'",
+                name
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '-' || c == '_'
+}
+
+/// `input` with every `:name` of a snippet replaced by its text, and the
+/// names that were, in order.
+pub fn expand(input: &str, snippets: &Snippets) -> (String, Vec<String>) {
+    let mut out = String::new();
+    let mut used = Vec::new();
+    let mut rest = input;
+    let mut word_start = true;
+    while let Some(c) = rest.chars().next() {
+        if c == ':' && word_start {
+            let escaped = rest.starts_with("::");
+            let after = &rest[if escaped { 2 } else { 1 }..];
+            let end = after.find(|c| !is_name_char(c)).unwrap_or(after.len());
+            let name = &after[..end];
+            if let Some(text) = snippets.get(name) {
+                if escaped {
+                    out.push(':');
+                    out.push_str(name);
+                } else {
+                    out.push_str(text);
+                    used.push(name.to_string());
+                }
+                rest = &after[end..];
+                word_start = false;
+                continue;
+            }
+        }
+        out.push(c);
+        word_start = c.is_whitespace();
+        rest = &rest[c.len_utf8()..];
+    }
+    (out, used)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expands_known_names_at_word_starts() {
+        let snippets = Snippets::from([
+            ("review".to_string(), "Review this for bugs.".to_string()),
+            ("ja".to_string(), "Answer in Japanese, :review".to_string()),
+        ]);
+        assert_eq!(
+            expand(":review\n@src/main.rs, then :ja.", &snippets),
+            (
+                "Review this for bugs.\n@src/main.rs, then Answer in Japanese, :review."
+                    .to_string(),
+                vec!["review".to_string(), "ja".to_string()]
+            )
+        );
+        let (text, used) = expand("a:review ::review :reviews http://x :", &snippets);
+        assert_eq!(text, "a:review :review :reviews http://x :");
+        assert!(used.is_empty());
+    }
+
+    #[test]
+    fn rejects_names_that_cannot_be_typed() {
+        assert!(check(&Snippets::from([(
+            "code-review_2".to_string(),
+            String::new()
+        )]))
+        .is_ok());
+        assert!(check(&Snippets::from([("a b".to_string(), String::new())]))
+            .unwrap_err()
+            .starts_with("snippet \"a b\": names are"));
+    }
+}
@@ -0,0 +1,139 @@
+//! Text-to-speech output.
+//!
+//! `--speak` pipes each response through a TTS command in addition to
+//! printing it. `KOMATACHI_SPEAK_COMMAND` (run with `sh -c`, text on
+//! stdin) takes precedence; otherwise the first available of `say`,
+//! `espeak-ng`, `espeak` is used. A new response interrupts one still
+//! being spoken.
+
+use std::io::Write;
+use std::process::{Child, Command, Stdio};
+
+use crate::style;
+
+pub const SPEAK_COMMAND_VAR: &str = "KOMATACHI_SPEAK_COMMAND";
+
+/// Built-in TTS commands that read text from stdin, tried in order.
+const BUILTIN_COMMANDS: [&[&str]; 3] = [
+    &["say", "-f", "-"],
+    &["espeak-ng", "--stdin"],
+    &["espeak", "--stdin"],
+];
+
+pub struct Speaker {
+    program: String,
+    args: Vec<String>,
+    current: Option<Child>,
+}
+
+impl Speaker {
+    /// Find a TTS backend. Returns `None` if nothing is configured or
+    /// installed.
+    pub fn detect() -> Option<Speaker> {
+        if let Ok(command) = std::env::var(SPEAK_COMMAND_VAR) {
+            if !command.trim().is_empty() {
+                return Some(Speaker {
+                    program: "sh".to_string(),
+                    args: vec!["-c".to_string(), command],
+                    current: None,
+                });
+            }
+        }
+        BUILTIN_COMMANDS.iter().find_map(|command| {
+            let available = Command::new("which")
+                .arg(command[0])
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .status()
+                .is_ok_and(|status| status.success());
+            available.then(|| Speaker {
+                program: command[0].to_string(),
+                args: command[1..].iter().map(|a| a.to_string()).collect(),
+                current: None,
+            })
+        })
+    }
+
+    /// Start speaking `text` in the background, interrupting any speech
+    /// still in progress.
+    pub fn speak(&mut self, text: &str) {
+        self.stop();
+        let text = speakable(text);
+        if text.trim().is_empty() {
+            return;
+        }
+        let child = Command::new(&self.program)
+            .args(&self.args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn();
+        match child {
+            Ok(mut child) => {
+                if let Some(mut stdin) = child.stdin.take() {
+                    // Dropping stdin at the end of this block signals end of text.
+                    let _ = stdin.write_all(text.as_bytes());
+                }
+                self.current = Some(child);
+            }
+            Err(e) => eprintln!(
+                "{} cannot run {}: {}",
+                style::warning("warning:"),
+                self.program,
+                e
+            ),
+        }
+    }
+
+    /// Interrupt speech in progress, if any.
+    pub fn stop(&mut self) {
+        if let Some(mut child) = self.current.take() {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
+
+impl Drop for Speaker {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+/// Strip fenced code blocks, which are noise when read aloud.
+fn speakable(text: &str) -> String {
+    let mut out = Vec::new();
+    let mut in_code = false;
+    for line in text.lines() {
+        if line.trim_start().starts_with("```") {
+            if !in_code {
+                out.push("(code omitted)");
+            }
+            in_code = !in_code;
+            continue;
+        }
+        if !in_code {
+            out.push(line);
+        }
+    }
+    out.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn omits_code_blocks() {
+        let text = "Here is the fix:\n```rust\nfn main() {}\n```\nThat should work.";
+        assert_eq!(
+            speakable(text),
+            "Here is the fix:\n(code omitted)\nThat should work."
+        );
+    }
+
+    #[test]
+    fn leaves_prose_untouched() {
+        assert_eq!(speakable("one\ntwo"), "one\ntwo");
+    }
+}
@@ -0,0 +1,69 @@
+//! A spinner on stderr while a turn is in flight.
+//!
+//! Unless the answer is streamed, the agent answers in one piece, so
+//! between sending a prompt and the answer the terminal would show
+//! nothing. The spinner shows the turn is still running and for how
+//! long, and is erased when the reply, or its first streamed text,
+//! arrives.
+//! Only on a terminal: redirected stderr gets no spinner.
+
+use std::io::{self, IsTerminal, Write};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+const FRAMES: [char; 4] = ['|', '/', '-', '\\'];
+const INTERVAL: Duration = Duration::from_millis(100);
+
+pub struct Spinner {
+    running: Option<(Sender<()>, JoinHandle<()>)>,
+}
+
+impl Spinner {
+    pub fn start() -> Spinner {
+        if !io::stderr().is_terminal() {
+            return Spinner { running: None };
+        }
+        let (stop, stopped) = mpsc::channel();
+        let started = Instant::now();
+        let drawer = thread::spawn(move || {
+            let mut frame = 0;
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(INTERVAL) {
+                eprint!(
+                    "\r{}",
+                    line(FRAMES[frame % FRAMES.len()], started.elapsed())
+                );
+                io::stderr().flush().ok();
+                frame += 1;
+            }
+            eprint!("\r\x1b[K");
+            io::stderr().flush().ok();
+        });
+        Spinner {
+            running: Some((stop, drawer)),
+        }
+    }
+
+    /// Erase the spinner, waiting until it is gone. Once erased it stays
+    /// erased.
+    pub fn finish(&mut self) {
+        if let Some((stop, drawer)) = self.running.take() {
+            let _ = stop.send(());
+            let _ = drawer.join();
+        }
+    }
+}
+
+fn line(frame: char, elapsed: Duration) -> String {
+    format!("{} {}s", frame, elapsed.as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shows_whole_seconds() {
+        assert_eq!(line('|', Duration::from_millis(12_900)), "| 12s");
+    }
+}
@@ -0,0 +1,414 @@
+//! Splitting text into chunks of at most a number of tokens.
+//!
+//! `komatachi-cli split <file> --max-tokens <n> [--overlap <m>]` prints
+//! the chunks, and `split` is the function anything that sends a long
+//! text in parts should call, so there is one way to cut text.
+//!
+//! Tokens are estimated with the agent's chars/4 heuristic, as `/inspect`
+//! and `references.maxTokens` count them; the CLI has no tokenizer, and
+//! the estimate errs on the side of smaller chunks for English. Text is
+//! cut at the largest boundary that keeps a chunk under the limit: a
+//! paragraph or fenced code block whole if it fits, else between
+//! sentences (prose) or lines (code), else between words, and only a
+//! word longer than a chunk is cut mid-word. In a code file every
+//! paragraph is code, so nothing is cut at a period. Each chunk is a
+//! slice of the original text. With an overlap, a chunk starts with the
+//! last pieces of the one before, up to that many tokens, so a sentence
+//! cut off at the end of one chunk is whole at the start of the next.
+
+use std::fs;
+use std::io::{self, Read};
+use std::ops::Range;
+use std::path::Path;
+
+use serde_json::json;
+
+use crate::inspect;
+use crate::style;
+
+const USAGE: &str = "\
+usage: komatachi-cli split <file> [options]
+
+Reads standard input when <file> is -.
+
+Options:
+  --max-tokens <n>       Largest chunk, in estimated tokens (default 1000)
+  --overlap <n>          Tokens each chunk repeats from the one before (default 0)
+  --json                 One JSON object per chunk";
+
+const DEFAULT_MAX_TOKENS: usize = 1000;
+
+/// Files split as prose; any other extension is code.
+const PROSE_EXTENSIONS: [&str; 6] = ["md", "markdown", "txt", "rst", "adoc", "org"];
+
+struct Options {
+    file: String,
+    max_tokens: usize,
+    overlap: usize,
+    json: bool,
+}
+
+/// How a block of text may be cut when it does not fit in a chunk.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Kind {
+    /// Between sentences.
+    Prose,
+    /// Between lines.
+    Code,
+}
+
+/// `komatachi-cli split <file> [options]`
+pub fn run_command(args: &[String]) {
+    let options = parse_args(args).unwrap_or_else(|e| {
+        eprintln!("{} {}\n\n{}", style::error("error:"), e, USAGE);
+        std::process::exit(2);
+    });
+    let text = if options.file == "-" {
+        let mut text = String::new();
+        io::stdin().read_to_string(&mut text).map(|_| text)
+    } else {
+        fs::read_to_string(&options.file)
+    }
+    .unwrap_or_else(|e| {
+        eprintln!(
+            "{} cannot read {}: {}",
+            style::error("error:"),
+            options.file,
+            e
+        );
+        std::process::exit(2);
+    });
+    let code = options.file != "-"
+        && Path::new(&options.file)
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .is_some_and(|extension| {
+                !PROSE_EXTENSIONS.contains(&extension.to_ascii_lowercase().as_str())
+            });
+
+    let chunks = split(&text, options.max_tokens, options.overlap, code);
+    for (index, range) in chunks.iter().enumerate() {
+        let chunk = &text[range.clone()];
+        let (first, last) = lines(&text, range);
+        let tokens = inspect::estimate_tokens(chunk);
+        if options.json {
+            let object = json!({
+                "index": index + 1,
+                "startLine": first,
+                "endLine": last,
+                "tokens": tokens,
+                "text": chunk,
+            });
+            println!("{}", object);
+        } else {
+            println!(
+                "{}",
+                style::dim(&format!(
+                    "--- chunk {} of {} (lines {}-{}, ~{} tokens) ---",
+                    index + 1,
+                    chunks.len(),
+                    first,
+                    last,
+                    tokens
+                ))
+            );
+            println!("{}", chunk.trim_end());
+        }
+    }
+}
+
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut options = Options {
+        file: String::new(),
+        max_tokens: DEFAULT_MAX_TOKENS,
+        overlap: 0,
+        json: false,
+    };
+    let mut file = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| {
+            args.next()
+                .cloned()
+                .ok_or_else(|| format!("{} needs a value", name))
+        };
+        let count = |text: String| {
+            text.parse::<usize>()
+                .map_err(|_| format!("expected a number of tokens, got {:?}", text))
+        };
+        match arg.as_str() {
+            "--max-tokens" => options.max_tokens = count(value(arg)?)?,
+            "--overlap" => options.overlap = count(value(arg)?)?,
+            "--json" => options.json = true,
+            other if other.starts_with('-') && other.len() > 1 => {
+                return Err(format!("unknown option: {}", other))
+            }
+            _ if file.is_some() => return Err("only one file can be given".to_string()),
+            _ => file = Some(arg.clone()),
+        }
+    }
+    options.file = file.ok_or("missing file")?;
+    if options.max_tokens == 0 {
+        return Err("--max-tokens must be at least 1".to_string());
+    }
+    if options.overlap >= options.max_tokens {
+        return Err("--overlap must be less than --max-tokens".to_string());
+    }
+    Ok(options)
+}
+
+/// `text` as byte ranges of at most `max_tokens` estimated tokens, each
+/// starting with up to `overlap` tokens of the one before. `code` cuts
+/// paragraphs between lines rather than sentences.
+pub fn split(text: &str, max_tokens: usize, overlap: usize, code: bool) -> Vec<Range<usize>> {
+    let max_chars = max_tokens.max(1) * 4;
+    let mut pieces = Vec::new();
+    for (range, kind) in blocks(text, code) {
+        refine(text, range, kind, 0, max_chars, &mut pieces);
+    }
+    // Tokens are counted as inspect::estimate_tokens does, from the
+    // characters of the whole chunk.
+    let tokens = |chars: usize| chars.div_ceil(4);
+    let chars: Vec<usize> = pieces
+        .iter()
+        .map(|range| text[range.clone()].chars().count())
+        .collect();
+
+    let mut chunks = Vec::new();
+    let mut current: Vec<usize> = Vec::new();
+    let mut current_chars = 0;
+    for (index, &piece_chars) in chars.iter().enumerate() {
+        if !current.is_empty() && tokens(current_chars + piece_chars) > max_tokens {
+            chunks.push(pieces[current[0]].start..pieces[*current.last().unwrap()].end);
+            let mut kept = 0;
+            let mut kept_chars = 0;
+            for &piece in current.iter().rev() {
+                if tokens(kept_chars + chars[piece]) > overlap {
+                    break;
+                }
+                kept += 1;
+                kept_chars += chars[piece];
+            }
+            current.drain(..current.len() - kept);
+            current_chars = kept_chars;
+            while !current.is_empty() && tokens(current_chars + piece_chars) > max_tokens {
+                current_chars -= chars[current.remove(0)];
+            }
+        }
+        current.push(index);
+        current_chars += piece_chars;
+    }
+    if let (Some(&first), Some(&last)) = (current.first(), current.last()) {
+        chunks.push(pieces[first].start..pieces[last].end);
+    }
+    chunks
+}
+
+/// Paragraphs and fenced code blocks, each with the blank lines after it.
+fn blocks(text: &str, code: bool) -> Vec<(Range<usize>, Kind)> {
+    let paragraph = if code { Kind::Code } else { Kind::Prose };
+    let mut blocks: Vec<(Range<usize>, Kind)> = Vec::new();
+    let mut fence: Option<&str> = None;
+    // Blank lines before the first text join the first block.
+    let mut started = false;
+    let mut after_blank = false;
+    let mut at = 0;
+    for line in text.split_inclusive('\n') {
+        let range = at..at + line.len();
+        at = range.end;
+        let trimmed = line.trim_start();
+        let marker = ["```", "~~~"].into_iter().find(|m| trimmed.starts_with(m));
+        let new_block = match (fence, marker) {
+            (Some(open), _) => {
+                if marker == Some(open) {
+                    fence = None;
+                    after_blank = true;
+                }
+                None
+            }
+            (None, Some(open)) => {
+                fence = Some(open);
+                Some(Kind::Code)
+            }
+            (None, None) if trimmed.is_empty() => {
+                after_blank = true;
+                None
+            }
+            (None, None) => {
+                let new = after_blank && started;
+                after_blank = false;
+                new.then_some(paragraph)
+            }
+        };
+        match (new_block, blocks.last_mut()) {
+            (None, Some(last)) => last.0.end = range.end,
+            (kind, _) => blocks.push((range, kind.unwrap_or(paragraph))),
+        }
+        started |= !trimmed.is_empty();
+    }
+    blocks
+}
+
+/// Push `range` to `out`, cut into pieces of at most `max_chars` at the
+/// largest boundary that makes them fit. `level` is the boundary tried
+/// next: 0 sentences or lines, 1 words, then characters.
+fn refine(
+    text: &str,
+    range: Range<usize>,
+    kind: Kind,
+    level: usize,
+    max_chars: usize,
+    out: &mut Vec<Range<usize>>,
+) {
+    if text[range.clone()].chars().count() <= max_chars {
+        out.push(range);
+        return;
+    }
+    let pieces = match (level, kind) {
+        (0, Kind::Prose) => cut(text, range, sentence_ends()),
+        (0, Kind::Code) => cut(text, range, |prev, _| prev == '\n'),
+        (1, _) => cut(text, range, |prev, next| {
+            prev.is_whitespace() && !next.is_whitespace()
+        }),
+        _ => {
+            let mut start = range.start;
+            for (count, (offset, _)) in text[range.clone()].char_indices().enumerate() {
+                if count > 0 && count % max_chars == 0 {
+                    out.push(start..range.start + offset);
+                    start = range.start + offset;
+                }
+            }
+            out.push(start..range.end);
+            return;
+        }
+    };
+    for piece in pieces {
+        refine(text, piece, kind, level + 1, max_chars, out);
+    }
+}
+
+/// `range` cut before every character `next` for which `boundary(prev,
+/// next)` holds.
+fn cut(
+    text: &str,
+    range: Range<usize>,
+    mut boundary: impl FnMut(char, char) -> bool,
+) -> Vec<Range<usize>> {
+    let mut pieces = Vec::new();
+    let mut start = range.start;
+    let mut prev = None;
+    for (offset, c) in text[range.clone()].char_indices() {
+        if prev.is_some_and(|prev| boundary(prev, c)) {
+            pieces.push(start..range.start + offset);
+            start = range.start + offset;
+        }
+        prev = Some(c);
+    }
+    pieces.push(start..range.end);
+    pieces
+}
+
+/// A boundary for `cut`: sentences end at a line break, or where a
+/// word starts after `.`, `!`, or `?` (and any closing bracket or quote)
+/// and some spaces. The spaces stay with the sentence.
+fn sentence_ends() -> impl FnMut(char, char) -> bool {
+    let mut after_stop = false;
+    move |prev, next| {
+        if matches!(prev, '.' | '!' | '?') {
+            after_stop = true;
+        } else if !prev.is_whitespace() && !matches!(prev, ')' | ']' | '"' | '\'') {
+            after_stop = false;
+        }
+        let ends = prev == '\n' || (after_stop && prev.is_whitespace() && !next.is_whitespace());
+        after_stop &= !ends;
+        ends
+    }
+}
+
+/// The first and last line of `range`, from 1, not counting the blank
+/// lines it ends with.
+fn lines(text: &str, range: &Range<usize>) -> (usize, usize) {
+    let first = text[..range.start].matches('\n').count() + 1;
+    let body = text[range.clone()].trim_end();
+    (first, first + body.matches('\n').count())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunks(text: &str, max_tokens: usize, overlap: usize, code: bool) -> Vec<&str> {
+        split(text, max_tokens, overlap, code)
+            .into_iter()
+            .map(|range| &text[range])
+            .collect()
+    }
+
+    #[test]
+    fn keeps_paragraphs_and_code_blocks_whole_when_they_fit() {
+        let text = "One two three.\nFour.\n\n```\nlet a = 1.5;\n\nlet b = 2;\n```\nAfter.\n";
+        let blocks: Vec<(&str, Kind)> = blocks(text, false)
+            .into_iter()
+            .map(|(range, kind)| (&text[range], kind))
+            .collect();
+        assert_eq!(
+            blocks,
+            vec![
+                ("One two three.\nFour.\n\n", Kind::Prose),
+                ("```\nlet a = 1.5;\n\nlet b = 2;\n```\n", Kind::Code),
+                ("After.\n", Kind::Prose),
+            ]
+        );
+        // 22, 33, and 7 characters: the code block does not fit beside
+        // the paragraph, but the last paragraph fits beside it.
+        assert_eq!(
+            chunks(text, 10, 0, false),
+            vec![
+                "One two three.\nFour.\n\n",
+                "```\nlet a = 1.5;\n\nlet b = 2;\n```\nAfter.\n"
+            ]
+        );
+        assert_eq!(chunks(text, 1000, 0, false), vec![text]);
+        assert!(chunks("", 10, 0, false).is_empty());
+    }
+
+    #[test]
+    fn cuts_between_sentences_then_words() {
+        // "(one!)" ends a sentence: a closing bracket after a stop does.
+        let text = "First one here. Second (one!) is here. Third.";
+        assert_eq!(
+            chunks(text, 10, 0, false),
+            vec!["First one here. Second (one!) is here. ", "Third."]
+        );
+        assert_eq!(
+            chunks(text, 5, 0, false),
+            vec!["First one here. ", "Second (one!) ", "is here. Third."]
+        );
+        // Code is cut between lines, never at a period.
+        let code = "a.b(); c.d();\ne.f();\n";
+        assert_eq!(
+            chunks(code, 4, 0, true),
+            vec!["a.b(); c.d();\n", "e.f();\n"]
+        );
+        assert_eq!(
+            chunks(&"x".repeat(9), 1, 0, false),
+            vec!["xxxx", "xxxx", "x"]
+        );
+    }
+
+    #[test]
+    fn overlap_repeats_the_last_pieces() {
+        let text = "Aaaa. Bbbb. Cccc. Dddd.";
+        assert_eq!(
+            chunks(text, 4, 2, false),
+            vec!["Aaaa. Bbbb. ", "Bbbb. Cccc. ", "Cccc. Dddd."]
+        );
+        for (range, chunk) in split(text, 4, 2, false)
+            .iter()
+            .zip(chunks(text, 4, 2, false))
+        {
+            assert!(inspect::estimate_tokens(chunk) <= 4, "{:?}", range);
+        }
+        assert_eq!(lines("a\nb\n\nc\n\n", &(2..8)), (2, 4));
+    }
+}
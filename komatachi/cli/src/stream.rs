@@ -0,0 +1,90 @@
+//! Showing an answer while the agent writes it: `stream.enabled`.
+//!
+//! With streaming on, each prompt asks the agent for deltas (`"stream":
+//! true` on the input), and the text of the answer arrives in
+//! `output_delta` messages while the turn runs. They are printed as they
+//! come, wrapped as a whole answer would be (`wrap::Wrapper`). The
+//! closing `output` still carries the whole answer, and that is what is
+//! kept, copied, spoken, and sent to sinks. A streamed answer is not
+//! paged, since it is on screen before its length is known, and nothing
+//! is streamed while validation rules are set, since a rule can send an
+//! answer back for correction after it was shown. Only to a terminal;
+//! off by default.
+
+use serde::Deserialize;
+use std::io::{self, IsTerminal, Write};
+
+use crate::lineedit;
+use crate::wrap::{self, Wrapper};
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "camelCase")]
+pub struct Settings {
+    /// Print answers as they are written (default false).
+    pub enabled: Option<bool>,
+}
+
+impl Settings {
+    pub fn enabled(&self) -> bool {
+        self.enabled.unwrap_or(false)
+    }
+
+    /// Whether to stream the next answer.
+    pub fn wanted(&self) -> bool {
+        self.enabled() && io::stdout().is_terminal()
+    }
+}
+
+/// Prints one streamed answer to stdout.
+pub struct Printer {
+    /// `None` with wrapping off: deltas are printed as they are.
+    wrapper: Option<Wrapper>,
+    /// Whether any text has arrived, and the last character printed.
+    started: bool,
+    last: Option<char>,
+}
+
+impl Printer {
+    pub fn new(settings: &wrap::Settings) -> Printer {
+        Printer {
+            wrapper: settings
+                .enabled()
+                .then(|| Wrapper::new(lineedit::terminal_size().1)),
+            started: false,
+            last: None,
+        }
+    }
+
+    /// Whether any of the answer has been printed.
+    pub fn started(&self) -> bool {
+        self.started
+    }
+
+    pub fn delta(&mut self, text: &str) {
+        self.started = true;
+        let text = match &mut self.wrapper {
+            Some(wrapper) => wrapper.push(text),
+            None => text.to_string(),
+        };
+        self.print(&text);
+    }
+
+    /// End the answer, on a line of its own.
+    pub fn finish(&mut self) {
+        if let Some(wrapper) = &mut self.wrapper {
+            let rest = wrapper.finish();
+            self.print(&rest);
+        }
+        if self.started && self.last != Some('\n') {
+            self.print("\n");
+        }
+    }
+
+    fn print(&mut self, text: &str) {
+        if let Some(last) = text.chars().last() {
+            self.last = Some(last);
+            print!("{}", text);
+            io::stdout().flush().ok();
+        }
+    }
+}
@@ -0,0 +1,207 @@
+//! Terminal styling, themed by `theme` in the config file.
+//!
+//! Every styled piece of output has a role -- secondary text, errors,
+//! warnings, and the two sides of a diff -- and `theme` maps each role to
+//! the look it gets: `"bold red"`, `"dim"`, `"none"`, or raw SGR
+//! parameters such as `"38;5;208"`. The theme is read from the config
+//! file the first time something is styled, so no caller passes it
+//! around; an invalid config file is reported by whoever loads it, and
+//! styling falls back to the defaults.
+//!
+//! ANSI escapes are emitted only when stdout and stderr are both a
+//! terminal, `NO_COLOR` is unset or empty, and `--no-color` was not
+//! given, so piped output stays plain whichever stream it is on.
+
+use serde::Deserialize;
+use std::io::IsTerminal;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::OnceLock;
+
+use crate::config;
+
+static DISABLED: AtomicBool = AtomicBool::new(false);
+
+/// SGR parameters for each role, in `ROLES` order; empty for plain text.
+static PALETTE: OnceLock<[String; 5]> = OnceLock::new();
+
+/// The roles, with their default styles.
+const ROLES: [(&str, &str); 5] = [
+    ("dim", "dim"),
+    ("error", "bold red"),
+    ("warning", "yellow"),
+    ("added", "green"),
+    ("removed", "red"),
+];
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "camelCase")]
+pub struct Theme {
+    /// Secondary text: turn metadata, headings, status lines.
+    pub dim: Option<String>,
+    /// The `error:` label.
+    pub error: Option<String>,
+    /// The `warning:` label.
+    pub warning: Option<String>,
+    /// Text a diff adds.
+    pub added: Option<String>,
+    /// Text a diff removes.
+    pub removed: Option<String>,
+}
+
+impl Theme {
+    /// Each role's name and style, defaults filled in.
+    pub fn roles(&self) -> [(&'static str, String); 5] {
+        let set = [
+            &self.dim,
+            &self.error,
+            &self.warning,
+            &self.added,
+            &self.removed,
+        ];
+        std::array::from_fn(|i| {
+            let (name, default) = ROLES[i];
+            (name, set[i].clone().unwrap_or_else(|| default.to_string()))
+        })
+    }
+}
+
+/// Check every role's style, for `config::load`.
+pub fn check(theme: &Theme) -> Result<(), String> {
+    for (name, value) in theme.roles() {
+        sgr(&value).map_err(|e| format!("theme.{}: {}", name, e))?;
+    }
+    Ok(())
+}
+
+/// `--no-color`: never style, whatever the terminal.
+pub fn disable() {
+    DISABLED.store(true, Ordering::Relaxed);
+}
+
+fn enabled() -> bool {
+    !DISABLED.load(Ordering::Relaxed)
+        && std::env::var_os("NO_COLOR").is_none_or(|value| value.is_empty())
+        && std::io::stderr().is_terminal()
+        && std::io::stdout().is_terminal()
+}
+
+/// Style `text` as the role at `index` in `ROLES`.
+fn paint(index: usize, text: &str) -> String {
+    if !enabled() {
+        return text.to_string();
+    }
+    let palette = PALETTE.get_or_init(|| {
+        let theme = config::load()
+            .map(|config| config.theme)
+            .unwrap_or_default();
+        theme
+            .roles()
+            .map(|(_, value)| sgr(&value).unwrap_or_default())
+    });
+    match palette[index].as_str() {
+        "" => text.to_string(),
+        code => format!("\x1b[{}m{}\x1b[0m", code, text),
+    }
+}
+
+/// Render text dimmed, for secondary information.
+pub fn dim(text: &str) -> String {
+    paint(0, text)
+}
+
+/// Render the `error:` label of an error message.
+pub fn error(text: &str) -> String {
+    paint(1, text)
+}
+
+/// Render the `warning:` label of a warning.
+pub fn warning(text: &str) -> String {
+    paint(2, text)
+}
+
+/// Render text added by a diff.
+pub fn added(text: &str) -> String {
+    paint(3, text)
+}
+
+/// Render text removed by a diff.
+pub fn removed(text: &str) -> String {
+    paint(4, text)
+}
+
+const ATTRIBUTES: [(&str, u8); 4] = [("bold", 1), ("dim", 2), ("italic", 3), ("underline", 4)];
+
+const COLORS: [&str; 8] = [
+    "black", "red", "green", "yellow", "blue", "magenta", "cyan", "white",
+];
+
+/// A role's value as SGR parameters: words such as `bold bright-red` or
+/// `on-blue`, `none`, or parameters given as they are (`38;5;208`).
+fn sgr(value: &str) -> Result<String, String> {
+    let value = value.trim();
+    if value == "none" {
+        return Ok(String::new());
+    }
+    if !value.is_empty() && value.chars().all(|c| c.is_ascii_digit() || c == ';') {
+        return Ok(value.to_string());
+    }
+    let mut codes = Vec::new();
+    for word in value.split_whitespace() {
+        let (background, color) = match word.strip_prefix("on-") {
+            Some(color) => (true, color),
+            None => (false, word),
+        };
+        let (bright, color) = match color.strip_prefix("bright-") {
+            Some(color) => (true, color),
+            None => (false, color),
+        };
+        let code = if let Some(index) = COLORS.iter().position(|c| *c == color) {
+            let base = match (background, bright) {
+                (false, false) => 30,
+                (true, false) => 40,
+                (false, true) => 90,
+                (true, true) => 100,
+            };
+            base + index as u8
+        } else if let Some((_, code)) = ATTRIBUTES.iter().find(|(name, _)| *name == word) {
+            *code
+        } else {
+            return Err(format!(
+                "unknown style {:?} (use bold, dim, italic, underline, a color \
+                 such as red or bright-red, on-<color> for the background, none, \
+                 or SGR numbers like 38;5;208)",
+                word
+            ));
+        };
+        codes.push(code.to_string());
+    }
+    if codes.is_empty() {
+        return Err("empty style (use none for plain text)".to_string());
+    }
+    Ok(codes.join(";"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn styles_are_words_or_sgr_parameters() {
+        assert_eq!(sgr("bold red").unwrap(), "1;31");
+        assert_eq!(sgr("bright-cyan on-blue").unwrap(), "96;44");
+        assert_eq!(sgr("38;5;208").unwrap(), "38;5;208");
+        assert_eq!(sgr("none").unwrap(), "");
+        assert!(sgr("rouge")
+            .unwrap_err()
+            .starts_with("unknown style \"rouge\""));
+        assert!(sgr(" ").is_err());
+        let theme = Theme {
+            error: Some("blink".to_string()),
+            ..Theme::default()
+        };
+        assert!(check(&theme)
+            .unwrap_err()
+            .starts_with("theme.error: unknown style"));
+        assert!(check(&Theme::default()).is_ok());
+    }
+}
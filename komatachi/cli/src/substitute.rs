@@ -0,0 +1,179 @@
+//! Command substitution in prompts: `` !`git diff --stat` ``.
+//!
+//! Off unless `substitution.enabled` is set in the config file, and never
+//! available with `--safe`. Before anything runs, the commands are listed
+//! and the user confirms them; declining sends nothing. Each command runs
+//! under `sh -c` in the CLI's working directory, and its stdout (trailing
+//! newlines trimmed, cut at `maxBytes`) replaces the `` !`...` `` span.
+//! Stderr goes to the terminal, and a failing command is reported but its
+//! output is still used, as the shell's `$(...)` does.
+
+use serde::Deserialize;
+use std::process::{Command, Stdio};
+
+use crate::style;
+use crate::terminal;
+
+/// Output beyond this many bytes per command is cut off.
+const DEFAULT_MAX_BYTES: usize = 16 * 1024;
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "camelCase")]
+pub struct Settings {
+    /// Expand `` !`command` `` in prompts (default false).
+    pub enabled: Option<bool>,
+    /// Most bytes of output kept per command.
+    pub max_bytes: Option<usize>,
+}
+
+impl Settings {
+    pub fn enabled(&self) -> bool {
+        self.enabled.unwrap_or(false)
+    }
+
+    pub fn max_bytes(&self) -> usize {
+        self.max_bytes.unwrap_or(DEFAULT_MAX_BYTES)
+    }
+}
+
+/// A `` !`command` `` span: byte range in the prompt and the command.
+#[derive(Debug, PartialEq)]
+struct Span<'a> {
+    start: usize,
+    end: usize,
+    command: &'a str,
+}
+
+/// Every `` !`...` `` span in `input`, in order. An unclosed `` !` `` and
+/// an empty command are left as text.
+fn spans(input: &str) -> Vec<Span<'_>> {
+    let mut found = Vec::new();
+    let mut from = 0;
+    while let Some(open) = input[from..].find("!`").map(|i| from + i) {
+        let body = open + 2;
+        let Some(close) = input[body..].find('`').map(|i| body + i) else {
+            break;
+        };
+        let command = input[body..close].trim();
+        if !command.is_empty() {
+            found.push(Span {
+                start: open,
+                end: close + 1,
+                command,
+            });
+        }
+        from = close + 1;
+    }
+    found
+}
+
+/// Cut `text` to at most `max` bytes on a character boundary, saying how
+/// much was dropped.
+fn truncate(text: &str, max: usize) -> String {
+    if text.len() <= max {
+        return text.to_string();
+    }
+    let mut cut = max;
+    while !text.is_char_boundary(cut) {
+        cut -= 1;
+    }
+    format!(
+        "{}\n[... {} more bytes cut by komatachi-cli]",
+        &text[..cut],
+        text.len() - cut
+    )
+}
+
+/// Run one command and return its stdout, cut to `max` bytes.
+fn run(command: &str, max: usize) -> Result<String, String> {
+    let output = Command::new("sh")
+        .args(["-c", command])
+        .stdin(Stdio::null())
+        .stderr(Stdio::inherit())
+        .output()
+        .map_err(|e| format!("cannot run sh: {}", e))?;
+    if !output.status.success() {
+        eprintln!(
+            "{} `{}` exited with {}",
+            style::warning("warning:"),
+            command,
+            output.status
+        );
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    Ok(truncate(stdout.trim_end_matches('\n'), max))
+}
+
+/// Expand the prompt's `` !`command` `` spans after confirmation.
+/// Returns the prompt unchanged if it has none, and `None` (after saying
+/// why) if the user declines or a command cannot be run.
+pub fn expand(input: &str, settings: &Settings) -> Option<String> {
+    let spans = spans(input);
+    if spans.is_empty() {
+        return Some(input.to_string());
+    }
+    eprintln!("The prompt runs these commands on the host:");
+    for span in &spans {
+        eprintln!("  {}", span.command);
+    }
+    if !terminal::confirm("Run them and send the prompt with their output?") {
+        eprintln!("Not sent.");
+        return None;
+    }
+
+    let mut expanded = String::new();
+    let mut last = 0;
+    let mut from_commands = 0;
+    for span in &spans {
+        let output = match run(span.command, settings.max_bytes()) {
+            Ok(output) => output,
+            Err(e) => {
+                eprintln!("{} {}", style::error("error:"), e);
+                return None;
+            }
+        };
+        expanded.push_str(&input[last..span.start]);
+        expanded.push_str(&output);
+        from_commands += output.len();
+        last = span.end;
+    }
+    expanded.push_str(&input[last..]);
+    eprintln!(
+        "Substituted {} command(s): prompt is {} bytes (~{} tokens), {} from commands.",
+        spans.len(),
+        expanded.len(),
+        expanded.chars().count() / 4,
+        from_commands
+    );
+    Some(expanded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_spans_and_skips_unclosed_or_empty_ones() {
+        let input = "Review !`git diff --stat` and !`` then !`date";
+        let found = spans(input);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].command, "git diff --stat");
+        assert_eq!(&input[found[0].start..found[0].end], "!`git diff --stat`");
+        assert!(spans("plain `code` and ! marks").is_empty());
+    }
+
+    #[test]
+    fn truncates_on_a_character_boundary() {
+        assert_eq!(truncate("short", 10), "short");
+        let cut = truncate("ééé", 3);
+        assert!(cut.starts_with("é\n"));
+        assert!(cut.ends_with("[... 4 more bytes cut by komatachi-cli]"));
+    }
+
+    #[test]
+    fn off_by_default() {
+        let settings = Settings::default();
+        assert!(!settings.enabled());
+        assert_eq!(settings.max_bytes(), 16 * 1024);
+    }
+}
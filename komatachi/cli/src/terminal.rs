@@ -0,0 +1,28 @@
+//! Small interactive helpers for the terminal.
+
+use std::io::{self, BufRead, Write};
+
+/// Ask a yes/no question on stderr. Anything but `y`/`yes` is no.
+pub fn confirm(question: &str) -> bool {
+    eprint!("{} [y/N] ", question);
+    io::stderr().flush().ok();
+
+    let mut answer = String::new();
+    if io::stdin().lock().read_line(&mut answer).is_err() {
+        return false;
+    }
+    matches!(answer.trim().to_ascii_lowercase().as_str(), "y" | "yes")
+}
+
+/// Ask a question whose answers are the letters of `keys`, or no. The
+/// letter of the answer given (`e` for `edit`), if it is one of them.
+pub fn choose(question: &str, keys: &str) -> Option<char> {
+    let letters: Vec<String> = keys.chars().map(String::from).collect();
+    eprint!("{} [{}/N] ", question, letters.join("/"));
+    io::stderr().flush().ok();
+
+    let mut answer = String::new();
+    io::stdin().lock().read_line(&mut answer).ok()?;
+    let first = answer.trim().to_ascii_lowercase().chars().next()?;
+    keys.contains(first).then_some(first)
+}
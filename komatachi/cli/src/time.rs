@@ -0,0 +1,101 @@
+//! Wall-clock helpers.
+//!
+//! Timestamps are stored as epoch milliseconds, matching the agent's
+//! conversation metadata. Formatting is UTC so no timezone database is
+//! needed.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Current time in milliseconds since the Unix epoch.
+pub fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// Format epoch milliseconds as `YYYY-MM-DD HH:MM` (UTC).
+pub fn format_utc(ms: u64) -> String {
+    let secs = ms / 1000;
+    let days = (secs / 86_400) as i64;
+    let secs_of_day = secs % 86_400;
+    let (year, month, day) = civil_from_days(days);
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}",
+        year,
+        month,
+        day,
+        secs_of_day / 3600,
+        (secs_of_day % 3600) / 60
+    )
+}
+
+/// Format epoch milliseconds as `HH:MM:SS UTC`.
+pub fn format_utc_time(ms: u64) -> String {
+    let secs_of_day = (ms / 1000) % 86_400;
+    format!(
+        "{:02}:{:02}:{:02} UTC",
+        secs_of_day / 3600,
+        (secs_of_day % 3600) / 60,
+        secs_of_day % 60
+    )
+}
+
+/// Format epoch milliseconds as `YYYYMMDD-HHMMSS` (UTC), for file names.
+pub fn format_utc_compact(ms: u64) -> String {
+    let secs = ms / 1000;
+    let (year, month, day) = civil_from_days((secs / 86_400) as i64);
+    let secs_of_day = secs % 86_400;
+    format!(
+        "{:04}{:02}{:02}-{:02}{:02}{:02}",
+        year,
+        month,
+        day,
+        secs_of_day / 3600,
+        (secs_of_day % 3600) / 60,
+        secs_of_day % 60
+    )
+}
+
+/// Convert days since 1970-01-01 to a (year, month, day) civil date.
+///
+/// Howard Hinnant's `civil_from_days` algorithm.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_epoch() {
+        assert_eq!(format_utc(0), "1970-01-01 00:00");
+    }
+
+    #[test]
+    fn formats_leap_day() {
+        // 2024-02-29 13:45:30 UTC
+        assert_eq!(format_utc(1_709_214_330_000), "2024-02-29 13:45");
+    }
+
+    #[test]
+    fn formats_compact() {
+        assert_eq!(format_utc_compact(1_709_214_330_000), "20240229-134530");
+    }
+
+    #[test]
+    fn formats_end_of_year() {
+        // 1999-12-31 23:59:59 UTC
+        assert_eq!(format_utc(946_684_799_000), "1999-12-31 23:59");
+    }
+}
@@ -0,0 +1,49 @@
+//! Wall-clock times on each exchange: `/timestamps` or
+//! `timestamps.enabled`.
+//!
+//! With timestamps on, a dimmed line marks when each prompt was sent and
+//! when its answer arrived, with how long the turn took, so a long
+//! session can be read back for where the time went. Times are UTC, like
+//! every other time the CLI prints or stores.
+
+use serde::Deserialize;
+use std::time::Duration;
+
+use crate::time;
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "camelCase")]
+pub struct Settings {
+    /// Start sessions with timestamps on (default false).
+    pub enabled: Option<bool>,
+}
+
+impl Settings {
+    pub fn enabled(&self) -> bool {
+        self.enabled.unwrap_or(false)
+    }
+}
+
+/// `[HH:MM:SS UTC] who`, and the turn's duration for an answer.
+pub fn line(who: &str, now_ms: u64, took: Option<Duration>) -> String {
+    let mut out = format!("[{}] {}", time::format_utc_time(now_ms), who);
+    if let Some(took) = took {
+        out.push_str(&format!(", after {:.1}s", took.as_secs_f64()));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stamps_prompts_and_answers() {
+        let at = 1_760_400_000_000 + 3_723_000;
+        assert_eq!(line("you", at, None), "[01:02:03 UTC] you");
+        assert_eq!(
+            line("agent", at, Some(Duration::from_millis(28_140))),
+            "[01:02:03 UTC] agent, after 28.1s"
+        );
+    }
+}
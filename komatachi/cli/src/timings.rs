@@ -0,0 +1,281 @@
+//! Startup phase timings for `--timings` and `komatachi-cli timings`.
+//!
+//! The image build check runs on its own thread while the main thread
+//! prepares the data and home directories, so their durations overlap;
+//! the total is wall-clock time, not the sum of the phases.
+//!
+//! Every interactive start (except with `--safe`) is also appended to
+//! `~/.komatachi/timings.jsonl` when the session ends, with the latency
+//! of its first answered turn. `komatachi-cli timings`
+//! lists recent starts with their median, so a slow phase shows up as a
+//! change from the usual rather than as a feeling.
+
+use serde::{Deserialize, Serialize};
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use crate::paths;
+use crate::style;
+use crate::time;
+
+const USAGE: &str = "usage: komatachi-cli timings [--last <n>]";
+
+/// Starts listed when `--last` is not given.
+const DEFAULT_LAST: usize = 10;
+
+pub struct Timings {
+    started: Instant,
+    started_at: u64,
+    phases: Vec<(&'static str, Duration)>,
+    /// Set by `finish`, once the agent is ready.
+    total: Option<Duration>,
+}
+
+/// One start as saved: milliseconds per phase, in the order measured.
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Record {
+    /// When the CLI started (epoch ms)
+    started_at: u64,
+    phases: Vec<(String, u64)>,
+    total_ms: u64,
+    #[serde(default)]
+    first_turn_ms: Option<u64>,
+}
+
+impl Timings {
+    pub fn start() -> Timings {
+        Timings {
+            started: Instant::now(),
+            started_at: time::now_ms(),
+            phases: Vec::new(),
+            total: None,
+        }
+    }
+
+    pub fn record(&mut self, phase: &'static str, duration: Duration) {
+        self.phases.push((phase, duration));
+    }
+
+    /// Time `f` and record it as `phase`.
+    pub fn measure<T>(&mut self, phase: &'static str, f: impl FnOnce() -> T) -> T {
+        let started = Instant::now();
+        let value = f();
+        self.record(phase, started.elapsed());
+        value
+    }
+
+    /// Mark startup as over: the total is the time until now.
+    pub fn finish(&mut self) {
+        self.total = Some(self.started.elapsed());
+    }
+
+    fn total(&self) -> Duration {
+        self.total.unwrap_or_else(|| self.started.elapsed())
+    }
+
+    pub fn render(&self) -> String {
+        render(&self.phases, self.total())
+    }
+
+    /// Append this start and its first turn's latency to the timings file.
+    pub fn save(&self, first_turn: Option<Duration>) -> io::Result<()> {
+        let record = Record {
+            started_at: self.started_at,
+            phases: self
+                .phases
+                .iter()
+                .map(|(name, duration)| (name.to_string(), duration.as_millis() as u64))
+                .collect(),
+            total_ms: self.total().as_millis() as u64,
+            first_turn_ms: first_turn.map(|d| d.as_millis() as u64),
+        };
+        let path = timings_file();
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let json = serde_json::to_string(&record).expect("serialize timings");
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        writeln!(file, "{}", json)
+    }
+}
+
+fn timings_file() -> PathBuf {
+    paths::komatachi_dir().join("timings.jsonl")
+}
+
+/// Saved starts, oldest first; unparseable lines are skipped.
+fn load(path: &Path) -> io::Result<Vec<Record>> {
+    match fs::read_to_string(path) {
+        Ok(contents) => Ok(contents
+            .lines()
+            .filter_map(|line| serde_json::from_str(line).ok())
+            .collect()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e),
+    }
+}
+
+/// `komatachi-cli timings [--last <n>]`: recent starts and their medians.
+pub fn run_command(args: &[String]) {
+    let last = match args {
+        [] => DEFAULT_LAST,
+        [flag, n] if flag == "--last" => n.parse().unwrap_or_else(|_| {
+            eprintln!("{}", USAGE);
+            std::process::exit(2);
+        }),
+        _ => {
+            eprintln!("{}", USAGE);
+            std::process::exit(2);
+        }
+    };
+    let path = timings_file();
+    let records = load(&path).unwrap_or_else(|e| {
+        eprintln!(
+            "{} cannot read {}: {}",
+            style::error("error:"),
+            path.display(),
+            e
+        );
+        std::process::exit(1);
+    });
+    if records.is_empty() {
+        eprintln!("No startups recorded yet.");
+        return;
+    }
+    let recent = &records[records.len().saturating_sub(last)..];
+    print!("{}", table(recent));
+}
+
+/// One row per start, a column per phase, then the medians.
+fn table(records: &[Record]) -> String {
+    let mut columns: Vec<&str> = Vec::new();
+    for record in records {
+        for (name, _) in &record.phases {
+            if !columns.contains(&name.as_str()) {
+                columns.push(name);
+            }
+        }
+    }
+    let values = |record: &Record| -> Vec<Option<u64>> {
+        let mut row: Vec<Option<u64>> = columns
+            .iter()
+            .map(|column| {
+                record
+                    .phases
+                    .iter()
+                    .find(|(name, _)| name == column)
+                    .map(|(_, ms)| *ms)
+            })
+            .collect();
+        row.push(Some(record.total_ms));
+        row.push(record.first_turn_ms);
+        row
+    };
+    let mut headers: Vec<&str> = columns.clone();
+    headers.extend(["total", "first turn"]);
+    let mut rows: Vec<(String, Vec<Option<u64>>)> = records
+        .iter()
+        .map(|record| (time::format_utc(record.started_at), values(record)))
+        .collect();
+    let medians = (0..headers.len())
+        .map(|i| median(rows.iter().filter_map(|(_, row)| row[i]).collect()))
+        .collect();
+    rows.push(("median".to_string(), medians));
+
+    let label_width = rows.iter().map(|(label, _)| label.len()).max().unwrap_or(0);
+    let widths: Vec<usize> = headers.iter().map(|h| h.len().max(6)).collect();
+    let mut out = format!(
+        "{:<label_width$}",
+        "started (UTC)",
+        label_width = label_width
+    );
+    for (header, width) in headers.iter().zip(&widths) {
+        out.push_str(&format!("  {:>width$}", header, width = width));
+    }
+    out.push('\n');
+    for (label, row) in &rows {
+        out.push_str(&format!(
+            "{:<label_width$}",
+            label,
+            label_width = label_width
+        ));
+        for (value, width) in row.iter().zip(&widths) {
+            let cell = value.map_or("-".to_string(), |ms| format!("{}ms", ms));
+            out.push_str(&format!("  {:>width$}", cell, width = width));
+        }
+        out.push('\n');
+    }
+    out
+}
+
+fn median(mut values: Vec<u64>) -> Option<u64> {
+    values.sort_unstable();
+    values.get(values.len().checked_sub(1)? / 2).copied()
+}
+
+fn render(phases: &[(&'static str, Duration)], total: Duration) -> String {
+    let width = phases.iter().map(|(name, _)| name.len()).max().unwrap_or(0);
+    let mut out = String::from("startup:\n");
+    for (name, duration) in phases {
+        out.push_str(&format!(
+            "  {:<width$}  {:>6.0}ms\n",
+            name,
+            duration.as_secs_f64() * 1000.0,
+            width = width
+        ));
+    }
+    out.push_str(&format!(
+        "  {:<width$}  {:>6.0}ms",
+        "total",
+        total.as_secs_f64() * 1000.0,
+        width = width
+    ));
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lists_starts_with_medians() {
+        let record = |started_at, handshake, first_turn_ms| Record {
+            started_at,
+            phases: vec![
+                ("image check".to_string(), 400),
+                ("handshake".to_string(), handshake),
+            ],
+            total_ms: 400 + handshake,
+            first_turn_ms,
+        };
+        let records = [
+            record(1_791_970_200_000, 600, Some(2100)),
+            record(1_791_970_260_000, 900, None),
+            record(1_791_970_320_000, 700, Some(1900)),
+        ];
+        assert_eq!(
+            table(&records),
+            "started (UTC)     image check  handshake   total  first turn\n\
+             2026-10-14 09:30        400ms      600ms  1000ms      2100ms\n\
+             2026-10-14 09:31        400ms      900ms  1300ms           -\n\
+             2026-10-14 09:32        400ms      700ms  1100ms      1900ms\n\
+             median                  400ms      700ms  1100ms      1900ms\n"
+        );
+    }
+
+    #[test]
+    fn aligns_phases_and_total() {
+        let phases = [
+            ("image check", Duration::from_millis(420)),
+            ("directories", Duration::from_micros(300)),
+            ("handshake", Duration::from_millis(611)),
+        ];
+        assert_eq!(
+            render(&phases, Duration::from_millis(1032)),
+            "startup:\n  image check     420ms\n  directories       0ms\n  handshake       611ms\n  total          1032ms"
+        );
+    }
+}
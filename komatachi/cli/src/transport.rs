@@ -0,0 +1,87 @@
+//! Line framing for the JSON-lines transport.
+//!
+//! `FrameReader` reads newline-terminated frames into one reusable byte
+//! buffer. Each frame is parsed straight from that buffer with
+//! `serde_json::from_slice` -- no per-line `String`, no UTF-8 copy, no
+//! `trim()` copy. The buffer grows to the largest frame seen and is kept.
+
+use std::io::{self, BufRead};
+
+pub struct FrameReader<R> {
+    inner: R,
+    buf: Vec<u8>,
+}
+
+impl<R: BufRead> FrameReader<R> {
+    pub fn new(inner: R) -> FrameReader<R> {
+        FrameReader {
+            inner,
+            buf: Vec::new(),
+        }
+    }
+
+    /// Read the next non-blank frame, without its line terminator.
+    /// `Ok(None)` means end of stream. A final frame without a trailing
+    /// newline is still returned.
+    pub fn next_frame(&mut self) -> io::Result<Option<&[u8]>> {
+        loop {
+            self.buf.clear();
+            if self.inner.read_until(b'\n', &mut self.buf)? == 0 {
+                return Ok(None);
+            }
+            let end = self
+                .buf
+                .iter()
+                .rposition(|b| !b.is_ascii_whitespace())
+                .map_or(0, |last| last + 1);
+            if end > 0 {
+                return Ok(Some(&self.buf[..end]));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn frames(input: &str) -> Vec<String> {
+        let mut reader = FrameReader::new(Cursor::new(input.as_bytes().to_vec()));
+        let mut out = Vec::new();
+        while let Some(frame) = reader.next_frame().unwrap() {
+            out.push(String::from_utf8(frame.to_vec()).unwrap());
+        }
+        out
+    }
+
+    #[test]
+    fn splits_on_newlines() {
+        assert_eq!(
+            frames("{\"a\":1}\n{\"b\":2}\n"),
+            vec!["{\"a\":1}", "{\"b\":2}"]
+        );
+    }
+
+    #[test]
+    fn strips_crlf_and_skips_blank_lines() {
+        assert_eq!(frames("{}\r\n\n  \n[]\n"), vec!["{}", "[]"]);
+    }
+
+    #[test]
+    fn returns_unterminated_final_frame() {
+        assert_eq!(
+            frames("{}\n{\"partial\":true}"),
+            vec!["{}", "{\"partial\":true}"]
+        );
+    }
+
+    #[test]
+    fn reuses_buffer_across_frames() {
+        let mut reader = FrameReader::new(Cursor::new(b"{\"long\":\"xxxxxxxx\"}\n{}\n".to_vec()));
+        reader.next_frame().unwrap();
+        let capacity = reader.buf.capacity();
+        reader.next_frame().unwrap();
+        assert_eq!(reader.buf.capacity(), capacity);
+    }
+}
@@ -0,0 +1,342 @@
+//! Receiving one turn's reply from the agent.
+//!
+//! Shared by the REPL and `run`: a turn ends with `output` or `error`,
+//! preceded by `usage` and `checkpoint`, and by `output_delta`s if the
+//! input asked to stream (see `stream.rs`), and from protocol version 3
+//! by a `heartbeat` every few seconds. Waiting happens under a stall
+//! limit, and past an optional deadline the agent is asked once to
+//! `finalize`. In the REPL, Ctrl-C asks for it early, and pressing it
+//! again (or Ctrl-D) abandons the turn.
+
+use std::time::{Duration, Instant};
+
+use crate::agent::{Agent, ReceiveError};
+use crate::lineedit::{KeyWatch, Press};
+use crate::protocol::{AgentMessage, ClientMessage, Usage};
+use crate::style;
+use crate::watchdog::Lost;
+
+/// How often the keyboard is checked while waiting.
+const KEY_POLL: Duration = Duration::from_millis(100);
+
+/// The side of the agent a turn talks to; `Agent` in the CLI, a script
+/// in tests.
+pub trait Peer {
+    fn last_heard(&self) -> Option<Instant>;
+    fn wait(&mut self, timeout: Duration) -> bool;
+    fn receive(&mut self) -> Result<AgentMessage, ReceiveError>;
+    fn send(&mut self, msg: &ClientMessage) -> Result<(), String>;
+    fn heartbeats(&self) -> bool;
+    fn exit_status(&mut self) -> Option<std::process::ExitStatus>;
+}
+
+impl Peer for Agent {
+    fn last_heard(&self) -> Option<Instant> {
+        Agent::last_heard(self)
+    }
+
+    fn wait(&mut self, timeout: Duration) -> bool {
+        Agent::wait(self, timeout)
+    }
+
+    fn receive(&mut self) -> Result<AgentMessage, ReceiveError> {
+        Agent::receive(self)
+    }
+
+    fn send(&mut self, msg: &ClientMessage) -> Result<(), String> {
+        Agent::send(self, msg)
+    }
+
+    fn heartbeats(&self) -> bool {
+        Agent::heartbeats(self)
+    }
+
+    fn exit_status(&mut self) -> Option<std::process::ExitStatus> {
+        Agent::exit_status(self)
+    }
+}
+
+/// Where Ctrl-C and Ctrl-D come from while a turn runs.
+pub trait Keys {
+    fn pressed(&self) -> Option<Press>;
+}
+
+impl Keys for KeyWatch {
+    fn pressed(&self) -> Option<Press> {
+        KeyWatch::pressed(self)
+    }
+}
+
+/// What the agent sent back for one turn.
+pub struct Reply {
+    pub result: Result<String, String>,
+    pub usage: Option<Usage>,
+    /// Checkpoint the agent recorded after the turn.
+    pub checkpoint: Option<u64>,
+    /// The turn hit its deadline; `result` is what it had so far.
+    pub partial: bool,
+    /// The turn was cut short with Ctrl-C rather than by the deadline.
+    pub cancelled: bool,
+}
+
+/// Read messages until the turn's output or error arrives, passing the
+/// text of each `output_delta` to `delta`. Silence for `stall_limit`
+/// loses the agent, once it sends heartbeats or has started streaming: an
+/// older agent is silent for the whole of a turn that does not stream.
+/// Once `finalize_at` passes, or on the first Ctrl-C
+/// seen by `keys`, `finalize` is sent and the deadline cleared.
+pub fn receive_reply(
+    agent: &mut dyn Peer,
+    stall_limit: Duration,
+    finalize_at: &mut Option<Instant>,
+    keys: Option<&dyn Keys>,
+    delta: &mut dyn FnMut(&str),
+) -> Result<Reply, Lost> {
+    let mut usage = None;
+    let mut checkpoint = None;
+    let mut partial = false;
+    let mut cancelled = false;
+    let mut streamed = false;
+    let mut silent_since = Instant::now();
+    loop {
+        // Silence is timed per input where the agent echoes ids, so
+        // replies to other inputs do not keep a stalled turn alive.
+        let heard = agent.last_heard().unwrap_or(silent_since);
+        let mut limit = stall_limit.saturating_sub(heard.elapsed());
+        if let Some(at) = *finalize_at {
+            limit = limit.min(at.saturating_duration_since(Instant::now()));
+        }
+        if keys.is_some() {
+            limit = limit.min(KEY_POLL);
+        }
+        if !agent.wait(limit) {
+            let finalize = match keys.and_then(Keys::pressed) {
+                Some(Press::Interrupt) if !cancelled => {
+                    // Erase the spinner, or end the line being streamed.
+                    let erase = if streamed { "\n" } else { "\r\x1b[K" };
+                    eprintln!("{}Cancelling the turn (Ctrl-C again to quit)...", erase);
+                    cancelled = true;
+                    true
+                }
+                Some(_) => return Err(Lost::Abandoned),
+                None if (agent.heartbeats() || streamed) && heard.elapsed() >= stall_limit => {
+                    return Err(Lost::Stalled(stall_limit));
+                }
+                None => finalize_at.is_some_and(|at| Instant::now() >= at),
+            };
+            // Ask for the partial answer, then keep waiting under the
+            // stall limit alone.
+            if finalize {
+                *finalize_at = None;
+                if let Err(e) = agent.send(&ClientMessage::Finalize) {
+                    return Err(Lost::Exited(format!("stopped accepting input ({})", e)));
+                }
+            }
+            continue;
+        }
+        silent_since = Instant::now();
+        let result = match agent.receive() {
+            Ok(msg) => match msg.r#type.as_str() {
+                "usage" => {
+                    usage = msg.usage();
+                    continue;
+                }
+                "checkpoint" => {
+                    checkpoint = msg.id;
+                    continue;
+                }
+                "heartbeat" => continue,
+                "output_delta" => {
+                    streamed = true;
+                    delta(msg.text.as_deref().unwrap_or(""));
+                    continue;
+                }
+                "output" => {
+                    partial = msg.partial;
+                    Ok(msg.text.unwrap_or_default())
+                }
+                "error" => Err(msg.message.unwrap_or_else(|| "unknown error".to_string())),
+                other => {
+                    eprintln!(
+                        "{} unexpected message type: {}",
+                        style::warning("warning:"),
+                        other
+                    );
+                    continue;
+                }
+            },
+            Err(ReceiveError::Invalid(e)) => Err(format!("invalid response from agent: {}", e)),
+            Err(ReceiveError::Closed) => {
+                return Err(Lost::Exited(match agent.exit_status() {
+                    Some(status) => format!("exited unexpectedly ({})", status),
+                    None => "closed its output unexpectedly".to_string(),
+                }))
+            }
+            Err(ReceiveError::Io(e)) => {
+                return Err(Lost::Exited(format!("could not be read from ({})", e)))
+            }
+        };
+        return Ok(Reply {
+            result,
+            usage,
+            checkpoint,
+            partial,
+            cancelled,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agent::Incoming;
+    use std::cell::RefCell;
+    use std::collections::VecDeque;
+    use std::thread;
+
+    const STALL: Duration = Duration::from_millis(300);
+
+    /// An agent that speaks version 3 and writes each message at its
+    /// offset from the turn's start, filtered as `Agent` filters them.
+    struct Script {
+        incoming: Incoming,
+        messages: VecDeque<(Instant, AgentMessage)>,
+        pending: Option<AgentMessage>,
+        finalizes: usize,
+    }
+
+    impl Script {
+        /// The agent's replies, from the start of a turn for input 1.
+        fn new(messages: &[(u64, &str)]) -> Script {
+            let start = Instant::now();
+            let mut incoming = Incoming::default();
+            let ready = r#"{"type":"ready","protocol":3}"#;
+            assert!(incoming.accept(&serde_json::from_str(ready).unwrap(), start));
+            assert_eq!(incoming.input_id(start), Some(1));
+            let messages = messages
+                .iter()
+                .map(|&(ms, json)| {
+                    let at = start + Duration::from_millis(ms);
+                    (at, serde_json::from_str(json).unwrap())
+                })
+                .collect();
+            Script {
+                incoming,
+                messages,
+                pending: None,
+                finalizes: 0,
+            }
+        }
+    }
+
+    impl Peer for Script {
+        fn last_heard(&self) -> Option<Instant> {
+            self.incoming.last_heard()
+        }
+
+        fn wait(&mut self, timeout: Duration) -> bool {
+            let until = Instant::now() + timeout;
+            while self.pending.is_none() {
+                match self.messages.front() {
+                    Some((at, _)) if *at <= until => {
+                        thread::sleep(at.saturating_duration_since(Instant::now()));
+                        let (at, msg) = self.messages.pop_front().unwrap();
+                        if self.incoming.accept(&msg, at) {
+                            self.pending = Some(msg);
+                        }
+                    }
+                    _ => {
+                        thread::sleep(until.saturating_duration_since(Instant::now()));
+                        return false;
+                    }
+                }
+            }
+            true
+        }
+
+        fn receive(&mut self) -> Result<AgentMessage, ReceiveError> {
+            self.pending.take().ok_or(ReceiveError::Closed)
+        }
+
+        fn send(&mut self, msg: &ClientMessage) -> Result<(), String> {
+            if matches!(msg, ClientMessage::Finalize) {
+                self.finalizes += 1;
+            }
+            Ok(())
+        }
+
+        fn heartbeats(&self) -> bool {
+            self.incoming.heartbeats()
+        }
+
+        fn exit_status(&mut self) -> Option<std::process::ExitStatus> {
+            None
+        }
+    }
+
+    /// Keys pressed one per check, then none.
+    struct Pressed(RefCell<VecDeque<Press>>);
+
+    impl Keys for Pressed {
+        fn pressed(&self) -> Option<Press> {
+            self.0.borrow_mut().pop_front()
+        }
+    }
+
+    fn receive(
+        script: &mut Script,
+        finalize_at: &mut Option<Instant>,
+        keys: Option<&dyn Keys>,
+    ) -> Result<Reply, Lost> {
+        receive_reply(script, STALL, finalize_at, keys, &mut |_| {})
+    }
+
+    #[test]
+    fn a_deadline_sends_one_finalize() {
+        let heartbeat = r#"{"type":"heartbeat","reply_to":1}"#;
+        let mut script = Script::new(&[
+            (100, heartbeat),
+            (200, heartbeat),
+            (300, heartbeat),
+            (
+                400,
+                r#"{"type":"output","reply_to":1,"text":"so far","partial":true}"#,
+            ),
+        ]);
+        let mut finalize_at = Some(Instant::now() + Duration::from_millis(50));
+        let reply = receive(&mut script, &mut finalize_at, None).ok().unwrap();
+        assert_eq!(script.finalizes, 1);
+        assert_eq!(finalize_at, None);
+        assert_eq!(reply.result, Ok("so far".to_string()));
+        assert!(reply.partial && !reply.cancelled);
+    }
+
+    #[test]
+    fn a_second_ctrl_c_abandons_the_turn() {
+        let mut script = Script::new(&[(2000, r#"{"type":"output","reply_to":1}"#)]);
+        let keys = Pressed(RefCell::new([Press::Interrupt, Press::Interrupt].into()));
+        let mut finalize_at = None;
+        let lost = receive(&mut script, &mut finalize_at, Some(&keys));
+        assert!(matches!(lost, Err(Lost::Abandoned)));
+        assert_eq!(script.finalizes, 1);
+    }
+
+    #[test]
+    fn a_late_reply_does_not_hold_off_the_stall_limit() {
+        // Input 1 was answered before this turn's input 2 was sent.
+        let late = r#"{"type":"heartbeat","reply_to":1}"#;
+        let mut script = Script::new(&[
+            (100, late),
+            (200, late),
+            (280, late),
+            (1000, r#"{"type":"output","reply_to":2}"#),
+        ]);
+        let answered = serde_json::from_str(r#"{"type":"output","reply_to":1}"#).unwrap();
+        let started = Instant::now();
+        assert!(script.incoming.accept(&answered, started));
+        assert_eq!(script.incoming.input_id(started), Some(2));
+        let lost = receive(&mut script, &mut None, None);
+        assert!(matches!(lost, Err(Lost::Stalled(limit)) if limit == STALL));
+        assert!(started.elapsed() < Duration::from_millis(450));
+    }
+}
@@ -0,0 +1,152 @@
+//! Response validation.
+//!
+//! The `validation` section of the config file lists rules every response
+//! must satisfy. When a response breaks one, the CLI tells the agent what
+//! was wrong and asks again, up to `retries` times, before showing the
+//! last response with the remaining violations. The follow-up prompts are
+//! ordinary turns: the agent sees them and they stay in its conversation.
+
+use serde::Deserialize;
+
+use crate::regex::Regex;
+
+/// Retries when the config does not say.
+const DEFAULT_RETRIES: u32 = 2;
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "camelCase")]
+pub struct Rules {
+    /// Patterns the response must match.
+    pub must_match: Vec<String>,
+    /// Patterns the response must not match.
+    pub must_not_match: Vec<String>,
+    /// Maximum response length in characters.
+    pub max_length: Option<usize>,
+    /// The response must be a JSON document.
+    pub json: bool,
+    /// Follow-up attempts before giving up (default 2).
+    pub retries: Option<u32>,
+}
+
+impl Rules {
+    pub fn retries(&self) -> u32 {
+        self.retries.unwrap_or(DEFAULT_RETRIES)
+    }
+}
+
+/// Compiled rules.
+pub struct Validator {
+    must_match: Vec<Regex>,
+    must_not_match: Vec<Regex>,
+    max_length: Option<usize>,
+    json: bool,
+    pub retries: u32,
+}
+
+impl Validator {
+    /// Compile the configured rules. `None` if there are none.
+    pub fn new(rules: &Rules) -> Result<Option<Validator>, String> {
+        if rules.must_match.is_empty()
+            && rules.must_not_match.is_empty()
+            && rules.max_length.is_none()
+            && !rules.json
+        {
+            return Ok(None);
+        }
+        let compile = |patterns: &[String]| {
+            patterns
+                .iter()
+                .map(|p| Regex::new(p).map_err(|e| format!("validation pattern {:?}: {}", p, e)))
+                .collect::<Result<Vec<_>, _>>()
+        };
+        Ok(Some(Validator {
+            must_match: compile(&rules.must_match)?,
+            must_not_match: compile(&rules.must_not_match)?,
+            max_length: rules.max_length,
+            json: rules.json,
+            retries: rules.retries(),
+        }))
+    }
+
+    /// Describe each rule `text` breaks. Empty if it passes.
+    pub fn check(&self, text: &str) -> Vec<String> {
+        let mut violations = Vec::new();
+        for regex in &self.must_match {
+            if !regex.is_match(text) {
+                violations.push(format!("must match /{}/", regex));
+            }
+        }
+        for regex in &self.must_not_match {
+            if regex.is_match(text) {
+                violations.push(format!("must not match /{}/", regex));
+            }
+        }
+        if let Some(max) = self.max_length {
+            let length = text.chars().count();
+            if length > max {
+                violations.push(format!(
+                    "must be at most {} characters (was {})",
+                    max, length
+                ));
+            }
+        }
+        if self.json {
+            if let Err(e) = serde_json::from_str::<serde_json::Value>(text) {
+                violations.push(format!("must be valid JSON ({})", e));
+            }
+        }
+        violations
+    }
+}
+
+/// The follow-up prompt asking the agent to correct its response.
+pub fn fix_prompt(violations: &[String]) -> String {
+    let mut prompt = String::from("Your last response broke these requirements:\n");
+    for violation in violations {
+        prompt.push_str(&format!("- it {}\n", violation));
+    }
+    prompt.push_str("Please reply again with a corrected response, and nothing else.");
+    prompt
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn validator(json: &str) -> Validator {
+        let rules: Rules = serde_json::from_str(json).unwrap();
+        Validator::new(&rules).unwrap().unwrap()
+    }
+
+    #[test]
+    fn no_rules_means_no_validator() {
+        assert!(Validator::new(&Rules::default()).unwrap().is_none());
+    }
+
+    #[test]
+    fn reports_each_broken_rule() {
+        let v = validator(r#"{"mustMatch":["^OK"],"mustNotMatch":["(?i)sorry"],"maxLength":10}"#);
+        assert!(v.check("OK done").is_empty());
+        assert_eq!(
+            v.check("Sorry, I can't"),
+            [
+                "must match /^OK/",
+                "must not match /(?i)sorry/",
+                "must be at most 10 characters (was 14)"
+            ]
+        );
+    }
+
+    #[test]
+    fn json_rule_parses_the_whole_response() {
+        let v = validator(r#"{"json":true}"#);
+        assert!(v.check(r#"{"a": 1}"#).is_empty());
+        assert_eq!(v.check("Here you go: {}").len(), 1);
+    }
+
+    #[test]
+    fn bad_pattern_is_a_config_error() {
+        let rules: Rules = serde_json::from_str(r#"{"mustMatch":["(oops"]}"#).unwrap();
+        assert!(Validator::new(&rules).is_err());
+    }
+}
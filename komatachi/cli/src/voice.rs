@@ -0,0 +1,39 @@
+//! Speech-to-text input.
+//!
+//! Recording and transcription are delegated to a user-supplied command
+//! (`KOMATACHI_VOICE_COMMAND`), run through `sh -c`. The command owns the
+//! microphone and the model -- a whisper.cpp script, a cloud API call,
+//! anything -- and prints the transcription on stdout. The CLI only shows
+//! the text for confirmation and sends it.
+
+use std::process::{Command, Stdio};
+
+pub const VOICE_COMMAND_VAR: &str = "KOMATACHI_VOICE_COMMAND";
+
+/// The configured voice command, if any.
+pub fn command() -> Option<String> {
+    std::env::var(VOICE_COMMAND_VAR)
+        .ok()
+        .filter(|command| !command.trim().is_empty())
+}
+
+/// Run the voice command and return its transcription.
+///
+/// The command inherits the terminal's stdin and stderr so it can show
+/// recording status and stop on a keypress.
+pub fn transcribe(command: &str) -> Result<String, String> {
+    let output = Command::new("sh")
+        .args(["-c", command])
+        .stdin(Stdio::inherit())
+        .stderr(Stdio::inherit())
+        .output()
+        .map_err(|e| format!("cannot run voice command: {}", e))?;
+    if !output.status.success() {
+        return Err(format!("voice command exited with {}", output.status));
+    }
+    let text = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    if text.is_empty() {
+        return Err("voice command produced no transcription".to_string());
+    }
+    Ok(text)
+}
@@ -0,0 +1,72 @@
+//! Agent watchdog.
+//!
+//! While a turn is in flight the REPL waits for the agent with a time
+//! limit. If the agent stays silent past `stallSecs`, or its container
+//! exits, the watchdog reports it and (unless `restart` is off) kills the
+//! container, starts a fresh agent on the same data directory, rolls the
+//! interrupted turn back to the checkpoint before it, and offers to resend
+//! the prompt as it was. Silence is timed on every turn of an agent that
+//! sends heartbeats (protocol version 3), and otherwise only once a turn
+//! has started streaming, since an older agent is silent while it waits
+//! on the model.
+
+use serde::Deserialize;
+use std::time::Duration;
+
+/// Silence, mid-turn, after which the agent counts as stalled. Generous:
+/// a turn can chain many model calls and a compaction.
+const DEFAULT_STALL_SECS: u64 = 600;
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "camelCase")]
+pub struct Settings {
+    /// Seconds without a message before the agent is considered stuck.
+    pub stall_secs: Option<u64>,
+    /// Restart the agent after a stall or crash (default true).
+    pub restart: Option<bool>,
+}
+
+impl Settings {
+    pub fn stall_limit(&self) -> Duration {
+        Duration::from_secs(self.stall_secs.unwrap_or(DEFAULT_STALL_SECS))
+    }
+
+    pub fn restart(&self) -> bool {
+        self.restart.unwrap_or(true)
+    }
+}
+
+/// Why the agent stopped answering mid-turn.
+pub enum Lost {
+    /// The container exited or its stdout failed; the text says how.
+    Exited(String),
+    /// No message within the stall limit.
+    Stalled(Duration),
+    /// The user pressed Ctrl-C again, or Ctrl-D, to quit mid-turn.
+    Abandoned,
+}
+
+impl Lost {
+    pub fn describe(&self) -> String {
+        match self {
+            Lost::Exited(how) => format!("the agent {}", how),
+            Lost::Stalled(limit) => format!(
+                "the agent sent nothing for {}s and was stopped",
+                limit.as_secs()
+            ),
+            Lost::Abandoned => "the turn was abandoned".to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn defaults_restart_after_ten_minutes() {
+        let settings = Settings::default();
+        assert_eq!(settings.stall_limit(), Duration::from_secs(600));
+        assert!(settings.restart());
+    }
+}
@@ -0,0 +1,352 @@
+//! Wrapping answers at word boundaries to the terminal's width.
+//!
+//! A terminal wraps a long line wherever it runs out of columns, often
+//! mid-word. Answers printed to a terminal are wrapped here instead, at
+//! the width it has when the answer arrives, so an answer after a resize
+//! fits the new width. Text already on screen stays as it was printed.
+//! Only prose is wrapped: code blocks, indented lines, and tables are
+//! printed as they are, since their line breaks mean something. A list
+//! item or quote keeps its marker, with continuation lines indented
+//! under its text. `wrap.enabled: false` prints answers verbatim.
+
+use serde::Deserialize;
+use std::io::{self, IsTerminal};
+
+use crate::lineedit;
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "camelCase")]
+pub struct Settings {
+    /// Wrap answers printed to a terminal (default true).
+    pub enabled: Option<bool>,
+}
+
+impl Settings {
+    pub fn enabled(&self) -> bool {
+        self.enabled.unwrap_or(true)
+    }
+}
+
+/// `text` wrapped to the terminal's width, if stdout is a terminal and
+/// wrapping is on; otherwise as it is.
+pub fn for_terminal(text: &str, settings: &Settings) -> String {
+    if settings.enabled() && io::stdout().is_terminal() {
+        wrap(text, lineedit::terminal_size().1)
+    } else {
+        text.to_string()
+    }
+}
+
+/// Wrap the prose lines of `text` to `columns`.
+pub fn wrap(text: &str, columns: usize) -> String {
+    let mut out = Vec::new();
+    let mut fence: Option<&str> = None;
+    for line in text.split('\n') {
+        let trimmed = line.trim_start();
+        let marker = ["```", "~~~"].into_iter().find(|m| trimmed.starts_with(m));
+        match (fence, marker) {
+            (Some(open), Some(close)) if open == close => fence = None,
+            (None, Some(open)) => fence = Some(open),
+            _ => {}
+        }
+        if fence.is_some() || marker.is_some() || verbatim(line) {
+            out.push(line.to_string());
+        } else {
+            out.extend(wrap_line(line, columns.max(20)));
+        }
+    }
+    out.join("\n")
+}
+
+/// Wraps an answer that arrives in pieces (see `stream.rs`), printing
+/// what it can as soon as it can: the output of every `push`, then
+/// `finish`, is `wrap` of the whole text. A line's start is held until
+/// it shows whether the line is prose, code, or a table, and a word
+/// until it is complete, since either can change where a break goes.
+pub struct Wrapper {
+    columns: usize,
+    fence: Option<&'static str>,
+    /// Received since the last newline.
+    line: String,
+    /// Whether the current line is wrapped, once enough of it is here.
+    prose: Option<bool>,
+    /// Bytes of `line` already printed.
+    done: usize,
+    /// Bytes of `line` taken by its list or quote marker.
+    prefix: usize,
+    /// Width of the output line so far, and of continuation indents.
+    width: usize,
+    indent: usize,
+    /// No word on the output line yet, and no break in this line yet.
+    empty: bool,
+    broken: bool,
+}
+
+impl Wrapper {
+    pub fn new(columns: usize) -> Wrapper {
+        Wrapper {
+            columns: columns.max(20),
+            fence: None,
+            line: String::new(),
+            prose: None,
+            done: 0,
+            prefix: 0,
+            width: 0,
+            indent: 0,
+            empty: true,
+            broken: false,
+        }
+    }
+
+    /// What can be printed of the answer now that `text` has arrived.
+    pub fn push(&mut self, text: &str) -> String {
+        let mut out = String::new();
+        for piece in text.split_inclusive('\n') {
+            let (body, ended) = match piece.strip_suffix('\n') {
+                Some(body) => (body, true),
+                None => (piece, false),
+            };
+            self.line.push_str(body);
+            self.advance(ended, &mut out);
+            if ended {
+                out.push('\n');
+                self.next_line();
+            }
+        }
+        out
+    }
+
+    /// The rest of the answer, once all of it has arrived.
+    pub fn finish(&mut self) -> String {
+        let mut out = String::new();
+        if !self.line.is_empty() {
+            self.advance(true, &mut out);
+        }
+        self.next_line();
+        out
+    }
+
+    fn advance(&mut self, ended: bool, out: &mut String) {
+        if self.prose.is_none() {
+            let trimmed = self.line.trim_start();
+            // Three characters and a space settle the fence and list
+            // markers that `wrap` looks for.
+            let settled = trimmed.len() >= 3 && trimmed.contains(char::is_whitespace);
+            if !ended && !settled {
+                return;
+            }
+            let marker = ["```", "~~~"].into_iter().find(|m| trimmed.starts_with(m));
+            match (self.fence, marker) {
+                (Some(open), Some(close)) if open == close => self.fence = None,
+                (None, Some(open)) => self.fence = Some(open),
+                _ => {}
+            }
+            let prose = self.fence.is_none() && marker.is_none() && !verbatim(&self.line);
+            self.prose = Some(prose);
+            if prose {
+                let prefix = prefix(&self.line);
+                out.push_str(prefix);
+                self.prefix = prefix.len();
+                self.done = prefix.len();
+                self.width = width(prefix);
+                self.indent = self.width;
+            }
+        }
+        if self.prose == Some(false) {
+            out.push_str(&self.line[self.done..]);
+            self.done = self.line.len();
+            return;
+        }
+        loop {
+            let rest = &self.line[self.done..];
+            let Some(start) = rest.find(|c: char| !c.is_whitespace()) else {
+                break;
+            };
+            let end = match rest[start..].find(char::is_whitespace) {
+                Some(length) => start + length,
+                None if ended => rest.len(),
+                None => break,
+            };
+            let word = rest[start..end].to_string();
+            self.place(&word, out);
+            self.done += end;
+        }
+        // A line of no words is printed as it is, as `wrap_line` does.
+        if ended && self.empty && !self.broken {
+            out.push_str(&self.line[self.done.max(self.prefix)..]);
+        }
+    }
+
+    /// `wrap_line`'s placement of one word, printed as it goes.
+    fn place(&mut self, word: &str, out: &mut String) {
+        if !self.empty && self.width + 1 + width(word) > self.columns {
+            self.break_line(out);
+            self.empty = true;
+        }
+        if !self.empty {
+            out.push(' ');
+            self.width += 1;
+        }
+        for c in word.chars() {
+            let c_width = lineedit::width(c);
+            if self.width + c_width > self.columns && self.width > self.indent {
+                self.break_line(out);
+            }
+            out.push(c);
+            self.width += c_width;
+        }
+        self.empty = false;
+    }
+
+    fn break_line(&mut self, out: &mut String) {
+        out.push('\n');
+        out.push_str(&" ".repeat(self.indent));
+        self.width = self.indent;
+        self.broken = true;
+    }
+
+    fn next_line(&mut self) {
+        self.line.clear();
+        self.prose = None;
+        self.done = 0;
+        self.prefix = 0;
+        self.empty = true;
+        self.broken = false;
+    }
+}
+
+/// Lines whose breaks mean something: indented code and tables.
+fn verbatim(line: &str) -> bool {
+    line.starts_with("    ") || line.starts_with('\t') || line.trim_start().starts_with('|')
+}
+
+/// One prose line as lines of at most `columns`, continuations indented
+/// under the text after any list or quote marker.
+fn wrap_line(line: &str, columns: usize) -> Vec<String> {
+    let prefix_len = prefix(line).len();
+    let (prefix, body) = line.split_at(prefix_len);
+    let indent = " ".repeat(width(prefix));
+    let mut lines = Vec::new();
+    let mut current = prefix.to_string();
+    let mut current_width = width(prefix);
+    let mut empty = true;
+    for word in body.split_whitespace() {
+        let word_width = width(word);
+        if !empty && current_width + 1 + word_width > columns {
+            lines.push(std::mem::replace(&mut current, indent.clone()));
+            current_width = indent.len();
+            empty = true;
+        }
+        if !empty {
+            current.push(' ');
+            current_width += 1;
+        }
+        // A word wider than a line (a URL, or text without spaces) is
+        // broken where the line ends.
+        for c in word.chars() {
+            let c_width = lineedit::width(c);
+            if current_width + c_width > columns && current_width > indent.len() {
+                lines.push(std::mem::replace(&mut current, indent.clone()));
+                current_width = indent.len();
+            }
+            current.push(c);
+            current_width += c_width;
+        }
+        empty = false;
+    }
+    lines.push(if empty && lines.is_empty() {
+        line.to_string()
+    } else {
+        current
+    });
+    lines
+}
+
+/// Leading whitespace and a list (`-`, `*`, `+`, `1.`) or quote (`>`)
+/// marker with its space.
+fn prefix(line: &str) -> &str {
+    let indent = line.len() - line.trim_start().len();
+    let rest = &line[indent..];
+    let digits = rest.chars().take_while(char::is_ascii_digit).count();
+    let marker = if ["- ", "* ", "+ ", "> "].iter().any(|m| rest.starts_with(m)) {
+        2
+    } else if digits > 0 && rest[digits..].starts_with(". ") {
+        digits + 2
+    } else {
+        0
+    };
+    &line[..indent + marker]
+}
+
+fn width(text: &str) -> usize {
+    text.chars().map(lineedit::width).sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wraps_prose_at_word_boundaries() {
+        let text = "The quick brown fox jumps over the lazy dog and keeps running.";
+        assert_eq!(
+            wrap(text, 24),
+            "The quick brown fox\njumps over the lazy dog\nand keeps running."
+        );
+        assert_eq!(
+            wrap(
+                "- one two three four five six\n12. seven eight nine ten",
+                20
+            ),
+            "- one two three four\n  five six\n12. seven eight nine\n    ten"
+        );
+        assert_eq!(wrap("short\n\nlines", 20), "short\n\nlines");
+        assert_eq!(
+            wrap(&format!("see {}", "x".repeat(30)), 20),
+            format!("see\n{}\n{}", "x".repeat(20), "x".repeat(10))
+        );
+        // Wide characters take two columns each.
+        assert_eq!(
+            wrap(&"日".repeat(15), 20),
+            format!("{}\n{}", "日".repeat(10), "日".repeat(5))
+        );
+    }
+
+    #[test]
+    fn wrapping_in_pieces_matches_wrapping_the_whole() {
+        let long = "word ".repeat(10);
+        let texts = [
+            "The quick brown fox jumps over the lazy dog and keeps running.".to_string(),
+            "- one two three four five six\n12. seven eight nine ten\n".to_string(),
+            format!("Intro  line\n\n```\n{long}\n```\n    {long}\n| {long} |\n> quoted {long}"),
+            format!(
+                "see {} then {}\n-  \n   \nend",
+                "x".repeat(30),
+                "日".repeat(15)
+            ),
+        ];
+        for text in &texts {
+            for size in [1, 2, 5, 64] {
+                let mut wrapper = Wrapper::new(24);
+                let chars: Vec<char> = text.chars().collect();
+                let mut out = String::new();
+                for piece in chars.chunks(size) {
+                    out.push_str(&wrapper.push(&piece.iter().collect::<String>()));
+                }
+                out.push_str(&wrapper.finish());
+                assert_eq!(out, wrap(text, 24), "{:?} in pieces of {}", text, size);
+            }
+        }
+        let mut wrapper = Wrapper::new(24);
+        assert_eq!(wrapper.push("Hello wor"), "Hello");
+        assert_eq!(wrapper.push("ld"), "");
+        assert_eq!(wrapper.finish(), " world");
+    }
+
+    #[test]
+    fn leaves_code_and_tables_alone() {
+        let long = "word ".repeat(10);
+        let text = format!("```\n{long}\n```\n    {long}\n| {long} |\n~~~\n```\n{long}\n~~~");
+        assert_eq!(wrap(&text, 20), text);
+    }
+}
@@ -465,6 +465,8 @@ describe("Agent Loop", () => {
       expect(response).toBe("Response after compaction");
       // Model was called for compaction + actual response
       expect(callModel.mock.calls.length).toBeGreaterThanOrEqual(2);
+      expect(callModel.mock.calls[0][0].purpose).toBe("compaction");
+      expect(callModel.mock.calls.at(-1)![0].purpose).toBe("turn");
 
       // Verify compaction metadata was updated
       const metadata = conversationStore.getMetadata();
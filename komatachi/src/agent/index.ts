@@ -58,6 +58,12 @@ export interface CallModelParams {
   readonly messages: readonly Message[];
   readonly tools?: readonly ApiToolDefinition[];
   readonly max_tokens: number;
+  /**
+   * Why the agent is calling: answering the turn, or summarizing for
+   * compaction. Lets the caller route or account for calls; not part of
+   * the API request.
+   */
+  readonly purpose: "turn" | "compaction";
 }
 
 /**
@@ -252,6 +258,7 @@ export function createAgent(config: AgentConfig): Agent {
           messages: [...selected],
           tools: apiTools.length > 0 ? apiTools : undefined,
           max_tokens: maxTokens,
+          purpose: "turn",
         });
       } catch (error) {
         throw new ModelCallError(
@@ -450,6 +457,7 @@ export function createAgent(config: AgentConfig): Agent {
         system: systemParts.join("\n\n"),
         messages: [{ role: "user", content: promptParts.join("\n") }],
         max_tokens: maxTokens,
+        purpose: "compaction",
       });
       return extractTextResponse(response.content);
     };
@@ -6,14 +6,66 @@
  * and runs the agent loop.
  *
  * Protocol:
- *   CLI -> Agent:  {"type":"input","text":"..."}
- *   Agent -> CLI:  {"type":"ready"}
- *   Agent -> CLI:  {"type":"output","text":"..."}
+ *   CLI -> Agent:  {"type":"input","text":"...","stream"?:true,"id"?:N}
+ *   CLI -> Agent:  {"type":"inspect"}
+ *   CLI -> Agent:  {"type":"checkpoint"}
+ *   CLI -> Agent:  {"type":"restore_checkpoint","id":N}
+ *   CLI -> Agent:  {"type":"finalize"}
+ *   Agent -> CLI:  {"type":"ready","protocol":N}
+ *   Agent -> CLI:  {"type":"usage","model":"...","input_tokens":N,"output_tokens":N,"route"?:"..."}
+ *   Agent -> CLI:  {"type":"heartbeat"}
+ *   Agent -> CLI:  {"type":"output_delta","text":"..."}
+ *   Agent -> CLI:  {"type":"output","text":"...","partial"?:true}
  *   Agent -> CLI:  {"type":"error","message":"..."}
+ *   Agent -> CLI:  {"type":"inspect","request":{...}|null}
+ *   Agent -> CLI:  {"type":"checkpoint","id":N}
+ *   Agent -> CLI:  {"type":"checkpoint_restored","id":N}
+ *
+ * Every agent -> CLI message carries "seq": 1, 2, 3, ... in send order,
+ * so the CLI can detect duplicated or dropped frames.
+ *
+ * Versions: the CLI passes the protocol versions it speaks in
+ * KOMATACHI_PROTOCOL ("1", or a list such as "1,2"). The agent speaks the
+ * newest one it shares and names it in "ready"; sharing none, it sends an
+ * error saying which side to upgrade, and exits. Without the variable it
+ * speaks its newest. A "ready" without "protocol" means version 1, the
+ * protocol as it was before versions were numbered.
+ *
+ * Input ids (version 2): the CLI numbers each input with "id", and every
+ * message of that turn ("output_delta", "usage", "checkpoint", "output",
+ * or "error") carries it back as "reply_to", so a reply that arrives late
+ * or for the wrong input is recognized. An input without "id" gets
+ * replies without "reply_to", as in version 1.
+ *
+ * Heartbeats (version 3): while a turn runs, the agent sends "heartbeat"
+ * every HEARTBEAT_MS, with the turn's "reply_to". The CLI times silence
+ * against them, so a turn waiting minutes on one model call is not taken
+ * for a stopped agent.
+ *
+ * A successful turn sends one "usage" message (token counts summed over
+ * every model call in the turn), then "checkpoint", then "output".
+ *
+ * Streaming: with "stream": true on an input, each piece of the turn's
+ * model calls' text is sent as it arrives as an "output_delta", before
+ * "usage". The closing "output" still carries the
+ * whole answer and ends the turn; the deltas are for display. Compaction
+ * calls are never streamed. Without "stream", no deltas are sent.
+ * "inspect" returns the most recent model request exactly as sent (system
+ * prompt, selected messages, tools), or null before the first call.
+ *
+ * Checkpoints: after every successful turn (and on "checkpoint") the
+ * conversation state is recorded in checkpoints/ on the data volume.
+ * "restore_checkpoint" rolls the conversation back to that point and
+ * forgets every later checkpoint.
+ *
+ * "finalize" may arrive while a turn is running: the turn's model calls
+ * are aborted and the text written so far, up to the last piece streamed
+ * in, is saved as the assistant's message and sent as output with
+ * "partial": true (or an error, if there is none). Outside a turn it is
+ * ignored. Every other message is handled in order, after the turn.
  */
 
 import Anthropic from "@anthropic-ai/sdk";
-import { createReadStream } from "node:fs";
 import { createInterface } from "node:readline";
 
 import { createStorage } from "./storage/index.js";
@@ -24,31 +76,22 @@ import {
   type CallModelResult,
   type CallModel,
 } from "./agent/index.js";
-
-// -----------------------------------------------------------------------------
-// Protocol types
-// -----------------------------------------------------------------------------
-
-interface InputMessage {
-  readonly type: "input";
-  readonly text: string;
-}
-
-interface ReadyMessage {
-  readonly type: "ready";
-}
-
-interface OutputMessage {
-  readonly type: "output";
-  readonly text: string;
-}
-
-interface ErrorMessage {
-  readonly type: "error";
-  readonly message: string;
-}
-
-type OutboundMessage = ReadyMessage | OutputMessage | ErrorMessage;
+import {
+  createCheckpoints,
+  createInbox,
+  frame,
+  negotiateProtocol,
+  readCheckpoints,
+  rebaseTurnStart,
+  routeTurn,
+  salvagePartial,
+  HEARTBEATS,
+  HEARTBEAT_MS,
+  type OutboundMessage,
+  type QueuedMessage,
+  type Route,
+  type TurnStart,
+} from "./worker/index.js";
 
 // -----------------------------------------------------------------------------
 // Config
@@ -61,6 +104,11 @@ interface Config {
   readonly model: string;
   readonly maxTokens: number;
   readonly contextWindow: number;
+  /** "auto" routes simple prompts to smallModel; "fixed" always uses model */
+  readonly routing: "fixed" | "auto";
+  readonly smallModel: string;
+  /** The protocol version agreed with the CLI */
+  readonly protocol: number;
 }
 
 function readConfig(): Config {
@@ -79,6 +127,9 @@ function readConfig(): Config {
       process.env["KOMATACHI_CONTEXT_WINDOW"] ?? "200000",
       10
     ),
+    routing: process.env["KOMATACHI_ROUTING"] === "auto" ? "auto" : "fixed",
+    smallModel: process.env["KOMATACHI_SMALL_MODEL"] ?? "claude-3-5-haiku-20241022",
+    protocol: negotiateProtocol(process.env["KOMATACHI_PROTOCOL"]),
   };
 }
 
@@ -86,8 +137,15 @@ function readConfig(): Config {
 // I/O helpers
 // -----------------------------------------------------------------------------
 
+/** Sequence number of the last message written to stdout. */
+let lastSeq = 0;
+
+/** The id of the input whose turn is running, echoed on its messages. */
+let replyTo: number | null = null;
+
 function send(message: OutboundMessage): void {
-  process.stdout.write(JSON.stringify(message) + "\n");
+  lastSeq += 1;
+  process.stdout.write(frame(message, lastSeq, replyTo));
 }
 
 // -----------------------------------------------------------------------------
@@ -116,15 +174,65 @@ async function main(): Promise<void> {
       throw error;
     }
   }
+  const checkpoints = createCheckpoints(storage, conversationStore);
+  if (conversationStore.getMessages().length === 0 && readCheckpoints(storage).length === 0) {
+    // Checkpoint 0: the empty conversation, so the first exchange can
+    // be undone.
+    checkpoints.write("");
+  }
 
   // Create callModel using Anthropic SDK
   const client = new Anthropic({ apiKey: config.apiKey });
 
+  // Token usage for the turn in progress. Reset before each turn; every
+  // model call within the turn (tool dispatch, compaction) adds to it.
+  let turnUsage = { inputTokens: 0, outputTokens: 0 };
+
+  // The most recent request sent to the model, for "inspect".
+  let lastRequest: CallModelParams | null = null;
+
+  // The turn in progress, so "finalize" can abort its model calls.
+  let turnAbort: AbortController | null = null;
+
+  // Model for the turn in progress. Compaction always uses config.model:
+  // the summary becomes the entity's memory.
+  let turnRoute: Route | null = null;
+
+  // Whether the turn in progress asked for "output_delta" messages.
+  let turnStream = false;
+
+  // Text of the turn's model call in flight. processTurn appends a call's
+  // answer only once it returns, so this is all a finalized turn has of
+  // the call it aborted.
+  let callText = "";
+
+  // Where the turn in progress starts in the transcript, for the partial
+  // answer of a finalized turn.
+  let turnStart: TurnStart | null = null;
+
+  /** Bring `turnStart` up to date with any compaction since it was set. */
+  function syncTurnStart(): TurnStart | null {
+    if (turnStart === null) return null;
+    turnStart = rebaseTurnStart(
+      turnStart,
+      conversationStore.getMessages().length,
+      conversationStore.getMetadata().compactionCount
+    );
+    return turnStart;
+  }
+
   const callModel: CallModel = async (
     params: CallModelParams
   ): Promise<CallModelResult> => {
-    const response = await client.messages.create({
-      model: params.model,
+    const start = syncTurnStart();
+    if (start !== null && params.purpose === "compaction") {
+      turnStart = { ...start, lengthAtCompaction: conversationStore.getMessages().length };
+    }
+    const model =
+      turnRoute !== null && params.purpose === "turn" ? turnRoute.model : params.model;
+    lastRequest = { ...params, model };
+    const body: Anthropic.MessageCreateParamsNonStreaming = {
+      model,
       system: params.system,
       messages: params.messages.map((m) => ({
         role: m.role,
@@ -132,7 +240,28 @@ async function main(): Promise<void> {
       })),
       tools: params.tools as Anthropic.Tool[] | undefined,
       max_tokens: params.max_tokens,
-    });
+    };
+    const options = { signal: turnAbort?.signal };
+    let response: Anthropic.Message;
+    if (params.purpose === "turn") {
+      // Streamed whether or not the input asked for deltas, so the text
+      // is at hand if "finalize" aborts the call.
+      callText = "";
+      const stream = client.messages.stream(body, options);
+      stream.on("text", (text) => {
+        callText += text;
+        if (turnStream) send({ type: "output_delta", text });
+      });
+      response = await stream.finalMessage();
+      callText = "";
+    } else {
+      response = await client.messages.create(body, options);
+    }
+
+    turnUsage = {
+      inputTokens: turnUsage.inputTokens + response.usage.input_tokens,
+      outputTokens: turnUsage.outputTokens + response.usage.output_tokens,
+    };
 
     return {
       content: response.content.map((block) => {
@@ -166,38 +295,110 @@ async function main(): Promise<void> {
   });
 
   // Signal readiness
-  send({ type: "ready" });
+  send({ type: "ready", protocol: config.protocol });
 
-  // Read stdin line by line
-  const rl = createInterface({ input: process.stdin });
-
-  for await (const line of rl) {
-    const trimmed = line.trim();
-    if (trimmed === "") continue;
+  function reportError(error: unknown): void {
+    const errorMessage = error instanceof Error ? error.message : String(error);
+    send({ type: "error", message: errorMessage });
+  }
 
-    let parsed: unknown;
+  /** Send the end of a turn: usage, a new checkpoint, the response. */
+  function finishTurn(prompt: string, text: string, partial: boolean): void {
+    const usage = {
+      type: "usage",
+      model: turnRoute?.model ?? config.model,
+      input_tokens: turnUsage.inputTokens,
+      output_tokens: turnUsage.outputTokens,
+    } as const;
+    send(turnRoute === null ? usage : { ...usage, route: turnRoute.reason });
+    // The turn is already persisted; a failed checkpoint must not
+    // cost the user its response.
     try {
-      parsed = JSON.parse(trimmed);
-    } catch {
-      send({ type: "error", message: "Invalid JSON on stdin" });
-      continue;
+      const checkpoint = checkpoints.write(prompt);
+      send({ type: "checkpoint", id: checkpoint.id });
+    } catch (error) {
+      process.stderr.write(
+        `checkpoint failed: ${error instanceof Error ? error.message : String(error)}\n`
+      );
     }
+    send(partial ? { type: "output", text, partial } : { type: "output", text });
+  }
 
-    const msg = parsed as InputMessage;
-    if (msg.type !== "input" || typeof msg.text !== "string") {
-      send({ type: "error", message: "Expected {\"type\":\"input\",\"text\":\"...\"}" });
-      continue;
+  async function handle(msg: QueuedMessage): Promise<void> {
+    if (msg.type === "inspect") {
+      send({ type: "inspect", request: lastRequest });
+      return;
     }
 
+    if (msg.type === "checkpoint" || msg.type === "restore_checkpoint") {
+      try {
+        if (msg.type === "checkpoint") {
+          const entry = checkpoints.write("");
+          send({ type: "checkpoint", id: entry.id });
+        } else {
+          checkpoints.restore(msg.id);
+          send({ type: "checkpoint_restored", id: msg.id });
+        }
+      } catch (error) {
+        reportError(error);
+      }
+      return;
+    }
+
+    const abort = new AbortController();
+    turnAbort = abort;
+    turnRoute = config.routing === "auto" ? routeTurn(msg.text, config) : null;
+    turnStream = msg.stream;
+    replyTo = msg.id;
+    turnStart = {
+      index: conversationStore.getMessages().length,
+      compactionCount: conversationStore.getMetadata().compactionCount,
+      lengthAtCompaction: null,
+    };
+    const heartbeat =
+      config.protocol >= HEARTBEATS
+        ? setInterval(() => send({ type: "heartbeat" }), HEARTBEAT_MS)
+        : null;
     try {
+      turnUsage = { inputTokens: 0, outputTokens: 0 };
       const response = await agent.processTurn(msg.text);
-      send({ type: "output", text: response });
+      finishTurn(msg.text, response, false);
     } catch (error) {
-      const errorMessage =
-        error instanceof Error ? error.message : String(error);
-      send({ type: "error", message: errorMessage });
+      if (!abort.signal.aborted) {
+        reportError(error);
+        return;
+      }
+      // Finalized: answer with whatever text the turn produced so far,
+      // including the aborted call's.
+      const start = syncTurnStart();
+      const partial =
+        start === null ? "" : salvagePartial(conversationStore, start.index, callText);
+      if (partial === "") {
+        send({ type: "error", message: "Turn finalized before the agent wrote any text" });
+      } else {
+        finishTurn(msg.text, partial, true);
+      }
+    } finally {
+      if (heartbeat !== null) clearInterval(heartbeat);
+      turnAbort = null;
+      turnRoute = null;
+      turnStream = false;
+      callText = "";
+      turnStart = null;
+      replyTo = null;
     }
   }
+
+  const inbox = createInbox({
+    handle,
+    finalize: () => turnAbort?.abort(),
+    reportError,
+  });
+  const rl = createInterface({ input: process.stdin });
+  rl.on("line", inbox.line);
+
+  await new Promise<void>((resolve) => rl.once("close", () => resolve()));
+  await inbox.drained();
 }
 
 main().catch((error) => {
@@ -150,6 +150,16 @@ describe("writeJson", () => {
     expect(result).toEqual(complex);
   });
 
+  it("writes large multi-byte content in full", () => {
+    // Megabytes of three-byte characters, so a byte offset into the
+    // content is not a character offset.
+    const text = "日本語".repeat(1024 * 1024);
+
+    storage.writeJson("large.json", { text });
+
+    expect(storage.readJson<{ text: string }>("large.json").text).toBe(text);
+  });
+
   it("writes no temp files on success", () => {
     storage.writeJson("clean.json", { data: true });
 
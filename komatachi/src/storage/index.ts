@@ -9,14 +9,17 @@
  * - Respect layer boundaries: Storage stores; it does not interpret
  * - Fail clearly: Specific error types for not-found, corruption, I/O
  * - Make state explicit: No caching, no hidden state
- * - Crash resilient: Atomic writes (write-to-temp, rename), partial line handling
+ * - Crash resilient: Atomic writes (write-to-temp, fsync, rename), fsynced
+ *   appends, partial line handling
  * - Synchronous I/O: Single-digit ms disk writes, dominated by LLM API latency
  */
 
 import {
   readFileSync,
-  writeFileSync,
-  appendFileSync,
+  openSync,
+  writeSync,
+  fsyncSync,
+  closeSync,
   renameSync,
   unlinkSync,
   mkdirSync,
@@ -118,14 +121,34 @@ export function createStorage(baseDir: string): Storage {
   }
 
   /**
-   * Atomic write: write to a temp file in the same directory, then rename.
-   * Rename is atomic on POSIX filesystems, providing crash safety.
+   * Write content to a file opened with the given flags and fsync it
+   * before closing, so the bytes are on disk when this returns. writeSync
+   * may write fewer bytes than asked, so it is called until all are.
+   */
+  function writeDurably(fullPath: string, flags: string, content: string): void {
+    const bytes = Buffer.from(content, "utf-8");
+    const fd = openSync(fullPath, flags);
+    try {
+      let written = 0;
+      while (written < bytes.length) {
+        written += writeSync(fd, bytes, written, bytes.length - written);
+      }
+      fsyncSync(fd);
+    } finally {
+      closeSync(fd);
+    }
+  }
+
+  /**
+   * Atomic write: write to a temp file in the same directory, fsync it,
+   * then rename. Rename is atomic on POSIX filesystems; the fsync ensures
+   * the renamed file is never empty after a power loss.
    */
   function atomicWrite(fullPath: string, content: string): void {
     ensureParentDir(fullPath);
     const tempPath = `${fullPath}.${randomUUID()}.tmp`;
     try {
-      writeFileSync(tempPath, content, "utf-8");
+      writeDurably(tempPath, "w", content);
       renameSync(tempPath, fullPath);
     } catch (error) {
       try {
@@ -210,7 +233,7 @@ export function createStorage(baseDir: string): Storage {
     const fullPath = resolve(path);
     try {
       ensureParentDir(fullPath);
-      appendFileSync(fullPath, JSON.stringify(entry) + "\n", "utf-8");
+      writeDurably(fullPath, "a", JSON.stringify(entry) + "\n");
     } catch (error) {
       throw new StorageIOError(path, error);
     }
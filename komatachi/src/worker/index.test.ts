@@ -0,0 +1,489 @@
+import { describe, it, expect, beforeEach, afterEach } from "vitest";
+import { mkdtempSync, rmSync } from "node:fs";
+import { tmpdir } from "node:os";
+import { join } from "node:path";
+
+import { createStorage, type Storage } from "../storage/index.js";
+import {
+  createConversationStore,
+  type ConversationStore,
+  type Message,
+} from "../conversation/index.js";
+import { createAgent } from "../agent/index.js";
+import {
+  createCheckpoints,
+  createInbox,
+  epochPath,
+  frame,
+  negotiateProtocol,
+  parseInbound,
+  partialResponse,
+  readCheckpoints,
+  rebaseTurnStart,
+  routeTurn,
+  salvagePartial,
+  PROTOCOL_VERSIONS,
+  type QueuedMessage,
+  type TurnStart,
+} from "./index.js";
+
+// -----------------------------------------------------------------------------
+// Test helpers
+// -----------------------------------------------------------------------------
+
+let testDir: string;
+let storage: Storage;
+let store: ConversationStore;
+
+beforeEach(() => {
+  testDir = mkdtempSync(join(tmpdir(), "komatachi-worker-test-"));
+  storage = createStorage(testDir);
+  store = createConversationStore(storage, "conversation");
+  store.initialize();
+});
+
+afterEach(() => {
+  rmSync(testDir, { recursive: true, force: true });
+});
+
+function user(text: string): Message {
+  return { role: "user", content: text };
+}
+
+function assistant(text: string): Message {
+  return { role: "assistant", content: [{ type: "text", text }] };
+}
+
+/** One exchange appended to the store. */
+function exchange(prompt: string, answer: string): void {
+  store.appendMessage(user(prompt));
+  store.appendMessage(assistant(answer));
+}
+
+/** A promise and the function that settles it. */
+function deferred(): { promise: Promise<void>; resolve: () => void } {
+  let resolve = (): void => {};
+  const promise = new Promise<void>((r) => {
+    resolve = r;
+  });
+  return { promise, resolve };
+}
+
+// -----------------------------------------------------------------------------
+// Parsing and framing
+// -----------------------------------------------------------------------------
+
+describe("parseInbound", () => {
+  it("reads inputs with their stream flag and id", () => {
+    expect(parseInbound({ type: "input", text: "hi", stream: true, id: 4 })).toEqual({
+      type: "input",
+      text: "hi",
+      stream: true,
+      id: 4,
+    });
+    expect(parseInbound({ type: "input", text: "hi" })).toEqual({
+      type: "input",
+      text: "hi",
+      stream: false,
+      id: null,
+    });
+  });
+
+  it("ignores an id that is not an integer", () => {
+    const msg = parseInbound({ type: "input", text: "hi", id: "4" });
+    expect(msg).toMatchObject({ id: null });
+  });
+
+  it("rejects unknown and malformed messages", () => {
+    expect(parseInbound({ type: "no_such_message" })).toBeNull();
+    expect(parseInbound({ type: "input" })).toBeNull();
+    expect(parseInbound({ type: "restore_checkpoint", id: "1" })).toBeNull();
+    expect(parseInbound("input")).toBeNull();
+    expect(parseInbound(null)).toBeNull();
+  });
+});
+
+describe("frame", () => {
+  it("adds the sequence number", () => {
+    const line = frame({ type: "ready", protocol: 3 }, 1, null);
+    expect(line).toBe('{"type":"ready","protocol":3,"seq":1}\n');
+  });
+
+  it("echoes the input id as reply_to during its turn", () => {
+    const line = frame({ type: "output", text: "done" }, 7, 4);
+    expect(JSON.parse(line)).toEqual({ type: "output", text: "done", reply_to: 4, seq: 7 });
+  });
+});
+
+// -----------------------------------------------------------------------------
+// Protocol versions
+// -----------------------------------------------------------------------------
+
+describe("negotiateProtocol", () => {
+  it("speaks the newest shared version", () => {
+    expect(negotiateProtocol("1,2")).toBe(2);
+    expect(negotiateProtocol(" 1 ")).toBe(1);
+    expect(negotiateProtocol("2,3,9")).toBe(3);
+  });
+
+  it("speaks its newest when the CLI offers none", () => {
+    const newest = PROTOCOL_VERSIONS[PROTOCOL_VERSIONS.length - 1];
+    expect(negotiateProtocol(undefined)).toBe(newest);
+    expect(negotiateProtocol("")).toBe(newest);
+  });
+
+  it("names the side to upgrade when no version is shared", () => {
+    expect(() => negotiateProtocol("9")).toThrow(/upgrade the agent image$/);
+    expect(() => negotiateProtocol("0")).toThrow(/must list version numbers/);
+  });
+
+  it("rejects a list that is not numbers", () => {
+    expect(() => negotiateProtocol("one")).toThrow(/must list version numbers/);
+  });
+});
+
+// -----------------------------------------------------------------------------
+// Inbox
+// -----------------------------------------------------------------------------
+
+describe("createInbox", () => {
+  it("handles messages one at a time, in arrival order", async () => {
+    const log: string[] = [];
+    const first = deferred();
+    const inbox = createInbox({
+      handle: async (msg: QueuedMessage) => {
+        log.push(`start ${msg.type}`);
+        if (msg.type === "input") await first.promise;
+        log.push(`end ${msg.type}`);
+      },
+      finalize: () => log.push("finalize"),
+      reportError: (error) => log.push(`error ${String(error)}`),
+    });
+
+    inbox.line('{"type":"input","text":"long turn"}');
+    inbox.line('{"type":"inspect"}');
+    await new Promise((r) => setTimeout(r, 0));
+    expect(log).toEqual(["start input"]);
+
+    first.resolve();
+    await inbox.drained();
+    expect(log).toEqual(["start input", "end input", "start inspect", "end inspect"]);
+  });
+
+  it("passes finalize to the running turn at once", async () => {
+    const log: string[] = [];
+    const turn = deferred();
+    const inbox = createInbox({
+      handle: async () => {
+        await turn.promise;
+        log.push("turn ended");
+      },
+      finalize: () => {
+        log.push("finalize");
+        turn.resolve();
+      },
+      reportError: () => log.push("error"),
+    });
+
+    inbox.line('{"type":"input","text":"hi","id":1}');
+    inbox.line('{"type":"finalize"}');
+    await inbox.drained();
+    expect(log).toEqual(["finalize", "turn ended"]);
+  });
+
+  it("reports a bad line after the messages before it", async () => {
+    const log: string[] = [];
+    const turn = deferred();
+    const inbox = createInbox({
+      handle: async () => {
+        await turn.promise;
+        log.push("turn ended");
+      },
+      finalize: () => {},
+      reportError: (error) =>
+        log.push(error instanceof Error ? error.message : String(error)),
+    });
+
+    inbox.line('{"type":"input","text":"hi"}');
+    inbox.line("{not json");
+    inbox.line('{"type":"no_such_message"}');
+    inbox.line("   ");
+    turn.resolve();
+    await inbox.drained();
+    expect(log).toEqual([
+      "turn ended",
+      "Invalid JSON on stdin",
+      'Expected {"type":"input","text":"..."}',
+    ]);
+  });
+
+  it("keeps handling after a handler throws", async () => {
+    const log: string[] = [];
+    const inbox = createInbox({
+      handle: async (msg) => {
+        if (msg.type === "checkpoint") throw new Error("disk full");
+        log.push(msg.type);
+      },
+      finalize: () => {},
+      reportError: (error) =>
+        log.push(error instanceof Error ? error.message : String(error)),
+    });
+
+    inbox.line('{"type":"checkpoint"}');
+    inbox.line('{"type":"inspect"}');
+    await inbox.drained();
+    expect(log).toEqual(["disk full", "inspect"]);
+  });
+});
+
+// -----------------------------------------------------------------------------
+// Routing
+// -----------------------------------------------------------------------------
+
+describe("routeTurn", () => {
+  const models = { model: "large", smallModel: "small" };
+
+  it("sends short plain prompts to the small model", () => {
+    expect(routeTurn("what time is it in Tokyo?", models)).toEqual({
+      model: "small",
+      reason: "short prompt",
+    });
+  });
+
+  it("sends long, structured, and reasoning prompts to the large model", () => {
+    expect(routeTurn("x".repeat(281), models).reason).toBe("long prompt");
+    expect(routeTurn("fix this:\n```\nls\n```", models).reason).toBe("structured prompt");
+    expect(routeTurn("one\n\ntwo", models).reason).toBe("structured prompt");
+    expect(routeTurn("Explain monads", models)).toEqual({
+      model: "large",
+      reason: "reasoning requested",
+    });
+  });
+});
+
+// -----------------------------------------------------------------------------
+// Checkpoints
+// -----------------------------------------------------------------------------
+
+describe("createCheckpoints", () => {
+  it("numbers checkpoints and records the message count", () => {
+    const checkpoints = createCheckpoints(storage, store);
+    exchange("first", "one");
+    const a = checkpoints.write("first");
+    exchange("second", "two");
+    const b = checkpoints.write("second");
+
+    expect([a.id, b.id]).toEqual([0, 1]);
+    expect(b.messageCount).toBe(4);
+    expect(b.preview).toBe("second");
+    expect(readCheckpoints(storage).map((entry) => entry.id)).toEqual([0, 1]);
+  });
+
+  it("appends each turn's messages to the epoch file", () => {
+    const checkpoints = createCheckpoints(storage, store);
+    exchange("first", "one");
+    checkpoints.write("first");
+    exchange("second", "two");
+    checkpoints.write("second");
+
+    expect(storage.readAllJsonl(epochPath(0))).toEqual(store.getMessages());
+  });
+
+  it("continues an epoch file written by an earlier process", () => {
+    exchange("first", "one");
+    createCheckpoints(storage, store).write("first");
+    exchange("second", "two");
+    createCheckpoints(storage, store).write("second");
+
+    expect(storage.readAllJsonl(epochPath(0))).toEqual(store.getMessages());
+  });
+
+  it("restores the transcript and forgets later checkpoints", () => {
+    const checkpoints = createCheckpoints(storage, store);
+    exchange("first", "one");
+    const a = checkpoints.write("first");
+    exchange("second", "two");
+    checkpoints.write("second");
+
+    checkpoints.restore(a.id);
+    expect(store.getMessages()).toEqual([user("first"), assistant("one")]);
+    expect(readCheckpoints(storage).map((entry) => entry.id)).toEqual([a.id]);
+  });
+
+  it("truncates the epoch file on restore, so later turns follow the kept prefix", () => {
+    const checkpoints = createCheckpoints(storage, store);
+    exchange("first", "one");
+    const a = checkpoints.write("first");
+    exchange("second", "two");
+    checkpoints.write("second");
+
+    checkpoints.restore(a.id);
+    exchange("other", "three");
+    const c = checkpoints.write("other");
+    expect(storage.readAllJsonl(epochPath(0))).toEqual(store.getMessages());
+
+    exchange("fourth", "four");
+    checkpoints.write("fourth");
+    checkpoints.restore(c.id);
+    expect(store.getMessages()).toEqual([
+      user("first"),
+      assistant("one"),
+      user("other"),
+      assistant("three"),
+    ]);
+  });
+
+  it("restores across a compaction from the epoch file", () => {
+    const checkpoints = createCheckpoints(storage, store);
+    exchange("first", "one");
+    const a = checkpoints.write("first");
+    store.replaceTranscript([user("[summary]")]);
+    store.updateMetadata({ compactionCount: 1 });
+    exchange("second", "two");
+    checkpoints.write("second");
+
+    checkpoints.restore(a.id);
+    expect(store.getMessages()).toEqual([user("first"), assistant("one")]);
+    expect(store.getMetadata().compactionCount).toBe(0);
+    expect(storage.readAllJsonl(epochPath(1))).toEqual([]);
+  });
+
+  it("starts a reused epoch number afresh", () => {
+    const checkpoints = createCheckpoints(storage, store);
+    exchange("first", "one");
+    const a = checkpoints.write("first");
+    store.replaceTranscript([user("[summary]")]);
+    store.updateMetadata({ compactionCount: 1 });
+    exchange("second", "two");
+    checkpoints.write("second");
+    checkpoints.restore(a.id);
+
+    store.replaceTranscript([user("[another summary]")]);
+    store.updateMetadata({ compactionCount: 1 });
+    const b = checkpoints.write("");
+    expect(storage.readAllJsonl(epochPath(1))).toEqual([user("[another summary]")]);
+    exchange("third", "three");
+    checkpoints.write("third");
+    checkpoints.restore(b.id);
+    expect(store.getMessages()).toEqual([user("[another summary]")]);
+  });
+
+  it("refuses an unknown id or a short epoch file", () => {
+    const checkpoints = createCheckpoints(storage, store);
+    expect(() => checkpoints.restore(9)).toThrow("No checkpoint 9");
+
+    exchange("first", "one");
+    const a = checkpoints.write("first");
+    storage.writeJsonl(epochPath(0), [user("first")]);
+    expect(() => createCheckpoints(storage, store).restore(a.id)).toThrow(/needs 2 messages/);
+  });
+});
+
+// -----------------------------------------------------------------------------
+// Partial answers
+// -----------------------------------------------------------------------------
+
+describe("partialResponse", () => {
+  it("joins the text the assistant wrote after the prompt", () => {
+    const messages: Message[] = [
+      user("earlier"),
+      assistant("not this"),
+      user("prompt"),
+      assistant("part one"),
+      user("tool result"),
+      { role: "assistant", content: "part two" },
+    ];
+    expect(partialResponse(messages, 2)).toBe("part one\n\npart two");
+  });
+
+  it("does not take an earlier turn with the same prompt", () => {
+    const messages = [user("again"), assistant("old answer"), user("again")];
+    expect(partialResponse(messages, 2)).toBe("");
+  });
+});
+
+describe("rebaseTurnStart", () => {
+  const start: TurnStart = { index: 10, compactionCount: 0, lengthAtCompaction: null };
+
+  it("keeps the index within the epoch", () => {
+    expect(rebaseTurnStart(start, 14, 0)).toBe(start);
+  });
+
+  it("keeps the prompt as far from the end after a compaction", () => {
+    const compacting = { ...start, lengthAtCompaction: 13 };
+    // The summary, then the kept prompt and the two messages after it.
+    expect(rebaseTurnStart(compacting, 4, 1)).toEqual({
+      index: 1,
+      compactionCount: 1,
+      lengthAtCompaction: null,
+    });
+  });
+
+  it("points at the summary when the prompt was compacted away", () => {
+    const compacting = { ...start, lengthAtCompaction: 13 };
+    expect(rebaseTurnStart(compacting, 2, 1).index).toBe(0);
+  });
+
+  it("finds the partial answer after a mid-turn compaction", () => {
+    exchange("again", "old answer");
+    const turn: TurnStart = {
+      index: store.getMessages().length,
+      compactionCount: 0,
+      lengthAtCompaction: null,
+    };
+    store.appendMessage(user("again"));
+    store.appendMessage(assistant("new answer"));
+    const compacting = { ...turn, lengthAtCompaction: store.getMessages().length };
+    store.replaceTranscript([user("[summary]"), user("again"), assistant("new answer")]);
+    store.updateMetadata({ compactionCount: 1 });
+
+    const rebased = rebaseTurnStart(compacting, store.getMessages().length, 1);
+    expect(partialResponse(store.getMessages(), rebased.index)).toBe("new answer");
+  });
+});
+
+describe("salvagePartial", () => {
+  it("answers with the text of the only model call, aborted mid-response", async () => {
+    // As the entry point does: text is collected as the call streams,
+    // and the finalize aborts the call before it returns.
+    let received = "";
+    const finalize = new AbortController();
+    const agent = createAgent({
+      conversationStore: store,
+      homeDir: testDir,
+      tools: [],
+      model: "test-model",
+      maxTokens: 4096,
+      contextWindow: 200000,
+      callModel: () =>
+        new Promise((_, reject) => {
+          received += "The answer ";
+          received += "so far";
+          finalize.signal.addEventListener("abort", () => reject(new Error("aborted")));
+          finalize.abort();
+        }),
+    });
+    exchange("earlier", "old answer");
+    const start = store.getMessages().length;
+
+    await expect(agent.processTurn("question")).rejects.toThrow("aborted");
+    expect(salvagePartial(store, start, received)).toBe("The answer so far");
+    expect(store.getMessages().slice(start)).toEqual([
+      user("question"),
+      assistant("The answer so far"),
+    ]);
+  });
+
+  it("adds the aborted call's text to the turn's finished calls", () => {
+    store.appendMessage(user("prompt"));
+    store.appendMessage(assistant("part one"));
+    store.appendMessage(user("tool result"));
+    expect(salvagePartial(store, 0, "part two")).toBe("part one\n\npart two");
+  });
+
+  it("leaves the transcript alone when nothing arrived", () => {
+    store.appendMessage(user("prompt"));
+    expect(salvagePartial(store, 0, "")).toBe("");
+    expect(store.getMessages()).toEqual([user("prompt")]);
+  });
+});
@@ -0,0 +1,534 @@
+/**
+ * Worker Module
+ *
+ * The agent process as a headless worker (ROADMAP Decision #22): the
+ * JSON-lines protocol it speaks on stdin/stdout, and the per-turn
+ * bookkeeping around processTurn() that the protocol needs. Checkpoints,
+ * model routing, and the partial answer of a finalized turn live here
+ * rather than in the entry point, so they can be tested without a process,
+ * a terminal, or the Anthropic SDK.
+ *
+ * See src/index.ts for the protocol itself.
+ *
+ * Design principles:
+ * - Pure functions where the job allows: parsing, framing, negotiation,
+ *   routing, and locating the partial answer take values and return values
+ * - State the caller owns: sequence numbers, the running turn, and the
+ *   message queue are passed in or returned, never module globals
+ * - Checkpoints go through Storage and ConversationStore like every other
+ *   write to the data volume, and append rather than rewrite
+ */
+
+import {
+  StorageNotFoundError,
+  type Storage,
+} from "../storage/index.js";
+import type { ConversationStore, Message } from "../conversation/index.js";
+import type { CallModelParams } from "../agent/index.js";
+
+// -----------------------------------------------------------------------------
+// Protocol types
+// -----------------------------------------------------------------------------
+
+export interface InputMessage {
+  readonly type: "input";
+  readonly text: string;
+  /** Send the answer's text as "output_delta" messages as it arrives */
+  readonly stream: boolean;
+  /** Echoed as "reply_to" on the turn's messages; null if not given */
+  readonly id: number | null;
+}
+
+export interface InspectMessage {
+  readonly type: "inspect";
+}
+
+export interface CheckpointMessage {
+  readonly type: "checkpoint";
+}
+
+export interface RestoreCheckpointMessage {
+  readonly type: "restore_checkpoint";
+  readonly id: number;
+}
+
+export interface FinalizeMessage {
+  readonly type: "finalize";
+}
+
+export type InboundMessage =
+  | InputMessage
+  | InspectMessage
+  | CheckpointMessage
+  | RestoreCheckpointMessage
+  | FinalizeMessage;
+
+/** Every inbound message but "finalize", which never waits in the queue. */
+export type QueuedMessage = Exclude<InboundMessage, FinalizeMessage>;
+
+export interface ReadyMessage {
+  readonly type: "ready";
+  /** The protocol version this session speaks */
+  readonly protocol: number;
+}
+
+export interface OutputMessage {
+  readonly type: "output";
+  readonly text: string;
+  /** Set when the turn was finalized before it completed */
+  readonly partial?: boolean;
+}
+
+export interface OutputDeltaMessage {
+  readonly type: "output_delta";
+  readonly text: string;
+}
+
+export interface HeartbeatMessage {
+  readonly type: "heartbeat";
+}
+
+export interface ErrorMessage {
+  readonly type: "error";
+  readonly message: string;
+}
+
+export interface UsageMessage {
+  readonly type: "usage";
+  readonly model: string;
+  readonly input_tokens: number;
+  readonly output_tokens: number;
+  /** With KOMATACHI_ROUTING=auto: why this turn went to `model` */
+  readonly route?: string;
+}
+
+export interface InspectResultMessage {
+  readonly type: "inspect";
+  readonly request: CallModelParams | null;
+}
+
+export interface CheckpointResultMessage {
+  readonly type: "checkpoint";
+  readonly id: number;
+}
+
+export interface CheckpointRestoredMessage {
+  readonly type: "checkpoint_restored";
+  readonly id: number;
+}
+
+export type OutboundMessage =
+  | ReadyMessage
+  | UsageMessage
+  | OutputMessage
+  | OutputDeltaMessage
+  | HeartbeatMessage
+  | ErrorMessage
+  | InspectResultMessage
+  | CheckpointResultMessage
+  | CheckpointRestoredMessage;
+
+// -----------------------------------------------------------------------------
+// Parsing and framing
+// -----------------------------------------------------------------------------
+
+/** Validate a parsed stdin line. Returns null if it is not a known message. */
+export function parseInbound(value: unknown): InboundMessage | null {
+  if (value === null || typeof value !== "object" || !("type" in value)) {
+    return null;
+  }
+  const msg = value as { type: unknown; text?: unknown; id?: unknown; stream?: unknown };
+  if (msg.type === "input" && typeof msg.text === "string") {
+    return {
+      type: "input",
+      text: msg.text,
+      stream: msg.stream === true,
+      id: Number.isInteger(msg.id) ? (msg.id as number) : null,
+    };
+  }
+  if (msg.type === "inspect") {
+    return { type: "inspect" };
+  }
+  if (msg.type === "checkpoint") {
+    return { type: "checkpoint" };
+  }
+  if (msg.type === "restore_checkpoint" && typeof msg.id === "number") {
+    return { type: "restore_checkpoint", id: msg.id };
+  }
+  if (msg.type === "finalize") {
+    return { type: "finalize" };
+  }
+  return null;
+}
+
+/**
+ * One stdout line: `message` with its sequence number, and with "reply_to"
+ * when it belongs to the turn of an input that carried an id.
+ */
+export function frame(message: OutboundMessage, seq: number, replyTo: number | null): string {
+  const echo = replyTo === null ? {} : { reply_to: replyTo };
+  return JSON.stringify({ ...message, ...echo, seq }) + "\n";
+}
+
+// -----------------------------------------------------------------------------
+// Protocol versions
+// -----------------------------------------------------------------------------
+
+/** Protocol versions this agent speaks, oldest first. */
+export const PROTOCOL_VERSIONS: readonly number[] = [1, 2, 3];
+
+/** The first version that sends "heartbeat" during a turn. */
+export const HEARTBEATS = 3;
+
+/** How often a running turn sends "heartbeat". */
+export const HEARTBEAT_MS = 5000;
+
+/**
+ * The newest version in both this agent's list and `offered`, the CLI's
+ * comma-separated list; the agent's newest when the CLI offered none.
+ * Throws, saying which side is behind, when they share no version.
+ */
+export function negotiateProtocol(offered: string | undefined): number {
+  const ours = PROTOCOL_VERSIONS;
+  const newest = ours[ours.length - 1];
+  if (offered === undefined || offered.trim() === "") {
+    return newest;
+  }
+  const theirs = offered.split(",").map((v) => Number(v.trim()));
+  if (theirs.some((v) => !Number.isInteger(v) || v < 1)) {
+    throw new Error(`KOMATACHI_PROTOCOL must list version numbers, got "${offered}"`);
+  }
+  const shared = theirs.filter((v) => ours.includes(v));
+  if (shared.length > 0) {
+    return Math.max(...shared);
+  }
+  const upgrade = Math.max(...theirs) < ours[0] ? "komatachi-cli" : "the agent image";
+  throw new Error(
+    `protocol mismatch: the CLI speaks version ${theirs.join(", ")} and this agent ` +
+      `speaks ${ours.join(", ")}; upgrade ${upgrade}`
+  );
+}
+
+// -----------------------------------------------------------------------------
+// Inbox
+// -----------------------------------------------------------------------------
+
+/** What the inbox does with each kind of line. */
+export interface InboxHandlers {
+  /** Handle one message; the next waits until the promise settles */
+  readonly handle: (msg: QueuedMessage) => Promise<void>;
+  /** Cut the running turn short, if there is one */
+  readonly finalize: () => void;
+  /** Report a line that is not a message, or a handler that threw */
+  readonly reportError: (error: unknown) => void;
+}
+
+export interface Inbox {
+  /** Take one stdin line */
+  readonly line: (text: string) => void;
+  /** Settles once every message taken so far has been handled */
+  readonly drained: () => Promise<void>;
+}
+
+/**
+ * Messages are handled one at a time, in arrival order -- except
+ * "finalize", which has to reach the turn that is still running. A line
+ * that is not a message gets its error in order too, after the messages
+ * before it.
+ */
+export function createInbox(handlers: InboxHandlers): Inbox {
+  let queue: Promise<void> = Promise.resolve();
+
+  function enqueue(work: () => Promise<void> | void): void {
+    queue = queue.then(work).catch(handlers.reportError);
+  }
+
+  function line(text: string): void {
+    const trimmed = text.trim();
+    if (trimmed === "") return;
+
+    let parsed: unknown;
+    try {
+      parsed = JSON.parse(trimmed);
+    } catch {
+      enqueue(() => handlers.reportError(new Error("Invalid JSON on stdin")));
+      return;
+    }
+
+    const msg = parseInbound(parsed);
+    if (msg === null) {
+      enqueue(() =>
+        handlers.reportError(new Error("Expected {\"type\":\"input\",\"text\":\"...\"}"))
+      );
+      return;
+    }
+
+    if (msg.type === "finalize") {
+      // Between turns there is nothing to finalize; the request raced
+      // with a turn that already finished.
+      handlers.finalize();
+      return;
+    }
+
+    const queued: QueuedMessage = msg;
+    enqueue(() => handlers.handle(queued));
+  }
+
+  return { line, drained: () => queue };
+}
+
+// -----------------------------------------------------------------------------
+// Routing
+// -----------------------------------------------------------------------------
+
+/** Prompts longer than this go to the large model. */
+const SIMPLE_PROMPT_CHARS = 280;
+
+/** Words that suggest the prompt needs reasoning rather than a quick reply. */
+const COMPLEX_PROMPT_WORDS =
+  /\b(why|explain|design|debug|analy[sz]e|compare|refactor|prove|plan|review|architecture)\b/i;
+
+export interface RouteModels {
+  /** The large model, for everything not plainly simple */
+  readonly model: string;
+  readonly smallModel: string;
+}
+
+export interface Route {
+  readonly model: string;
+  readonly reason: string;
+}
+
+/**
+ * Pick the model for a turn under KOMATACHI_ROUTING=auto. A heuristic, not
+ * a classifier: short single-paragraph prompts without code or
+ * reasoning words go to the small model; everything else to the large one.
+ */
+export function routeTurn(prompt: string, models: RouteModels): Route {
+  if (prompt.length > SIMPLE_PROMPT_CHARS) {
+    return { model: models.model, reason: "long prompt" };
+  }
+  if (prompt.includes("```") || prompt.includes("\n\n")) {
+    return { model: models.model, reason: "structured prompt" };
+  }
+  if (COMPLEX_PROMPT_WORDS.test(prompt)) {
+    return { model: models.model, reason: "reasoning requested" };
+  }
+  return { model: models.smallModel, reason: "short prompt" };
+}
+
+// -----------------------------------------------------------------------------
+// Checkpoints
+// -----------------------------------------------------------------------------
+
+/**
+ * One entry in checkpoints/index.jsonl.
+ *
+ * Within one compaction epoch the transcript only grows, so a checkpoint is
+ * just a message count. The epoch's messages are kept in
+ * checkpoints/epoch-<compactionCount>.jsonl, appended to at each
+ * checkpoint; once compaction rewrites the live transcript, that file still
+ * holds the prefix every earlier checkpoint of the epoch needs.
+ *
+ * Ids start at 0: the entry point records checkpoint 0, the empty
+ * conversation, when it creates the conversation, so the first exchange
+ * can be rolled back like any other.
+ */
+export interface CheckpointEntry {
+  readonly id: number;
+  readonly createdAt: number;
+  readonly compactionCount: number;
+  readonly messageCount: number;
+  /** Start of the prompt that ended at this checkpoint */
+  readonly preview: string;
+}
+
+export const CHECKPOINT_INDEX = "checkpoints/index.jsonl";
+const PREVIEW_CHARS = 80;
+
+export function epochPath(compactionCount: number): string {
+  return `checkpoints/epoch-${compactionCount}.jsonl`;
+}
+
+export function readCheckpoints(storage: Storage): CheckpointEntry[] {
+  try {
+    return storage.readAllJsonl<CheckpointEntry>(CHECKPOINT_INDEX);
+  } catch (error) {
+    if (error instanceof StorageNotFoundError) return [];
+    throw error;
+  }
+}
+
+/** Checkpoints of one conversation. */
+export interface Checkpoints {
+  /** Record the conversation as it is now; `prompt` is for the preview */
+  write(prompt: string): CheckpointEntry;
+
+  /** Return the conversation to checkpoint `id`, forgetting later ones */
+  restore(id: number): void;
+}
+
+/**
+ * Checkpoints for the conversation in `store`.
+ *
+ * A checkpoint appends only the messages added since the last one, so a
+ * turn costs its own messages, not the whole epoch. The epoch file is
+ * rewritten only when it holds messages the conversation no longer has:
+ * after a restore, or when a later conversation reuses an epoch number.
+ */
+export function createCheckpoints(storage: Storage, store: ConversationStore): Checkpoints {
+  // Messages in the current epoch's file, once known; read from the file
+  // the first time, then kept up to date.
+  let epochFile: { compactionCount: number; length: number } | null = null;
+
+  function epochLength(compactionCount: number): number {
+    if (epochFile !== null && epochFile.compactionCount === compactionCount) {
+      return epochFile.length;
+    }
+    try {
+      return storage.readAllJsonl<Message>(epochPath(compactionCount)).length;
+    } catch (error) {
+      if (error instanceof StorageNotFoundError) return 0;
+      throw error;
+    }
+  }
+
+  function write(prompt: string): CheckpointEntry {
+    const previous = readCheckpoints(storage);
+    const { compactionCount } = store.getMetadata();
+    const messages = store.getMessages();
+    const path = epochPath(compactionCount);
+    // The file is the transcript's prefix: the transcript only grows
+    // within an epoch, and restore() truncates both together.
+    const saved = epochLength(compactionCount);
+    if (saved > messages.length) {
+      storage.writeJsonl(path, [...messages]);
+    } else {
+      for (const message of messages.slice(saved)) {
+        storage.appendJsonl(path, message);
+      }
+    }
+    epochFile = { compactionCount, length: messages.length };
+
+    const entry: CheckpointEntry = {
+      id: previous.length === 0 ? 0 : previous[previous.length - 1].id + 1,
+      createdAt: Date.now(),
+      compactionCount,
+      messageCount: messages.length,
+      preview: prompt.slice(0, PREVIEW_CHARS),
+    };
+    storage.appendJsonl(CHECKPOINT_INDEX, entry);
+    return entry;
+  }
+
+  function restore(id: number): void {
+    const entries = readCheckpoints(storage);
+    const index = entries.findIndex((entry) => entry.id === id);
+    if (index === -1) {
+      throw new Error(`No checkpoint ${id}`);
+    }
+    const entry = entries[index];
+    const current = store.getMetadata().compactionCount;
+    const epoch = storage.readAllJsonl<Message>(epochPath(entry.compactionCount));
+    if (epoch.length < entry.messageCount) {
+      throw new Error(
+        `Checkpoint ${id} needs ${entry.messageCount} messages but its epoch file has ${epoch.length}`
+      );
+    }
+    const kept = epoch.slice(0, entry.messageCount);
+    store.replaceTranscript(kept);
+    store.updateMetadata({ compactionCount: entry.compactionCount });
+    // History is linear: checkpoints after the restored one described a
+    // conversation that no longer exists, and so do the messages after
+    // it in its epoch file and every later epoch file.
+    storage.writeJsonl(CHECKPOINT_INDEX, entries.slice(0, index + 1));
+    if (epoch.length > kept.length) {
+      storage.writeJsonl(epochPath(entry.compactionCount), kept);
+    }
+    for (let later = entry.compactionCount + 1; later <= current; later++) {
+      storage.writeJsonl(epochPath(later), []);
+    }
+    epochFile = { compactionCount: entry.compactionCount, length: kept.length };
+  }
+
+  return { write, restore };
+}
+
+// -----------------------------------------------------------------------------
+// Partial answers
+// -----------------------------------------------------------------------------
+
+/**
+ * Where a turn's prompt is in the transcript. Within one compaction epoch
+ * the transcript only grows, so the index holds. Compaction keeps the
+ * newest messages, so the turn's messages stay at the end and the prompt
+ * is as far from the end as it was: `lengthAtCompaction`, the length when
+ * a compaction started, locates it in the new transcript.
+ */
+export interface TurnStart {
+  readonly index: number;
+  readonly compactionCount: number;
+  /** Transcript length when a compaction during the turn started */
+  readonly lengthAtCompaction: number | null;
+}
+
+/**
+ * `start` in a transcript of `length` messages after `compactionCount`
+ * compactions. A prompt the compaction summarized away maps to index 0,
+ * the summary, so the turn's kept messages still follow it.
+ */
+export function rebaseTurnStart(
+  start: TurnStart,
+  length: number,
+  compactionCount: number
+): TurnStart {
+  if (compactionCount === start.compactionCount || start.lengthAtCompaction === null) {
+    return start;
+  }
+  const fromEnd = start.lengthAtCompaction - start.index;
+  return {
+    index: Math.max(0, length - fromEnd),
+    compactionCount,
+    lengthAtCompaction: null,
+  };
+}
+
+/**
+ * Text the assistant wrote after the prompt at `start` -- the partial
+ * answer of a finalized turn.
+ */
+export function partialResponse(messages: readonly Message[], start: number): string {
+  const texts: string[] = [];
+  for (const message of messages.slice(start + 1)) {
+    if (message.role !== "assistant") continue;
+    if (typeof message.content === "string") {
+      texts.push(message.content);
+      continue;
+    }
+    for (const block of message.content) {
+      if (block.type === "text" && block.text.trim() !== "") {
+        texts.push(block.text);
+      }
+    }
+  }
+  return texts.join("\n\n");
+}
+
+/**
+ * The partial answer of a finalized turn, with the transcript brought in
+ * line with it. The turn's finished model calls are in the transcript
+ * after the prompt at `start`; the call the finalize aborted is not, so
+ * `received`, the text it streamed before the abort, is appended as the
+ * assistant's message. The conversation then holds the answer the user is
+ * shown.
+ */
+export function salvagePartial(
+  store: ConversationStore,
+  start: number,
+  received: string
+): string {
+  if (received.trim() !== "") {
+    store.appendMessage({ role: "assistant", content: [{ type: "text", text: received }] });
+  }
+  return partialResponse(store.getMessages(), start);
+}